@@ -0,0 +1,26 @@
+from typing import Any, AsyncIterator, Optional
+
+class SpotifyError(Exception): ...
+class AuthError(SpotifyError): ...
+class RateLimitedError(SpotifyError): ...
+class NotFoundError(SpotifyError): ...
+class InsufficientScopeError(SpotifyError): ...
+
+class Items(AsyncIterator[dict[str, Any]]):
+    def __aiter__(self) -> Items: ...
+    async def __anext__(self) -> dict[str, Any]: ...
+
+class SpotifyClient:
+    @staticmethod
+    async def from_env(profile: str = "default") -> SpotifyClient: ...
+    @staticmethod
+    def with_token(token: str, profile: str = "default") -> SpotifyClient: ...
+    async def me(self) -> dict[str, Any]: ...
+    async def playlists(self) -> list[dict[str, Any]]: ...
+    def iter_playlists(self) -> Items: ...
+    async def playlist_tracks(
+        self, id: str, fields: Optional[list[str]] = None
+    ) -> list[dict[str, Any]]: ...
+    def iter_playlist_tracks(self, id: str, fields: Optional[list[str]] = None) -> Items: ...
+    async def liked_tracks(self, fields: Optional[list[str]] = None) -> list[dict[str, Any]]: ...
+    def iter_liked_tracks(self, fields: Optional[list[str]] = None) -> Items: ...
@@ -0,0 +1,208 @@
+use std::{
+    collections::BTreeMap,
+    io::{ErrorKind, IsTerminal},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cli::Args,
+    link::{Kind, Link},
+    output,
+    profile::Profile,
+    Output,
+};
+
+/// Name of the file in a profile's state dir holding the user's annotations.
+const FILE_NAME: &str = "annotations.json";
+
+/// The user's own tags, rating and note for a track or playlist, which Spotify has nowhere to
+/// keep.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Annotation {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// From 1 to 5
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rating: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// RFC 3339 timestamp of when the annotation last changed
+    pub updated_at: String,
+}
+
+impl Annotation {
+    fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.rating.is_none() && self.note.is_none()
+    }
+}
+
+/// Annotations of a profile, keyed by the URI of the track or playlist they're on.
+pub struct Annotations {
+    annotations: BTreeMap<String, Annotation>,
+}
+
+impl Annotations {
+    pub async fn load(profile: &Profile) -> Result<Self> {
+        let path = profile.dir().join(FILE_NAME);
+
+        let annotations = match tokio::fs::read(&path).await {
+            Ok(data) => serde_json::from_slice(&data)
+                .with_context(|| format!("Failed to parse {}", path.display()))?,
+            Err(e) if e.kind() == ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+
+        Ok(Self { annotations })
+    }
+
+    async fn save(&self, profile: &Profile) -> Result<()> {
+        profile.create_dir().await?;
+        let data = serde_json::to_vec_pretty(&self.annotations)
+            .context("Failed to serialize annotations")?;
+        crate::atomic::write(&profile.dir().join(FILE_NAME), data).await
+    }
+
+    /// The annotation of the track or playlist with the URI `uri`, if it has one.
+    pub fn get(&self, uri: &str) -> Option<&Annotation> {
+        self.annotations.get(uri)
+    }
+
+    /// Fills in the tags, rating and note of `output` from its track's annotation, leaving them
+    /// missing if it has none.
+    pub fn apply(&self, output: &mut Output) {
+        let Some(annotation) = self.get(&output.uri) else {
+            return;
+        };
+
+        output.tags = Some(annotation.tags.clone());
+        output.rating = annotation.rating;
+        output.note = annotation.note.clone();
+    }
+}
+
+/// Parses the track or playlist to annotate, given as a link or URI, returning its URI.
+pub fn parse_target(v: &str) -> Result<String> {
+    let link: Link = v.parse()?;
+    anyhow::ensure!(
+        matches!(link.kind, Kind::Track | Kind::Playlist),
+        "Only tracks and playlists can be annotated, not {}s",
+        link.kind.name()
+    );
+
+    Ok(link.to_string())
+}
+
+/// Adds `tags` to the annotation of `uri` and replaces its rating and note with the ones given,
+/// keeping whatever else it was annotated with.
+pub async fn set(
+    args: &Args,
+    uri: &str,
+    tags: &[String],
+    rating: Option<u8>,
+    note: Option<&str>,
+) -> Result<()> {
+    let profile = Profile::new(&args.profile)?;
+    let mut annotations = Annotations::load(&profile).await?;
+
+    let annotation = annotations.annotations.entry(uri.to_string()).or_default();
+    for tag in tags {
+        if !annotation.tags.contains(tag) {
+            annotation.tags.push(tag.clone());
+        }
+    }
+    annotation.rating = rating.or(annotation.rating);
+    if let Some(note) = note {
+        annotation.note = Some(note.to_string());
+    }
+    annotation.updated_at = chrono::Utc::now().to_rfc3339();
+
+    annotations.save(&profile).await
+}
+
+/// Removes `tags`, the rating with `rating` and the note with `note` from the annotation of
+/// `uri`, or the whole annotation if none of them are given.
+pub async fn remove(
+    args: &Args,
+    uri: &str,
+    tags: &[String],
+    rating: bool,
+    note: bool,
+) -> Result<()> {
+    let profile = Profile::new(&args.profile)?;
+    let mut annotations = Annotations::load(&profile).await?;
+
+    let Some(annotation) = annotations.annotations.get_mut(uri) else {
+        anyhow::bail!("{uri} isn't annotated");
+    };
+    annotation.tags.retain(|v| !tags.contains(v));
+    if rating {
+        annotation.rating = None;
+    }
+    if note {
+        annotation.note = None;
+    }
+    annotation.updated_at = chrono::Utc::now().to_rfc3339();
+
+    // nothing is kept for tracks and playlists left without any tags, rating or note
+    if (tags.is_empty() && !rating && !note) || annotation.is_empty() {
+        annotations.annotations.remove(uri);
+    }
+
+    annotations.save(&profile).await
+}
+
+/// Prints the annotation of `uri`, or every annotation if it isn't given, as JSON or a table.
+pub async fn show(args: &Args, uri: Option<&str>) -> Result<()> {
+    let profile = Profile::new(&args.profile)?;
+    let annotations = Annotations::load(&profile).await?;
+
+    let shown: BTreeMap<_, _> = match uri {
+        Some(uri) => {
+            let annotation = annotations
+                .get(uri)
+                .with_context(|| format!("{uri} isn't annotated"))?;
+            BTreeMap::from([(uri, annotation)])
+        }
+        None => annotations
+            .annotations
+            .iter()
+            .map(|(k, v)| (k.as_str(), v))
+            .collect(),
+    };
+    let terminal = std::io::stdout().is_terminal();
+
+    match args.format {
+        output::Format::Json => {
+            let json = if args.pretty || terminal {
+                serde_json::to_string_pretty(&shown)?
+            } else {
+                serde_json::to_string(&shown)?
+            };
+            println!("{json}");
+        }
+        output::Format::Table => {
+            let mut table = output::table(&["URI", "Tags", "Rating", "Note"], terminal, &[0]);
+
+            for (uri, annotation) in shown {
+                output::add_row(
+                    &mut table,
+                    [
+                        uri,
+                        &annotation.tags.join(", "),
+                        &annotation.rating.map(|v| v.to_string()).unwrap_or_default(),
+                        annotation.note.as_deref().unwrap_or_default(),
+                    ],
+                );
+            }
+
+            println!("{table}");
+        }
+        _ => {
+            anyhow::bail!("Annotations can only be written as JSON or a table")
+        }
+    }
+
+    Ok(())
+}
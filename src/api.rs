@@ -0,0 +1,736 @@
+use std::{
+    collections::{HashMap, HashSet},
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{
+    fixtures,
+    metadata_cache::{self, MetadataCache},
+    profile::Profile,
+    usage::Usage,
+    Error, Observer,
+};
+use anyhow::{Context, Result};
+use futures::{StreamExt, TryStreamExt};
+use governor::{DefaultDirectRateLimiter, Quota, RateLimiter};
+use rand::Rng;
+use reqwest::{
+    header::{ETAG, IF_NONE_MATCH, RETRY_AFTER},
+    Method, Response, StatusCode,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::{
+    sync::{Mutex, Semaphore},
+    time::Instant,
+};
+use tracing::{debug, warn};
+
+pub const BASE_URL: &str = "https://api.spotify.com/v1";
+
+/// Name of the directory in a profile's state dir responses are cached in.
+pub const CACHE_DIR: &str = "http-cache";
+
+/// How long to wait before retrying a rate limited request that didn't say when to retry.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(1);
+/// Longest a rate limited request waits to be retried before giving up.
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(10 * 60);
+/// Upper bound on the delay between retries of failed requests.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Options controlling how requests to the Spotify API are made.
+#[derive(clap::Args, Debug, Clone)]
+pub struct ApiArgs {
+    /// Number of times a request failing with a server error, timeout or connection error, or
+    /// being rate limited, is retried before giving up
+    #[arg(
+        long,
+        env = "SPOTIFY_BACKUP_RETRIES",
+        default_value_t = 5,
+        global = true
+    )]
+    pub retries: u32,
+    /// Delay before the first retry, doubled on every subsequent one
+    #[arg(
+        long,
+        env = "SPOTIFY_BACKUP_RETRY_DELAY",
+        default_value = "500ms",
+        value_parser = humantime::parse_duration,
+        global = true
+    )]
+    pub retry_delay: Duration,
+    /// Fraction of the retry delay that's randomised, so concurrent runs don't retry in lockstep
+    #[arg(
+        long,
+        env = "SPOTIFY_BACKUP_RETRY_JITTER",
+        default_value_t = 0.5,
+        value_parser = parse_fraction,
+        global = true
+    )]
+    pub retry_jitter: f64,
+    /// Maximum number of requests in flight at the same time, shared between every playlist being
+    /// backed up
+    #[arg(
+        long,
+        env = "SPOTIFY_BACKUP_CONCURRENCY",
+        default_value_t = 4,
+        global = true
+    )]
+    pub concurrency: usize,
+    /// Number of items requested per page, capped at the maximum each endpoint allows
+    #[arg(
+        long,
+        env = "SPOTIFY_BACKUP_PAGE_SIZE",
+        default_value_t = 50,
+        value_parser = clap::value_parser!(u32).range(1..=100),
+        global = true
+    )]
+    pub page_size: u32,
+    /// Minimum delay between the start of consecutive requests, for staying well under the rate
+    /// limits of shared client IDs (eg. 200ms)
+    #[arg(
+        long,
+        env = "SPOTIFY_BACKUP_REQUEST_DELAY",
+        value_parser = humantime::parse_duration,
+        global = true
+    )]
+    pub request_delay: Option<Duration>,
+    /// Maximum number of requests per second, shared between every concurrent request. Allows
+    /// short bursts of up to this many requests
+    #[arg(long, env = "SPOTIFY_BACKUP_RATE_LIMIT", global = true)]
+    pub rate_limit: Option<NonZeroU32>,
+    /// Country tracks are fetched for as an ISO 3166-1 alpha-2 code (eg. SE), which decides whether
+    /// they're playable and which version of a relinked track is returned. Defaults to the user's
+    /// own country
+    #[arg(long, env = "SPOTIFY_BACKUP_MARKET", value_parser = parse_market, global = true)]
+    pub market: Option<String>,
+    /// Disables caching responses and metadata in the state dir, which allows unchanged pages and
+    /// metadata to be re-fetched without downloading them again
+    #[arg(long, env = "SPOTIFY_BACKUP_NO_CACHE", global = true)]
+    pub no_cache: bool,
+    /// Answers every request from the responses and metadata cached by previous runs, without
+    /// making any network requests
+    #[arg(
+        long,
+        env = "SPOTIFY_BACKUP_OFFLINE",
+        conflicts_with = "no_cache",
+        global = true
+    )]
+    pub offline: bool,
+    /// Records every response from the Spotify API to this dir, with the user's email, tokens and
+    /// other personal details blanked and users' IDs replaced, so the run can be replayed later
+    /// with --replay
+    #[arg(
+        long,
+        env = "SPOTIFY_BACKUP_RECORD",
+        value_name = "DIR",
+        conflicts_with = "offline",
+        global = true
+    )]
+    pub record: Option<PathBuf>,
+    /// Answers every request with the responses recorded in this dir by --record, without
+    /// authenticating or making any network requests. Requests that weren't recorded fail
+    #[arg(
+        long,
+        env = "SPOTIFY_BACKUP_REPLAY",
+        value_name = "DIR",
+        conflicts_with_all = ["offline", "record"],
+        global = true
+    )]
+    pub replay: Option<PathBuf>,
+    /// Counts the requests of the run these options are for, by every client created with them
+    #[arg(skip)]
+    pub usage: Usage,
+}
+
+impl ApiArgs {
+    /// URL of the first page of a paginated endpoint, `max_page_size` being the largest page the
+    /// endpoint allows.
+    pub fn first_page_url(&self, path: &str, max_page_size: u32) -> String {
+        format!(
+            "{BASE_URL}/{path}?offset=0&limit={}",
+            self.page_size.min(max_page_size)
+        )
+    }
+
+    /// URL of the first page of tracks from `path`, available in the market given by `--market`.
+    pub fn first_tracks_page_url(&self, path: &str, max_page_size: u32) -> String {
+        format!(
+            "{}&market={}",
+            self.first_page_url(path, max_page_size),
+            // Spotify's way of asking for the country of the user the token belongs to
+            self.market.as_deref().unwrap_or("from_token")
+        )
+    }
+}
+
+fn parse_market(v: &str) -> Result<String> {
+    anyhow::ensure!(
+        v.len() == 2 && v.chars().all(|v| v.is_ascii_alphabetic()),
+        "Must be a two letter country code (eg. SE)"
+    );
+    Ok(v.to_ascii_uppercase())
+}
+
+fn parse_fraction(v: &str) -> Result<f64> {
+    let v: f64 = v.parse().context("Invalid number")?;
+    anyhow::ensure!((0.0..=1.0).contains(&v), "Must be between 0 and 1");
+    Ok(v)
+}
+
+/// Client for the Spotify API, retrying requests that fail for transient reasons and capping the
+/// number of requests in flight across every task sharing it.
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    http_cache_dir: PathBuf,
+    metadata_cache: Option<MetadataCache>,
+    args: ApiArgs,
+    permits: Arc<Semaphore>,
+    /// Earliest time the next request may be sent when pacing requests
+    next_request_at: Arc<Mutex<Instant>>,
+    rate_limiter: Option<Arc<DefaultDirectRateLimiter>>,
+    /// Told about the pages fetched, retries and rate limiting
+    observer: Option<Arc<dyn Observer>>,
+}
+
+impl Client {
+    /// Creates a client caching responses and metadata in the profile's state dir, unless caching
+    /// has been disabled.
+    pub fn new(http: reqwest::Client, mut args: ApiArgs, profile: &Profile) -> Self {
+        // cached responses would never reach the recording, or be answered in place of it
+        if args.record.is_some() || args.replay.is_some() {
+            args.no_cache = true;
+        }
+
+        let metadata_cache = if args.no_cache {
+            None
+        } else {
+            // the cache is only an optimisation, the backup can carry on without it
+            MetadataCache::open(&profile.dir().join(metadata_cache::DIR))
+                .inspect_err(|e| warn!("{e:#}, continuing without it"))
+                .ok()
+        };
+
+        Self {
+            http,
+            http_cache_dir: profile.dir().join(CACHE_DIR),
+            metadata_cache,
+            permits: Arc::new(Semaphore::new(args.concurrency.max(1))),
+            next_request_at: Arc::new(Mutex::new(Instant::now())),
+            rate_limiter: args
+                .rate_limit
+                .map(|v| Arc::new(RateLimiter::direct(Quota::per_second(v)))),
+            args,
+            observer: None,
+        }
+    }
+
+    /// Reports what the client does to `observer`, replacing any observer it had. Clones made
+    /// before this still share the concurrency limit but aren't observed.
+    pub fn with_observer(mut self, observer: Arc<dyn Observer>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Calls `f` with the observer, if there is one.
+    pub fn observe(&self, f: impl FnOnce(&dyn Observer)) {
+        if let Some(observer) = &self.observer {
+            f(observer.as_ref());
+        }
+    }
+
+    /// Cache of metadata looked up alongside tracks, unless caching has been disabled.
+    pub fn metadata_cache(&self) -> Option<&MetadataCache> {
+        self.metadata_cache.as_ref()
+    }
+
+    /// Whether requests are only answered from the cache.
+    pub fn offline(&self) -> bool {
+        self.args.offline
+    }
+
+    /// Number of requests that may be in flight at once.
+    pub fn concurrency(&self) -> usize {
+        self.args.concurrency.max(1)
+    }
+
+    /// Fetches and deserializes a JSON response, sending a conditional request if a previous
+    /// response was cached so unchanged responses aren't downloaded again.
+    pub async fn get_json<T: DeserializeOwned>(&self, url: &str) -> Result<T> {
+        let cache_path = self.cache_path(url);
+        let cached = match &cache_path {
+            Some(path) => read_cached_response(path).await,
+            None => None,
+        };
+
+        if self.args.offline {
+            let cached = cached.with_context(|| {
+                format!("{url} hasn't been cached yet, run once without --offline first")
+            })?;
+
+            return Ok(serde_json::from_str(&cached.body)
+                .map_err(|e| Error::deserialize(url, &cached.body, e))?);
+        }
+
+        let resp = self
+            .send(
+                Method::GET,
+                url,
+                cached.as_ref().and_then(|v| v.etag.as_deref()),
+                None,
+            )
+            .await?;
+
+        let body = if resp.status() == StatusCode::NOT_MODIFIED {
+            debug!(url, "Response hasn't changed, using cached copy");
+
+            cached
+                .context("Got 304 response to a request that wasn't conditional")?
+                .body
+        } else {
+            let etag = resp
+                .headers()
+                .get(ETAG)
+                .and_then(|v| v.to_str().ok())
+                .map(ToString::to_string);
+            let body = self.read_body(resp, url).await?;
+
+            // responses without an ETag can't be revalidated, but are still cached for --offline
+            if let Some(path) = &cache_path {
+                let cached = CachedResponse {
+                    etag,
+                    body: body.clone(),
+                };
+
+                // the cache is only an optimisation, the backup can carry on without it
+                if let Err(e) = write_cached_response(path, &cached).await {
+                    warn!("Failed to cache response from {url}: {e:#}");
+                }
+            }
+
+            body
+        };
+
+        Ok(serde_json::from_str(&body).map_err(|e| Error::deserialize(url, &body, e))?)
+    }
+
+    /// Fetches and deserializes a JSON response that changes from one request to the next, so
+    /// isn't cached. Some endpoints respond with 204 when there's nothing to return, which gives
+    /// `None`.
+    pub async fn get_json_uncached<T: DeserializeOwned>(&self, url: &str) -> Result<Option<T>> {
+        anyhow::ensure!(
+            !self.args.offline,
+            "{url} isn't cached, so can't be requested with --offline"
+        );
+
+        let resp = self.send(Method::GET, url, None, None).await?;
+        if resp.status() == StatusCode::NO_CONTENT {
+            return Ok(None);
+        }
+
+        let body = self.read_body(resp, url).await?;
+
+        Ok(serde_json::from_str(&body).map_err(|e| Error::deserialize(url, &body, e))?)
+    }
+
+    /// Sends `body` as JSON in a POST request, deserializing the JSON response.
+    pub async fn post_json<T: DeserializeOwned>(
+        &self,
+        url: &str,
+        body: &serde_json::Value,
+    ) -> Result<T> {
+        anyhow::ensure!(
+            !self.args.offline,
+            "{url} changes the library, so can't be requested with --offline"
+        );
+
+        let resp = self.send(Method::POST, url, None, Some(body)).await?;
+        let body = self.read_body(resp, url).await?;
+
+        Ok(serde_json::from_str(&body).map_err(|e| Error::deserialize(url, &body, e))?)
+    }
+
+    /// Sends `body` as JSON in a DELETE request, deserializing the JSON response.
+    pub async fn delete_json<T: DeserializeOwned>(
+        &self,
+        url: &str,
+        body: &serde_json::Value,
+    ) -> Result<T> {
+        anyhow::ensure!(
+            !self.args.offline,
+            "{url} changes the library, so can't be requested with --offline"
+        );
+
+        let resp = self.send(Method::DELETE, url, None, Some(body)).await?;
+        let body = self.read_body(resp, url).await?;
+
+        Ok(serde_json::from_str(&body).map_err(|e| Error::deserialize(url, &body, e))?)
+    }
+
+    /// Path the response to `url` is cached at, if caching is enabled.
+    fn cache_path(&self, url: &str) -> Option<PathBuf> {
+        if self.args.no_cache {
+            return None;
+        }
+
+        Some(
+            self.http_cache_dir
+                .join(format!("{}.json", hex::encode(Sha256::digest(url)))),
+        )
+    }
+
+    /// Sends a request, waiting out any rate limiting and retrying server errors and network
+    /// failures with exponential backoff, up to `--retries` times in all. Only GET requests are
+    /// retried after failing other than by being rate limited, as others may have been carried out
+    /// before the failure.
+    async fn send(
+        &self,
+        method: Method,
+        url: &str,
+        etag: Option<&str>,
+        body: Option<&serde_json::Value>,
+    ) -> Result<Response> {
+        let mut attempt = 0;
+
+        loop {
+            let permit = self.permits.acquire().await?;
+            self.pace().await;
+            let mut request = self.http.request(method.clone(), url);
+            if let Some(etag) = etag {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(body) = body {
+                request = request.json(body);
+            }
+            let started = Instant::now();
+            crate::metrics::api_request();
+            let result = match &self.args.replay {
+                Some(dir) => Ok(fixtures::replay(dir, &method, url, body).await?),
+                None => request.send().await,
+            };
+            drop(permit);
+
+            self.args.usage.request(
+                url,
+                started.elapsed(),
+                result.as_ref().ok().map(|v| v.status().as_u16()),
+            );
+            match &result {
+                Ok(resp) => debug!(
+                    url,
+                    attempt,
+                    status = resp.status().as_u16(),
+                    elapsed = ?started.elapsed(),
+                    "Request finished"
+                ),
+                Err(e) => debug!(url, attempt, elapsed = ?started.elapsed(), "Request failed: {e}"),
+            }
+
+            let err = match result {
+                Ok(resp) if resp.status() == StatusCode::TOO_MANY_REQUESTS => {
+                    crate::metrics::rate_limited();
+                    self.wait_for_rate_limit(url, &resp, attempt).await?;
+                    attempt += 1;
+                    continue;
+                }
+                Ok(resp) if resp.status().is_server_error() => {
+                    Error::from_status(url, resp.status())
+                }
+                Ok(resp) if resp.status().is_client_error() => {
+                    let status = resp.status();
+                    self.record(&method, url, body, resp).await?;
+                    return Err(Error::from_status(url, status).into());
+                }
+                Ok(resp) => return self.record(&method, url, body, resp).await,
+                Err(e) if is_transient(&e) => Error::Network {
+                    url: url.to_string(),
+                    source: e,
+                },
+                Err(e) => {
+                    return Err(Error::Network {
+                        url: url.to_string(),
+                        source: e,
+                    }
+                    .into())
+                }
+            };
+
+            if attempt >= self.args.retries || method != Method::GET {
+                return Err(
+                    anyhow::Error::new(err).context(format!("Gave up after {attempt} retries"))
+                );
+            }
+
+            let delay = self.retry_delay(attempt);
+            self.observe(|v| v.on_retry(url, attempt + 1, delay, &err));
+            warn!(
+                url,
+                attempt,
+                "Request to {url} failed ({err:#}), retrying in {:.1}s...",
+                delay.as_secs_f64()
+            );
+            tokio::time::sleep(delay).await;
+            self.args.usage.slept(delay);
+
+            attempt += 1;
+        }
+    }
+
+    /// Waits out the time a rate limited response to `url` asks for, unless it's longer than
+    /// [`MAX_RATE_LIMIT_WAIT`], which Spotify asks for when an app has been making far too many
+    /// requests and it's better to give up. Giving up once `attempt` has used up the retries too, so
+    /// a request that's never let through doesn't wait forever.
+    async fn wait_for_rate_limit(&self, url: &str, resp: &Response, attempt: u32) -> Result<()> {
+        let retry_after = resp
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse().ok())
+            .map_or(DEFAULT_RETRY_AFTER, Duration::from_secs);
+        if retry_after > MAX_RATE_LIMIT_WAIT {
+            return Err(Error::RateLimited { retry_after }.into());
+        }
+        if attempt >= self.args.retries {
+            return Err(anyhow::Error::new(Error::RateLimited { retry_after })
+                .context(format!("Gave up after {attempt} retries")));
+        }
+
+        self.observe(|v| v.on_rate_limited(url, retry_after));
+        warn!(
+            "Rate limited by Spotify, retrying {url} in {}s...",
+            retry_after.as_secs()
+        );
+        tokio::time::sleep(retry_after).await;
+        self.args.usage.slept(retry_after);
+
+        Ok(())
+    }
+
+    /// Reads the body of a response to `url`, counting how much was downloaded.
+    async fn read_body(&self, resp: Response, url: &str) -> Result<String> {
+        let body = resp
+            .text()
+            .await
+            .with_context(|| format!("Failed to read response from {url}"))?;
+        self.args.usage.downloaded(body.len());
+
+        Ok(body)
+    }
+
+    /// Records `resp` with --record, returning it to be read as if it hadn't been.
+    async fn record(
+        &self,
+        method: &Method,
+        url: &str,
+        body: Option<&serde_json::Value>,
+        resp: Response,
+    ) -> Result<Response> {
+        match &self.args.record {
+            Some(dir) => fixtures::record(dir, method, url, body, resp).await,
+            None => Ok(resp),
+        }
+    }
+
+    /// Waits until the rate limiter allows another request and `--request-delay` has passed
+    /// since the previous request was sent.
+    async fn pace(&self) {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.until_ready().await;
+        }
+
+        let Some(delay) = self.args.request_delay else {
+            return;
+        };
+
+        let send_at = {
+            let mut next_request_at = self.next_request_at.lock().await;
+            let send_at = (*next_request_at).max(Instant::now());
+            *next_request_at = send_at + delay;
+            send_at
+        };
+
+        tokio::time::sleep_until(send_at).await;
+    }
+
+    /// Looks up objects through one of the bulk endpoints (eg. `/v1/artists?ids=`), deduplicating
+    /// the IDs and splitting them into requests of at most `chunk_size`. `key` is the field of the
+    /// response holding the objects. Objects found in the metadata cache aren't fetched again, and
+    /// IDs Spotify doesn't know about are left out of the result.
+    pub async fn batch_get<'a, T: DeserializeOwned>(
+        &self,
+        path: &str,
+        key: &str,
+        ids: impl IntoIterator<Item = &'a str>,
+        chunk_size: usize,
+    ) -> Result<HashMap<String, T>> {
+        let mut seen = HashSet::new();
+        let mut objects = HashMap::new();
+        let mut missing = Vec::new();
+
+        for id in ids.into_iter().filter(|id| seen.insert(*id)) {
+            match self.cached_metadata(path, id) {
+                Some(object) => {
+                    objects.insert(id.to_string(), object);
+                }
+                None => missing.push(id),
+            }
+        }
+
+        debug!(
+            cached = objects.len(),
+            missing = missing.len(),
+            "Looking up {path}"
+        );
+
+        let chunks: Vec<_> = futures::stream::iter(missing.chunks(chunk_size.max(1)))
+            .map(|chunk| async move {
+                let url = format!("{BASE_URL}/{path}?ids={}", chunk.join(","));
+                let mut resp: HashMap<String, Vec<Option<serde_json::Value>>> =
+                    self.get_json(&url).await?;
+                let objects = resp.remove(key).unwrap_or_default();
+
+                // objects are returned in the order they were requested
+                Ok::<_, anyhow::Error>(chunk.iter().copied().zip(objects).collect::<Vec<_>>())
+            })
+            .buffered(self.concurrency())
+            .try_collect()
+            .await?;
+
+        self.cache_fetched(path, objects, chunks.into_iter().flatten())
+    }
+
+    /// Looks up objects that have no bulk endpoint (eg. `/v1/users/{id}`) with a request for each,
+    /// sent concurrently. As with [`Self::batch_get`], the IDs are deduplicated, objects found in
+    /// the metadata cache aren't fetched again and IDs Spotify doesn't know about are left out.
+    pub async fn get_each<'a, T: DeserializeOwned>(
+        &self,
+        path: &str,
+        ids: impl IntoIterator<Item = &'a str>,
+    ) -> Result<HashMap<String, T>> {
+        let mut seen = HashSet::new();
+        let mut objects = HashMap::new();
+        let mut missing = Vec::new();
+
+        for id in ids.into_iter().filter(|id| seen.insert(*id)) {
+            match self.cached_metadata(path, id) {
+                Some(object) => {
+                    objects.insert(id.to_string(), object);
+                }
+                None => missing.push(id),
+            }
+        }
+
+        debug!(
+            cached = objects.len(),
+            missing = missing.len(),
+            "Looking up {path}"
+        );
+
+        let fetched: Vec<_> = futures::stream::iter(missing)
+            .map(|id| async move {
+                let url = format!(
+                    "{BASE_URL}/{path}/{}",
+                    percent_encoding::utf8_percent_encode(id, percent_encoding::NON_ALPHANUMERIC)
+                );
+
+                match self.get_json(&url).await {
+                    Ok(object) => Ok((id, Some(object))),
+                    Err(e) if matches!(e.downcast_ref(), Some(Error::NotFound { .. })) => {
+                        Ok((id, None))
+                    }
+                    Err(e) => Err(e),
+                }
+            })
+            .buffer_unordered(self.concurrency())
+            .try_collect()
+            .await?;
+
+        self.cache_fetched(path, objects, fetched)
+    }
+
+    /// Caches the objects that were fetched for [`Self::batch_get`] or [`Self::get_each`], and
+    /// parses them along with those that were already cached.
+    fn cache_fetched<'a, T: DeserializeOwned>(
+        &self,
+        path: &str,
+        mut objects: HashMap<String, serde_json::Value>,
+        fetched: impl IntoIterator<Item = (&'a str, Option<serde_json::Value>)>,
+    ) -> Result<HashMap<String, T>> {
+        for (id, object) in fetched {
+            let Some(object) = object else {
+                continue;
+            };
+
+            if let Some(metadata_cache) = &self.metadata_cache {
+                if let Err(e) = metadata_cache.insert(path, id, &object) {
+                    warn!("Failed to cache {path} {id}: {e:#}");
+                }
+            }
+
+            objects.insert(id.to_string(), object);
+        }
+
+        objects
+            .into_iter()
+            .map(|(id, object)| {
+                let object = serde_json::from_value(object)
+                    .with_context(|| format!("Failed to parse {path} {id}"))?;
+                Ok((id, object))
+            })
+            .collect()
+    }
+
+    fn cached_metadata(&self, kind: &str, id: &str) -> Option<serde_json::Value> {
+        self.metadata_cache
+            .as_ref()?
+            .get(kind, id)
+            .inspect_err(|e| warn!("Failed to read {kind} {id} from cache: {e:#}"))
+            .ok()
+            .flatten()
+    }
+
+    fn retry_delay(&self, attempt: u32) -> Duration {
+        let delay = self
+            .args
+            .retry_delay
+            .saturating_mul(2_u32.saturating_pow(attempt))
+            .min(MAX_RETRY_DELAY);
+
+        delay.mul_f64(1.0 - self.args.retry_jitter * rand::thread_rng().gen::<f64>())
+    }
+}
+
+/// Response body cached along with the ETag it was served with, if any.
+#[derive(Serialize, Deserialize)]
+struct CachedResponse {
+    etag: Option<String>,
+    body: String,
+}
+
+async fn read_cached_response(path: &Path) -> Option<CachedResponse> {
+    let data = tokio::fs::read(path).await.ok()?;
+    serde_json::from_slice(&data).ok()
+}
+
+async fn write_cached_response(path: &Path, cached: &CachedResponse) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .context("Failed to create cache dir")?;
+    }
+
+    let data = serde_json::to_vec(cached).context("Failed to serialize cached response")?;
+    crate::atomic::write(path, data)
+        .await
+        .context("Failed to write cached response")
+}
+
+/// Whether the request failed in a way that's likely to succeed when retried, such as a timeout,
+/// DNS failure or connection reset.
+fn is_transient(e: &reqwest::Error) -> bool {
+    e.is_timeout() || e.is_connect() || e.is_request() || e.is_body()
+}
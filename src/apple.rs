@@ -0,0 +1,149 @@
+use std::num::NonZeroU32;
+
+use anyhow::{Context, Result};
+use governor::{DefaultDirectRateLimiter, Quota, RateLimiter};
+use reqwest::Url;
+use serde::Deserialize;
+
+use crate::{
+    cli::Args,
+    map::{Candidate, Service, Track},
+};
+
+const SEARCH_URL: &str = "https://itunes.apple.com/search";
+
+const LOOKUP_URL: &str = "https://itunes.apple.com/lookup";
+
+/// The iTunes Search API allows roughly 20 requests a minute.
+const REQUESTS_PER_MINUTE: u32 = 20;
+
+/// Most search results scored for each track.
+const MAX_RESULTS: &str = "5";
+
+/// Store tracks are looked up in when `--market` isn't given.
+const DEFAULT_COUNTRY: &str = "US";
+
+/// Finds tracks in the Apple Music catalog through the iTunes Search API, which needs no account.
+pub struct AppleMusic {
+    http: reqwest::Client,
+    country: String,
+    limiter: DefaultDirectRateLimiter,
+}
+
+#[derive(Deserialize, Debug)]
+struct Response {
+    results: Vec<Item>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Item {
+    /// "song" for tracks, as lookups can return other kinds of items
+    kind: Option<String>,
+    track_id: Option<u64>,
+    track_name: Option<String>,
+    artist_name: Option<String>,
+    collection_id: Option<u64>,
+    collection_name: Option<String>,
+    track_time_millis: Option<u64>,
+}
+
+impl AppleMusic {
+    pub fn new(args: &Args) -> Self {
+        Self {
+            http: crate::http::client(),
+            country: args
+                .api
+                .market
+                .clone()
+                .unwrap_or_else(|| DEFAULT_COUNTRY.to_string()),
+            limiter: RateLimiter::direct(Quota::per_minute(
+                NonZeroU32::new(REQUESTS_PER_MINUTE).unwrap(),
+            )),
+        }
+    }
+
+    async fn get(&self, url: Url) -> Result<Vec<Item>> {
+        self.limiter.until_ready().await;
+
+        let response: Response = self
+            .http
+            .get(url)
+            .send()
+            .await
+            .context("Failed to send request to the iTunes Search API")?
+            .error_for_status()
+            .context("Failed to query the iTunes Search API")?
+            .json()
+            .await
+            .context("Failed to parse response from the iTunes Search API")?;
+
+        Ok(response.results)
+    }
+}
+
+impl Service for AppleMusic {
+    fn name(&self) -> &'static str {
+        "apple"
+    }
+
+    async fn search(&self, track: &Track) -> Result<Vec<Candidate>> {
+        if let Some(isrc) = &track.isrc {
+            let url = Url::parse_with_params(
+                LOOKUP_URL,
+                [
+                    ("isrc", isrc.as_str()),
+                    ("country", &self.country),
+                    ("entity", "song"),
+                ],
+            )?;
+            let found: Vec<_> = self
+                .get(url)
+                .await?
+                .into_iter()
+                .filter_map(|v| candidate(v, true))
+                .collect();
+            if !found.is_empty() {
+                return Ok(found);
+            }
+        }
+
+        let term = match track.artists.first() {
+            Some(artist) => format!("{artist} {}", track.name),
+            None => track.name.clone(),
+        };
+        let url = Url::parse_with_params(
+            SEARCH_URL,
+            [
+                ("term", term.as_str()),
+                ("country", &self.country),
+                ("media", "music"),
+                ("entity", "song"),
+                ("limit", MAX_RESULTS),
+            ],
+        )?;
+
+        Ok(self
+            .get(url)
+            .await?
+            .into_iter()
+            .filter_map(|v| candidate(v, false))
+            .collect())
+    }
+}
+
+fn candidate(item: Item, by_isrc: bool) -> Option<Candidate> {
+    if item.kind.as_deref() != Some("song") {
+        return None;
+    }
+
+    Some(Candidate {
+        id: item.track_id?.to_string(),
+        title: item.track_name?,
+        artists: item.artist_name.into_iter().collect(),
+        album: item.collection_name,
+        album_id: item.collection_id.map(|v| v.to_string()),
+        duration_ms: item.track_time_millis,
+        by_isrc,
+    })
+}
@@ -0,0 +1,127 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use tokio::io::AsyncWriteExt;
+
+/// Path a file is written to before being moved into place at `path`.
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+/// A temporary file that's deleted when dropped unless it's been committed, which covers both
+/// errors and the run being interrupted partway through writing it.
+pub struct TmpFile {
+    path: PathBuf,
+    /// Where the file is moved to once committed
+    target: PathBuf,
+    committed: bool,
+}
+
+impl TmpFile {
+    /// Reserves the temporary file for eventually writing `target`.
+    pub fn new(target: PathBuf) -> Self {
+        Self {
+            path: tmp_path(&target),
+            target,
+            committed: false,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Moves the temporary file into place once it's been synced to disk.
+    pub async fn commit(self) -> Result<()> {
+        let target = self.target.clone();
+        self.commit_as(target).await
+    }
+
+    /// Moves the temporary file to `target` instead of where it was reserved for, once it's been
+    /// synced to disk.
+    pub async fn commit_as(mut self, target: PathBuf) -> Result<()> {
+        commit(&self.path, &target).await?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Drop for TmpFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Writes `data` to `path` via a temporary file that's renamed into place once it's been fully
+/// written, so an interrupted write never leaves a truncated file in place of the previous one.
+pub async fn write(path: &Path, data: impl AsRef<[u8]>) -> Result<()> {
+    let tmp = TmpFile::new(path.to_path_buf());
+
+    tokio::fs::write(tmp.path(), data)
+        .await
+        .with_context(|| format!("Failed to write {}", tmp.path().display()))?;
+
+    tmp.commit().await
+}
+
+/// Writes `data` to `path` like [`write`], with the file only readable and writable by its owner
+/// on unix, for files holding secrets such as tokens.
+pub async fn write_private(path: &Path, data: impl AsRef<[u8]>) -> Result<()> {
+    let tmp = TmpFile::new(path.to_path_buf());
+
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    // set when the file is created, so it's never readable by anyone else even briefly
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    let mut file = options
+        .open(tmp.path())
+        .await
+        .with_context(|| format!("Failed to create {}", tmp.path().display()))?;
+    file.write_all(data.as_ref())
+        .await
+        .with_context(|| format!("Failed to write {}", tmp.path().display()))?;
+    drop(file);
+
+    tmp.commit().await
+}
+
+async fn commit(tmp: &Path, path: &Path) -> Result<()> {
+    tokio::fs::File::open(tmp)
+        .await
+        .with_context(|| format!("Failed to open {}", tmp.display()))?
+        .sync_all()
+        .await
+        .with_context(|| format!("Failed to sync {}", tmp.display()))?;
+
+    tokio::fs::rename(tmp, path)
+        .await
+        .with_context(|| format!("Failed to move {} into place", tmp.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn writes_private_files_only_the_owner_can_read() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token.json");
+        std::fs::write(&path, "old").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        write_private(&path, "new").await.unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
@@ -1,7 +1,7 @@
 use std::{
     collections::HashMap,
     io::ErrorKind,
-    path::PathBuf,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     sync::Mutex,
     time::{Duration, SystemTime},
 };
@@ -20,48 +20,351 @@ use rand::distributions::{Alphanumeric, DistString};
 use reqwest::Url;
 use serde::{Deserialize, Serialize};
 use sha2::{digest::FixedOutput, Digest, Sha256};
-use tokio::net::TcpListener;
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    net::TcpListener,
+};
+use tracing::{info, warn};
+
+use crate::{
+    profile::{Profile, TOKEN_FILES},
+    token_store::{TokenStore, TokenStoreKind},
+};
 
 const AUTH_URL: &str = "https://accounts.spotify.com/authorize";
 const TOKEN_URL: &str = "https://accounts.spotify.com/api/token";
-const SCOPES: &str = "playlist-read-private user-library-read";
-const CLIENT_ID: &str = "b6146c081df54ae79e42258a8619f570";
+/// Scopes requested for every Spotify token, commands needing anything beyond these request them
+/// on top.
+const SCOPES: &[&str] = &[scope::PLAYLIST_READ_PRIVATE, scope::USER_LIBRARY_READ];
+pub const CLIENT_ID: &str = "b6146c081df54ae79e42258a8619f570";
+/// Port registered as the redirect URI of the built-in client.
+const DEFAULT_CALLBACK_PORT: u16 = 8888;
+
+/// Spotify authorization scopes required by the various commands.
+pub mod scope {
+    pub const PLAYLIST_READ_PRIVATE: &str = "playlist-read-private";
+    pub const PLAYLIST_MODIFY_PRIVATE: &str = "playlist-modify-private";
+    pub const PLAYLIST_MODIFY_PUBLIC: &str = "playlist-modify-public";
+    pub const USER_LIBRARY_READ: &str = "user-library-read";
+    pub const USER_READ_RECENTLY_PLAYED: &str = "user-read-recently-played";
+    pub const USER_READ_CURRENTLY_PLAYING: &str = "user-read-currently-playing";
+}
+
+/// An OAuth 2.0 authorization server supporting the PKCE authorization code flow with a loopback
+/// redirect.
+#[derive(Clone)]
+pub struct OAuthProvider {
+    /// Human readable name of the service, shown when asking the user to authenticate
+    pub name: &'static str,
+    /// Name of the token state in the profile's state dir or keyring
+    pub token_file: &'static str,
+    pub auth_url: &'static str,
+    pub token_url: &'static str,
+    pub client_id: String,
+    /// Some providers (eg. Google) require the secret of "desktop" clients even when using PKCE
+    pub client_secret: Option<String>,
+    /// Space separated scopes the token needs to be granted
+    pub scopes: String,
+    /// Space separated scopes granted to tokens persisted before their scopes were recorded, which
+    /// is what was asked for back then
+    pub default_scopes: String,
+    /// Additional query parameters required to be issued a refresh token
+    pub extra_auth_params: &'static [(&'static str, &'static str)],
+}
+
+impl OAuthProvider {
+    pub fn spotify(client_id: &str, scopes: &[&str]) -> Self {
+        Self {
+            name: "Spotify",
+            token_file: "token.json",
+            auth_url: AUTH_URL,
+            token_url: TOKEN_URL,
+            client_id: client_id.to_string(),
+            client_secret: None,
+            scopes: merge_scopes(SCOPES.iter().copied(), scopes.iter().copied()),
+            default_scopes: SCOPES.join(" "),
+            extra_auth_params: &[],
+        }
+    }
+
+    /// Returns the scopes this provider requires that are missing from `granted`.
+    fn missing_scopes<'a>(&'a self, granted: &str) -> Vec<&'a str> {
+        self.scopes
+            .split_whitespace()
+            .filter(|v| !granted.split_whitespace().any(|granted| granted == *v))
+            .collect()
+    }
+}
+
+/// Joins two sets of scopes into a single space separated list without duplicates.
+fn merge_scopes<'a>(a: impl Iterator<Item = &'a str>, b: impl Iterator<Item = &'a str>) -> String {
+    let mut out: Vec<&str> = Vec::new();
+
+    for scope in a.chain(b) {
+        if !out.contains(&scope) {
+            out.push(scope);
+        }
+    }
+
+    out.join(" ")
+}
+
+/// Options controlling how tokens are obtained and stored.
+#[derive(clap::Args, Debug, Clone)]
+pub struct AuthArgs {
+    /// Client ID of the Spotify app to authenticate as, for using your own app registration
+    #[arg(
+        long,
+        env = "SPOTIFY_BACKUP_CLIENT_ID",
+        default_value = CLIENT_ID,
+        global = true
+    )]
+    pub client_id: String,
+    /// Where OAuth tokens are stored
+    #[arg(
+        long,
+        value_enum,
+        env = "SPOTIFY_BACKUP_TOKEN_STORE",
+        default_value_t = TokenStoreKind::Auto,
+        global = true
+    )]
+    pub token_store: TokenStoreKind,
+    /// Encrypts the token file with a passphrase, for machines without a keyring. Prompts for the
+    /// passphrase if no value is given
+    #[arg(
+        long,
+        env = "SPOTIFY_BACKUP_TOKEN_PASSPHRASE",
+        hide_env_values = true,
+        global = true
+    )]
+    pub token_passphrase: Option<Option<String>>,
+    /// Prints the authentication URL instead of opening a browser, for authenticating over SSH
+    #[arg(long, env = "SPOTIFY_BACKUP_NO_BROWSER", global = true)]
+    pub no_browser: bool,
+    /// Interface the authentication callback server listens on, use 0.0.0.0 to accept callbacks
+    /// forwarded from another machine
+    #[arg(
+        long,
+        env = "SPOTIFY_BACKUP_CALLBACK_HOST",
+        default_value = "127.0.0.1",
+        global = true
+    )]
+    pub callback_host: IpAddr,
+    /// Port the authentication callback server listens on, defaults to 8888 with a fallback to a
+    /// random port if it's in use
+    #[arg(long, env = "SPOTIFY_BACKUP_CALLBACK_PORT", global = true)]
+    pub callback_port: Option<u16>,
+    /// Redirect URI to send the browser to instead of the callback server's loopback address, for
+    /// reaching the callback server through a reverse proxy (eg. https://backup.example.com/callback)
+    #[arg(long, env = "SPOTIFY_BACKUP_REDIRECT_URI", global = true)]
+    pub redirect_uri: Option<String>,
+    /// Spotify refresh token to authenticate with instead of the profile's stored token, for CI
+    /// and containers without a writable state dir. Nothing is persisted when set, obtain one with
+    /// `auth export-refresh-token`
+    #[arg(
+        long,
+        env = "SPOTIFY_REFRESH_TOKEN",
+        hide_env_values = true,
+        global = true
+    )]
+    pub refresh_token: Option<String>,
+    /// Never opens a browser, binds the callback port or prompts, failing straight away instead
+    /// when there's no usable token, for CI where an interactive login would hang until timed out
+    #[arg(long, env = "SPOTIFY_BACKUP_NON_INTERACTIVE", global = true)]
+    pub non_interactive: bool,
+}
+
+impl AuthArgs {
+    /// Prompts for the token passphrase if `--token-passphrase` was passed without a value, so
+    /// the user is only asked once regardless of how many tokens are used.
+    pub fn prompt_for_passphrase(&mut self) -> Result<()> {
+        if let Some(passphrase @ None) = &mut self.token_passphrase {
+            anyhow::ensure!(
+                !self.non_interactive,
+                "--token-passphrase needs a value with --non-interactive, set \
+                 SPOTIFY_BACKUP_TOKEN_PASSPHRASE instead"
+            );
+
+            *passphrase = Some(
+                rpassword::prompt_password("Token passphrase: ")
+                    .context("Failed to read passphrase")?,
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Deletes every token stored for the profile, returning a description of each one removed.
+pub async fn logout(profile: &Profile, args: &AuthArgs) -> Result<Vec<String>> {
+    let mut removed = Vec::new();
+
+    for token_file in TOKEN_FILES {
+        removed.extend(
+            TokenStore::new(args.token_store, profile, token_file)
+                .delete()
+                .await?,
+        );
+    }
+
+    Ok(removed)
+}
+
+/// Reads the stored Spotify token of the profile without refreshing it or starting a new
+/// authentication flow.
+pub async fn read_token(profile: &Profile, args: &AuthArgs) -> Result<Option<TokenState>> {
+    let provider = OAuthProvider::spotify(&args.client_id, &[]);
+    let token_store = build_token_store(&provider, profile, args);
+
+    let Some(data) = token_store.read().await? else {
+        return Ok(None);
+    };
+
+    serde_json::from_str(&data)
+        .map(Some)
+        .context("Failed to parse token state")
+}
+
+/// Authenticates with Spotify, making sure the token has been granted `scopes` in addition to the
+/// default ones.
+pub async fn authenticate(profile: &Profile, args: &AuthArgs, scopes: &[&str]) -> Result<String> {
+    let provider = OAuthProvider::spotify(&args.client_id, scopes);
 
-pub async fn authenticate() -> Result<String> {
-    let access_token = match read_token_state().await? {
+    match &args.refresh_token {
+        Some(refresh_token) => authenticate_from_refresh_token(&provider, refresh_token).await,
+        None => authenticate_with(&provider, profile, args).await,
+    }
+}
+
+/// Authenticates with Spotify, starting the interactive flow if the profile doesn't hold a usable
+/// token yet, and returns the refresh token for use with `SPOTIFY_REFRESH_TOKEN`.
+pub async fn export_refresh_token(profile: &Profile, args: &AuthArgs) -> Result<String> {
+    if let Some(refresh_token) = &args.refresh_token {
+        return Ok(refresh_token.clone());
+    }
+
+    let provider = OAuthProvider::spotify(&args.client_id, &[]);
+    let token = fetch_token_state(&provider, profile, args).await?;
+
+    Ok(token.refresh_token)
+}
+
+/// Exchanges a refresh token passed in by the user for an access token without reading or writing
+/// any state.
+async fn authenticate_from_refresh_token(
+    provider: &OAuthProvider,
+    refresh_token: &str,
+) -> Result<String> {
+    let token = fetch_access_token_from_refresh(provider, refresh_token)
+        .await
+        .context("Failed to authenticate with SPOTIFY_REFRESH_TOKEN")?;
+
+    if let Some(granted) = &token.scope {
+        let missing = provider.missing_scopes(granted);
+
+        anyhow::ensure!(
+            missing.is_empty(),
+            "SPOTIFY_REFRESH_TOKEN is missing the {} scope(s), obtain a new one with `auth \
+             export-refresh-token`",
+            missing.join(", ")
+        );
+    }
+
+    Ok(token.access_token)
+}
+
+pub async fn authenticate_with(
+    provider: &OAuthProvider,
+    profile: &Profile,
+    args: &AuthArgs,
+) -> Result<String> {
+    Ok(fetch_token_state(provider, profile, args)
+        .await?
+        .access_token)
+}
+
+/// Returns a valid token for the provider, refreshing or requesting a new one as needed and
+/// persisting the result.
+async fn fetch_token_state(
+    provider: &OAuthProvider,
+    profile: &Profile,
+    args: &AuthArgs,
+) -> Result<TokenState> {
+    profile.create_dir().await?;
+
+    let token_store = build_token_store(provider, profile, args);
+
+    // the state has to be read after the lock is acquired, another run may have just rotated it
+    let _lock = token_store.lock().await?;
+
+    let mut access_token = match read_token_state(&token_store, provider).await? {
         CurrentTokenState::Expired(refresh_token) => {
-            fetch_access_token_from_refresh(&refresh_token).await?
+            fetch_access_token_from_refresh(provider, &refresh_token).await?
         }
         CurrentTokenState::Valid(token) => token,
-        CurrentTokenState::Missing => fetch_fresh_access_token().await?,
-    };
+        CurrentTokenState::Missing => fetch_fresh_access_token(provider, args).await?,
+        CurrentTokenState::InsufficientScope(granted) => {
+            // ask for everything granted previously too, so the new token doesn't lose access
+            // other commands depend on
+            let provider = OAuthProvider {
+                scopes: merge_scopes(
+                    provider.scopes.split_whitespace(),
+                    granted.split_whitespace(),
+                ),
+                ..provider.clone()
+            };
 
-    tokio::fs::create_dir_all(build_state_dir_path()?).await?;
+            fetch_fresh_access_token(&provider, args).await?
+        }
+    };
+    access_token.client_id = Some(provider.client_id.clone());
 
     let serialized_state =
         serde_json::to_string(&access_token).context("Failed to serialize token state")?;
-    tokio::fs::write(build_token_state_path()?, serialized_state)
-        .await
-        .context("Failed to write token state")?;
+    token_store.write(&serialized_state).await?;
 
-    Ok(access_token.access_token)
+    Ok(access_token)
 }
 
-async fn read_token_state() -> Result<CurrentTokenState> {
-    let data = match tokio::fs::read(build_token_state_path()?).await {
-        Ok(v) => v,
-        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(CurrentTokenState::Missing),
-        Err(e) => return Err(e).context("Failed to read token state"),
+fn build_token_store(provider: &OAuthProvider, profile: &Profile, args: &AuthArgs) -> TokenStore {
+    let token_store = TokenStore::new(args.token_store, profile, provider.token_file);
+
+    match &args.token_passphrase {
+        Some(Some(passphrase)) => token_store.with_passphrase(passphrase.clone()),
+        _ => token_store,
+    }
+}
+
+async fn read_token_state(
+    token_store: &TokenStore,
+    provider: &OAuthProvider,
+) -> Result<CurrentTokenState> {
+    let Some(data) = token_store.read().await? else {
+        return Ok(CurrentTokenState::Missing);
     };
 
-    let data: TokenState = match serde_json::from_slice(&data) {
+    let data: TokenState = match serde_json::from_str(&data) {
         Ok(v) => v,
         Err(e) => {
-            eprintln!("Failed to read token state ({e}), invalidating...");
+            warn!("Failed to read token state ({e}), invalidating...");
             return Ok(CurrentTokenState::Missing);
         }
     };
 
+    // tokens can only be refreshed by the client they were issued to
+    if data
+        .client_id
+        .as_ref()
+        .is_some_and(|v| *v != provider.client_id)
+    {
+        info!("Token was issued to a different client ID, reauthenticating...");
+        return Ok(CurrentTokenState::Missing);
+    }
+
+    if let Some(granted) = insufficient_scope(provider, &data) {
+        return Ok(CurrentTokenState::InsufficientScope(granted));
+    }
+
     let current_timestamp = SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
         .context("the end of days is nigh")?
@@ -74,25 +377,38 @@ async fn read_token_state() -> Result<CurrentTokenState> {
     }
 }
 
-fn build_token_state_path() -> Result<PathBuf> {
-    Ok(build_state_dir_path()?.join("token.json"))
-}
+/// Scopes granted to `token` if it lacks any `provider` requires. Tokens persisted by older
+/// versions don't record their scopes, those were granted the provider's default scopes.
+fn insufficient_scope(provider: &OAuthProvider, token: &TokenState) -> Option<String> {
+    let granted = token.scope.as_ref().unwrap_or(&provider.default_scopes);
+    let missing = provider.missing_scopes(granted);
+    if missing.is_empty() {
+        return None;
+    }
 
-fn build_state_dir_path() -> Result<PathBuf> {
-    let base = dirs::data_local_dir().context("Unsupported operating system, no data dir")?;
-    Ok(base.join("spotify-backup"))
+    info!(
+        "Token is missing the {} scope(s), asking for consent again...",
+        missing.join(", ")
+    );
+    Some(granted.clone())
 }
 
-async fn fetch_access_token_from_refresh(refresh_token: &str) -> Result<TokenState> {
-    eprintln!("Refreshing token...");
+async fn fetch_access_token_from_refresh(
+    provider: &OAuthProvider,
+    refresh_token: &str,
+) -> Result<TokenState> {
+    info!("Refreshing {} token...", provider.name);
 
     let mut params = HashMap::new();
     params.insert("grant_type", "refresh_token");
     params.insert("refresh_token", refresh_token);
-    params.insert("client_id", CLIENT_ID);
+    params.insert("client_id", &provider.client_id);
+    if let Some(client_secret) = &provider.client_secret {
+        params.insert("client_secret", client_secret);
+    }
 
-    reqwest::Client::default()
-        .post(TOKEN_URL)
+    let mut resp = crate::http::client()
+        .post(provider.token_url)
         .form(&params)
         .send()
         .await
@@ -101,41 +417,201 @@ async fn fetch_access_token_from_refresh(refresh_token: &str) -> Result<TokenSta
         .context("Got non-200 response when requesting access token")?
         .json::<AccessTokenResponse>()
         .await
-        .context("Failed to deserialize access token response")?
-        .try_into()
+        .context("Failed to deserialize access token response")?;
+
+    // not every provider rotates refresh tokens, keep using the current one if we weren't issued
+    // a new one
+    resp.refresh_token
+        .get_or_insert_with(|| refresh_token.to_string());
+
+    resp.try_into()
         .context("Failed to convert to internal state")
 }
 
-async fn fetch_fresh_access_token() -> Result<TokenState> {
-    let tcp_listener = TcpListener::bind("127.0.0.1:8888")
-        .await
-        .context("Failed to open TCP listener")?;
+async fn fetch_fresh_access_token(provider: &OAuthProvider, args: &AuthArgs) -> Result<TokenState> {
+    // checked before anything is bound or opened, so CI fails straight away instead of waiting
+    // for a callback that will never arrive
+    anyhow::ensure!(
+        !args.non_interactive,
+        "No usable {} token is stored and --non-interactive is set, log in on a machine with a \
+         browser first or pass SPOTIFY_REFRESH_TOKEN",
+        provider.name
+    );
+
+    let tcp_listener = bind_callback_listener(args).await?;
     let local_addr = tcp_listener
         .local_addr()
         .context("Failed to read local socket address")?;
 
-    let redirect_url = format!("http://{local_addr}/");
+    let redirect_url = match &args.redirect_uri {
+        Some(redirect_uri) => redirect_uri.clone(),
+        None => {
+            // when listening on all interfaces the redirect still has to point at loopback, since
+            // that's the only plain HTTP redirect Spotify accepts
+            let redirect_ip = match local_addr.ip() {
+                IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+                ip => ip,
+            };
+
+            format!(
+                "http://{}/",
+                SocketAddr::new(redirect_ip, local_addr.port())
+            )
+        }
+    };
+    let callback_path = validate_redirect_url(&redirect_url)?;
 
     let (code_verifier, code_challenge) = generate_code_challenge();
 
-    eprintln!("Opening Spotify for authentication...");
-    webbrowser::open(build_spotify_auth_url(&code_challenge, &redirect_url)?.as_str())
-        .context("Failed to open browser")?;
+    let auth_url = build_auth_url(provider, &code_challenge, &redirect_url)?;
 
-    eprintln!("Waiting for callback...");
-    let code = spawn_http_server_wait_for_callback(tcp_listener)
-        .await
-        .context("Failed to wait for callback")?;
-    eprintln!("Successfully received Spotify callback, fetching access token...");
+    // the prompt is easy to miss when the backup was started from another window
+    crate::desktop::notify(
+        "Authentication needed",
+        &format!("Approve access to {} to continue the backup", provider.name),
+    )
+    .await;
+
+    let open_browser = if args.no_browser {
+        false
+    } else {
+        eprintln!("Opening {} for authentication...", provider.name);
+
+        webbrowser::open(auth_url.as_str())
+            .inspect_err(|e| warn!("Failed to open browser: {e}"))
+            .is_ok()
+    };
+
+    let code = if open_browser {
+        eprintln!(
+            "Waiting for callback... If your browser is on another machine, forward port {} \
+             to it (eg. ssh -L {0}:127.0.0.1:{0} <this host>) and open:\n\n{auth_url}\n",
+            local_addr.port()
+        );
+
+        spawn_http_server_wait_for_callback(tcp_listener, &callback_path)
+            .await
+            .context("Failed to wait for callback")?
+    } else {
+        eprintln!(
+            "Open the following URL in a browser to authenticate with {}:\n\n{auth_url}\n",
+            provider.name
+        );
+        eprintln!(
+            "Once approved you'll be redirected to {redirect_url}, if that page doesn't load \
+             paste the full URL from the address bar here:"
+        );
+
+        // the callback can still arrive if the browser is on this machine or the port is
+        // forwarded, so wait for whichever comes first
+        tokio::select! {
+            code = spawn_http_server_wait_for_callback(tcp_listener, &callback_path) => {
+                code.context("Failed to wait for callback")?
+            }
+            code = read_code_from_stdin() => code?,
+        }
+    };
+    info!(
+        "Successfully received {} callback, fetching access token...",
+        provider.name
+    );
 
-    fetch_access_token(&code, &code_verifier, &redirect_url)
+    fetch_access_token(provider, &code, &code_verifier, &redirect_url)
         .await
         .context("Failed to fetch access token")?
         .try_into()
         .context("Failed to convert to internal state")
 }
 
+/// Binds the callback server to the configured port, or to 8888 falling back to an ephemeral port
+/// when 8888 is already in use.
+async fn bind_callback_listener(args: &AuthArgs) -> Result<TcpListener> {
+    // a reverse proxy needs to know which port to forward to, so there's no falling back to a
+    // random one
+    let port = args
+        .callback_port
+        .or(args.redirect_uri.is_some().then_some(DEFAULT_CALLBACK_PORT));
+
+    if let Some(port) = port {
+        return TcpListener::bind((args.callback_host, port))
+            .await
+            .with_context(|| format!("Failed to listen on port {port}"));
+    }
+
+    match TcpListener::bind((args.callback_host, DEFAULT_CALLBACK_PORT)).await {
+        Ok(v) => Ok(v),
+        Err(e) if e.kind() == ErrorKind::AddrInUse => {
+            warn!(
+                "Port {DEFAULT_CALLBACK_PORT} is in use, falling back to a random port. This \
+                 requires the app's redirect URI to allow any loopback port, use --callback-port \
+                 to pick a specific one instead"
+            );
+
+            TcpListener::bind((args.callback_host, 0))
+                .await
+                .context("Failed to open TCP listener")
+        }
+        Err(e) => Err(e).context("Failed to open TCP listener"),
+    }
+}
+
+/// Checks that the redirect URL is one Spotify would accept, so the user isn't sent to a
+/// confusing error page, and returns the path the callback will be received on. Plain HTTP is
+/// only allowed to a loopback IP literal, `localhost` isn't permitted.
+fn validate_redirect_url(redirect_url: &str) -> Result<String> {
+    let url = Url::parse(redirect_url).context("Invalid redirect URL")?;
+
+    match (url.scheme(), url.host()) {
+        ("https", Some(_)) => Ok(url.path().to_string()),
+        ("http", Some(url::Host::Ipv4(ip))) if ip.is_loopback() => Ok(url.path().to_string()),
+        ("http", Some(url::Host::Ipv6(ip))) if ip.is_loopback() => Ok(url.path().to_string()),
+        ("http", Some(_)) => anyhow::bail!(
+            "Redirect URL {redirect_url} isn't allowed, plain HTTP redirects must use a loopback \
+             IP address such as 127.0.0.1"
+        ),
+        _ => anyhow::bail!("Redirect URL {redirect_url} must be an http(s) URL with a host"),
+    }
+}
+
+/// Reads the URL the browser was redirected to (or just the `code` parameter from it) from stdin.
+/// Never resolves if stdin is closed.
+async fn read_code_from_stdin() -> Result<String> {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+
+    loop {
+        // without stdin the callback is the only way left to receive the code
+        let Some(line) = lines
+            .next_line()
+            .await
+            .context("Failed to read from stdin")?
+        else {
+            return std::future::pending().await;
+        };
+        let line = line.trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        let Ok(url) = Url::parse(line) else {
+            return Ok(line.to_string());
+        };
+
+        if let Some((_, error)) = url.query_pairs().find(|(key, _)| key == "error") {
+            anyhow::bail!("Authorization was not granted: {error}");
+        }
+
+        if let Some((_, code)) = url.query_pairs().find(|(key, _)| key == "code") {
+            return Ok(code.into_owned());
+        }
+
+        eprintln!("URL is missing the code query parameter, try again:");
+    }
+}
+
 async fn fetch_access_token(
+    provider: &OAuthProvider,
     code: &str,
     code_verifier: &str,
     redirect_url: &str,
@@ -144,11 +620,14 @@ async fn fetch_access_token(
     params.insert("grant_type", "authorization_code");
     params.insert("code", code);
     params.insert("redirect_uri", redirect_url);
-    params.insert("client_id", CLIENT_ID);
+    params.insert("client_id", &provider.client_id);
     params.insert("code_verifier", code_verifier);
+    if let Some(client_secret) = &provider.client_secret {
+        params.insert("client_secret", client_secret);
+    }
 
-    let resp = reqwest::Client::default()
-        .post(TOKEN_URL)
+    let resp = crate::http::client()
+        .post(provider.token_url)
         .form(&params)
         .send()
         .await
@@ -162,7 +641,12 @@ async fn fetch_access_token(
     Ok(resp)
 }
 
-async fn spawn_http_server_wait_for_callback(tcp_listener: TcpListener) -> Result<String> {
+/// Serves the callback on `path`, or at the root for reverse proxies that strip the path prefix
+/// before forwarding, until a request carrying the authorization code (or an error) arrives.
+async fn spawn_http_server_wait_for_callback(
+    tcp_listener: TcpListener,
+    path: &str,
+) -> Result<String> {
     let mut http = http1::Builder::new();
     http.keep_alive(false);
 
@@ -176,9 +660,11 @@ async fn spawn_http_server_wait_for_callback(tcp_listener: TcpListener) -> Resul
 
         let out2 = &out;
         let service = service_fn(|req: Request<body::Incoming>| async move {
-            let (Method::GET, "/", Some(query)) =
-                (req.method().clone(), req.uri().path(), req.uri().query())
-            else {
+            let (&Method::GET, true, Some(query)) = (
+                req.method(),
+                req.uri().path() == path || req.uri().path() == "/",
+                req.uri().query(),
+            ) else {
                 let mut resp = hyper::Response::new(Full::<Bytes>::from(
                     "Invalid request, bad method/path/query params",
                 ));
@@ -186,6 +672,20 @@ async fn spawn_http_server_wait_for_callback(tcp_listener: TcpListener) -> Resul
                 return Ok(resp);
             };
 
+            // the user declined the authorization (or the provider rejected the request), there's
+            // no point waiting around for a code that'll never arrive
+            if let Some((_, error)) =
+                form_urlencoded::parse(query.as_bytes()).find(|(key, _value)| key == "error")
+            {
+                *out2.lock().unwrap() = Some(Err(anyhow::anyhow!(
+                    "Authorization was not granted: {error}"
+                )));
+
+                return Ok(hyper::Response::new(Full::<Bytes>::from(format!(
+                    "Authentication failed ({error}), please return to your terminal"
+                ))));
+            }
+
             let Some((_, value)) =
                 form_urlencoded::parse(query.as_bytes()).find(|(key, _value)| key == "code")
             else {
@@ -196,7 +696,7 @@ async fn spawn_http_server_wait_for_callback(tcp_listener: TcpListener) -> Resul
                 return Ok(resp);
             };
 
-            *out2.lock().unwrap() = Some(value.into_owned());
+            *out2.lock().unwrap() = Some(Ok(value.into_owned()));
 
             Ok::<_, anyhow::Error>(hyper::Response::new(Full::<Bytes>::from(
                 "Successfully authenticated, please return to your terminal",
@@ -204,27 +704,32 @@ async fn spawn_http_server_wait_for_callback(tcp_listener: TcpListener) -> Resul
         });
 
         if let Err(e) = http.serve_connection(TokioIo::new(stream), service).await {
-            eprintln!("Failed to serve HTTP request: {e}");
+            warn!("Failed to serve HTTP request: {e}");
         }
 
         let Some(v) = out.lock().unwrap().take() else {
             continue;
         };
 
-        break Ok(v);
+        break v;
     }
 }
 
-fn build_spotify_auth_url(code_challenge: &str, redirect_url: &str) -> Result<Url> {
-    let mut base = Url::parse(AUTH_URL).context("Failed to parse base URL")?;
+fn build_auth_url(
+    provider: &OAuthProvider,
+    code_challenge: &str,
+    redirect_url: &str,
+) -> Result<Url> {
+    let mut base = Url::parse(provider.auth_url).context("Failed to parse base URL")?;
 
     base.query_pairs_mut()
         .append_pair("response_type", "code")
-        .append_pair("client_id", CLIENT_ID)
-        .append_pair("scope", SCOPES)
+        .append_pair("client_id", &provider.client_id)
+        .append_pair("scope", &provider.scopes)
         .append_pair("code_challenge_method", "S256")
         .append_pair("code_challenge", code_challenge)
-        .append_pair("redirect_uri", redirect_url);
+        .append_pair("redirect_uri", redirect_url)
+        .extend_pairs(provider.extra_auth_params);
 
     Ok(base)
 }
@@ -243,6 +748,8 @@ pub enum CurrentTokenState {
     Expired(String),
     Valid(TokenState),
     Missing,
+    /// The token lacks scopes the command needs, holds the scopes it was granted
+    InsufficientScope(String),
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -250,13 +757,34 @@ pub struct TokenState {
     access_token: String,
     expires_at: u64,
     refresh_token: String,
+    /// Client the token was issued to, missing for tokens persisted by older versions
+    #[serde(default)]
+    client_id: Option<String>,
+    /// Space separated scopes granted to the token, missing for tokens persisted by older versions
+    #[serde(default)]
+    scope: Option<String>,
+}
+
+impl TokenState {
+    pub fn expires_at(&self) -> u64 {
+        self.expires_at
+    }
+
+    pub fn client_id(&self) -> Option<&str> {
+        self.client_id.as_deref()
+    }
+
+    pub fn scope(&self) -> Option<&str> {
+        self.scope.as_deref()
+    }
 }
 
 #[derive(Deserialize)]
 pub struct AccessTokenResponse {
     access_token: String,
     expires_in: u64,
-    refresh_token: String,
+    refresh_token: Option<String>,
+    scope: Option<String>,
 }
 
 impl TryFrom<AccessTokenResponse> for TokenState {
@@ -267,6 +795,7 @@ impl TryFrom<AccessTokenResponse> for TokenState {
             access_token,
             expires_in,
             refresh_token,
+            scope,
         }: AccessTokenResponse,
     ) -> Result<Self> {
         let expires_at = (SystemTime::now() + Duration::from_secs(expires_in))
@@ -276,8 +805,63 @@ impl TryFrom<AccessTokenResponse> for TokenState {
 
         Ok(TokenState {
             access_token,
-            refresh_token,
+            refresh_token: refresh_token.context("No refresh token was issued")?,
             expires_at,
+            client_id: None,
+            scope,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(scope: Option<&str>) -> TokenState {
+        TokenState {
+            access_token: "access".to_string(),
+            expires_at: 0,
+            refresh_token: "refresh".to_string(),
+            client_id: Some(CLIENT_ID.to_string()),
+            scope: scope.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn asks_again_for_scopes_a_token_lacks() {
+        let provider = OAuthProvider::spotify(CLIENT_ID, &[scope::PLAYLIST_MODIFY_PUBLIC]);
+
+        assert_eq!(
+            insufficient_scope(&provider, &token(Some("user-library-read"))).as_deref(),
+            Some("user-library-read")
+        );
+        assert_eq!(
+            insufficient_scope(
+                &provider,
+                &token(Some(
+                    "playlist-modify-public playlist-read-private user-library-read"
+                ))
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn takes_tokens_without_scopes_to_have_the_default_ones() {
+        // stored before scopes were recorded, when every command asked for the same ones
+        let old = token(None);
+
+        assert_eq!(
+            insufficient_scope(&OAuthProvider::spotify(CLIENT_ID, &[]), &old),
+            None
+        );
+        assert_eq!(
+            insufficient_scope(
+                &OAuthProvider::spotify(CLIENT_ID, &[scope::PLAYLIST_MODIFY_PUBLIC]),
+                &old
+            )
+            .as_deref(),
+            Some("playlist-read-private user-library-read")
+        );
+    }
+}
@@ -0,0 +1,223 @@
+use std::{
+    io::IsTerminal,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use rusqlite::{Connection, OpenFlags};
+use serde::Serialize;
+
+use crate::{
+    cli::Args,
+    collection::{self, Collection, File, MatchedBy},
+    map, output,
+};
+
+/// What `beets` prints.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Emit {
+    /// Every track with the file it was matched to, in `--format`
+    Report,
+    /// Only the tracks missing from the collection, in `--format`
+    Missing,
+    /// An M3U playlist of the matched files, in the order of the backup
+    M3u,
+    /// A beets query for the matched tracks (eg. `id:12 , id:34`), for passing to beet commands
+    Query,
+}
+
+#[derive(Serialize, Debug)]
+struct Row<'a> {
+    uri: &'a str,
+    name: &'a str,
+    artists: &'a [String],
+    album: Option<&'a str>,
+    isrc: Option<&'a str>,
+    matched_by: Option<MatchedBy>,
+    /// How likely the file is the same recording, from 0 to 1, 1 for those matched by an ID
+    confidence: Option<f64>,
+    beets_id: Option<i64>,
+    path: Option<String>,
+}
+
+/// Items of the beets library, along with the beets ID of each.
+fn open(path: &Path) -> Result<(Collection, Vec<i64>)> {
+    let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .with_context(|| format!("Failed to open beets library {}", path.display()))?;
+
+    // ISRCs are a column of newer libraries and a flexible attribute of older ones
+    let has_isrc = connection
+        .prepare("SELECT 1 FROM pragma_table_info('items') WHERE name = 'isrc'")?
+        .exists([])?;
+    let isrc = match has_isrc {
+        true => "NULLIF(i.isrc, '')",
+        false => {
+            "(SELECT value FROM item_attributes a WHERE a.entity_id = i.id AND a.key = 'isrc')"
+        }
+    };
+
+    let mut statement = connection
+        .prepare(&format!(
+            "SELECT i.id, i.path, i.title, i.artist, i.album, i.length, \
+             NULLIF(i.mb_trackid, ''), {isrc} FROM items i"
+        ))
+        .context("Failed to read beets library, is it a beets database?")?;
+    let items = statement
+        .query_map([], |row| {
+            let file = File {
+                // stored as a blob, but as text by some older versions
+                path: path_from_bytes(row.get_ref(1)?.as_bytes()?),
+                title: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+                artists: row.get::<_, Option<String>>(3)?.into_iter().collect(),
+                album: row.get(4)?,
+                duration_ms: row.get::<_, Option<f64>>(5)?.map(|v| (v * 1000.0) as u64),
+                recording_mbid: row.get(6)?,
+                isrc: row.get(7)?,
+            };
+            Ok((row.get::<_, i64>(0)?, file))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .context("Failed to read beets library")?;
+
+    let (ids, files) = items.into_iter().unzip();
+
+    Ok((Collection::new(files), ids))
+}
+
+/// beets stores paths as the bytes the filesystem gave it.
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// Where beets keeps its library unless configured otherwise: library.db in `$BEETSDIR`, or in
+/// the beets directory of the platform's config dir (~/.config/beets outside of Windows).
+fn default_library() -> Option<PathBuf> {
+    let dir = match std::env::var_os("BEETSDIR") {
+        Some(dir) => PathBuf::from(dir),
+        None if cfg!(windows) => dirs::config_dir()?.join("beets"),
+        None => std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| Some(dirs::home_dir()?.join(".config")))?
+            .join("beets"),
+    };
+
+    Some(dir.join("library.db"))
+}
+
+/// Matches the tracks of the backup at `path` against the beets library at `library` (or the
+/// default one), printing what `emit` asks for.
+pub async fn run(
+    args: &Args,
+    path: &Path,
+    library: Option<&Path>,
+    emit: Emit,
+    min_confidence: f64,
+) -> Result<()> {
+    let library = match library {
+        Some(library) => library.to_path_buf(),
+        None => default_library()
+            .filter(|v| v.exists())
+            .context("Couldn't find the beets library, pass it with --library")?,
+    };
+
+    let tracks = map::read_tracks(args, path).await?;
+    let (library, ids) = open(&library)?;
+
+    let found: Vec<_> = tracks
+        .iter()
+        .map(|track| library.find(track, min_confidence))
+        .collect();
+    let rows: Vec<_> = tracks
+        .iter()
+        .zip(&found)
+        .map(|(track, found)| Row {
+            uri: &track.uri,
+            name: &track.name,
+            artists: &track.artists,
+            album: track.album.as_deref(),
+            isrc: track.isrc.as_deref(),
+            matched_by: found.map(|v| v.by),
+            confidence: found.map(|v| v.confidence),
+            beets_id: found.map(|v| ids[v.index]),
+            path: found.map(|v| v.file.path.to_string_lossy().into_owned()),
+        })
+        .collect();
+
+    match emit {
+        Emit::Report => print(args, &rows),
+        Emit::Missing => {
+            let missing: Vec<_> = rows.into_iter().filter(|v| v.path.is_none()).collect();
+            print(args, &missing)
+        }
+        Emit::M3u => {
+            let matched = tracks
+                .iter()
+                .zip(&found)
+                .filter_map(|(track, found)| Some((track, found.as_ref()?.file)));
+            collection::write_m3u(&mut std::io::stdout().lock(), matched)?;
+            Ok(())
+        }
+        Emit::Query => {
+            // beets ORs the queries between commas, which have to be arguments of their own
+            let queries: Vec<_> = rows
+                .iter()
+                .filter_map(|v| Some(format!("id:{}", v.beets_id?)))
+                .collect();
+            // an empty query matches the whole library, which isn't what anyone piping this wants
+            if queries.is_empty() {
+                anyhow::bail!("None of the tracks are in the beets library");
+            }
+            println!("{}", queries.join(" , "));
+            Ok(())
+        }
+    }
+}
+
+fn print(args: &Args, rows: &[Row]) -> Result<()> {
+    let terminal = std::io::stdout().is_terminal();
+
+    match args.format {
+        output::Format::Json => {
+            let json = if args.pretty || terminal {
+                serde_json::to_string_pretty(rows)?
+            } else {
+                serde_json::to_string(rows)?
+            };
+            println!("{json}");
+        }
+        output::Format::Table => {
+            let mut table =
+                output::table(&["Name", "Artists", "Matched by", "Path"], terminal, &[2]);
+            for row in rows {
+                let matched_by = row.matched_by.map_or("missing", MatchedBy::label);
+                output::add_row(
+                    &mut table,
+                    [
+                        row.name,
+                        &row.artists.join(", "),
+                        matched_by,
+                        row.path.as_deref().unwrap_or_default(),
+                    ],
+                );
+            }
+            println!("{table}");
+
+            let matched = rows.iter().filter(|v| v.path.is_some()).count();
+            println!("{matched} of {} track(s) in the collection", rows.len());
+        }
+        _ => {
+            anyhow::bail!("beets results can only be written as JSON or a table")
+        }
+    }
+
+    Ok(())
+}
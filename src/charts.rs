@@ -0,0 +1,352 @@
+use std::{collections::BTreeMap, f64::consts::PI, path::Path};
+
+use anyhow::{Context, Result};
+use plotters::{
+    coord::Shift,
+    prelude::*,
+    style::text_anchor::{HPos, Pos, VPos},
+};
+use tracing::info;
+
+/// Audio features drawn on the radar chart, all of which are between 0 and 1 (unlike loudness and
+/// tempo).
+const RADAR_FEATURES: &[&str] = &[
+    "danceability",
+    "energy",
+    "valence",
+    "acousticness",
+    "instrumentalness",
+    "liveness",
+    "speechiness",
+];
+
+/// Artist names longer than this are cut short, so the bars have room.
+const MAX_LABEL: usize = 32;
+
+const FONT: &str = "sans-serif";
+
+/// Spotify green
+const COLOR: RGBColor = RGBColor(29, 185, 84);
+
+/// Image format charts are rendered in.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Svg,
+    Png,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Svg => "svg",
+            Self::Png => "png",
+        }
+    }
+}
+
+/// A chart of one of the statistics, with what's drawn on it.
+enum Chart<'a> {
+    /// Tracks added in each month, including the months none were
+    AddedPerMonth(Vec<(String, usize)>),
+    /// Tracks released in each decade
+    ReleaseDecades(Vec<(&'a str, usize)>),
+    /// Artists with the most tracks, from the one with the most
+    TopArtists(Vec<(&'a str, usize)>),
+    /// Average of each audio feature on the radar
+    AudioFeatures(Vec<(&'a str, f64)>),
+}
+
+/// Renders charts of the tracks added per month, the release decades, the top artists and the
+/// average audio features into `dir` as `format` images. A chart with nothing to draw, because the
+/// backup wasn't written with the fields it needs, isn't rendered.
+pub async fn render(
+    dir: &Path,
+    format: Format,
+    added_per_month: &BTreeMap<String, usize>,
+    release_decades: &BTreeMap<String, usize>,
+    top_artists: &[(&str, usize)],
+    audio_features: &BTreeMap<String, f64>,
+) -> Result<()> {
+    tokio::fs::create_dir_all(dir)
+        .await
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    let mut features: Vec<_> = RADAR_FEATURES
+        .iter()
+        .filter_map(|v| Some((*v, *audio_features.get(*v)?)))
+        .collect();
+    // a radar needs at least three axes to have an area
+    if features.len() < 3 {
+        features.clear();
+    }
+
+    let charts = [
+        (
+            "added-per-month",
+            Chart::AddedPerMonth(months(added_per_month)),
+        ),
+        (
+            "release-decades",
+            Chart::ReleaseDecades(
+                release_decades
+                    .iter()
+                    .map(|(k, v)| (k.as_str(), *v))
+                    .collect(),
+            ),
+        ),
+        ("top-artists", Chart::TopArtists(top_artists.to_vec())),
+        ("audio-features", Chart::AudioFeatures(features)),
+    ];
+
+    for (name, chart) in charts {
+        if chart.is_empty() {
+            info!("Not rendering {name}, the backup doesn't have the fields it needs");
+            continue;
+        }
+
+        let path = dir.join(format!("{name}.{}", format.extension()));
+        let size = chart.size();
+        match format {
+            Format::Svg => chart.draw(&SVGBackend::new(&path, size).into_drawing_area()),
+            Format::Png => chart.draw(&BitMapBackend::new(&path, size).into_drawing_area()),
+        }
+        .with_context(|| format!("Failed to render {}", path.display()))?;
+        info!("Wrote {}", path.display());
+    }
+
+    Ok(())
+}
+
+impl Chart<'_> {
+    fn is_empty(&self) -> bool {
+        match self {
+            Self::AddedPerMonth(v) => v.is_empty(),
+            Self::ReleaseDecades(v) | Self::TopArtists(v) => v.is_empty(),
+            Self::AudioFeatures(v) => v.is_empty(),
+        }
+    }
+
+    fn size(&self) -> (u32, u32) {
+        match self {
+            Self::AudioFeatures(_) => (720, 720),
+            _ => (1024, 576),
+        }
+    }
+
+    fn draw<DB: DrawingBackend>(&self, root: &DrawingArea<DB, Shift>) -> Result<()>
+    where
+        DB::ErrorType: 'static,
+    {
+        root.fill(&WHITE)?;
+
+        match self {
+            Self::AddedPerMonth(months) => columns(root, "Tracks added per month", months)?,
+            Self::ReleaseDecades(decades) => columns(root, "Tracks by decade of release", decades)?,
+            Self::TopArtists(artists) => bars(root, "Artists with the most tracks", artists)?,
+            Self::AudioFeatures(features) => radar(root, "Average audio features", features)?,
+        }
+
+        root.present()?;
+        Ok(())
+    }
+}
+
+/// Every month from the first to the last one tracks were added in (eg. 2024-03), with how many
+/// were, so months without any still take up room.
+fn months(added: &BTreeMap<String, usize>) -> Vec<(String, usize)> {
+    let parse = |v: &str| -> Option<(i32, u32)> {
+        Some((v.get(..4)?.parse().ok()?, v.get(5..7)?.parse().ok()?))
+    };
+    let (Some(first), Some(last)) = (
+        added.keys().find_map(|v| parse(v)),
+        added.keys().rev().find_map(|v| parse(v)),
+    ) else {
+        return Vec::new();
+    };
+
+    let mut months = Vec::new();
+    let (mut year, mut month) = first;
+    while (year, month) <= last {
+        let key = format!("{year:04}-{month:02}");
+        months.push((key.clone(), added.get(&key).copied().unwrap_or_default()));
+        (year, month) = if month == 12 {
+            (year + 1, 1)
+        } else {
+            (year, month + 1)
+        };
+    }
+
+    months
+}
+
+/// Draws a column for each of `counts`, labelled with its key.
+fn columns<DB: DrawingBackend, K: AsRef<str>>(
+    root: &DrawingArea<DB, Shift>,
+    title: &str,
+    counts: &[(K, usize)],
+) -> Result<()>
+where
+    DB::ErrorType: 'static,
+{
+    let max = counts.iter().map(|v| v.1).max().unwrap_or_default();
+    // a segmented range has a segment for its end as well, and one that's empty can't be drawn
+    let mut chart = ChartBuilder::on(root)
+        .caption(title, (FONT, 24))
+        .margin(16)
+        .x_label_area_size(40)
+        .y_label_area_size(56)
+        .build_cartesian_2d(
+            (0..(counts.len() - 1).max(1)).into_segmented(),
+            0..max + max / 10 + 1,
+        )?;
+
+    chart
+        .configure_mesh()
+        .disable_x_mesh()
+        .x_labels(counts.len().min(12))
+        .x_label_formatter(&|v| match v {
+            SegmentValue::Exact(i) | SegmentValue::CenterOf(i) => counts
+                .get(*i)
+                .map(|v| v.0.as_ref().to_string())
+                .unwrap_or_default(),
+            SegmentValue::Last => String::new(),
+        })
+        .y_desc("Tracks")
+        .draw()?;
+    chart.draw_series(
+        Histogram::vertical(&chart)
+            .style(COLOR.filled())
+            .margin(2)
+            .data(counts.iter().enumerate().map(|(i, v)| (i, v.1))),
+    )?;
+
+    Ok(())
+}
+
+/// Draws a bar for each of `counts`, with the first at the top.
+fn bars<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    title: &str,
+    counts: &[(&str, usize)],
+) -> Result<()>
+where
+    DB::ErrorType: 'static,
+{
+    let max = counts.iter().map(|v| v.1).max().unwrap_or_default();
+    // the bars are numbered from the bottom
+    let label = |i: usize| {
+        let name = counts[counts.len() - 1 - i].0;
+        match name.char_indices().nth(MAX_LABEL) {
+            Some((end, _)) => format!("{}…", &name[..end]),
+            None => name.to_string(),
+        }
+    };
+
+    // as with columns, the segment for the end of the range is left out
+    let mut chart = ChartBuilder::on(root)
+        .caption(title, (FONT, 24))
+        .margin(16)
+        .x_label_area_size(40)
+        .y_label_area_size(240)
+        .build_cartesian_2d(
+            0..max + max / 10 + 1,
+            (0..(counts.len() - 1).max(1)).into_segmented(),
+        )?;
+
+    chart
+        .configure_mesh()
+        .disable_y_mesh()
+        .y_labels(counts.len())
+        .y_label_formatter(&|v| match v {
+            SegmentValue::Exact(i) | SegmentValue::CenterOf(i) if *i < counts.len() => label(*i),
+            _ => String::new(),
+        })
+        .x_desc("Tracks")
+        .draw()?;
+    chart.draw_series(
+        Histogram::horizontal(&chart)
+            .style(COLOR.filled())
+            .margin(4)
+            .data(counts.iter().rev().enumerate().map(|(i, v)| (i, v.1))),
+    )?;
+
+    Ok(())
+}
+
+/// Draws a radar with an axis for each of `values`, going clockwise from the top, from 0 in the
+/// middle to 1 at the edge.
+fn radar<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    title: &str,
+    values: &[(&str, f64)],
+) -> Result<()>
+where
+    DB::ErrorType: 'static,
+{
+    let area = root.titled(title, (FONT, 24))?;
+    let (width, height) = area.dim_in_pixel();
+    let center = (f64::from(width) / 2.0, f64::from(height) / 2.0);
+    let radius = f64::from(width.min(height)) / 2.0 - 100.0;
+    let point = |axis: usize, value: f64| {
+        let angle = 2.0 * PI * axis as f64 / values.len() as f64 - PI / 2.0;
+        (
+            (center.0 + radius * value * angle.cos()).round() as i32,
+            (center.1 + radius * value * angle.sin()).round() as i32,
+        )
+    };
+
+    let grid = RGBColor(200, 200, 200);
+    for step in 1..=4 {
+        let ring: Vec<_> = (0..=values.len())
+            .map(|axis| point(axis % values.len(), f64::from(step) / 4.0))
+            .collect();
+        area.draw(&PathElement::new(ring, grid))?;
+    }
+    for axis in 0..values.len() {
+        area.draw(&PathElement::new(
+            vec![point(axis, 0.0), point(axis, 1.0)],
+            grid,
+        ))?;
+    }
+
+    let shape: Vec<_> = values
+        .iter()
+        .enumerate()
+        .map(|(axis, v)| point(axis, v.1.clamp(0.0, 1.0)))
+        .collect();
+    area.draw(&Polygon::new(shape.clone(), COLOR.mix(0.3).filled()))?;
+    area.draw(&PathElement::new(
+        shape
+            .iter()
+            .chain(shape.first())
+            .copied()
+            .collect::<Vec<_>>(),
+        COLOR.stroke_width(2),
+    ))?;
+
+    for (axis, (feature, value)) in values.iter().enumerate() {
+        let (x, y) = point(axis, 1.0);
+        let pos = Pos::new(
+            match x.cmp(&(center.0 as i32)) {
+                std::cmp::Ordering::Less => HPos::Right,
+                std::cmp::Ordering::Equal => HPos::Center,
+                std::cmp::Ordering::Greater => HPos::Left,
+            },
+            if y < center.1 as i32 {
+                VPos::Bottom
+            } else {
+                VPos::Top
+            },
+        );
+        let (dx, dy) = (x - center.0 as i32, y - center.1 as i32);
+        let offset = (dx.signum() * 8, dy.signum() * 8);
+        area.draw(&Text::new(
+            format!("{feature} {value:.2}"),
+            (x + offset.0, y + offset.1),
+            TextStyle::from((FONT, 16).into_font()).pos(pos),
+        ))?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,2587 @@
+//! The `spotify-backup` command line interface, parsing the arguments and running the command
+//! they ask for on top of the library.
+
+use std::{
+    collections::HashMap,
+    io::{IsTerminal, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    pin::Pin,
+    process::ExitCode,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use clap::{parser::ValueSource, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+use clap_complete::{engine::ArgValueCandidates, CompleteEnv};
+use futures::{Stream, StreamExt, TryStreamExt};
+use reqwest::Url;
+use serde::de::DeserializeOwned;
+use tracing::{debug, error, info, info_span, warn, Instrument};
+
+use crate::{
+    annotations, api, apple, atomic, authentication, beets, charts, client_with_token, compare,
+    completions, compression, config, coverage, daemon, deezer, desktop, dupes, encryption,
+    everything, export, fetch_current_user, filter, funkwhale, gdpr, healthcheck, http, init,
+    jellyfin, last_run, lastfm, link, listenbrainz, local, logging, lyrics, manifest, map, merge,
+    metadata_cache, metrics, migrate, musicbrainz, normalize, notify, output, parts, plex, profile,
+    progress, resolve, schema, scrobble, search, service, similar, smart, stats, storage,
+    storage::Storage, subsonic, summary, sync, tidal, unavailable, validate, verify_live, writer,
+    youtube, ytdlp, Error, Exporter, GetArtistResponse, GetPlaylistTracksResponseItem,
+    GetPlaylistsResponseItem, GetUserResponse, Output, OutputPlaylist, Ownership, Page, Paginator,
+};
+
+#[derive(Parser, Debug, Clone)]
+#[command(version)]
+pub struct Args {
+    #[command(subcommand)]
+    pub(crate) command: Command,
+    /// Config file to read defaults from, instead of config.toml in the platform's config dir
+    #[arg(long, env = "SPOTIFY_BACKUP_CONFIG", global = true)]
+    pub(crate) config: Option<PathBuf>,
+    /// Directory each profile's tokens, caches and state are kept in, instead of spotify-backup in
+    /// the platform's data dir
+    #[arg(long, env = "SPOTIFY_BACKUP_STATE_DIR", global = true)]
+    pub(crate) state_dir: Option<PathBuf>,
+    /// Profile to use, allowing several accounts to be backed up from the same machine
+    #[arg(
+        short,
+        long,
+        env = "SPOTIFY_BACKUP_PROFILE",
+        default_value = profile::DEFAULT_PROFILE,
+        add = ArgValueCandidates::new(completions::profiles),
+        global = true
+    )]
+    pub(crate) profile: String,
+    /// Writes the backup into the given directory along with a checksum manifest, instead of
+    /// printing it to stdout
+    #[arg(short, long, env = "SPOTIFY_BACKUP_OUTPUT", global = true)]
+    pub(crate) output: Option<PathBuf>,
+    /// Prints every backup of the run as one JSON object keyed by their file names, rather than
+    /// one document after another
+    #[arg(long, conflicts_with = "output", global = true)]
+    pub(crate) combine: bool,
+    /// Skips playlists the user follows when backing up every playlist, keeping only their own and
+    /// those they collaborate on
+    #[arg(long, env = "SPOTIFY_BACKUP_OWNED_ONLY", global = true)]
+    pub(crate) owned_only: bool,
+    /// Backs up followed playlists along with every other playlist, even if `owned_only` is set in
+    /// the config file
+    #[arg(long, conflicts_with = "owned_only", global = true)]
+    pub(crate) include_followed: bool,
+    /// Uploads the backup to the given destination after a successful run (eg. s3://bucket/prefix)
+    // the conflict is declared here rather than on --offline, as the library parses the API
+    // options without this one
+    #[arg(
+        long,
+        env = "SPOTIFY_BACKUP_UPLOAD",
+        conflicts_with = "offline",
+        global = true
+    )]
+    pub(crate) upload: Option<Url>,
+    /// Also uploads a copy of each backup with the time in its name (eg.
+    /// liked.20240131T020000Z.json), keeping this many of the most recent copies and deleting the
+    /// rest
+    #[arg(
+        long,
+        env = "SPOTIFY_BACKUP_UPLOAD_KEEP",
+        requires = "upload",
+        global = true
+    )]
+    pub(crate) upload_keep: Option<NonZeroUsize>,
+    /// Pings a healthchecks.io-style check URL when a run starts, and again with its outcome and
+    /// log when it finishes (eg. https://hc-ping.com/<uuid>)
+    #[arg(long, env = "SPOTIFY_BACKUP_HEALTHCHECK_URL", global = true)]
+    pub(crate) healthcheck_url: Option<Url>,
+    #[command(flatten)]
+    pub(crate) auth: authentication::AuthArgs,
+    #[command(flatten)]
+    pub(crate) api: api::ApiArgs,
+    #[command(flatten)]
+    pub(crate) http: http::HttpArgs,
+    #[command(flatten)]
+    pub(crate) log: logging::LogArgs,
+    #[command(flatten)]
+    pub(crate) progress: progress::ProgressArgs,
+    #[command(flatten)]
+    pub(crate) storage: storage::StorageArgs,
+    #[command(flatten)]
+    pub(crate) notify: notify::NotifyArgs,
+    /// Splits backups written into a directory into numbered parts of at most this size before
+    /// compression (eg. 500M), listed in a <name>.parts.json index next to them. Applies to JSON,
+    /// NDJSON, CSV and template backups
+    #[arg(long, env = "SPOTIFY_BACKUP_SPLIT_SIZE", global = true)]
+    pub(crate) split_size: Option<parts::Size>,
+    /// Splits backups written into a directory into numbered parts of at most this many tracks,
+    /// like `--split-size`
+    #[arg(long, env = "SPOTIFY_BACKUP_SPLIT_TRACKS", global = true)]
+    pub(crate) split_tracks: Option<NonZeroUsize>,
+    /// Compresses the backup before writing or uploading it
+    #[arg(long, env = "SPOTIFY_BACKUP_COMPRESS", global = true)]
+    pub(crate) compress: Option<compression::Compression>,
+    /// Encrypts the backup before writing or uploading it (eg. age:age1ql3z7hjy...), may be given
+    /// multiple times to encrypt to several recipients
+    #[arg(
+        long,
+        env = "SPOTIFY_BACKUP_ENCRYPT",
+        value_delimiter = ',',
+        global = true
+    )]
+    pub(crate) encrypt: Vec<String>,
+    /// Adds the genres of each track's artists to the backup, looked up in bulk
+    #[arg(long, env = "SPOTIFY_BACKUP_GENRES", global = true)]
+    pub(crate) genres: bool,
+    /// Adds the MusicBrainz recording and release IDs of each track to the backup, looked up by
+    /// ISRC or failing that by title and artist, at one request per second
+    #[arg(long, env = "SPOTIFY_BACKUP_MUSICBRAINZ", global = true)]
+    pub(crate) musicbrainz: bool,
+    /// Fetches the lyrics of each track, embedding them in the backup or with `=sidecar` writing
+    /// them as .lrc files into a lyrics directory next to it
+    #[arg(
+        long,
+        value_enum,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "embed",
+        env = "SPOTIFY_BACKUP_WITH_LYRICS",
+        global = true
+    )]
+    pub(crate) with_lyrics: Option<lyrics::Mode>,
+    /// Adds your own tags, ratings and notes (see `annotate`) to the backup, and to playlists when
+    /// they're listed as JSON
+    #[arg(long, env = "SPOTIFY_BACKUP_WITH_ANNOTATIONS", global = true)]
+    pub(crate) with_annotations: bool,
+    /// LRCLIB-compatible API lyrics are fetched from
+    #[arg(
+        long,
+        env = "SPOTIFY_BACKUP_LYRICS_PROVIDER",
+        default_value = lyrics::DEFAULT_PROVIDER,
+        global = true
+    )]
+    pub(crate) lyrics_provider: Url,
+    /// Fields of each track to write to the backup, in order (eg. name,artists,uri,added_at,isrc)
+    #[arg(
+        long,
+        value_delimiter = ',',
+        env = "SPOTIFY_BACKUP_FIELDS",
+        global = true
+    )]
+    pub(crate) fields: Vec<output::Field>,
+    /// Format the backup is written in
+    #[arg(
+        long,
+        value_enum,
+        env = "SPOTIFY_BACKUP_FORMAT",
+        default_value_t = output::Format::Json,
+        global = true
+    )]
+    pub(crate) format: output::Format,
+    /// Line written for each track with `--format template`, with placeholders for its fields (eg.
+    /// "{artists} — {name} ({album})")
+    #[arg(
+        long,
+        env = "SPOTIFY_BACKUP_TEMPLATE",
+        required_if_eq("format", "template"),
+        global = true
+    )]
+    pub(crate) template: Option<output::Template>,
+    /// Sorts the tracks of each backup, which are otherwise written in the order Spotify returns
+    /// them in. Every track has to be fetched before any are written
+    #[arg(long, value_enum, env = "SPOTIFY_BACKUP_SORT", global = true)]
+    pub(crate) sort: Option<output::Sort>,
+    /// Reverses the order given by `--sort`
+    #[arg(long, requires = "sort", env = "SPOTIFY_BACKUP_REVERSE", global = true)]
+    pub(crate) reverse: bool,
+    /// Nests the tracks of JSON backups under their albums, with each album's tracks in disc and
+    /// track order. Every track has to be fetched before any are written
+    #[arg(long, value_enum, env = "SPOTIFY_BACKUP_GROUP_BY", global = true)]
+    pub(crate) group_by: Option<output::GroupBy>,
+    /// Only writes tracks meeting a condition (eg. artist=Radiohead, year=1990..1999,
+    /// min-duration=2:30, explicit=false, added-after=2023-01-01), may be given multiple times to
+    /// only write tracks meeting all of them
+    #[arg(long, env = "SPOTIFY_BACKUP_FILTER", global = true)]
+    pub(crate) filter: Vec<filter::Filter>,
+    /// Only writes tracks added since a date or timestamp (eg. 2024-01-01), or since the same
+    /// backup last succeeded with "last-run"
+    #[arg(long, env = "SPOTIFY_BACKUP_SINCE", global = true)]
+    pub(crate) since: Option<filter::Since>,
+    /// Pretty-prints JSON backups, one field per line, which is the default when printing to a
+    /// terminal
+    #[arg(long, env = "SPOTIFY_BACKUP_PRETTY", global = true)]
+    pub(crate) pretty: bool,
+    /// Waits for another run using the same profile to finish instead of failing straight away
+    #[arg(long, env = "SPOTIFY_BACKUP_WAIT", global = true)]
+    pub(crate) wait: bool,
+    /// age identity file used to decrypt encrypted backups, may be given multiple times
+    #[arg(long, env = "SPOTIFY_BACKUP_IDENTITY", global = true)]
+    pub(crate) identity: Vec<PathBuf>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Prints playlists to stdout as JSON
+    Playlist {
+        /// Playlist IDs (eg. 3cEYpjA9oz9GiPac4AsH4n), links or URIs
+        #[arg(
+            required_unless_present_any = ["all", "pick", "from_file"],
+            value_parser = link::parse_playlist_id,
+            add = ArgValueCandidates::new(completions::aliases)
+        )]
+        ids: Vec<String>,
+        /// Also backs up the playlists listed in a file, one ID, link or URI per line (`-` reads
+        /// them from stdin). Blank lines and lines starting with # are skipped
+        #[arg(long, value_name = "PATH")]
+        from_file: Option<PathBuf>,
+        /// Backs up every playlist in the user's library
+        #[arg(long, conflicts_with_all = ["ids", "from_file"])]
+        all: bool,
+        /// Picks the playlists to back up by name from the user's library with a fuzzy finder
+        #[arg(long, conflicts_with_all = ["ids", "from_file", "all"])]
+        pick: bool,
+    },
+    /// Prints liked songs to stdout as JSON
+    Liked,
+    /// Backs up the whole library into the --output directory in one go: the user's profile, liked
+    /// songs, every playlist, saved albums and saved shows, along with an index.json of what's in
+    /// each file
+    Everything,
+    /// Lists the user's playlists with their IDs, owners and track counts, without fetching any of
+    /// their tracks
+    Playlists,
+    /// Prints the folders the user's playlists are organised into, read from an unofficial
+    /// endpoint of the Spotify clients that may change or stop working at any time
+    Folders {
+        /// Value of the sp_dc cookie of open.spotify.com, for getting a token from the web player
+        /// that can read folders
+        #[arg(
+            long,
+            env = "SPOTIFY_SP_DC",
+            hide_env_values = true,
+            required_unless_present = "rootlist"
+        )]
+        sp_dc: Option<String>,
+        /// Reads the folders from a saved response of the web player's rootlist request instead
+        #[arg(long, conflicts_with = "sp_dc")]
+        rootlist: Option<PathBuf>,
+    },
+    /// Prints how many liked songs, saved albums, saved shows and playlists are in the library,
+    /// quickly, as only the first page of each is fetched
+    Summary,
+    /// Reports the tracks in playlists or liked songs that can no longer be played, which Spotify
+    /// greys out, with how many there are in each
+    #[command(group(
+        clap::ArgGroup::new("sources")
+            .args(["ids", "all", "liked"])
+            .multiple(true)
+            .required(true)
+    ))]
+    Unavailable {
+        /// Playlist IDs to check
+        #[arg(
+            value_parser = link::parse_playlist_id,
+            add = ArgValueCandidates::new(completions::aliases)
+        )]
+        ids: Vec<String>,
+        /// Checks every playlist in the user's library
+        #[arg(long, conflicts_with = "ids")]
+        all: bool,
+        /// Checks liked songs
+        #[arg(long)]
+        liked: bool,
+    },
+    /// Looks up every track in a JSON backup on Spotify, reporting those that are gone or can no
+    /// longer be played, with suggested replacements
+    VerifyLive {
+        /// Path to the backup file
+        path: PathBuf,
+        /// Most replacements to suggest for each track, 0 to not search for any
+        #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u32).range(0..=50))]
+        suggestions: u32,
+    },
+    /// Reports liked songs that aren't in any playlist, and tracks in playlists that aren't liked
+    Coverage,
+    /// Reports pairs of playlists sharing most of their tracks, for finding copies of the same
+    /// playlist
+    Similar {
+        /// Playlist IDs to compare, every playlist in the library if none are given
+        #[arg(
+            value_parser = link::parse_playlist_id,
+            add = ArgValueCandidates::new(completions::aliases)
+        )]
+        ids: Vec<String>,
+        /// How similar two playlists have to be to be reported, as the tracks in both over the
+        /// tracks in either
+        #[arg(long, default_value_t = 0.8, value_parser = similar::parse_threshold)]
+        threshold: f64,
+    },
+    /// Searches Spotify, printing the results with their URIs
+    Search {
+        /// What to search for, with "artist - title" searching for that artist and title when
+        /// searching for tracks
+        query: String,
+        /// Kind of results to search for
+        #[arg(long = "type", value_enum, default_value_t)]
+        kind: search::Kind,
+        /// Most results to print
+        #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..=50))]
+        limit: u32,
+    },
+    /// Prints the full metadata of the track, album, playlist, artist, episode or show an
+    /// open.spotify.com link or spotify: URI points to, as JSON
+    Resolve {
+        /// Link or URI (eg. https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC)
+        link: link::Link,
+    },
+    /// Reports tracks that appear more than once in playlists or liked songs, either as the same
+    /// track or as different releases of the same song
+    #[command(group(
+        clap::ArgGroup::new("sources")
+            .args(["ids", "all", "liked"])
+            .multiple(true)
+            .required(true)
+    ))]
+    Dupes {
+        /// Playlist IDs to check
+        #[arg(
+            value_parser = link::parse_playlist_id,
+            add = ArgValueCandidates::new(completions::aliases)
+        )]
+        ids: Vec<String>,
+        /// Checks every playlist in the user's library
+        #[arg(long, conflicts_with = "ids")]
+        all: bool,
+        /// Checks liked songs
+        #[arg(long)]
+        liked: bool,
+    },
+    /// Finds tracks that are the same recording under different URIs, because Spotify relinked
+    /// them to another release or they share an ISRC, and collapses each to one canonical URI
+    Normalize {
+        /// Playlist IDs to check
+        #[arg(
+            value_parser = link::parse_playlist_id,
+            add = ArgValueCandidates::new(completions::aliases)
+        )]
+        ids: Vec<String>,
+        /// Checks every playlist in the user's library
+        #[arg(long, conflicts_with = "ids")]
+        all: bool,
+        /// Checks liked songs
+        #[arg(long)]
+        liked: bool,
+        /// Rewrites the tracks in a JSON backup file to their canonical URIs, can be given more
+        /// than once
+        #[arg(long, value_name = "PATH")]
+        rewrite: Vec<PathBuf>,
+        /// Replaces the tracks in the user's playlists on Spotify with their canonical URIs, after
+        /// asking for confirmation
+        #[arg(long)]
+        apply: bool,
+        /// Doesn't ask for confirmation before changing playlists with `--apply`
+        #[arg(long, short, requires = "apply")]
+        yes: bool,
+    },
+    /// Compares liked songs with the tracks loved on Last.fm, optionally loving the liked songs
+    /// that aren't
+    Lastfm {
+        #[command(subcommand)]
+        command: LastfmCommand,
+        #[command(flatten)]
+        lastfm: lastfm::LastfmArgs,
+    },
+    /// Prints statistics about a JSON backup: top artists and albums, tracks per year and decade of
+    /// release, additions per month, total duration and more
+    Stats {
+        /// Path to the backup file
+        path: PathBuf,
+        /// How many of the artists and albums with the most tracks to list
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+        /// Directory to also render charts of the additions per month, release decades, top
+        /// artists and audio features into
+        #[arg(long, value_name = "DIR")]
+        charts: Option<PathBuf>,
+        /// Image format of the charts
+        #[arg(long, value_enum, default_value_t, requires = "charts")]
+        chart_format: charts::Format,
+    },
+    /// Builds a playlist out of the tracks in JSON backups meeting every `--rule`, printing it as a
+    /// backup and optionally creating it on Spotify
+    Smart {
+        /// Paths to the backup files the tracks are picked from
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+        /// Condition every track has to meet, as a field, an operator (=, !=, >, >=, <, <=, ~ for
+        /// contains or !~) and a value (eg. added-year=2021, energy>0.7 or genres~house). Audio
+        /// features and any other field of the backups can be used
+        #[arg(long = "rule", value_name = "RULE")]
+        rules: Vec<smart::Rule>,
+        /// Leaves out the tracks of a playlist (ID, link or URI) or backup file, can be given more
+        /// than once
+        #[arg(long, value_name = "PLAYLIST")]
+        not_in: Vec<String>,
+        /// Most tracks to keep, in the order they're found
+        #[arg(long)]
+        limit: Option<usize>,
+        /// Creates a private playlist with this name on Spotify holding the tracks
+        #[arg(long, value_name = "NAME")]
+        push: Option<String>,
+    },
+    /// Reports the tracks two playlists have in common, and those only in one of them
+    Compare {
+        /// ID, link or URI of the first playlist, or a backup file of it
+        a: String,
+        /// ID, link or URI of the second playlist, or a backup file of it
+        b: String,
+    },
+    /// Prints a backup file to stdout, decrypting and decompressing it if needed
+    Cat {
+        /// Path to the backup file
+        path: PathBuf,
+    },
+    /// Checks the files in a backup directory against the checksums in its manifest
+    Verify {
+        /// Path to the backup directory
+        dir: PathBuf,
+    },
+    /// Checks a backup file, or every file in a backup directory, can be restored from: that its
+    /// schema version is understood, its checksums match and its tracks have well-formed URIs
+    Validate {
+        /// Path to the backup file or directory
+        path: PathBuf,
+    },
+    /// Prints a JSON Schema of JSON backups, for validating them or generating types from it
+    Schema,
+    /// Upgrades a backup file, or every file in a backup directory, written by an older version to
+    /// the current schema
+    Migrate {
+        /// Path to the backup file or directory
+        path: PathBuf,
+    },
+    /// Combines JSON backups into one, printing every track in any of them once, with the earliest
+    /// date it was added
+    Merge {
+        /// Paths to the backup files
+        #[arg(required = true, num_args = 2..)]
+        paths: Vec<PathBuf>,
+        /// Which copy of a track in several of the backups is kept
+        #[arg(long, value_enum, default_value_t)]
+        strategy: merge::Strategy,
+    },
+    /// Sets up a profile interactively and authenticates it, writing the choices to the config
+    /// file
+    Init,
+    /// Lists the profiles that have been created
+    Profiles,
+    /// Deletes the stored credentials of the active profile
+    Logout,
+    /// Manages authentication with Spotify
+    Auth {
+        #[command(subcommand)]
+        command: AuthCommand,
+    },
+    /// Manages the active profile's caches of API responses and metadata
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+    /// Manages your own tags, ratings and notes on tracks and playlists, which Spotify has nowhere
+    /// to keep, for adding to backups with `--with-annotations`
+    Annotate {
+        #[command(subcommand)]
+        command: AnnotateCommand,
+    },
+    /// Browses the library interactively, backing up the playlists picked
+    Tui,
+    /// Stays running and backs up on a schedule, for running in a container instead of from cron
+    #[command(group(
+        clap::ArgGroup::new("backups")
+            .args(["liked", "all", "ids"])
+            .multiple(true)
+            .requires("interval")
+    ))]
+    Daemon {
+        /// Time between the start of each backup (eg. 6h), instead of running the jobs in the
+        /// config file on their own schedules
+        #[arg(
+            long,
+            env = "SPOTIFY_BACKUP_INTERVAL",
+            value_parser = humantime::parse_duration,
+            requires = "backups"
+        )]
+        interval: Option<Duration>,
+        /// Backs up liked songs
+        #[arg(long)]
+        liked: bool,
+        /// Backs up every playlist in the user's library
+        #[arg(long)]
+        all: bool,
+        /// Playlist IDs to back up
+        #[arg(
+            value_parser = link::parse_playlist_id,
+            add = ArgValueCandidates::new(completions::aliases)
+        )]
+        ids: Vec<String>,
+        /// Serves Prometheus metrics at /metrics on the given address (eg. 0.0.0.0:9090)
+        #[arg(long, env = "SPOTIFY_BACKUP_METRICS_ADDR")]
+        metrics_addr: Option<std::net::SocketAddr>,
+    },
+    /// Stays running and appends each play in the listening history to a log, as Spotify only keeps
+    /// the last 50
+    ScrobbleLog {
+        /// File plays are appended to, as JSON Lines
+        path: PathBuf,
+        /// Time between checks of the listening history (eg. 5m)
+        #[arg(
+            long,
+            env = "SPOTIFY_BACKUP_SCROBBLE_INTERVAL",
+            value_parser = humantime::parse_duration,
+            default_value = "5m"
+        )]
+        interval: Duration,
+        /// Also checks what's playing, to log podcast episodes, which aren't in the listening
+        /// history
+        #[arg(long)]
+        currently_playing: bool,
+    },
+    /// Imports data from elsewhere into what spotify-backup keeps
+    Import {
+        #[command(subcommand)]
+        command: ImportCommand,
+    },
+    /// Converts the plays logged by scrobble-log, or the listening history, to ListenBrainz listens,
+    /// printing them or submitting them to ListenBrainz
+    Listenbrainz {
+        /// Play log written by scrobble-log, instead of the last 50 plays in the listening history
+        path: Option<PathBuf>,
+        /// Submits the listens to ListenBrainz instead of printing them
+        #[arg(long, requires = "token")]
+        submit: bool,
+        /// ListenBrainz user token to submit with (https://listenbrainz.org/settings/)
+        #[arg(long, env = "LISTENBRAINZ_TOKEN", hide_env_values = true)]
+        token: Option<String>,
+    },
+    /// Finds each track in a JSON backup on another service, printing the best match for each with
+    /// how confident the match is
+    Map {
+        #[command(subcommand)]
+        command: MapCommand,
+        #[command(flatten)]
+        tidal: tidal::TidalArgs,
+    },
+    /// Matches the tracks in a JSON backup against a beets library, printing which files they are
+    /// and which are missing from the collection
+    Beets {
+        /// Path to the backup file
+        path: PathBuf,
+        /// beets library database, instead of library.db in $BEETSDIR or ~/.config/beets
+        #[arg(long, env = "BEETS_LIBRARY")]
+        library: Option<PathBuf>,
+        /// What to print
+        #[arg(long, value_enum, default_value_t = beets::Emit::Report)]
+        emit: beets::Emit,
+        /// Lowest confidence, from 0 to 1, a match by title, artists and length needs to be kept
+        #[arg(long, default_value_t = 0.6, value_parser = similar::parse_threshold)]
+        min_confidence: f64,
+    },
+    /// Matches the tracks in JSON backups against the audio files in a directory by their tags,
+    /// writing an M3U playlist of the files for each and printing the tracks that weren't found
+    MatchLocal {
+        /// Paths to the backup files
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+        /// Directory of the music collection, searched recursively
+        #[arg(long, env = "MUSIC_DIR")]
+        music_dir: PathBuf,
+        /// Directory the M3U playlists are written into, named after the backups
+        #[arg(long, default_value = ".")]
+        playlist_dir: PathBuf,
+        /// Lowest confidence, from 0 to 1, a match by title, artists and length needs to be kept
+        #[arg(long, default_value_t = 0.6, value_parser = similar::parse_threshold)]
+        min_confidence: f64,
+    },
+    /// Prints a script downloading the tracks missing from the local collection with yt-dlp, from
+    /// the JSON report of match-local or beets
+    YtDlp {
+        /// Report printed by match-local or beets, or - to read it from stdin
+        report: PathBuf,
+        /// Prints a batch file of YouTube searches for `yt-dlp -a` instead of a script
+        #[arg(long)]
+        batch: bool,
+    },
+    /// Mirrors playlists onto a Plex server, matching their tracks against its music library
+    Plex {
+        #[command(subcommand)]
+        command: PlexCommand,
+        #[command(flatten)]
+        plex: plex::PlexArgs,
+    },
+    /// Mirrors playlists onto a Jellyfin server, matching their tracks against its music libraries
+    Jellyfin {
+        #[command(subcommand)]
+        command: JellyfinCommand,
+        #[command(flatten)]
+        jellyfin: jellyfin::JellyfinArgs,
+    },
+    /// Mirrors playlists onto a Funkwhale pod, matching their tracks against its library
+    Funkwhale {
+        #[command(subcommand)]
+        command: FunkwhaleCommand,
+        #[command(flatten)]
+        funkwhale: funkwhale::FunkwhaleArgs,
+    },
+    /// Mirrors playlists onto a Subsonic server (eg. Navidrome, Airsonic or Gonic), matching their
+    /// tracks against its library
+    Subsonic {
+        #[command(subcommand)]
+        command: SubsonicCommand,
+        #[command(flatten)]
+        subsonic: subsonic::SubsonicArgs,
+    },
+    /// Serves a REST API for triggering backups, checking on them and downloading the files in the
+    /// output directory
+    Serve {
+        /// Address to listen on
+        #[arg(long, env = "SPOTIFY_BACKUP_LISTEN", default_value = "127.0.0.1:8080")]
+        listen: std::net::SocketAddr,
+        /// Bearer token every request has to carry, which should be set whenever the API is
+        /// reachable from other machines
+        #[arg(long, env = "SPOTIFY_BACKUP_API_TOKEN", hide_env_values = true)]
+        api_token: Option<String>,
+        /// Serves a web UI at / for browsing playlists and backups, and comparing backups
+        #[arg(long, env = "SPOTIFY_BACKUP_WEB_UI")]
+        web_ui: bool,
+    },
+    /// Prints a systemd service and timer (or launchd agent on macOS) running a backup every day
+    /// with the current profile and config file, or installs them with --install
+    InstallService {
+        /// Time of day the backup runs at
+        #[arg(long, default_value = "03:00", value_parser = service::parse_time)]
+        at: chrono::NaiveTime,
+        /// Service manager to generate units for, defaults to the one of the current platform
+        #[arg(long, value_enum)]
+        manager: Option<service::Manager>,
+        /// Writes the units into place and enables them, instead of printing them
+        #[arg(long)]
+        install: bool,
+        /// Command the service runs, after a -- (eg. -- playlist --all)
+        #[arg(last = true, default_values = ["playlist", "--all"])]
+        command: Vec<String>,
+    },
+    /// Prints the script enabling tab completion in a shell (eg. `source <(spotify-backup
+    /// completions bash)`)
+    Completions {
+        #[arg(value_parser = completions::SHELLS.to_vec())]
+        shell: String,
+    },
+    /// Writes man pages for the tool and each of its subcommands, for packaging
+    #[command(hide = true)]
+    Mangen {
+        /// Directory to write the man pages into
+        dir: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum AuthCommand {
+    /// Shows whether the active profile is authenticated, the token's scopes and expiry, and the
+    /// user it belongs to
+    #[command(alias = "whoami")]
+    Status,
+    /// Authenticates if needed and prints the profile's refresh token, for passing to
+    /// non-interactive runs via SPOTIFY_REFRESH_TOKEN
+    ExportRefreshToken,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum LastfmCommand {
+    /// Signs in to Last.fm through the browser, storing the session in the profile for loving
+    /// tracks
+    Login,
+    /// Reports liked songs that aren't loved on Last.fm, and loved tracks that aren't liked,
+    /// matched by title and artist
+    Compare {
+        /// Backup of liked songs to compare, instead of fetching them from Spotify
+        path: Option<PathBuf>,
+        /// Last.fm user whose loved tracks are compared, instead of the signed in user
+        #[arg(long)]
+        user: Option<String>,
+        /// Loves the liked songs that aren't loved yet, which needs a session from `lastfm login`
+        #[arg(long)]
+        sync: bool,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum PlexCommand {
+    /// Creates or updates a Plex playlist for each playlist with the tracks found in the library,
+    /// printing the tracks that weren't
+    Sync(sync::SyncArgs),
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum JellyfinCommand {
+    /// Creates or updates a Jellyfin playlist for each playlist with the tracks found in the
+    /// libraries, printing the tracks that weren't
+    Sync(sync::SyncArgs),
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum FunkwhaleCommand {
+    /// Creates or updates a Funkwhale playlist for each playlist with the tracks found on the pod,
+    /// printing the tracks that weren't
+    Sync(sync::SyncArgs),
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum SubsonicCommand {
+    /// Creates or updates a playlist on the server for each playlist with the tracks found in the
+    /// library, printing the tracks that weren't
+    Sync(sync::SyncArgs),
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum MapCommand {
+    /// Finds each track on YouTube Music, by searching for its artist and title
+    Youtube(map::MapArgs),
+    /// Finds each track on Deezer, by its ISRC or failing that by searching for its artist and
+    /// title
+    Deezer(map::MapArgs),
+    /// Finds each track on Tidal, by its ISRC or failing that by searching for its artist and
+    /// title, with the credentials of a Tidal developer app
+    Tidal(map::MapArgs),
+    /// Finds each track in the Apple Music catalog, by its ISRC or failing that by searching for
+    /// its artist and title, through the iTunes Search API
+    Apple(map::MapArgs),
+    /// Finds each track on several services at once, printing its ID on each of them
+    Concordance {
+        #[command(flatten)]
+        map: map::MapArgs,
+        /// Services to find the tracks on
+        #[arg(
+            long,
+            value_enum,
+            value_delimiter = ',',
+            default_values_t = [map::Kind::Deezer, map::Kind::Tidal]
+        )]
+        services: Vec<map::Kind>,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum CacheCommand {
+    /// Shows how much is cached and the size of the caches on disk
+    Stats,
+    /// Deletes everything cached
+    Clear,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum ImportCommand {
+    /// Imports the streaming history from Spotify's privacy data download into a play log written
+    /// by scrobble-log, skipping plays already in it
+    Gdpr {
+        /// The zip file of the download, or the directory it was extracted into
+        path: PathBuf,
+        /// Play log the plays are merged into, created if it doesn't exist
+        #[arg(long)]
+        log: PathBuf,
+        /// Leaves out plays shorter than this (eg. 30s), which Spotify doesn't count as streams
+        #[arg(long, value_parser = humantime::parse_duration, default_value = "30s")]
+        min_played: Duration,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum AnnotateCommand {
+    /// Tags, rates or adds a note to a track or playlist, keeping anything else it was annotated
+    /// with
+    #[command(group(
+        clap::ArgGroup::new("annotation")
+            .args(["tags", "rating", "note"])
+            .multiple(true)
+            .required(true)
+    ))]
+    Set {
+        /// Link or URI of the track or playlist
+        #[arg(value_parser = annotations::parse_target)]
+        target: String,
+        /// Tag to add, may be given multiple times
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+        /// Rating from 1 to 5, replacing any there was
+        #[arg(long, value_parser = clap::value_parser!(u8).range(1..=5))]
+        rating: Option<u8>,
+        /// Note replacing any there was
+        #[arg(long)]
+        note: Option<String>,
+    },
+    /// Removes tags, the rating or the note of a track or playlist, or everything it was annotated
+    /// with if none of them are given
+    Remove {
+        /// Link or URI of the track or playlist
+        #[arg(value_parser = annotations::parse_target)]
+        target: String,
+        /// Tag to remove, may be given multiple times
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+        /// Removes the rating
+        #[arg(long)]
+        rating: bool,
+        /// Removes the note
+        #[arg(long)]
+        note: bool,
+    },
+    /// Prints the annotations of a track or playlist, or of everything annotated
+    Show {
+        /// Link or URI of the track or playlist
+        #[arg(value_parser = annotations::parse_target)]
+        target: Option<String>,
+    },
+}
+
+/// Whether `--non-interactive` was given, in which case failures are also printed as JSON.
+static NON_INTERACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Runs the command line interface with the process's arguments, returning the code to exit
+/// with. Failures are printed along with a hint at what to do about them if there is one, and
+/// exit with the code of the [`Error`] in their chain.
+pub fn main() -> ExitCode {
+    // shells call back into the binary with COMPLETE set to ask for completions, which are
+    // answered before the runtime is started since the completers start their own
+    CompleteEnv::with_factory(Args::command)
+        .var(completions::VAR)
+        .complete();
+
+    let Err(e) = start() else {
+        return ExitCode::SUCCESS;
+    };
+
+    eprintln!("Error: {e:?}");
+    let error = e.downcast_ref::<Error>();
+    if let Some(hint) = error.and_then(Error::hint) {
+        eprintln!("\n{hint}");
+    }
+
+    let code = error.map_or(1, Error::exit_code);
+    // the last line of stderr, so CI can tell why the run failed without parsing the rest
+    if NON_INTERACTIVE.load(Ordering::Relaxed) {
+        eprintln!(
+            "{}",
+            serde_json::json!({
+                "error": error.map_or("other", Error::kind),
+                "message": format!("{e:#}"),
+                "exit_code": code,
+            })
+        );
+    }
+
+    ExitCode::from(code)
+}
+
+#[tokio::main]
+async fn start() -> Result<()> {
+    // playlists can be given by the aliases in the config file, so it's read before the command
+    // line is parsed for real. Asking for help or the version fails here, and exits there
+    let config = match Args::command().ignore_errors(true).try_get_matches() {
+        Ok(matches) => {
+            let path = matches.get_one::<PathBuf>("config");
+            config::Config::load(path.map(PathBuf::as_path)).await?
+        }
+        Err(_) => config::Config::default(),
+    };
+    link::set_aliases(&config.aliases).context("Invalid aliases in the config file")?;
+
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    NON_INTERACTIVE.store(args.auth.non_interactive, Ordering::Relaxed);
+
+    logging::init(&args.log)?;
+
+    if let Some(dir) = &args.state_dir {
+        profile::set_state_dir(dir)?;
+    }
+
+    args.apply_config(&config, &matches);
+
+    if args.notify.notify_desktop {
+        desktop::enable()?;
+    }
+    http::init(&args.http).await?;
+
+    args.auth.prompt_for_passphrase()?;
+
+    // dropping the command's future aborts any requests in flight and cleans up partially written
+    // files, leaving the previous backup in place
+    let code = tokio::select! {
+        result = run(&args) => {
+            args.api.usage.report(args.log.stats);
+            return result;
+        }
+        code = shutdown_signal() => code,
+    };
+
+    warn!("Interrupted, any partially written files have been removed");
+    std::process::exit(code);
+}
+
+async fn run(args: &Args) -> Result<()> {
+    let _lock = if args.command.uses_profile_state() {
+        Some(
+            profile::Profile::new(&args.profile)?
+                .lock(args.wait)
+                .await?,
+        )
+    } else {
+        None
+    };
+
+    match &args.command {
+        Command::Playlist {
+            ids,
+            from_file,
+            all,
+            pick,
+        } => {
+            let mut ids = ids.clone();
+            if let Some(path) = from_file {
+                ids.extend(read_playlist_ids(path).await?);
+            }
+            backup_library(args, false, &ids, *all, *pick).await
+        }
+        Command::Liked => backup_library(args, true, &[], false, false).await,
+        Command::Cat { path } => cat(args, path).await,
+        Command::Compare { a, b } => compare::run(args, a, b).await,
+        Command::Stats {
+            path,
+            top,
+            charts,
+            chart_format,
+        } => stats::run(args, path, *top, charts.as_deref(), *chart_format).await,
+        Command::Verify { dir } => verify(dir).await,
+        Command::Validate { path } => validate::run(path, &args.identity).await,
+        Command::Schema => schema::print(args),
+        Command::Migrate { path } => migrate::run(path, &args.identity, &args.encrypt).await,
+        Command::Merge { paths, strategy } => merge::run(args, paths, *strategy).await,
+        Command::Smart {
+            paths,
+            rules,
+            not_in,
+            limit,
+            push,
+        } => smart::run(args, paths, rules, not_in, *limit, push.as_deref()).await,
+        Command::Init => init::run(args).await,
+        Command::Everything => everything::run(args).await,
+        Command::Playlists => list_playlists(args).await,
+        #[cfg(feature = "folders")]
+        Command::Folders { sp_dc, rootlist } => {
+            crate::folders::run(args, sp_dc.as_deref(), rootlist.as_deref()).await
+        }
+        #[cfg(not(feature = "folders"))]
+        Command::Folders { .. } => not_built_in("Exporting folders", "folders"),
+        Command::Summary => summary::run(args).await,
+        Command::Unavailable { ids, all, liked } => unavailable::run(args, ids, *all, *liked).await,
+        Command::VerifyLive { path, suggestions } => {
+            verify_live::run(args, path, *suggestions).await
+        }
+        Command::Coverage => coverage::run(args).await,
+        Command::Similar { ids, threshold } => similar::run(args, ids, *threshold).await,
+        Command::Search { query, kind, limit } => search::run(args, query, *kind, *limit).await,
+        Command::Resolve { link } => resolve::run(args, link).await,
+        Command::Dupes { ids, all, liked } => dupes::run(args, ids, *all, *liked).await,
+        Command::Normalize {
+            ids,
+            all,
+            liked,
+            rewrite,
+            apply,
+            yes,
+        } => normalize::run(args, ids, *all, *liked, rewrite, *apply, *yes).await,
+        Command::Profiles => list_profiles(args).await,
+        Command::Logout => logout(args).await,
+        Command::Auth {
+            command: AuthCommand::Status,
+        } => auth_status(args).await,
+        Command::Auth {
+            command: AuthCommand::ExportRefreshToken,
+        } => export_refresh_token(args).await,
+        Command::Lastfm {
+            command: LastfmCommand::Login,
+            lastfm,
+        } => lastfm::login(args, lastfm).await,
+        Command::Lastfm {
+            command: LastfmCommand::Compare { path, user, sync },
+            lastfm,
+        } => lastfm::compare(args, lastfm, path.as_deref(), user.as_deref(), *sync).await,
+        Command::Cache {
+            command: CacheCommand::Stats,
+        } => cache_stats(args).await,
+        Command::Cache {
+            command: CacheCommand::Clear,
+        } => cache_clear(args).await,
+        Command::Annotate {
+            command:
+                AnnotateCommand::Set {
+                    target,
+                    tags,
+                    rating,
+                    note,
+                },
+        } => annotations::set(args, target, tags, *rating, note.as_deref()).await,
+        Command::Annotate {
+            command:
+                AnnotateCommand::Remove {
+                    target,
+                    tags,
+                    rating,
+                    note,
+                },
+        } => annotations::remove(args, target, tags, *rating, *note).await,
+        Command::Annotate {
+            command: AnnotateCommand::Show { target },
+        } => annotations::show(args, target.as_deref()).await,
+        #[cfg(feature = "tui")]
+        Command::Tui => crate::tui::run(args).await,
+        #[cfg(not(feature = "tui"))]
+        Command::Tui => not_built_in("The TUI", "tui"),
+        Command::Daemon {
+            interval,
+            liked,
+            all,
+            ids,
+            metrics_addr,
+        } => {
+            let backups = daemon::Backups {
+                liked: *liked,
+                all: *all,
+                ids: ids.clone(),
+            };
+            daemon::run(args, *interval, backups, *metrics_addr).await
+        }
+        Command::ScrobbleLog {
+            path,
+            interval,
+            currently_playing,
+        } => scrobble::run(args, path, *interval, *currently_playing).await,
+        Command::Import {
+            command:
+                ImportCommand::Gdpr {
+                    path,
+                    log,
+                    min_played,
+                },
+        } => gdpr::import(path, log, *min_played).await,
+        Command::Listenbrainz {
+            path,
+            submit,
+            token,
+        } => listenbrainz::run(args, path.as_deref(), *submit, token.as_deref()).await,
+        Command::Map { command, tidal } => match command {
+            MapCommand::Youtube(map) => map::run(args, &youtube::YoutubeMusic::new(), map).await,
+            MapCommand::Deezer(map) => map::run(args, &deezer::Deezer::new(), map).await,
+            MapCommand::Tidal(map) => map::run(args, &tidal::Tidal::new(args, tidal)?, map).await,
+            MapCommand::Apple(map) => map::run(args, &apple::AppleMusic::new(args), map).await,
+            MapCommand::Concordance { map, services } => {
+                let services = services
+                    .iter()
+                    .map(|v| v.service(args, tidal))
+                    .collect::<Result<Vec<_>>>()?;
+                map::concordance(args, &services, map).await
+            }
+        },
+        Command::Beets {
+            path,
+            library,
+            emit,
+            min_confidence,
+        } => beets::run(args, path, library.as_deref(), *emit, *min_confidence).await,
+        Command::MatchLocal {
+            paths,
+            music_dir,
+            playlist_dir,
+            min_confidence,
+        } => local::run(args, paths, music_dir, playlist_dir, *min_confidence).await,
+        Command::YtDlp { report, batch } => ytdlp::run(report, *batch).await,
+        Command::Plex {
+            command: PlexCommand::Sync(sync),
+            plex,
+        } => sync::run(args, &plex::Plex::connect(plex).await?, sync).await,
+        Command::Jellyfin {
+            command: JellyfinCommand::Sync(sync),
+            jellyfin,
+        } => sync::run(args, &jellyfin::Jellyfin::connect(jellyfin).await?, sync).await,
+        Command::Funkwhale {
+            command: FunkwhaleCommand::Sync(sync),
+            funkwhale,
+        } => sync::run(args, &funkwhale::Funkwhale::connect(funkwhale).await?, sync).await,
+        Command::Subsonic {
+            command: SubsonicCommand::Sync(sync),
+            subsonic,
+        } => sync::run(args, &subsonic::Subsonic::connect(subsonic).await?, sync).await,
+        #[cfg(feature = "serve")]
+        Command::Serve {
+            listen,
+            api_token,
+            web_ui,
+        } => crate::serve::run(args, *listen, api_token.clone(), *web_ui).await,
+        #[cfg(not(feature = "serve"))]
+        Command::Serve { .. } => not_built_in("The REST API", "serve"),
+        Command::InstallService {
+            at,
+            manager,
+            install,
+            command,
+        } => {
+            let manager = manager.unwrap_or_else(service::Manager::native);
+            service::run(args, manager, *at, *install, command).await
+        }
+        Command::Completions { shell } => completions::print(shell),
+        Command::Mangen { dir } => mangen(dir).await,
+    }
+}
+
+/// Resolves once SIGINT or SIGTERM is received, with the exit code conventionally used for being
+/// killed by that signal.
+async fn shutdown_signal() -> i32 {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let terminate = async {
+            match signal(SignalKind::terminate()) {
+                Ok(mut v) => v.recv().await,
+                Err(_) => std::future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => 130,
+            _ = terminate => 143,
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        130
+    }
+}
+
+impl Args {
+    /// Fields to write for each track, with the genres added when `--genres` was given, the
+    /// MusicBrainz IDs when `--musicbrainz` was, the lyrics when `--with-lyrics embed` was and the
+    /// user's annotations when `--with-annotations` was.
+    /// Templates write the fields they have placeholders for.
+    pub(crate) fn fields(&self) -> Vec<output::Field> {
+        if let (output::Format::Template, Some(template)) = (self.format, &self.template) {
+            return template.fields();
+        }
+
+        let mut fields = if self.fields.is_empty() {
+            output::DEFAULT_FIELDS.to_vec()
+        } else {
+            self.fields.clone()
+        };
+
+        if self.genres && !fields.contains(&output::Field::Genres) {
+            fields.push(output::Field::Genres);
+        }
+        if self.musicbrainz {
+            for field in [output::Field::RecordingMbid, output::Field::ReleaseMbid] {
+                if !fields.contains(&field) {
+                    fields.push(field);
+                }
+            }
+        }
+        if self.with_lyrics == Some(lyrics::Mode::Embed) && !fields.contains(&output::Field::Lyrics)
+        {
+            fields.push(output::Field::Lyrics);
+        }
+        if self.with_annotations {
+            for field in [
+                output::Field::Tags,
+                output::Field::Rating,
+                output::Field::Note,
+            ] {
+                if !fields.contains(&field) {
+                    fields.push(field);
+                }
+            }
+        }
+
+        fields
+    }
+
+    /// Exporter writing tracks in the format that was asked for. JSON written to a terminal is
+    /// pretty-printed even when `--pretty` wasn't given, and tables are fitted to its width.
+    pub(crate) fn exporter<'a>(
+        &'a self,
+        fields: &'a [output::Field],
+    ) -> Result<Box<dyn Exporter + 'a>> {
+        let terminal = self.output.is_none() && std::io::stdout().is_terminal();
+
+        Ok(match (self.format, &self.template) {
+            (output::Format::Template, Some(template)) => {
+                Box::new(export::TemplateExporter::new(template))
+            }
+            (output::Format::Template, None) => {
+                anyhow::bail!("--format template needs a --template, or `template` in the config")
+            }
+            (output::Format::Table, _) => Box::new(export::TableExporter::new(terminal)),
+            (output::Format::Csv, _) => Box::new(export::CsvExporter::new(fields)),
+            (output::Format::Ndjson, _) => Box::new(export::NdjsonExporter::new(fields)),
+            (output::Format::Sqlite, _) => {
+                anyhow::ensure!(
+                    !terminal,
+                    "SQLite backups can't be written to a terminal, pass --output or redirect stdout"
+                );
+                Box::new(export::SqliteExporter::new(fields)?)
+            }
+            _ if self.group_by == Some(output::GroupBy::Album) => {
+                Box::new(export::AlbumsExporter::new(fields, self.pretty || terminal))
+            }
+            _ => Box::new(export::JsonExporter::new(fields, self.pretty || terminal)),
+        })
+    }
+
+    /// Fills in options that weren't given on the command line or through the environment from
+    /// the config file.
+    fn apply_config(&mut self, config: &config::Config, matches: &ArgMatches) {
+        let unset = |id: &str| {
+            matches!(
+                matches.value_source(id),
+                None | Some(ValueSource::DefaultValue)
+            )
+        };
+
+        if let (true, Some(profile)) = (unset("profile"), &config.profile) {
+            self.profile = profile.clone();
+        }
+
+        self.apply_profile_config(config.resolve(&self.profile), matches);
+    }
+
+    /// Options for running a daemon job as `profile` with its own `settings`, with the command
+    /// line read again and the gaps in it filled from the job's settings, and then from that
+    /// profile's settings in the config file.
+    pub fn for_job(
+        &self,
+        config: &config::Config,
+        profile: &str,
+        settings: &config::ProfileConfig,
+    ) -> Result<Self> {
+        let matches = Self::command().try_get_matches()?;
+        let mut args = Self::from_arg_matches(&matches)?;
+
+        args.profile = profile.to_string();
+        args.apply_profile_config(settings.clone().or(&config.resolve(profile)), &matches);
+        // the passphrase is only asked for once, and used for every profile
+        args.auth.token_passphrase = self.auth.token_passphrase.clone();
+
+        Ok(args)
+    }
+
+    fn apply_profile_config(&mut self, config: config::ProfileConfig, matches: &ArgMatches) {
+        let unset = |id: &str| {
+            matches!(
+                matches.value_source(id),
+                None | Some(ValueSource::DefaultValue)
+            )
+        };
+
+        if let (true, Some(v)) = (unset("client_id"), config.client_id) {
+            self.auth.client_id = v;
+        }
+        if let (true, Some(v)) = (unset("format"), config.format) {
+            self.format = v;
+        }
+        if let (true, Some(v)) = (unset("encrypt"), config.encrypt) {
+            self.encrypt = v;
+        }
+        if let (true, Some(v)) = (unset("genres"), config.genres) {
+            self.genres = v;
+        }
+        if let (true, Some(v)) = (unset("musicbrainz"), config.musicbrainz) {
+            self.musicbrainz = v;
+        }
+        if let (true, Some(v)) = (unset("with_annotations"), config.with_annotations) {
+            self.with_annotations = v;
+        }
+        if let (true, Some(v)) = (unset("lyrics_provider"), config.lyrics_provider) {
+            self.lyrics_provider = v;
+        }
+        if let (true, Some(v)) = (unset("fields"), config.fields) {
+            self.fields = v;
+        }
+        if let (true, Some(v)) = (unset("pretty"), config.pretty) {
+            self.pretty = v;
+        }
+        if let (true, Some(v)) = (unset("owned_only"), config.owned_only) {
+            self.owned_only = v;
+        }
+        if let (true, Some(v)) = (unset("notify_desktop"), config.notify_desktop) {
+            self.notify.notify_desktop = v;
+        }
+        if let (true, Some(v)) = (unset("concurrency"), config.concurrency) {
+            self.api.concurrency = v;
+        }
+        if let (true, Some(v)) = (unset("page_size"), config.page_size) {
+            self.api.page_size = v;
+        }
+        if let (true, Some(v)) = (unset("retries"), config.retries) {
+            self.api.retries = v;
+        }
+
+        self.output = self.output.take().or(config.output);
+        self.template = self.template.take().or(config.template);
+        self.compress = self.compress.or(config.compress);
+        self.split_size = self.split_size.or(config.split_size);
+        self.split_tracks = self.split_tracks.or(config.split_tracks);
+        self.with_lyrics = self.with_lyrics.or(config.with_lyrics);
+        self.upload = self.upload.take().or(config.upload);
+        self.upload_keep = self.upload_keep.or(config.upload_keep);
+        self.notify.notify_webhook = self.notify.notify_webhook.take().or(config.notify_webhook);
+        self.notify.notify_discord = self.notify.notify_discord.take().or(config.notify_discord);
+        self.notify.notify_slack = self.notify.notify_slack.take().or(config.notify_slack);
+        self.healthcheck_url = self.healthcheck_url.take().or(config.healthcheck_url);
+        self.api.rate_limit = self.api.rate_limit.or(config.rate_limit);
+        self.api.market = self.api.market.take().or(config.market);
+    }
+}
+
+impl Command {
+    /// Whether the command reads or writes the profile's tokens, caches or output, and so can't
+    /// run at the same time as another command doing so.
+    fn uses_profile_state(&self) -> bool {
+        matches!(
+            self,
+            Self::Playlist { .. }
+                | Self::Liked
+                | Self::Everything
+                | Self::Playlists
+                | Self::Folders { .. }
+                | Self::Summary
+                | Self::Unavailable { .. }
+                | Self::Dupes { .. }
+                | Self::Normalize { .. }
+                | Self::Compare { .. }
+                | Self::Search { .. }
+                | Self::Resolve { .. }
+                | Self::Similar { .. }
+                | Self::Coverage
+                | Self::VerifyLive { .. }
+                | Self::Lastfm { .. }
+                | Self::Listenbrainz { .. }
+                | Self::Plex { .. }
+                | Self::Jellyfin { .. }
+                | Self::Funkwhale { .. }
+                | Self::Subsonic { .. }
+                | Self::Smart { .. }
+                | Self::Logout
+                | Self::Cache { .. }
+                | Self::Annotate { .. }
+                | Self::Tui
+        )
+    }
+
+    /// Spotify scopes the command needs beyond the default ones.
+    fn required_scopes(&self) -> &'static [&'static str] {
+        match self {
+            Self::Playlist { .. }
+            | Self::Playlists
+            | Self::Folders { .. }
+            | Self::Resolve { .. }
+            | Self::Tui => &[authentication::scope::PLAYLIST_READ_PRIVATE],
+            Self::Liked | Self::Lastfm { .. } => &[authentication::scope::USER_LIBRARY_READ],
+            Self::Daemon { .. }
+            | Self::Everything
+            | Self::Serve { .. }
+            | Self::Summary
+            | Self::Unavailable { .. }
+            | Self::Dupes { .. }
+            | Self::Normalize { apply: false, .. }
+            | Self::Compare { .. }
+            | Self::Similar { .. }
+            | Self::Coverage
+            | Self::Plex { .. }
+            | Self::Jellyfin { .. }
+            | Self::Funkwhale { .. }
+            | Self::Subsonic { .. }
+            | Self::Smart { push: None, .. } => &[
+                authentication::scope::PLAYLIST_READ_PRIVATE,
+                authentication::scope::USER_LIBRARY_READ,
+            ],
+            // pushed playlists are created private
+            Self::Smart { .. } => &[
+                authentication::scope::PLAYLIST_READ_PRIVATE,
+                authentication::scope::USER_LIBRARY_READ,
+                authentication::scope::PLAYLIST_MODIFY_PRIVATE,
+            ],
+            // while the playlists normalized can be public
+            Self::Normalize { .. } => &[
+                authentication::scope::PLAYLIST_READ_PRIVATE,
+                authentication::scope::USER_LIBRARY_READ,
+                authentication::scope::PLAYLIST_MODIFY_PRIVATE,
+                authentication::scope::PLAYLIST_MODIFY_PUBLIC,
+            ],
+            Self::Listenbrainz { .. } => &[authentication::scope::USER_READ_RECENTLY_PLAYED],
+            Self::ScrobbleLog { .. } => &[
+                authentication::scope::USER_READ_RECENTLY_PLAYED,
+                authentication::scope::USER_READ_CURRENTLY_PLAYING,
+            ],
+            _ => &[],
+        }
+    }
+}
+
+pub(crate) async fn build_client(profile: &profile::Profile, args: &Args) -> Result<api::Client> {
+    if args.api.offline || args.api.replay.is_some() {
+        return Ok(api::Client::new(http::client(), args.api.clone(), profile));
+    }
+
+    let token = authentication::authenticate(profile, &args.auth, args.command.required_scopes())
+        .await
+        .map_err(|e| Error::Auth(e.into()))?;
+
+    client_with_token(&token, &args.api, profile)
+}
+
+/// Backs up liked songs with `liked` and the playlists given by `ids`, `all` and `pick` (see
+/// [`playlist_ids`]), pinging the healthcheck URL if there is one.
+pub(crate) async fn backup_library(
+    args: &Args,
+    liked: bool,
+    ids: &[String],
+    all: bool,
+    pick: bool,
+) -> Result<()> {
+    let healthcheck = match &args.healthcheck_url {
+        Some(url) => Some(healthcheck::Healthcheck::start(url).await),
+        None => None,
+    };
+
+    let result = async {
+        let mut jobs = Vec::new();
+        if liked {
+            jobs.push(liked_job(args));
+        }
+
+        let ids = match playlist_ids(args, ids, all, pick).await {
+            Ok(v) => v,
+            Err(e) => {
+                // nothing has been backed up yet, but notifications should still be sent about it
+                if args.notify.is_enabled() {
+                    notify::Summary::new(&args.profile)
+                        .send(&args.notify, Some(&e))
+                        .await;
+                }
+                return Err(e);
+            }
+        };
+        jobs.extend(playlist_jobs(args, &ids));
+
+        backup(args, jobs).await
+    }
+    .await;
+
+    if let Some(healthcheck) = healthcheck {
+        healthcheck.finish(result.as_ref().err()).await;
+    }
+
+    result
+}
+
+/// IDs of the playlists to back up, which are either those given, every playlist in the user's
+/// library with `all`, or those picked from it with `pick`.
+async fn playlist_ids(args: &Args, ids: &[String], all: bool, pick: bool) -> Result<Vec<String>> {
+    anyhow::ensure!(
+        !(pick && args.auth.non_interactive),
+        "--pick can't be used with --non-interactive, pass the playlists or --all instead"
+    );
+
+    Ok(if all || pick {
+        let (user_id, playlists) = fetch_playlists(args).await?;
+
+        if pick {
+            pick_playlists(playlists).await?
+        } else {
+            included_playlists(args, &user_id, playlists)
+                .into_iter()
+                .map(|v| v.id)
+                .collect()
+        }
+    } else {
+        // a playlist given twice would be written to the same file twice at once
+        let mut seen = std::collections::HashSet::new();
+        ids.iter().filter(|v| seen.insert(*v)).cloned().collect()
+    })
+}
+
+/// Which of every playlist in the library are backed up, which is all of them unless followed
+/// playlists are skipped with `--owned-only`.
+pub(crate) fn included_playlists(
+    args: &Args,
+    user_id: &str,
+    playlists: Vec<GetPlaylistsResponseItem>,
+) -> Vec<GetPlaylistsResponseItem> {
+    let mut counts = HashMap::new();
+    for playlist in &playlists {
+        *counts.entry(playlist.ownership(user_id)).or_insert(0) += 1;
+    }
+    let count = |v| counts.get(&v).copied().unwrap_or(0);
+    info!(
+        "Found {} owned, {} collaborative and {} followed playlist(s)",
+        count(Ownership::Owned),
+        count(Ownership::Collaborative),
+        count(Ownership::Followed)
+    );
+
+    let owned_only = args.owned_only && !args.include_followed;
+    if owned_only && count(Ownership::Followed) > 0 {
+        info!(
+            "Skipping {} followed playlist(s), pass --include-followed to back them up too",
+            count(Ownership::Followed)
+        );
+    }
+
+    playlists
+        .into_iter()
+        .filter(|v| !owned_only || v.ownership(user_id) != Ownership::Followed)
+        .collect()
+}
+
+/// Reads the playlists listed one per line in the file at `path`, or stdin if it's `-`.
+async fn read_playlist_ids(path: &Path) -> Result<Vec<String>> {
+    let (data, source) = if path == Path::new("-") {
+        let data = tokio::task::spawn_blocking(|| std::io::read_to_string(std::io::stdin()))
+            .await?
+            .context("Failed to read playlists from stdin")?;
+        (data, "stdin".to_string())
+    } else {
+        let data = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        (data, path.display().to_string())
+    };
+
+    data.lines()
+        .enumerate()
+        .map(|(i, line)| (i, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(i, line)| {
+            link::parse_playlist_id(line)
+                .with_context(|| format!("Invalid playlist on line {} of {source}", i + 1))
+        })
+        .collect()
+}
+
+/// Opens a fuzzy finder over the names of `playlists`, returning the IDs of the ones picked.
+#[cfg(feature = "tui")]
+async fn pick_playlists(playlists: Vec<GetPlaylistsResponseItem>) -> Result<Vec<String>> {
+    let names = playlists
+        .iter()
+        .map(|v| format!("{} ({} tracks)", v.name, v.tracks.total))
+        .collect::<Vec<_>>();
+
+    let picked = tokio::task::spawn_blocking(move || crate::picker::pick("Playlists", &names))
+        .await
+        .context("Failed to wait for picker")??;
+
+    Ok(picked
+        .into_iter()
+        .map(|i| playlists[i].id.clone())
+        .collect())
+}
+
+#[cfg(not(feature = "tui"))]
+async fn pick_playlists(_: Vec<GetPlaylistsResponseItem>) -> Result<Vec<String>> {
+    not_built_in("Picking playlists", "tui")
+}
+
+/// Fails a command needing a cargo feature the binary was built without.
+#[cfg(not(all(feature = "folders", feature = "serve", feature = "tui")))]
+fn not_built_in<T>(what: &str, feature: &str) -> Result<T> {
+    anyhow::bail!(
+        "{what} wasn't built in, reinstall with `cargo install spotify-backup --features {feature}`"
+    )
+}
+
+/// Backup job for the user's liked songs.
+pub(crate) fn liked_job(args: &Args) -> (String, String) {
+    (
+        format!("liked.{}", args.format.extension()),
+        args.api.first_tracks_page_url("me/tracks", 50),
+    )
+}
+
+/// Backup jobs for each of the given playlists.
+pub(crate) fn playlist_jobs(args: &Args, ids: &[String]) -> Vec<(String, String)> {
+    ids.iter()
+        .map(|id| {
+            (
+                format!("playlist-{id}.{}", args.format.extension()),
+                args.api
+                    .first_tracks_page_url(&format!("playlists/{id}/tracks"), 100),
+            )
+        })
+        .collect()
+}
+
+/// Backs up each `(file name, first page URL)` pair, fetching them concurrently and streaming each
+/// one to its output as pages arrive.
+async fn backup(args: &Args, jobs: Vec<(String, String)>) -> Result<()> {
+    let progress = progress::Progress::new(jobs.len(), &args.progress)?;
+    backup_with_progress(args, jobs, progress).await
+}
+
+/// Same as [`backup`], reporting progress through `progress`.
+pub(crate) async fn backup_with_progress(
+    args: &Args,
+    jobs: Vec<(String, String)>,
+    progress: progress::Progress,
+) -> Result<()> {
+    let started = std::time::Instant::now();
+    let total = jobs.len();
+
+    let mut summary = args
+        .notify
+        .is_enabled()
+        .then(|| notify::Summary::new(&args.profile));
+    let result = run_backups(args, jobs, progress, summary.as_mut()).await;
+
+    if let Some(summary) = summary {
+        summary.send(&args.notify, result.as_ref().err()).await;
+    }
+
+    // only worth interrupting for if there's been time to switch to something else
+    if started.elapsed() >= desktop::LONG_BACKUP {
+        let took = humantime::format_duration(Duration::from_secs(started.elapsed().as_secs()));
+        match &result {
+            Ok(()) => {
+                let body = format!("Backed up {total} file(s) in {took}");
+                desktop::notify("Backup finished", &body).await;
+            }
+            Err(e) => desktop::notify("Backup failed", &format!("{e:#}")).await,
+        }
+    }
+
+    result
+}
+
+/// Runs each backup job, recording how each one went in `summary` if one is to be sent.
+async fn run_backups(
+    args: &Args,
+    jobs: Vec<(String, String)>,
+    progress: progress::Progress,
+    mut summary: Option<&mut notify::Summary>,
+) -> Result<()> {
+    anyhow::ensure!(
+        !args.combine
+            || (args.format == output::Format::Json
+                && args.compress.is_none()
+                && args.encrypt.is_empty()),
+        "Only uncompressed, unencrypted JSON backups can be combined"
+    );
+
+    let profile = profile::Profile::new(&args.profile)?;
+    let client = build_client(&profile, args).await?;
+
+    let account_id = match &args.output {
+        Some(_) => Some(fetch_current_user(&client).await?.id),
+        None => None,
+    };
+    let destination_storage = args
+        .upload
+        .as_ref()
+        .map(|url| storage::Destination::from_url(url, &args.storage, &profile, &args.auth))
+        .transpose()?;
+
+    if let (Some(summary), Some(destination)) = (summary.as_deref_mut(), &destination_storage) {
+        summary.uploading_to(destination.kind());
+    }
+
+    // backups written to stdout would end up interleaved if they were fetched concurrently, unless
+    // they're combined and so only printed at the end
+    let job_concurrency = match &args.output {
+        Some(_) => client.concurrency(),
+        None if args.combine => client.concurrency(),
+        None => 1,
+    };
+
+    // recorded as the time each successful backup last ran, so tracks added while this run is
+    // fetching are picked up by the next one
+    let started_at = chrono::Utc::now();
+    let mut last_runs = last_run::LastRuns::load(&profile).await?;
+
+    let jobs: Vec<_> = jobs
+        .into_iter()
+        .map(|(name, first_url)| {
+            let since = match args.since {
+                Some(filter::Since::Time(time)) => Some(time),
+                Some(filter::Since::LastRun) => last_runs.get(&name),
+                None => None,
+            };
+            (name, first_url, since)
+        })
+        .collect();
+
+    let total = jobs.len();
+    let mut results = futures::stream::iter(jobs)
+        .map(|(name, first_url, since)| {
+            let progress = &progress;
+            let span = info_span!("backup", file = %name);
+            let job = Arc::new(progress.job(&name));
+            let client = client.clone().with_observer(job.clone());
+            async move {
+                let result = write_backup(args, &client, name.clone(), first_url, since).await;
+                job.finish(result.as_ref().err());
+
+                (name, result)
+            }
+            .instrument(span)
+        })
+        .buffered(job_concurrency);
+    let mut failures = Vec::new();
+    let mut combined = Vec::new();
+
+    while let Some((job, result)) = results.next().await {
+        // one deleted or unreadable playlist shouldn't stop the rest from being backed up
+        let backup = match result {
+            Ok(v) => v,
+            Err(e) => {
+                error!(file = %job, "Failed to back up {job}: {e:#}");
+                metrics::backup_failed();
+                if let Some(summary) = summary.as_deref_mut() {
+                    summary.failed(&job, &e);
+                }
+                failures.push((job, e));
+                continue;
+            }
+        };
+        metrics::backup_succeeded(backup.uris.len());
+        let mut manifest = None;
+
+        if args.combine {
+            combined.extend(
+                backup.files.iter().map(|(name, written)| {
+                    (name.clone(), written.data.clone().unwrap_or_default())
+                }),
+            );
+        }
+
+        if let (Some(dir), Some(account_id)) = (&args.output, &account_id) {
+            for name in &backup.removed {
+                info!("Deleted {}, it's been replaced", dir.join(name).display());
+            }
+            for (name, _) in &backup.files {
+                info!("Wrote {}", dir.join(name).display());
+            }
+
+            let files = backup
+                .files
+                .iter()
+                .map(|(name, written)| manifest::ManifestFile::new(name.clone(), written))
+                .collect();
+            manifest = Some(manifest::update(dir, account_id, files, &backup.removed).await?);
+        }
+
+        if let (Some(destination), Some(destination_storage)) = (&args.upload, &destination_storage)
+        {
+            let mut files = Vec::new();
+            for (name, written) in &backup.files {
+                let data = match (&written.data, &args.output) {
+                    (Some(data), _) => data.clone(),
+                    (None, Some(dir)) => tokio::fs::read(dir.join(name))
+                        .await
+                        .with_context(|| format!("Failed to read back {name}"))?,
+                    (None, None) => unreachable!("a copy is kept of backups written to stdout"),
+                };
+                if let Some(keep) = args.upload_keep {
+                    storage::archive(destination_storage, name, &data, started_at, keep.get())
+                        .await
+                        .context("Failed to archive backup")?;
+                }
+
+                files.push((name.clone(), data));
+            }
+            files.extend(manifest.map(|v| (manifest::FILE_NAME.to_string(), v)));
+
+            for (name, data) in files {
+                info!("Uploading {name} to {destination}...");
+
+                destination_storage
+                    .put(&name, &mut data.as_slice())
+                    .await
+                    .context("Failed to upload backup")?;
+            }
+        }
+
+        last_runs.record(&profile, &job, started_at).await?;
+
+        if let Some(summary) = summary.as_deref_mut() {
+            let size = backup.files.iter().map(|(_, v)| v.size).sum();
+            summary
+                .succeeded(&profile, &job, &backup.uris, size)
+                .await?;
+        }
+    }
+
+    drop(results);
+    progress.finish();
+
+    if args.combine {
+        print_combined(&combined).context("Failed to print backups")?;
+    }
+
+    if !failures.is_empty() {
+        error!("{} of {total} backup(s) failed:", failures.len());
+        for (job, e) in &failures {
+            error!("  {job}: {e:#}");
+        }
+
+        // with a single backup the CLI can exit with the code of its error
+        if total == 1 && failures.len() == 1 {
+            let (_, e) = failures.remove(0);
+            return Err(e.context("1 backup(s) failed"));
+        }
+        anyhow::bail!("{} backup(s) failed", failures.len());
+    }
+
+    Ok(())
+}
+
+/// Prints `backups` as one JSON object, with each backup under its file name. Those that failed
+/// are left out, rather than leaving the object unreadable.
+fn print_combined(backups: &[(String, Vec<u8>)]) -> Result<()> {
+    let mut stdout = std::io::stdout().lock();
+
+    stdout.write_all(b"{")?;
+    for (i, (name, data)) in backups.iter().enumerate() {
+        if i > 0 {
+            stdout.write_all(b",")?;
+        }
+        write!(stdout, "{}:", serde_json::to_string(name)?)?;
+        stdout.write_all(data.trim_ascii_end())?;
+    }
+    stdout.write_all(b"}\n")?;
+
+    Ok(stdout.flush()?)
+}
+
+/// Files a backup wrote, along with the URIs of the tracks written to them.
+struct Backup {
+    /// Names and details of each file written, of which there's one unless the backup was split
+    /// into parts
+    files: Vec<(String, writer::Written)>,
+    /// Files of a previous run of the backup that were deleted, as they aren't part of it anymore
+    removed: Vec<String>,
+    uris: Vec<String>,
+}
+
+/// Streams a backup to the output directory (or stdout), compressing and encrypting it on the
+/// way, and returns the files it was written to along with the URIs of the tracks written.
+async fn write_backup(
+    args: &Args,
+    client: &api::Client,
+    name: String,
+    first_url: String,
+    since: Option<chrono::DateTime<chrono::Utc>>,
+) -> Result<Backup> {
+    // the first page is fetched before creating the file, so a playlist that can't be read
+    // doesn't leave an empty file behind
+    let mut pages = Paginator::<GetPlaylistTracksResponseItem>::new(client, first_url)
+        .pages()
+        .peekable();
+    if let Some(Err(_)) = Pin::new(&mut pages).peek().await {
+        pages.next().await.transpose()?;
+    }
+
+    let fields = args.fields();
+    let split = args.split_size.is_some() || args.split_tracks.is_some();
+
+    if let (true, Some(dir)) = (split, &args.output) {
+        anyhow::ensure!(
+            parts::can_split(args),
+            "Only JSON, NDJSON, CSV and template backups can be split, and not when grouped"
+        );
+        tokio::fs::create_dir_all(dir)
+            .await
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+
+        let mut exporter = parts::Writer::new(args, &fields, dir, &name);
+        let uris = write_tracks(
+            args,
+            client,
+            &fields,
+            pages,
+            since,
+            &mut exporter,
+            &mut std::io::sink(),
+        )
+        .await
+        .with_context(|| format!("Failed to write {name}"))?;
+        let committed = exporter.commit().await?;
+
+        return Ok(Backup {
+            files: committed.files,
+            removed: committed.removed,
+            uris,
+        });
+    }
+    anyhow::ensure!(
+        !split,
+        "Backups can only be split when they're written into a directory, pass --output"
+    );
+
+    let previous = match &args.output {
+        Some(dir) => parts::previous_files(args, dir, &name).await?,
+        None => Vec::new(),
+    };
+    let name = file_name(args, name);
+
+    let mut tmp = None;
+    let mut writer: Box<dyn writer::FinishWrite> = match &args.output {
+        Some(dir) => {
+            tokio::fs::create_dir_all(dir)
+                .await
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+
+            // written to a temporary file first, so a failed or interrupted run never replaces the
+            // previous backup with a truncated one
+            let tmp = tmp.insert(atomic::TmpFile::new(dir.join(&name)));
+            open_backup(args, tmp.path()).with_context(|| format!("Failed to create {name}"))?
+        }
+        // combined backups are printed together once they've all been written
+        None if args.combine => wrap_backup(args, writer::Sink::new(std::io::sink(), true))?,
+        None => wrap_backup(
+            args,
+            writer::Sink::new(std::io::stdout(), args.upload.is_some()),
+        )?,
+    };
+
+    let mut exporter = args.exporter(&fields)?;
+    let uris = write_tracks(
+        args,
+        client,
+        &fields,
+        pages,
+        since,
+        exporter.as_mut(),
+        &mut writer,
+    )
+    .await
+    .with_context(|| format!("Failed to write {name}"))?;
+    let written = writer.finish()?;
+
+    let mut removed = Vec::new();
+    if let (Some(tmp), Some(dir)) = (tmp, &args.output) {
+        tmp.commit().await?;
+        removed = parts::remove_stale(dir, previous, std::slice::from_ref(&name)).await?;
+    }
+
+    Ok(Backup {
+        files: vec![(name, written)],
+        removed,
+        uris,
+    })
+}
+
+/// Creates the file at `path` for writing a backup to, compressing and encrypting what's written
+/// if asked to.
+pub(crate) fn open_backup(args: &Args, path: &Path) -> Result<Box<dyn writer::FinishWrite>> {
+    let file = std::fs::File::create(path)?;
+
+    wrap_backup(
+        args,
+        writer::Sink::new(std::io::BufWriter::new(file), false),
+    )
+}
+
+/// Puts compression and encryption, if they were asked for, in front of `sink`.
+fn wrap_backup(
+    args: &Args,
+    sink: writer::Sink<impl Write + 'static>,
+) -> Result<Box<dyn writer::FinishWrite>> {
+    let mut writer: Box<dyn writer::FinishWrite> = Box::new(sink);
+
+    if !args.encrypt.is_empty() {
+        writer = encryption::writer(writer, &args.encrypt).context("Failed to encrypt backup")?;
+    }
+
+    if let Some(compression) = args.compress {
+        writer = compression.writer(writer)?;
+    }
+
+    Ok(writer)
+}
+
+/// Name `name` is written under once compressed and encrypted, if it is.
+pub(crate) fn file_name(args: &Args, mut name: String) -> String {
+    if let Some(compression) = args.compress {
+        name = format!("{name}.{}", compression.extension());
+    }
+
+    if !args.encrypt.is_empty() {
+        name = format!("{name}.{}", encryption::EXTENSION);
+    }
+
+    name
+}
+
+/// Writes every track from `pages` meeting the filters (and added since `since`) through
+/// `exporter` to `writer`, sorting them first if asked to. Returns the URIs of the tracks written.
+async fn write_tracks(
+    args: &Args,
+    client: &api::Client,
+    fields: &[output::Field],
+    mut pages: impl Stream<Item = Result<Page<GetPlaylistTracksResponseItem>, Error>> + Unpin,
+    since: Option<chrono::DateTime<chrono::Utc>>,
+    exporter: &mut dyn Exporter,
+    mut writer: &mut dyn Write,
+) -> Result<Vec<String>> {
+    let mut filters = args.filter.clone();
+    filters.extend(since.map(filter::Filter::AddedAfter));
+
+    anyhow::ensure!(
+        args.group_by.is_none() || args.format == output::Format::Json,
+        "Only JSON backups can be grouped, pass --format json"
+    );
+    let sidecar_dir = match (args.with_lyrics, &args.output) {
+        (Some(lyrics::Mode::Sidecar), Some(dir)) => Some(dir),
+        (Some(lyrics::Mode::Sidecar), None) => {
+            anyhow::bail!(
+                "Lyrics can only be written as sidecar files into a directory, pass --output"
+            )
+        }
+        _ => None,
+    };
+
+    let order = args.sort.map(|by| output::Order {
+        by,
+        reverse: args.reverse,
+    });
+
+    // tracks can only be sorted once they've all been fetched, otherwise they're written as each
+    // page arrives
+    let mut buffered = Vec::new();
+    let mut uris = Vec::new();
+
+    let annotations = if fields.iter().any(|v| {
+        matches!(
+            v,
+            output::Field::Tags | output::Field::Rating | output::Field::Note
+        )
+    }) {
+        let profile = profile::Profile::new(&args.profile)?;
+        Some(annotations::Annotations::load(&profile).await?)
+    } else {
+        None
+    };
+
+    exporter.begin(&mut writer)?;
+
+    while let Some(page) = pages.next().await {
+        let page = page?;
+        let count = page.items.len() as u64;
+        debug!(
+            offset = page.offset,
+            count,
+            total = page.total,
+            "Fetched page"
+        );
+
+        let mut outputs = to_outputs(args, client, fields, page.items).await?;
+        if let Some(annotations) = &annotations {
+            outputs.iter_mut().for_each(|v| annotations.apply(v));
+        }
+        outputs.retain(|output| filters.iter().all(|v| v.matches(output)));
+
+        if let Some(dir) = sidecar_dir {
+            for output in &outputs {
+                if let (Some(id), Some(lyrics)) = (&output.id, &output.lyrics) {
+                    lyrics::write_sidecar(dir, id, lyrics).await?;
+                }
+            }
+        }
+        uris.extend(outputs.iter().map(|v| v.uri.clone()));
+
+        if order.is_some() {
+            buffered.extend(outputs);
+        } else {
+            for output in &outputs {
+                exporter.write_item(&mut writer, output)?;
+                client.observe(|v| v.on_item(output));
+            }
+        }
+    }
+
+    if let Some(order) = order {
+        order.sort(&mut buffered);
+        for output in &buffered {
+            exporter.write_item(&mut writer, output)?;
+            client.observe(|v| v.on_item(output));
+        }
+    }
+
+    exporter.finish(&mut writer)?;
+
+    Ok(uris)
+}
+
+async fn to_outputs(
+    args: &Args,
+    client: &api::Client,
+    fields: &[output::Field],
+    items: Vec<GetPlaylistTracksResponseItem>,
+) -> Result<Vec<Output>> {
+    let artists = if fields.contains(&output::Field::Genres) {
+        let ids = items
+            .iter()
+            .flat_map(|v| &v.track.artists)
+            .filter_map(|v| v.id.as_deref());
+
+        Some(
+            client
+                .batch_get::<GetArtistResponse>("artists", "artists", ids, 50)
+                .await
+                .context("Failed to fetch artists")?,
+        )
+    } else {
+        None
+    };
+
+    let users = if fields.contains(&output::Field::AddedByName) {
+        let ids = items
+            .iter()
+            .filter_map(|v| v.added_by.as_ref())
+            .map(|v| v.id.as_str())
+            .filter(|v| !v.is_empty());
+
+        client
+            .get_each::<GetUserResponse>("users", ids)
+            .await
+            .context("Failed to fetch users")?
+    } else {
+        HashMap::new()
+    };
+
+    let mbids = if fields.contains(&output::Field::RecordingMbid)
+        || fields.contains(&output::Field::ReleaseMbid)
+    {
+        let queries: Vec<_> = items
+            .iter()
+            .map(|v| musicbrainz::Query {
+                uri: &v.track.uri,
+                isrc: v
+                    .track
+                    .external_ids
+                    .as_ref()
+                    .and_then(|v| v.isrc.as_deref()),
+                name: &v.track.name,
+                artist: v.track.artists.first().map(|v| v.name.as_str()),
+                album: &v.track.album.name,
+            })
+            .collect();
+
+        musicbrainz::lookup(client, &queries).await
+    } else {
+        Vec::new()
+    };
+    let mut mbids = mbids.into_iter();
+
+    let lyrics = if fields.contains(&output::Field::Lyrics)
+        || args.with_lyrics == Some(lyrics::Mode::Sidecar)
+    {
+        let queries: Vec<_> = items
+            .iter()
+            .map(|v| lyrics::Query {
+                id: v.track.id.as_deref(),
+                name: &v.track.name,
+                artist: v.track.artists.first().map(|v| v.name.as_str()),
+                album: &v.track.album.name,
+                duration_ms: v.track.duration_ms,
+            })
+            .collect();
+
+        lyrics::lookup(client, &args.lyrics_provider, &queries).await
+    } else {
+        Vec::new()
+    };
+    let mut lyrics = lyrics.into_iter();
+
+    Ok(items
+        .into_iter()
+        .map(|v| {
+            let mbids = mbids.next().unwrap_or_default();
+            let genres = artists.as_ref().map(|artists| {
+                let mut genres: Vec<_> = v
+                    .track
+                    .artists
+                    .iter()
+                    .filter_map(|v| artists.get(v.id.as_deref()?))
+                    .flat_map(|v| v.genres.iter().cloned())
+                    .collect();
+                genres.sort();
+                genres.dedup();
+                genres
+            });
+
+            let output = Output::from(v);
+            let added_by_name = output
+                .added_by
+                .as_ref()
+                .and_then(|v| users.get(v)?.display_name.clone());
+
+            Output {
+                genres,
+                added_by_name,
+                recording_mbid: mbids.recording,
+                release_mbid: mbids.release,
+                lyrics: lyrics.next().flatten(),
+                ..output
+            }
+        })
+        .collect())
+}
+
+/// Tracks of one playlist, or of liked songs.
+pub struct TrackSource {
+    /// Playlist ID, missing for liked songs
+    pub(crate) id: Option<String>,
+    pub(crate) name: String,
+    /// Snapshot of the playlist from before its tracks were fetched, if it's in the user's library
+    pub(crate) snapshot_id: Option<String>,
+    pub(crate) items: Vec<GetPlaylistTracksResponseItem>,
+}
+
+/// Fetches every item of a paginated endpoint.
+pub(crate) async fn fetch_all<T: DeserializeOwned + Send + 'static>(
+    client: &api::Client,
+    first_url: String,
+) -> Result<Vec<T>> {
+    Ok(Paginator::new(client, first_url).try_collect().await?)
+}
+
+/// Fetches the tracks in liked songs with `liked` and the playlists given by `ids` (or every
+/// playlist with `all`), for commands reporting on the library rather than backing it up.
+pub(crate) async fn fetch_sources(
+    client: &api::Client,
+    args: &Args,
+    ids: &[String],
+    all: bool,
+    liked: bool,
+) -> Result<Vec<TrackSource>> {
+    // for naming playlists given by ID, and finding every one with `all`
+    let playlists: Vec<GetPlaylistsResponseItem> = if all || !ids.is_empty() {
+        fetch_all(client, args.api.first_page_url("me/playlists", 50))
+            .await
+            .context("Failed to fetch playlists")?
+    } else {
+        Vec::new()
+    };
+    let by_id: HashMap<_, _> = playlists.iter().map(|v| (&v.id, v)).collect();
+
+    let mut sources = Vec::new();
+    if liked {
+        sources.push((
+            None,
+            "Liked songs".to_string(),
+            None,
+            args.api.first_tracks_page_url("me/tracks", 50),
+        ));
+    }
+    let ids = match all {
+        true => playlists.iter().map(|v| v.id.clone()).collect(),
+        false => ids.to_vec(),
+    };
+    for id in ids {
+        let playlist = by_id.get(&id);
+        sources.push((
+            Some(id.clone()),
+            playlist.map_or_else(|| id.clone(), |v| v.name.clone()),
+            playlist.and_then(|v| v.snapshot_id.clone()),
+            args.api
+                .first_tracks_page_url(&format!("playlists/{id}/tracks"), 100),
+        ));
+    }
+
+    futures::stream::iter(sources)
+        .map(|(id, name, snapshot_id, url)| async move {
+            let items = fetch_all(client, url)
+                .await
+                .with_context(|| format!("Failed to fetch tracks of {name}"))?;
+
+            Ok::<_, anyhow::Error>(TrackSource {
+                id,
+                name,
+                snapshot_id,
+                items,
+            })
+        })
+        .buffered(client.concurrency())
+        .try_collect()
+        .await
+}
+
+/// Fetches every playlist in the user's library, along with the user's ID for telling which of
+/// them are theirs.
+pub(crate) async fn fetch_playlists(
+    args: &Args,
+) -> Result<(String, Vec<GetPlaylistsResponseItem>)> {
+    let profile = profile::Profile::new(&args.profile)?;
+    let client = build_client(&profile, args).await?;
+
+    let user = fetch_current_user(&client).await?;
+    let playlists =
+        fetch_all::<GetPlaylistsResponseItem>(&client, args.api.first_page_url("me/playlists", 50))
+            .await
+            .context("Failed to fetch playlists")?;
+
+    Ok((user.id, playlists))
+}
+
+/// Prints the user's playlists as JSON or a table.
+async fn list_playlists(args: &Args) -> Result<()> {
+    let (user_id, playlists) = fetch_playlists(args).await?;
+
+    let terminal = std::io::stdout().is_terminal();
+
+    match args.format {
+        output::Format::Json => {
+            let annotations = if args.with_annotations {
+                let profile = profile::Profile::new(&args.profile)?;
+                Some(annotations::Annotations::load(&profile).await?)
+            } else {
+                None
+            };
+
+            let playlists: Vec<_> = playlists
+                .iter()
+                .map(|v| OutputPlaylist {
+                    annotation: annotations
+                        .as_ref()
+                        .and_then(|a| a.get(&format!("spotify:playlist:{}", v.id))),
+                    ..OutputPlaylist::for_user(v, &user_id)
+                })
+                .collect();
+
+            let json = if args.pretty || terminal {
+                serde_json::to_string_pretty(&playlists)?
+            } else {
+                serde_json::to_string(&playlists)?
+            };
+            println!("{json}");
+        }
+        output::Format::Table => {
+            let mut table = output::table(
+                &["ID", "Name", "Owner", "Ownership", "Tracks", "Snapshot"],
+                terminal,
+                &[0, 4],
+            );
+
+            for playlist in &playlists {
+                output::add_row(
+                    &mut table,
+                    [
+                        playlist.id.as_str(),
+                        &playlist.name,
+                        playlist
+                            .owner
+                            .as_ref()
+                            .map(|v| v.name())
+                            .unwrap_or_default(),
+                        playlist.ownership(&user_id).name(),
+                        &playlist.tracks.total.to_string(),
+                        playlist.snapshot_id.as_deref().unwrap_or_default(),
+                    ],
+                );
+            }
+
+            println!("{table}");
+        }
+        _ => {
+            anyhow::bail!("Playlists can only be listed as JSON or a table")
+        }
+    }
+
+    Ok(())
+}
+
+async fn list_profiles(args: &Args) -> Result<()> {
+    let active = profile::Profile::new(&args.profile)?;
+
+    for name in profile::list().await? {
+        let marker = if name == active.name() { "*" } else { " " };
+        println!("{marker} {name}");
+    }
+
+    Ok(())
+}
+
+async fn logout(args: &Args) -> Result<()> {
+    let profile = profile::Profile::new(&args.profile)?;
+    let removed = authentication::logout(&profile, &args.auth).await?;
+
+    if removed.is_empty() {
+        info!("No credentials stored for profile {}", profile.name());
+    }
+
+    for v in removed {
+        info!("Removed {v}");
+    }
+
+    Ok(())
+}
+
+async fn auth_status(args: &Args) -> Result<()> {
+    let profile = profile::Profile::new(&args.profile)?;
+
+    println!("Profile:    {}", profile.name());
+
+    if args.auth.refresh_token.is_some() {
+        println!("Token:      from SPOTIFY_REFRESH_TOKEN, not persisted");
+    } else {
+        print_stored_token(&profile, args).await?;
+    }
+
+    let client = build_client(&profile, args).await?;
+    let user = fetch_current_user(&client).await?;
+
+    println!(
+        "User:       {} ({})",
+        user.display_name.as_deref().unwrap_or("-"),
+        user.id
+    );
+
+    Ok(())
+}
+
+async fn print_stored_token(profile: &profile::Profile, args: &Args) -> Result<()> {
+    let Some(token) = authentication::read_token(profile, &args.auth).await? else {
+        println!("Status:     not authenticated");
+        anyhow::bail!("Profile {} is not authenticated", profile.name());
+    };
+
+    let expires_at = chrono::DateTime::from_timestamp(token.expires_at() as i64, 0)
+        .context("Invalid token expiry")?;
+    let expiry_note = if expires_at < chrono::Utc::now() {
+        " (expired, will be refreshed on next use)"
+    } else {
+        ""
+    };
+
+    println!(
+        "Client ID:  {}",
+        token.client_id().unwrap_or(authentication::CLIENT_ID)
+    );
+    println!("Scopes:     {}", token.scope().unwrap_or("unknown"));
+    println!("Expires at: {}{expiry_note}", expires_at.to_rfc3339());
+
+    Ok(())
+}
+
+async fn export_refresh_token(args: &Args) -> Result<()> {
+    let profile = profile::Profile::new(&args.profile)?;
+    let refresh_token = authentication::export_refresh_token(&profile, &args.auth).await?;
+
+    eprintln!(
+        "Set SPOTIFY_REFRESH_TOKEN to the following to run without any stored state, keep it \
+         secret as it grants access to your account:"
+    );
+    println!("{refresh_token}");
+
+    Ok(())
+}
+
+async fn cache_stats(args: &Args) -> Result<()> {
+    let profile = profile::Profile::new(&args.profile)?;
+
+    let (mut files, mut size) = (0, 0);
+    if let Ok(mut entries) = tokio::fs::read_dir(profile.dir().join(api::CACHE_DIR)).await {
+        while let Some(entry) = entries.next_entry().await? {
+            files += 1;
+            size += entry.metadata().await?.len();
+        }
+    }
+
+    println!("HTTP responses: {files} ({size} bytes)");
+
+    let metadata_cache =
+        metadata_cache::MetadataCache::open(&profile.dir().join(metadata_cache::DIR))?;
+    let (counts, size) = metadata_cache.stats()?;
+
+    for (kind, count) in counts {
+        println!("{kind}: {count}");
+    }
+    println!("Metadata cache size: {size} bytes");
+
+    Ok(())
+}
+
+async fn cache_clear(args: &Args) -> Result<()> {
+    let profile = profile::Profile::new(&args.profile)?;
+
+    match tokio::fs::remove_dir_all(profile.dir().join(api::CACHE_DIR)).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e).context("Failed to clear HTTP cache"),
+    }
+
+    metadata_cache::MetadataCache::open(&profile.dir().join(metadata_cache::DIR))?.clear()?;
+
+    info!("Cleared caches of profile {}", profile.name());
+
+    Ok(())
+}
+
+async fn mangen(dir: &Path) -> Result<()> {
+    tokio::fs::create_dir_all(dir)
+        .await
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    clap_mangen::generate_to(Args::command(), dir).context("Failed to write man pages")?;
+    info!("Wrote man pages to {}", dir.display());
+
+    Ok(())
+}
+
+async fn verify(dir: &Path) -> Result<()> {
+    let failures = manifest::verify(dir).await?;
+    anyhow::ensure!(failures == 0, "{failures} file(s) failed verification");
+
+    Ok(())
+}
+
+async fn cat(args: &Args, path: &Path) -> Result<()> {
+    let data = read_backup(path, &args.identity).await?;
+    std::io::stdout().write_all(&data)?;
+
+    Ok(())
+}
+
+/// Reads a backup written by any previous run, transparently decrypting and decompressing it, and
+/// joining the parts of one that was split back together.
+pub(crate) async fn read_backup(path: &Path, identities: &[PathBuf]) -> Result<Vec<u8>> {
+    match parts::find_index(path).await? {
+        Some(index) => parts::read(&index, identities).await,
+        None => read_file(path, identities).await,
+    }
+}
+
+/// Reads a single backup file, decrypting and decompressing it.
+pub(crate) async fn read_file(path: &Path, identities: &[PathBuf]) -> Result<Vec<u8>> {
+    let mut data = tokio::fs::read(path)
+        .await
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+
+    if encryption::is_encrypted(&data) {
+        data = encryption::decrypt(&data, identities)
+            .with_context(|| format!("Failed to decrypt {}", path.display()))?;
+    }
+
+    compression::decompress(data).with_context(|| format!("Failed to read {}", path.display()))
+}
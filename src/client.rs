@@ -0,0 +1,178 @@
+use std::{io::Write, sync::Arc};
+
+use anyhow::Context;
+use clap::Parser;
+use futures::{Stream, TryStreamExt};
+
+use crate::{
+    api::{self, ApiArgs},
+    authentication::{self, AuthArgs},
+    profile::Profile,
+    Error, Exporter, GetCurrentUserResponse, GetPlaylistTracksResponseItem,
+    GetPlaylistsResponseItem, Observer, Output, Paginator,
+};
+
+/// Scopes every token a [`SpotifyClient`] is authenticated with is granted.
+const SCOPES: &[&str] = &[
+    authentication::scope::PLAYLIST_READ_PRIVATE,
+    authentication::scope::USER_LIBRARY_READ,
+];
+
+/// Client for reading a user's library from the Spotify API, for programs embedding the crate
+/// rather than running the binary. Requests are retried, rate limited and cached in the profile's
+/// state dir just as they are for backups.
+#[derive(Clone)]
+pub struct SpotifyClient {
+    client: api::Client,
+    api: ApiArgs,
+}
+
+/// Options read the way the command line reads them when none are given, from the environment or
+/// their defaults.
+#[derive(Parser)]
+pub(crate) struct Options {
+    #[command(flatten)]
+    pub auth: AuthArgs,
+    #[command(flatten)]
+    pub api: ApiArgs,
+}
+
+impl Options {
+    pub fn from_env() -> Result<Self, Error> {
+        Ok(Self::try_parse_from(["spotify-backup"])
+            .context("Invalid options in the environment")?)
+    }
+}
+
+impl SpotifyClient {
+    /// Authenticates as the user of `profile` with the options set in the environment (eg.
+    /// `SPOTIFY_REFRESH_TOKEN`), or the defaults of the command line otherwise.
+    pub async fn from_env(profile: &Profile) -> Result<Self, Error> {
+        let options = Options::from_env()?;
+
+        Self::authenticate(profile, &options.auth, options.api).await
+    }
+
+    /// Authenticates as the user of `profile`, using their stored token or `auth.refresh_token`,
+    /// and logging in through the browser if there's neither.
+    pub async fn authenticate(
+        profile: &Profile,
+        auth: &AuthArgs,
+        api: ApiArgs,
+    ) -> Result<Self, Error> {
+        let token = authentication::authenticate(profile, auth, SCOPES)
+            .await
+            .map_err(|e| Error::Auth(e.into()))?;
+
+        Self::with_token(&token, profile, api)
+    }
+
+    /// Makes requests with an access token obtained elsewhere, which has to have been granted the
+    /// `playlist-read-private` and `user-library-read` scopes. Responses are cached in `profile`'s
+    /// state dir.
+    pub fn with_token(token: &str, profile: &Profile, api: ApiArgs) -> Result<Self, Error> {
+        Ok(Self {
+            client: crate::client_with_token(token, &api, profile)?,
+            api,
+        })
+    }
+
+    /// Reports the pages fetched, retries, rate limiting and tracks exported to `observer`, eg.
+    /// for showing progress. Clones of the client made before this aren't observed.
+    pub fn with_observer(self, observer: Arc<dyn Observer>) -> Self {
+        Self {
+            client: self.client.with_observer(observer),
+            ..self
+        }
+    }
+
+    /// The user the client is authenticated as.
+    pub async fn me(&self) -> Result<GetCurrentUserResponse, Error> {
+        Ok(crate::fetch_current_user(&self.client).await?)
+    }
+
+    /// Every playlist in the user's library, including those they follow.
+    pub async fn playlists(&self) -> Result<Vec<GetPlaylistsResponseItem>, Error> {
+        self.playlists_stream().try_collect().await
+    }
+
+    /// Every playlist in the user's library as they're fetched, see [`Paginator`].
+    pub fn playlists_stream(&self) -> Paginator<GetPlaylistsResponseItem> {
+        Paginator::new(&self.client, self.api.first_page_url("me/playlists", 50))
+    }
+
+    /// Tracks of the playlist with the ID `id`, in order. Convert them with
+    /// [`crate::Output::from`] for the fields written to backups.
+    pub async fn playlist_tracks(
+        &self,
+        id: &str,
+    ) -> Result<Vec<GetPlaylistTracksResponseItem>, Error> {
+        self.playlist_tracks_stream(id).try_collect().await
+    }
+
+    /// Tracks of the playlist with the ID `id` as they're fetched, see [`Paginator`].
+    pub fn playlist_tracks_stream(&self, id: &str) -> Paginator<GetPlaylistTracksResponseItem> {
+        let url = self
+            .api
+            .first_tracks_page_url(&format!("playlists/{id}/tracks"), 100);
+
+        Paginator::new(&self.client, url)
+    }
+
+    /// The user's liked songs, most recently liked first.
+    pub async fn liked_tracks(&self) -> Result<Vec<GetPlaylistTracksResponseItem>, Error> {
+        self.liked_tracks_stream().try_collect().await
+    }
+
+    /// The user's liked songs as they're fetched, see [`Paginator`].
+    pub fn liked_tracks_stream(&self) -> Paginator<GetPlaylistTracksResponseItem> {
+        Paginator::new(
+            &self.client,
+            self.api.first_tracks_page_url("me/tracks", 50),
+        )
+    }
+
+    /// Writes the tracks of the playlist with the ID `id` to `writer` through `exporter`, a page
+    /// at a time as they're fetched, eg. with [`crate::export::JsonExporter`] for a backup like
+    /// the ones the CLI writes.
+    pub async fn export_playlist(
+        &self,
+        id: &str,
+        exporter: &mut dyn Exporter,
+        writer: &mut dyn Write,
+    ) -> Result<(), Error> {
+        export(
+            &self.client,
+            self.playlist_tracks_stream(id),
+            exporter,
+            writer,
+        )
+        .await
+    }
+
+    /// Writes the user's liked songs to `writer` through `exporter` as they're fetched.
+    pub async fn export_liked_tracks(
+        &self,
+        exporter: &mut dyn Exporter,
+        writer: &mut dyn Write,
+    ) -> Result<(), Error> {
+        export(&self.client, self.liked_tracks_stream(), exporter, writer).await
+    }
+}
+
+async fn export(
+    client: &api::Client,
+    mut tracks: impl Stream<Item = Result<GetPlaylistTracksResponseItem, Error>> + Unpin,
+    exporter: &mut dyn Exporter,
+    writer: &mut dyn Write,
+) -> Result<(), Error> {
+    exporter.begin(writer)?;
+    while let Some(track) = tracks.try_next().await? {
+        let output = Output::from(track);
+        exporter.write_item(writer, &output)?;
+        client.observe(|v| v.on_item(&output));
+    }
+    exporter.finish(writer)?;
+
+    Ok(())
+}
@@ -0,0 +1,160 @@
+use std::{collections::HashMap, io::Write, path::PathBuf};
+
+use serde::Serialize;
+
+use crate::{
+    dupes,
+    map::{self, Candidate, Track},
+};
+
+/// A file of a local music collection, with what its tags say.
+pub struct File {
+    pub path: PathBuf,
+    pub title: String,
+    pub artists: Vec<String>,
+    pub album: Option<String>,
+    pub duration_ms: Option<u64>,
+    pub recording_mbid: Option<String>,
+    pub isrc: Option<String>,
+}
+
+/// How a track was found in the collection, from most to least certain.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchedBy {
+    Mbid,
+    Isrc,
+    Metadata,
+}
+
+impl MatchedBy {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Mbid => "MBID",
+            Self::Isrc => "ISRC",
+            Self::Metadata => "metadata",
+        }
+    }
+}
+
+/// The file a track was found to be.
+#[derive(Clone, Copy)]
+pub struct Match<'a> {
+    /// Position of the file in those the collection was made from
+    pub index: usize,
+    pub file: &'a File,
+    pub by: MatchedBy,
+    /// How likely the file is the same recording, from 0 to 1, 1 for those matched by an ID
+    pub confidence: f64,
+}
+
+/// A local music collection, indexed for looking tracks up.
+pub struct Collection {
+    files: Vec<File>,
+    by_mbid: HashMap<String, usize>,
+    by_isrc: HashMap<String, usize>,
+    /// Files by their title without versions like "(Remastered)", for matching by metadata
+    by_title: HashMap<String, Vec<usize>>,
+}
+
+impl Collection {
+    pub fn new(files: Vec<File>) -> Self {
+        let mut by_mbid = HashMap::new();
+        let mut by_isrc = HashMap::new();
+        let mut by_title: HashMap<_, Vec<_>> = HashMap::new();
+
+        for (i, file) in files.iter().enumerate() {
+            if let Some(mbid) = &file.recording_mbid {
+                by_mbid.entry(mbid.clone()).or_insert(i);
+            }
+            if let Some(isrc) = &file.isrc {
+                by_isrc.entry(isrc.to_uppercase()).or_insert(i);
+            }
+            if let Some(title) = dupes::normalize_title(&file.title) {
+                by_title.entry(title).or_default().push(i);
+            }
+        }
+
+        Self {
+            files,
+            by_mbid,
+            by_isrc,
+            by_title,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Finds `track` by its MusicBrainz recording ID, then its ISRC, then its title, artists and
+    /// length, keeping matches by the latter scoring at least `min_confidence`.
+    pub fn find(&self, track: &Track, min_confidence: f64) -> Option<Match<'_>> {
+        let by_mbid = track
+            .recording_mbid
+            .as_ref()
+            .and_then(|v| self.by_mbid.get(v));
+        if let Some(&index) = by_mbid {
+            return Some(self.found(index, MatchedBy::Mbid, 1.0));
+        }
+
+        let by_isrc = track
+            .isrc
+            .as_ref()
+            .and_then(|v| self.by_isrc.get(&v.to_uppercase()));
+        if let Some(&index) = by_isrc {
+            return Some(self.found(index, MatchedBy::Isrc, 1.0));
+        }
+
+        let title = dupes::normalize_title(&track.name)?;
+        self.by_title
+            .get(&title)?
+            .iter()
+            .map(|&index| {
+                let file = &self.files[index];
+                let candidate = Candidate {
+                    id: index.to_string(),
+                    title: file.title.clone(),
+                    artists: file.artists.clone(),
+                    album: file.album.clone(),
+                    album_id: None,
+                    duration_ms: file.duration_ms,
+                    by_isrc: false,
+                };
+                (index, map::confidence(track, &candidate))
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .filter(|v| v.1 >= min_confidence)
+            .map(|(index, confidence)| self.found(index, MatchedBy::Metadata, confidence))
+    }
+
+    fn found(&self, index: usize, by: MatchedBy, confidence: f64) -> Match<'_> {
+        Match {
+            index,
+            file: &self.files[index],
+            by,
+            confidence,
+        }
+    }
+}
+
+/// Writes an extended M3U playlist of `entries`, each track's length and name taken from the
+/// backup rather than the file.
+pub fn write_m3u<'a>(
+    out: &mut impl Write,
+    entries: impl IntoIterator<Item = (&'a Track, &'a File)>,
+) -> std::io::Result<()> {
+    writeln!(out, "#EXTM3U")?;
+    for (track, file) in entries {
+        let seconds = track.duration_ms.map_or(-1, |v| (v / 1000) as i64);
+        writeln!(
+            out,
+            "#EXTINF:{seconds},{} - {}\n{}",
+            track.artists.join(", "),
+            track.name,
+            file.path.display()
+        )?;
+    }
+
+    Ok(())
+}
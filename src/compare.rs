@@ -0,0 +1,171 @@
+use std::{collections::HashSet, io::IsTerminal, path::Path};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{api, cli::Args, migrate, output};
+
+/// Tracks in either or both of two playlists.
+#[derive(Serialize, Debug)]
+struct Comparison {
+    a: String,
+    b: String,
+    both: Vec<Track>,
+    only_a: Vec<Track>,
+    only_b: Vec<Track>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct Track {
+    name: String,
+    artists: Vec<String>,
+    uri: String,
+}
+
+/// Prints the tracks that two playlists have in common and those only in one of them, matched by
+/// URI. Each of `a` and `b` is a backup file, or otherwise the ID of a playlist to fetch.
+pub async fn run(args: &Args, a: &str, b: &str) -> Result<()> {
+    let mut client = None;
+    let (a_name, a) = load(args, &mut client, a).await?;
+    let (b_name, b) = load(args, &mut client, b).await?;
+
+    let a_uris: HashSet<_> = a.iter().map(|v| &v.uri).collect();
+    let b_uris: HashSet<_> = b.iter().map(|v| &v.uri).collect();
+
+    let comparison = Comparison {
+        both: dedup(a.iter().filter(|v| b_uris.contains(&v.uri))),
+        only_a: dedup(a.iter().filter(|v| !b_uris.contains(&v.uri))),
+        only_b: dedup(b.iter().filter(|v| !a_uris.contains(&v.uri))),
+        a: a_name,
+        b: b_name,
+    };
+
+    print(args, &comparison)
+}
+
+/// Tracks of the backup file at `source`, or of the playlist with `source` as its ID, link or URI, along with
+/// the playlist's name. The client is only built the first time a playlist is fetched.
+async fn load(
+    args: &Args,
+    client: &mut Option<api::Client>,
+    source: &str,
+) -> Result<(String, Vec<Track>)> {
+    let path = Path::new(source);
+
+    if tokio::fs::metadata(path).await.is_ok_and(|v| v.is_file()) {
+        let data = crate::cli::read_backup(path, &args.identity).await?;
+        let tracks = migrate::tracks(&data)
+            .with_context(|| format!("Failed to read tracks from {source}"))?
+            .with_context(|| format!("{source} isn't a JSON backup"))?;
+
+        let tracks = tracks
+            .iter()
+            .enumerate()
+            .map(|(i, track)| {
+                Ok(Track {
+                    name: track["name"].as_str().unwrap_or_default().to_string(),
+                    artists: track["artists"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .filter_map(Value::as_str)
+                        .map(str::to_string)
+                        .collect(),
+                    uri: track["uri"]
+                        .as_str()
+                        .with_context(|| {
+                            format!(
+                                "Track {i} of {source} has no URI, back it up with uri in --fields"
+                            )
+                        })?
+                        .to_string(),
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        return Ok((source.to_string(), tracks));
+    }
+
+    let client = match client {
+        Some(client) => client,
+        None => {
+            let profile = crate::profile::Profile::new(&args.profile)?;
+            client.insert(crate::cli::build_client(&profile, args).await?)
+        }
+    };
+
+    let id = crate::link::parse_playlist_id(source)?;
+    let source = crate::cli::fetch_sources(client, args, &[id], false, false)
+        .await?
+        .pop()
+        .context("Playlist wasn't fetched")?;
+
+    let tracks = source
+        .items
+        .into_iter()
+        .map(|v| Track {
+            artists: v.track.artists.into_iter().map(|v| v.name).collect(),
+            name: v.track.name,
+            uri: v.track.uri,
+        })
+        .collect();
+
+    Ok((source.name, tracks))
+}
+
+/// `tracks` with only the first of any that are in the playlist more than once.
+fn dedup<'a>(tracks: impl Iterator<Item = &'a Track>) -> Vec<Track> {
+    let mut seen = HashSet::new();
+    tracks.filter(|v| seen.insert(&v.uri)).cloned().collect()
+}
+
+fn print(args: &Args, comparison: &Comparison) -> Result<()> {
+    let terminal = std::io::stdout().is_terminal();
+
+    match args.format {
+        output::Format::Json => {
+            let json = if args.pretty || terminal {
+                serde_json::to_string_pretty(comparison)?
+            } else {
+                serde_json::to_string(comparison)?
+            };
+            println!("{json}");
+        }
+        output::Format::Table => {
+            let only_a = format!("Only in {}", comparison.a);
+            let only_b = format!("Only in {}", comparison.b);
+
+            let mut summary = output::table(&["Where", "Tracks"], terminal, &[1]);
+            for (name, tracks) in [
+                ("In both", &comparison.both),
+                (only_a.as_str(), &comparison.only_a),
+                (only_b.as_str(), &comparison.only_b),
+            ] {
+                output::add_row(&mut summary, [name, &tracks.len().to_string()]);
+            }
+            println!("{summary}");
+
+            if !comparison.only_a.is_empty() || !comparison.only_b.is_empty() {
+                let mut table = output::table(&["In", "Track", "Artists", "URI"], terminal, &[3]);
+                for (name, tracks) in [
+                    (comparison.a.as_str(), &comparison.only_a),
+                    (comparison.b.as_str(), &comparison.only_b),
+                ] {
+                    for track in tracks {
+                        output::add_row(
+                            &mut table,
+                            [name, &track.name, &track.artists.join(", "), &track.uri],
+                        );
+                    }
+                }
+                println!("\n{table}");
+            }
+        }
+        _ => {
+            anyhow::bail!("Playlists can only be compared as JSON or a table")
+        }
+    }
+
+    Ok(())
+}
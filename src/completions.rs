@@ -0,0 +1,98 @@
+use std::{io::Write, path::PathBuf};
+
+use anyhow::{Context, Result};
+use clap::{ArgMatches, CommandFactory};
+use clap_complete::{env::Shells, CompletionCandidate};
+use tokio::runtime::Runtime;
+
+use crate::config::Config;
+
+/// Environment variable the shell sets when calling back into the binary for completions.
+pub const VAR: &str = "COMPLETE";
+
+const BIN: &str = env!("CARGO_PKG_NAME");
+
+/// Shells completion scripts can be generated for.
+pub const SHELLS: &[&str] = &["bash", "elvish", "fish", "powershell", "zsh"];
+
+/// Prints the script registering completions with the given shell. Rather than listing every
+/// option up front, the script calls back into the binary so values like profile names can be
+/// completed from what's on disk.
+pub fn print(shell: &str) -> Result<()> {
+    let shells = Shells::builtins();
+    let completer = shells
+        .completer(shell)
+        .with_context(|| format!("Unsupported shell {shell}"))?;
+
+    let mut buf = Vec::new();
+    completer
+        .write_registration(VAR, BIN, BIN, BIN, &mut buf)
+        .context("Failed to generate completions")?;
+    std::io::stdout()
+        .write_all(&buf)
+        .context("Failed to write completions")
+}
+
+/// Names of every profile that's been created or is set up in the config file.
+pub fn profiles() -> Vec<CompletionCandidate> {
+    let Some((runtime, matches)) = setup() else {
+        return Vec::new();
+    };
+
+    runtime.block_on(async {
+        let mut names = crate::profile::list().await.unwrap_or_default();
+        if let Ok(config) = load_config(&matches).await {
+            names.extend(config.profiles.into_keys());
+        }
+
+        names.sort();
+        names.dedup();
+        names.into_iter().map(CompletionCandidate::new).collect()
+    })
+}
+
+/// Names of the playlist aliases in the config file, described by the playlist they stand for.
+pub fn aliases() -> Vec<CompletionCandidate> {
+    let Some((runtime, matches)) = setup() else {
+        return Vec::new();
+    };
+
+    runtime.block_on(async {
+        let Ok(config) = load_config(&matches).await else {
+            return Vec::new();
+        };
+
+        config
+            .aliases
+            .into_iter()
+            .map(|(name, playlist)| CompletionCandidate::new(name).help(Some(playlist.into())))
+            .collect()
+    })
+}
+
+/// Starts a runtime for reading what's on disk, and parses as much of the command line being
+/// completed as can be, using the state dir it names like [`crate::cli::start`] does.
+fn setup() -> Option<(Runtime, ArgMatches)> {
+    // completions are answered before the main runtime is started, and have no way of reporting
+    // errors, so anything that can't be read is left out
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .ok()?;
+
+    // the shell passes the words being completed after --
+    let words = std::env::args_os().skip_while(|v| v != "--").skip(1);
+    let matches = crate::cli::Args::command()
+        .ignore_errors(true)
+        .try_get_matches_from(words)
+        .ok()?;
+    if let Some(dir) = matches.get_one::<PathBuf>("state_dir") {
+        crate::profile::set_state_dir(dir).ok()?;
+    }
+
+    Some((runtime, matches))
+}
+
+async fn load_config(matches: &ArgMatches) -> Result<Config> {
+    Config::load(matches.get_one::<PathBuf>("config").map(PathBuf::as_path)).await
+}
@@ -0,0 +1,82 @@
+use std::io::{self, Read};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::writer::{FinishWrite, Written};
+
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+#[derive(ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Zstd,
+    Gzip,
+}
+
+impl Compression {
+    /// Extension appended to the names of backups compressed with this algorithm.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Zstd => "zst",
+            Self::Gzip => "gz",
+        }
+    }
+
+    /// Wraps `inner` in a writer compressing everything written to it.
+    pub fn writer(self, inner: Box<dyn FinishWrite>) -> Result<Box<dyn FinishWrite>> {
+        Ok(match self {
+            Self::Zstd => Box::new(
+                zstd::stream::write::Encoder::new(inner, 0)
+                    .context("Failed to start zstd compression")?,
+            ),
+            Self::Gzip => Box::new(flate2::write::GzEncoder::new(
+                inner,
+                flate2::Compression::default(),
+            )),
+        })
+    }
+
+    /// Detects the compression algorithm used for `data` from its magic bytes.
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        if data.starts_with(ZSTD_MAGIC) {
+            Some(Self::Zstd)
+        } else if data.starts_with(GZIP_MAGIC) {
+            Some(Self::Gzip)
+        } else {
+            None
+        }
+    }
+}
+
+impl FinishWrite for zstd::stream::write::Encoder<'static, Box<dyn FinishWrite>> {
+    fn finish(self: Box<Self>) -> io::Result<Written> {
+        zstd::stream::write::Encoder::finish(*self)?.finish()
+    }
+}
+
+impl FinishWrite for flate2::write::GzEncoder<Box<dyn FinishWrite>> {
+    fn finish(self: Box<Self>) -> io::Result<Written> {
+        flate2::write::GzEncoder::finish(*self)?.finish()
+    }
+}
+
+/// Decompresses `data` if it was compressed with any of the supported algorithms, otherwise
+/// returns it as-is.
+pub fn decompress(data: Vec<u8>) -> Result<Vec<u8>> {
+    match Compression::detect(&data) {
+        Some(Compression::Zstd) => {
+            zstd::decode_all(data.as_slice()).context("Failed to decompress zstd backup")
+        }
+        Some(Compression::Gzip) => {
+            let mut out = Vec::new();
+            flate2::read::GzDecoder::new(data.as_slice())
+                .read_to_end(&mut out)
+                .context("Failed to decompress gzip backup")?;
+            Ok(out)
+        }
+        None => Ok(data),
+    }
+}
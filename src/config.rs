@@ -0,0 +1,348 @@
+use std::{
+    collections::BTreeMap,
+    io::ErrorKind,
+    num::{NonZeroU32, NonZeroUsize},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    compression::Compression,
+    lyrics,
+    output::{self, Field},
+    parts::Size,
+};
+
+const FILE_NAME: &str = "config.toml";
+
+/// Settings read from `config.toml` in the platform's config dir (or the file given with
+/// `--config`), used as defaults for options not given on the command line or through the
+/// environment. Top-level settings apply to every profile, and can be overridden per profile in
+/// `[profiles.<name>]` tables.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Config {
+    /// Profile used when none is given on the command line
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    #[serde(flatten)]
+    pub defaults: ProfileConfig,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, ProfileConfig>,
+    /// Backups run on a schedule by the daemon, keyed by name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub jobs: BTreeMap<String, JobConfig>,
+    /// Names playlists can be given by instead of their ID, link or URI, mapped to the playlist
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub aliases: BTreeMap<String, String>,
+    /// Top-level keys that aren't settings, which are rejected once the file's read (serde can't
+    /// deny unknown fields of a struct flattened into another)
+    #[serde(flatten, skip_serializing)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+/// A backup run by the daemon on its own schedule, with settings overriding those of the profile
+/// (eg. its own `output` and `upload` destination).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JobConfig {
+    /// Profile the job runs as, for backing up another Spotify account, with its own token and
+    /// settings
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// Cron expression of when the job runs, in local time (eg. "0 3 * * *" for 3am every day)
+    pub schedule: String,
+    /// Whether liked songs are backed up
+    #[serde(default)]
+    pub liked: bool,
+    /// Whether every playlist in the user's library is backed up
+    #[serde(default)]
+    pub all: bool,
+    /// IDs, links or URIs of playlists to back up
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub playlists: Vec<String>,
+    #[serde(flatten)]
+    pub settings: ProfileConfig,
+    /// Keys of the job that aren't settings, rejected like those of [`Config`]
+    #[serde(flatten, skip_serializing)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileConfig {
+    /// Client ID of the Spotify app the profile authenticates as
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    /// Directory backups are written into
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<PathBuf>,
+    /// Format backups are written in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<output::Format>,
+    /// Line written for each track when the format is `template`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template: Option<output::Template>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compress: Option<Compression>,
+    /// Recipients backups are encrypted to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encrypt: Option<Vec<String>>,
+    /// Whether artist genres are added to backups
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub genres: Option<bool>,
+    /// Whether MusicBrainz IDs are added to backups
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub musicbrainz: Option<bool>,
+    /// Where lyrics are stored, if they're fetched at all
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub with_lyrics: Option<lyrics::Mode>,
+    /// Whether the user's own tags, ratings and notes are added to backups
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub with_annotations: Option<bool>,
+    /// LRCLIB-compatible API lyrics are fetched from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lyrics_provider: Option<Url>,
+    /// Fields of each track written to backups
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fields: Option<Vec<Field>>,
+    /// Whether JSON backups are pretty-printed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pretty: Option<bool>,
+    /// Whether followed playlists are skipped when backing up every playlist
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owned_only: Option<bool>,
+    /// Size past which backups are split into parts (eg. "500M")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub split_size: Option<Size>,
+    /// Number of tracks past which backups are split into parts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub split_tracks: Option<NonZeroUsize>,
+    /// Destination backups are uploaded to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upload: Option<Url>,
+    /// How many archived copies of each backup are kept in the upload destination
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upload_keep: Option<NonZeroUsize>,
+    /// URL a summary of each run is POSTed to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify_webhook: Option<Url>,
+    /// Discord webhook URL a message summarising each run is posted to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify_discord: Option<Url>,
+    /// Slack incoming webhook URL a message summarising each run is posted to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify_slack: Option<Url>,
+    /// Whether desktop notifications are raised
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify_desktop: Option<bool>,
+    /// healthchecks.io-style check URL pinged when each run starts and finishes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub healthcheck_url: Option<Url>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub concurrency: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page_size: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+    /// Maximum number of requests per second
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<NonZeroU32>,
+    /// Country tracks are fetched for (eg. SE)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub market: Option<String>,
+}
+
+impl ProfileConfig {
+    /// Fills in any settings missing from `self` from `fallback`.
+    pub fn or(self, fallback: &Self) -> Self {
+        let fallback = fallback.clone();
+
+        Self {
+            client_id: self.client_id.or(fallback.client_id),
+            output: self.output.or(fallback.output),
+            format: self.format.or(fallback.format),
+            template: self.template.or(fallback.template),
+            compress: self.compress.or(fallback.compress),
+            encrypt: self.encrypt.or(fallback.encrypt),
+            genres: self.genres.or(fallback.genres),
+            musicbrainz: self.musicbrainz.or(fallback.musicbrainz),
+            with_lyrics: self.with_lyrics.or(fallback.with_lyrics),
+            with_annotations: self.with_annotations.or(fallback.with_annotations),
+            lyrics_provider: self.lyrics_provider.or(fallback.lyrics_provider),
+            fields: self.fields.or_else(|| fallback.fields.clone()),
+            pretty: self.pretty.or(fallback.pretty),
+            owned_only: self.owned_only.or(fallback.owned_only),
+            split_size: self.split_size.or(fallback.split_size),
+            split_tracks: self.split_tracks.or(fallback.split_tracks),
+            upload: self.upload.or(fallback.upload),
+            upload_keep: self.upload_keep.or(fallback.upload_keep),
+            notify_webhook: self.notify_webhook.or(fallback.notify_webhook),
+            notify_discord: self.notify_discord.or(fallback.notify_discord),
+            notify_slack: self.notify_slack.or(fallback.notify_slack),
+            notify_desktop: self.notify_desktop.or(fallback.notify_desktop),
+            healthcheck_url: self.healthcheck_url.or(fallback.healthcheck_url),
+            concurrency: self.concurrency.or(fallback.concurrency),
+            page_size: self.page_size.or(fallback.page_size),
+            retries: self.retries.or(fallback.retries),
+            rate_limit: self.rate_limit.or(fallback.rate_limit),
+            market: self.market.or(fallback.market),
+        }
+    }
+}
+
+impl Config {
+    /// Path of the config file, `explicit` being the path given with `--config`.
+    pub fn path(explicit: Option<&Path>) -> Result<PathBuf> {
+        if let Some(path) = explicit {
+            return Ok(path.to_path_buf());
+        }
+
+        let base = dirs::config_dir().context("Unsupported operating system, no config dir")?;
+        Ok(base.join("spotify-backup").join(FILE_NAME))
+    }
+
+    /// Reads the config file, returning an empty config if the default one hasn't been written
+    /// yet. A config file given explicitly has to exist.
+    pub async fn load(explicit: Option<&Path>) -> Result<Self> {
+        let path = Self::path(explicit)?;
+
+        let data = match tokio::fs::read_to_string(&path).await {
+            Ok(v) => v,
+            Err(e) if e.kind() == ErrorKind::NotFound && explicit.is_none() => {
+                return Ok(Self::default())
+            }
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+
+        let config: Self =
+            toml::from_str(&data).with_context(|| format!("Failed to parse {}", path.display()))?;
+        config
+            .check_unknown()
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+        Ok(config)
+    }
+
+    /// Fails on the first key at the top level or in a job that isn't a setting, as those in
+    /// profiles already do.
+    fn check_unknown(&self) -> Result<()> {
+        if let Some(key) = self.unknown.keys().next() {
+            anyhow::bail!("Unknown setting `{key}`");
+        }
+        for (name, job) in &self.jobs {
+            if let Some(key) = job.unknown.keys().next() {
+                anyhow::bail!("Unknown setting `{key}` in job {name}");
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn save(&self, explicit: Option<&Path>) -> Result<()> {
+        let path = Self::path(explicit)?;
+
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .context("Failed to create config dir")?;
+        }
+
+        let data = toml::to_string_pretty(self).context("Failed to serialize config")?;
+        crate::atomic::write(&path, data)
+            .await
+            .context("Failed to write config")
+    }
+
+    /// Settings specific to the given profile, without the top-level defaults.
+    pub fn profile(&self, name: &str) -> Option<&ProfileConfig> {
+        self.profiles.get(name)
+    }
+
+    /// Settings for the given profile, falling back to the top-level defaults.
+    pub fn resolve(&self, name: &str) -> ProfileConfig {
+        self.profile(name)
+            .cloned()
+            .unwrap_or_default()
+            .or(&self.defaults)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(toml: &str) -> Result<Config> {
+        let config: Config = toml::from_str(toml)?;
+        config.check_unknown()?;
+        Ok(config)
+    }
+
+    #[test]
+    fn rejects_unknown_keys_wherever_they_are() {
+        for toml in [
+            "bogus_key = 1",
+            "[profiles.x]\nbogus_key = 1",
+            "[jobs.x]\nschedule = \"0 3 * * *\"\nbogus_key = 1",
+        ] {
+            let e = parse(toml).unwrap_err();
+            assert!(format!("{e:#}").contains("bogus_key"), "{toml}: {e:#}");
+        }
+    }
+
+    #[test]
+    fn reads_format_and_template() {
+        let config = parse(
+            "format = \"csv\"\n\
+             [profiles.x]\n\
+             format = \"template\"\n\
+             template = \"{artists} — {name} {{}}\"",
+        )
+        .unwrap();
+
+        assert_eq!(config.resolve("y").format, Some(output::Format::Csv));
+        let x = config.resolve("x");
+        assert_eq!(x.format, Some(output::Format::Template));
+        assert_eq!(
+            x.template.map(|v| v.to_string()).as_deref(),
+            Some("{artists} — {name} {{}}")
+        );
+    }
+
+    #[test]
+    fn profile_settings_take_precedence() {
+        let config = parse(
+            "compress = \"zstd\"\n\
+             concurrency = 8\n\
+             [profiles.x]\n\
+             concurrency = 2",
+        )
+        .unwrap();
+
+        let x = config.resolve("x");
+        assert_eq!(x.concurrency, Some(2));
+        assert_eq!(x.compress, Some(Compression::Zstd));
+    }
+
+    #[test]
+    fn job_settings_take_precedence() {
+        let config = parse(
+            "concurrency = 8\n\
+             [profiles.x]\n\
+             compress = \"gzip\"\n\
+             output = \"/backups\"\n\
+             [jobs.liked]\n\
+             profile = \"x\"\n\
+             schedule = \"0 3 * * *\"\n\
+             output = \"/backups/liked\"",
+        )
+        .unwrap();
+
+        let job = &config.jobs["liked"];
+        let settings = job.settings.clone().or(&config.resolve("x"));
+        assert_eq!(settings.output, Some(PathBuf::from("/backups/liked")));
+        assert_eq!(settings.compress, Some(Compression::Gzip));
+        assert_eq!(settings.concurrency, Some(8));
+    }
+}
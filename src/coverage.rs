@@ -0,0 +1,163 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::IsTerminal,
+};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{cli::Args, output, profile::Profile};
+
+/// How liked songs and the tracks in playlists cover each other.
+#[derive(Serialize, Debug)]
+struct Coverage {
+    liked: usize,
+    /// Distinct tracks across every playlist
+    playlist_tracks: usize,
+    /// Liked songs that aren't in any playlist
+    liked_not_in_playlists: Vec<Track>,
+    /// Tracks in playlists that aren't liked
+    not_liked: Vec<Track>,
+}
+
+#[derive(Serialize, Debug)]
+struct Track {
+    name: String,
+    artists: Vec<String>,
+    uri: String,
+    /// Names of the playlists the track is in
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    playlists: Vec<String>,
+}
+
+/// Cross-references liked songs against every playlist in the library, printing the liked songs
+/// that aren't in any playlist and the tracks in playlists that aren't liked.
+pub async fn run(args: &Args) -> Result<()> {
+    let profile = Profile::new(&args.profile)?;
+    let client = crate::cli::build_client(&profile, args).await?;
+
+    let mut sources = crate::cli::fetch_sources(&client, args, &[], true, true)
+        .await?
+        .into_iter();
+    // liked songs come first
+    let liked = sources.next().map(|v| v.items).unwrap_or_default();
+    let liked_uris: HashSet<_> = liked.iter().map(|v| v.track.uri.clone()).collect();
+
+    // along with the order each track was first seen in, to list them in
+    let mut in_playlists: HashMap<String, (usize, Track)> = HashMap::new();
+    for source in sources {
+        for item in source.items {
+            let seen = in_playlists.len();
+            let (_, track) = in_playlists
+                .entry(item.track.uri.clone())
+                .or_insert_with(|| {
+                    (
+                        seen,
+                        Track {
+                            name: item.track.name,
+                            artists: item.track.artists.into_iter().map(|v| v.name).collect(),
+                            uri: item.track.uri,
+                            playlists: Vec::new(),
+                        },
+                    )
+                });
+            if !track.playlists.contains(&source.name) {
+                track.playlists.push(source.name.clone());
+            }
+        }
+    }
+
+    let liked_not_in_playlists = liked
+        .into_iter()
+        .filter(|v| !in_playlists.contains_key(&v.track.uri))
+        .map(|v| Track {
+            name: v.track.name,
+            artists: v.track.artists.into_iter().map(|v| v.name).collect(),
+            uri: v.track.uri,
+            playlists: Vec::new(),
+        })
+        .collect();
+
+    let playlist_tracks = in_playlists.len();
+    let mut not_liked: Vec<_> = in_playlists
+        .into_values()
+        // local files can't be liked
+        .filter(|(_, v)| !liked_uris.contains(&v.uri) && !v.uri.starts_with("spotify:local:"))
+        .collect();
+    not_liked.sort_by_key(|(seen, _)| *seen);
+
+    let coverage = Coverage {
+        liked: liked_uris.len(),
+        playlist_tracks,
+        liked_not_in_playlists,
+        not_liked: not_liked.into_iter().map(|(_, v)| v).collect(),
+    };
+
+    print(args, &coverage)
+}
+
+fn print(args: &Args, coverage: &Coverage) -> Result<()> {
+    let terminal = std::io::stdout().is_terminal();
+
+    match args.format {
+        output::Format::Json => {
+            let json = if args.pretty || terminal {
+                serde_json::to_string_pretty(coverage)?
+            } else {
+                serde_json::to_string(coverage)?
+            };
+            println!("{json}");
+        }
+        output::Format::Table => {
+            let mut summary = output::table(&["Library", "Tracks"], terminal, &[1]);
+            for (name, count) in [
+                ("Liked", coverage.liked),
+                ("In playlists", coverage.playlist_tracks),
+                (
+                    "Liked, not in any playlist",
+                    coverage.liked_not_in_playlists.len(),
+                ),
+                ("In playlists, not liked", coverage.not_liked.len()),
+            ] {
+                output::add_row(&mut summary, [name, &count.to_string()]);
+            }
+            println!("{summary}");
+
+            if !coverage.liked_not_in_playlists.is_empty() {
+                let mut table = output::table(&["Liked track", "Artists", "URI"], terminal, &[2]);
+                for track in &coverage.liked_not_in_playlists {
+                    output::add_row(
+                        &mut table,
+                        [track.name.as_str(), &track.artists.join(", "), &track.uri],
+                    );
+                }
+                println!("\n{table}");
+            }
+
+            if !coverage.not_liked.is_empty() {
+                let mut table = output::table(
+                    &["Unliked track", "Artists", "Playlists", "URI"],
+                    terminal,
+                    &[3],
+                );
+                for track in &coverage.not_liked {
+                    output::add_row(
+                        &mut table,
+                        [
+                            track.name.as_str(),
+                            &track.artists.join(", "),
+                            &track.playlists.join(", "),
+                            &track.uri,
+                        ],
+                    );
+                }
+                println!("\n{table}");
+            }
+        }
+        _ => {
+            anyhow::bail!("Coverage can only be printed as JSON or a table")
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,185 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use chrono::{Local, Utc};
+use croner::Cron;
+use reqwest::Url;
+use tokio::time::MissedTickBehavior;
+use tracing::{error, info, info_span, Instrument};
+
+use crate::{cli::Args, config::Config, link, metrics, profile::Profile, usage::Usage};
+
+/// Name runs on a fixed interval are reported under in metrics, as opposed to a config file job.
+const INTERVAL_JOB: &str = "interval";
+
+/// What each scheduled run backs up.
+pub struct Backups {
+    pub liked: bool,
+    pub all: bool,
+    pub ids: Vec<String>,
+}
+
+/// Backs up every `interval`, or runs the jobs in the config file without one, until the process
+/// is stopped. Metrics are served on `metrics_addr` alongside if given.
+pub async fn run(
+    args: &Args,
+    interval: Option<Duration>,
+    backups: Backups,
+    metrics_addr: Option<SocketAddr>,
+) -> Result<()> {
+    let backups = async {
+        match interval {
+            Some(interval) => run_every(args, interval, backups).await,
+            None => run_jobs(args).await,
+        }
+    };
+
+    match metrics_addr {
+        Some(addr) => tokio::try_join!(backups, metrics::serve(addr)).map(|_| ()),
+        None => backups.await,
+    }
+}
+
+/// Backs up on a fixed interval until the process is stopped, starting straight away. A failed
+/// run is logged and retried at the next interval rather than stopping the daemon.
+async fn run_every(args: &Args, interval: Duration, backups: Backups) -> Result<()> {
+    let mut ticker = tokio::time::interval(interval);
+    // a run taking longer than the interval delays the next one, rather than starting several
+    // back to back to catch up
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        ticker.tick().await;
+
+        let started = Instant::now();
+        // failures are logged, and tried again at the next interval
+        let _ = run_logged(args, INTERVAL_JOB, &backups).await;
+
+        info!(
+            "Next backup in {}",
+            humantime::format_duration(whole_seconds(interval.saturating_sub(started.elapsed())))
+        );
+    }
+}
+
+/// Runs every job in the config file on its own cron schedule until the process is stopped. Jobs
+/// can run as different profiles, for backing up several accounts, in which case each uses that
+/// profile's token and settings. Jobs for the same profile due at the same time take turns, as only
+/// one run can use a profile at once.
+async fn run_jobs(args: &Args) -> Result<()> {
+    let config = Config::load(args.config.as_deref()).await?;
+    anyhow::ensure!(
+        !config.jobs.is_empty(),
+        "No jobs are set up in the config file, add a [jobs.<name>] table or pass --interval"
+    );
+
+    let mut jobs = Vec::new();
+    // where each profile's backups go, as jobs for different accounts writing to the same place
+    // would replace each other's backups
+    let mut destinations = HashMap::new();
+
+    for (name, job) in &config.jobs {
+        let schedule: Cron = job
+            .schedule
+            .parse()
+            .with_context(|| format!("Invalid schedule {:?} for job {name}", job.schedule))?;
+
+        let profile = job.profile.as_deref().unwrap_or(&args.profile);
+        let job_args = args.for_job(&config, profile, &job.settings)?;
+
+        let outputs = job_args.output.iter().map(|v| v.display().to_string());
+        for destination in outputs.chain(job_args.upload.iter().map(Url::to_string)) {
+            let profile = destinations
+                .entry(destination.clone())
+                .or_insert_with(|| job_args.profile.clone());
+            anyhow::ensure!(
+                *profile == job_args.profile,
+                "Job {name} would back up profile {} to {destination}, which profile {profile} is \
+                 already backed up to, give it its own output or upload destination",
+                job_args.profile,
+            );
+        }
+
+        let backups = Backups {
+            liked: job.liked,
+            all: job.all,
+            ids: job
+                .playlists
+                .iter()
+                .map(|v| link::parse_playlist_id(v))
+                .collect::<Result<_>>()
+                .with_context(|| format!("Job {name} has an invalid playlist"))?,
+        };
+        anyhow::ensure!(
+            backups.liked || backups.all || !backups.ids.is_empty(),
+            "Job {name} doesn't back anything up, set liked, all or playlists"
+        );
+
+        let span = info_span!("job", name, profile = job_args.profile);
+        jobs.push(run_scheduled(job_args, name.clone(), schedule, backups).instrument(span));
+    }
+
+    futures::future::try_join_all(jobs).await?;
+
+    Ok(())
+}
+
+async fn run_scheduled(args: Args, name: String, schedule: Cron, backups: Backups) -> Result<()> {
+    loop {
+        let now = Local::now();
+        let next = schedule
+            .find_next_occurrence(&now, false)
+            .context("Failed to find when the job next runs")?;
+        info!("Next backup at {}", next.format("%Y-%m-%d %H:%M"));
+
+        tokio::time::sleep((next - now).to_std().unwrap_or_default()).await;
+
+        // failures are logged, and tried again at the next scheduled time
+        let _ = run_logged(&args, &name, &backups).await;
+    }
+}
+
+/// Runs a backup, logging how it went and recording it in the metrics of `job`.
+pub async fn run_logged(args: &Args, job: &str, backups: &Backups) -> Result<()> {
+    // counted afresh, so requests made between runs (eg. by the API) aren't reported as its own
+    let mut args = args.clone();
+    args.api.usage = Usage::default();
+    let args = &args;
+
+    let started = Instant::now();
+    let started_at = Utc::now();
+    info!("Starting backup");
+
+    let result = run_once(args, backups).await;
+    metrics::run_finished(job, started_at, started.elapsed(), result.is_ok());
+
+    match &result {
+        Ok(()) => info!(
+            "Backup finished in {}",
+            humantime::format_duration(whole_seconds(started.elapsed()))
+        ),
+        Err(e) => error!("Backup failed: {e:#}"),
+    }
+    args.api.usage.report(args.log.stats);
+
+    result
+}
+
+async fn run_once(args: &Args, backups: &Backups) -> Result<()> {
+    // only held while backing up, so other commands can use the profile between runs
+    let _lock = Profile::new(&args.profile)?
+        .lock(true)
+        .await
+        .context("Failed to lock profile")?;
+
+    crate::cli::backup_library(args, backups.liked, &backups.ids, backups.all, false).await
+}
+
+/// Rounds `duration` down to whole seconds, for logging.
+fn whole_seconds(duration: Duration) -> Duration {
+    Duration::from_secs(duration.as_secs())
+}
@@ -0,0 +1,162 @@
+use std::{num::NonZeroU32, time::Duration};
+
+use anyhow::{Context, Result};
+use governor::{DefaultDirectRateLimiter, Quota, RateLimiter};
+use serde::Deserialize;
+
+use crate::map::{Candidate, Service, Track};
+
+const API_URL: &str = "https://api.deezer.com";
+
+/// Deezer allows 50 requests every 5 seconds.
+const REQUESTS_PER_SECOND: u32 = 10;
+
+/// Most search results scored for each track.
+const MAX_RESULTS: usize = 5;
+
+/// Finds tracks on Deezer, through its public API which needs no account.
+pub struct Deezer {
+    http: reqwest::Client,
+    limiter: DefaultDirectRateLimiter,
+}
+
+#[derive(Deserialize, Debug)]
+struct DeezerTrack {
+    id: u64,
+    title: String,
+    /// Length in seconds
+    duration: Option<u64>,
+    artist: DeezerArtist,
+    /// Every artist credited, which is only returned when looking a track up by ID or ISRC
+    #[serde(default)]
+    contributors: Vec<DeezerArtist>,
+    album: Option<DeezerAlbum>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DeezerArtist {
+    name: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct DeezerAlbum {
+    id: u64,
+    title: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchResponse {
+    data: Vec<DeezerTrack>,
+}
+
+/// Deezer answers requests for things it doesn't have with a 200 and an error in the body.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum Response<T> {
+    Error { error: ErrorBody },
+    Ok(T),
+}
+
+#[derive(Deserialize, Debug)]
+struct ErrorBody {
+    code: Option<u32>,
+    message: Option<String>,
+}
+
+/// Code of the error returned for things that don't exist.
+const DATA_NOT_FOUND: u32 = 800;
+
+/// Code of the error returned when over the rate limit.
+const QUOTA_EXCEEDED: u32 = 4;
+
+impl Deezer {
+    pub fn new() -> Self {
+        Self {
+            http: crate::http::client(),
+            limiter: RateLimiter::direct(Quota::per_second(
+                NonZeroU32::new(REQUESTS_PER_SECOND).unwrap(),
+            )),
+        }
+    }
+
+    /// Fetches `url`, returning `None` if Deezer doesn't have what it points to and waiting a
+    /// little when over the rate limit.
+    async fn get<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<Option<T>> {
+        loop {
+            self.limiter.until_ready().await;
+
+            let response: Response<T> = self
+                .http
+                .get(url)
+                .send()
+                .await
+                .context("Failed to send request to Deezer")?
+                .error_for_status()
+                .context("Failed to query Deezer")?
+                .json()
+                .await
+                .context("Failed to parse response from Deezer")?;
+
+            match response {
+                Response::Ok(v) => return Ok(Some(v)),
+                Response::Error { error } if error.code == Some(DATA_NOT_FOUND) => return Ok(None),
+                Response::Error { error } if error.code == Some(QUOTA_EXCEEDED) => {
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                }
+                Response::Error { error } => anyhow::bail!(
+                    "Deezer returned an error: {}",
+                    error.message.unwrap_or_default()
+                ),
+            }
+        }
+    }
+}
+
+impl Service for Deezer {
+    fn name(&self) -> &'static str {
+        "deezer"
+    }
+
+    async fn search(&self, track: &Track) -> Result<Vec<Candidate>> {
+        if let Some(isrc) = &track.isrc {
+            let url = format!("{API_URL}/track/isrc:{isrc}");
+            if let Some(found) = self.get::<DeezerTrack>(&url).await? {
+                return Ok(vec![candidate(found, true)]);
+            }
+        }
+
+        let query = match track.artists.first() {
+            Some(artist) => format!("artist:\"{artist}\" track:\"{}\"", track.name),
+            None => format!("track:\"{}\"", track.name),
+        };
+        let url = reqwest::Url::parse_with_params(&format!("{API_URL}/search"), [("q", query)])?;
+
+        Ok(self
+            .get::<SearchResponse>(url.as_str())
+            .await?
+            .map(|v| v.data)
+            .unwrap_or_default()
+            .into_iter()
+            .take(MAX_RESULTS)
+            .map(|v| candidate(v, false))
+            .collect())
+    }
+}
+
+fn candidate(track: DeezerTrack, by_isrc: bool) -> Candidate {
+    let artists = if track.contributors.is_empty() {
+        vec![track.artist.name]
+    } else {
+        track.contributors.into_iter().map(|v| v.name).collect()
+    };
+
+    Candidate {
+        id: track.id.to_string(),
+        title: track.title,
+        artists,
+        album: track.album.as_ref().map(|v| v.title.clone()),
+        album_id: track.album.map(|v| v.id.to_string()),
+        duration_ms: track.duration.map(|v| v * 1000),
+        by_isrc,
+    }
+}
@@ -0,0 +1,67 @@
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use anyhow::Result;
+use tracing::{debug, warn};
+
+/// How long a backup has to take before a notification is raised when it finishes, shorter ones
+/// are done before there's been time to switch to another window.
+pub const LONG_BACKUP: Duration = Duration::from_secs(10);
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Platforms notifications can be raised on, with `osascript` on macOS or `notify-send` elsewhere.
+const SUPPORTED: bool = cfg!(any(
+    target_os = "macos",
+    target_os = "linux",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly"
+));
+
+/// Turns on desktop notifications, which are otherwise never raised. Fails on platforms they
+/// can't be raised on rather than leaving them to silently never show up.
+pub fn enable() -> Result<()> {
+    if !SUPPORTED {
+        anyhow::bail!("--notify-desktop is only supported on Linux, the BSDs and macOS");
+    }
+
+    ENABLED.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Raises a desktop notification if they've been turned on, using `notify-send` on Linux and the
+/// BSDs or `osascript` on macOS. Failing to do so is only logged, as the notification is a
+/// nicety and the same information is in the terminal.
+pub async fn notify(title: &str, body: &str) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let mut command = if cfg!(target_os = "macos") {
+        let mut command = tokio::process::Command::new("osascript");
+        command.arg("-e").arg(format!(
+            "display notification {} with title {}",
+            applescript_quote(body),
+            applescript_quote(title)
+        ));
+        command
+    } else {
+        let mut command = tokio::process::Command::new("notify-send");
+        command.args(["--app-name", "spotify-backup", title, body]);
+        command
+    };
+
+    match command.kill_on_drop(true).status().await {
+        Ok(status) if status.success() => debug!(title, "Raised desktop notification"),
+        Ok(status) => warn!("Failed to raise desktop notification, {status}"),
+        Err(e) => warn!("Failed to raise desktop notification: {e}"),
+    }
+}
+
+fn applescript_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
@@ -0,0 +1,234 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::IsTerminal,
+};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{cli::Args, output, profile::Profile};
+
+/// How the tracks in a group were found to be duplicates.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+enum Kind {
+    /// The same track in several places
+    Exact,
+    /// Different tracks with the same ISRC, ie. the same recording on different releases
+    Isrc,
+    /// Different tracks with the same title and artists, which may or may not be the same recording
+    Similar,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Exact => "exact",
+            Self::Isrc => "isrc",
+            Self::Similar => "similar",
+        }
+    }
+}
+
+/// Tracks found to be duplicates of each other, and everywhere they are.
+#[derive(Serialize, Debug)]
+struct Group {
+    kind: Kind,
+    name: String,
+    artists: Vec<String>,
+    occurrences: Vec<Occurrence>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct Occurrence {
+    /// Playlist ID, missing for liked songs
+    id: Option<String>,
+    playlist: String,
+    /// Zero-based position of the track in the playlist, as taken by the API when removing it
+    position: usize,
+    uri: String,
+    album: String,
+    added_at: Option<String>,
+    #[serde(skip)]
+    track_name: String,
+    #[serde(skip)]
+    artists: Vec<String>,
+    #[serde(skip)]
+    isrc: Option<String>,
+}
+
+/// Prints the tracks in liked songs with `liked` and the playlists given by `ids` (or every
+/// playlist with `all`) that appear more than once: the same track in several places, and
+/// different tracks that are likely the same song, sharing an ISRC or a title and artists.
+pub async fn run(args: &Args, ids: &[String], all: bool, liked: bool) -> Result<()> {
+    let profile = Profile::new(&args.profile)?;
+    let client = crate::cli::build_client(&profile, args).await?;
+
+    let occurrences: Vec<Occurrence> = crate::cli::fetch_sources(&client, args, ids, all, liked)
+        .await?
+        .into_iter()
+        .flat_map(|source| {
+            source
+                .items
+                .into_iter()
+                .enumerate()
+                .map(move |(position, v)| Occurrence {
+                    id: source.id.clone(),
+                    playlist: source.name.clone(),
+                    position,
+                    uri: v.track.uri,
+                    album: v.track.album.name,
+                    added_at: v.added_at,
+                    track_name: v.track.name,
+                    artists: v.track.artists.into_iter().map(|v| v.name).collect(),
+                    isrc: v.track.external_ids.and_then(|v| v.isrc),
+                })
+        })
+        .collect();
+
+    print(args, &groups(&occurrences))
+}
+
+fn groups(occurrences: &[Occurrence]) -> Vec<Group> {
+    let mut by_uri: BTreeMap<&str, Vec<&Occurrence>> = BTreeMap::new();
+    for occurrence in occurrences {
+        by_uri.entry(&occurrence.uri).or_default().push(occurrence);
+    }
+
+    let mut groups: Vec<Group> = by_uri
+        .values()
+        .filter(|v| v.len() > 1)
+        .map(|v| group(Kind::Exact, v))
+        .collect();
+
+    // the fuzzy matches are between distinct tracks, wherever each of them is
+    let mut by_isrc: BTreeMap<&str, Vec<&Vec<&Occurrence>>> = BTreeMap::new();
+    for track in by_uri.values() {
+        if let Some(isrc) = &track[0].isrc {
+            by_isrc.entry(isrc).or_default().push(track);
+        }
+    }
+
+    let mut grouped = HashSet::new();
+    for tracks in by_isrc.values().filter(|v| v.len() > 1) {
+        grouped.extend(tracks.iter().map(|v| v[0].uri.as_str()));
+        groups.push(group(Kind::Isrc, &flatten(tracks)));
+    }
+
+    let mut by_title: BTreeMap<(String, Vec<String>), Vec<&Vec<&Occurrence>>> = BTreeMap::new();
+    for track in by_uri.values() {
+        if grouped.contains(track[0].uri.as_str()) {
+            continue;
+        }
+        if let Some(key) = title_key(track[0]) {
+            by_title.entry(key).or_default().push(track);
+        }
+    }
+
+    for tracks in by_title.values().filter(|v| v.len() > 1) {
+        groups.push(group(Kind::Similar, &flatten(tracks)));
+    }
+
+    groups.sort_by(|a, b| (a.kind, &a.name).cmp(&(b.kind, &b.name)));
+    groups
+}
+
+fn group(kind: Kind, occurrences: &[&Occurrence]) -> Group {
+    Group {
+        kind,
+        name: occurrences[0].track_name.clone(),
+        artists: occurrences[0].artists.clone(),
+        occurrences: occurrences.iter().map(|v| (*v).clone()).collect(),
+    }
+}
+
+/// Every occurrence of each of `tracks`.
+fn flatten<'a>(tracks: &[&Vec<&'a Occurrence>]) -> Vec<&'a Occurrence> {
+    tracks.iter().flat_map(|v| v.iter().copied()).collect()
+}
+
+/// Title and artists of a track normalized for finding the same song on different releases, so
+/// "Song (Remastered 2011)" by "Artist" and "Song - 2011 Remaster" by "artist" are the same.
+/// Tracks whose title is nothing but a bracketed version (eg. "(Intro)") have no key.
+fn title_key(occurrence: &Occurrence) -> Option<(String, Vec<String>)> {
+    let title = normalize_title(&occurrence.track_name)?;
+
+    let mut artists: Vec<_> = occurrence
+        .artists
+        .iter()
+        .map(|v| v.to_lowercase())
+        .collect();
+    artists.sort();
+
+    Some((title, artists))
+}
+
+/// Title of a track lowercased without anything in brackets or after " - ", which is where
+/// versions like "(Remastered 2011)" or "- Live" go. `None` if that leaves nothing.
+pub fn normalize_title(name: &str) -> Option<String> {
+    let mut title = String::new();
+    let mut depth = 0;
+    for c in name.chars() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth = (depth - 1).max(0),
+            _ if depth == 0 => title.push(c),
+            _ => {}
+        }
+    }
+    let title = title.split(" - ").next().unwrap_or_default();
+    if title.trim().is_empty() {
+        return None;
+    }
+
+    Some(
+        title
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_lowercase(),
+    )
+}
+
+fn print(args: &Args, groups: &[Group]) -> Result<()> {
+    let terminal = std::io::stdout().is_terminal();
+
+    match args.format {
+        output::Format::Json => {
+            let json = if args.pretty || terminal {
+                serde_json::to_string_pretty(groups)?
+            } else {
+                serde_json::to_string(groups)?
+            };
+            println!("{json}");
+        }
+        output::Format::Table => {
+            let mut table =
+                output::table(&["Kind", "Track", "Artists", "Found in"], terminal, &[3]);
+            for group in groups {
+                let found_in = group
+                    .occurrences
+                    .iter()
+                    .map(|v| format!("{} #{}", v.playlist, v.position + 1))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+
+                output::add_row(
+                    &mut table,
+                    [
+                        group.kind.name(),
+                        &group.name,
+                        &group.artists.join(", "),
+                        &found_in,
+                    ],
+                );
+            }
+            println!("{table}");
+        }
+        _ => {
+            anyhow::bail!("Duplicates can only be listed as JSON or a table")
+        }
+    }
+
+    Ok(())
+}
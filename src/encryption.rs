@@ -0,0 +1,85 @@
+use std::{
+    io::{self, BufReader, Read},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use age::{armor::ArmoredReader, stream::StreamWriter};
+use anyhow::{Context, Result};
+
+use crate::writer::{FinishWrite, Written};
+
+/// Extension appended to the names of encrypted backups.
+pub const EXTENSION: &str = "age";
+
+const BINARY_MAGIC: &[u8] = b"age-encryption.org/";
+const ARMORED_MAGIC: &[u8] = b"-----BEGIN AGE ENCRYPTED FILE-----";
+
+/// Wraps `inner` in a writer encrypting everything written to it to every recipient, given as
+/// `age:<recipient>` (eg. `age:age1ql3z7hjy...`).
+pub fn writer(inner: Box<dyn FinishWrite>, recipients: &[String]) -> Result<Box<dyn FinishWrite>> {
+    let recipients = recipients
+        .iter()
+        .map(|v| parse_recipient(v))
+        .collect::<Result<Vec<_>>>()?;
+
+    let encryptor =
+        age::Encryptor::with_recipients(recipients.iter().map(|v| v as &dyn age::Recipient))
+            .context("Failed to create encryptor")?;
+
+    Ok(Box::new(
+        encryptor
+            .wrap_output(inner)
+            .context("Failed to start encryption")?,
+    ))
+}
+
+impl FinishWrite for StreamWriter<Box<dyn FinishWrite>> {
+    fn finish(self: Box<Self>) -> io::Result<Written> {
+        StreamWriter::finish(*self)?.finish()
+    }
+}
+
+/// Decrypts binary or ASCII-armored age files using the identities in the given identity files.
+pub fn decrypt(data: &[u8], identity_files: &[PathBuf]) -> Result<Vec<u8>> {
+    anyhow::ensure!(
+        !identity_files.is_empty(),
+        "Backup is encrypted, pass an identity file with --identity to decrypt it"
+    );
+
+    let mut identities = Vec::new();
+    for path in identity_files {
+        identities.extend(
+            age::IdentityFile::from_file(path.to_string_lossy().into_owned())
+                .with_context(|| format!("Failed to read identity file {}", path.display()))?
+                .into_identities()
+                .with_context(|| format!("Invalid identity file {}", path.display()))?,
+        );
+    }
+
+    let decryptor = age::Decryptor::new(ArmoredReader::new(BufReader::new(data)))
+        .context("Failed to read age header")?;
+
+    let mut out = Vec::new();
+    decryptor
+        .decrypt(identities.iter().map(|v| v.as_ref() as &dyn age::Identity))
+        .context("Failed to decrypt backup, none of the identities matched")?
+        .read_to_end(&mut out)
+        .context("Failed to decrypt backup")?;
+
+    Ok(out)
+}
+
+/// Returns whether `data` looks like an age file, either binary or ASCII-armored.
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(BINARY_MAGIC) || data.starts_with(ARMORED_MAGIC)
+}
+
+fn parse_recipient(value: &str) -> Result<age::x25519::Recipient> {
+    let recipient = value.strip_prefix("age:").with_context(|| {
+        format!("Unsupported encryption method {value}, expected age:<recipient>")
+    })?;
+
+    age::x25519::Recipient::from_str(recipient)
+        .map_err(|e| anyhow::anyhow!("Invalid age recipient {recipient}: {e}"))
+}
@@ -0,0 +1,137 @@
+use std::time::Duration;
+
+use reqwest::StatusCode;
+
+/// Longest part of a response body kept in [`Error::Deserialize`].
+const MAX_BODY_SNIPPET: usize = 200;
+
+/// Failures of the Spotify API that callers may want to handle differently, returned by
+/// [`crate::SpotifyClient`] and found in the chain of any error the CLI fails with.
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// No usable token could be obtained, or Spotify rejected the one sent
+    #[error("Not authenticated with Spotify")]
+    Auth(#[source] Box<dyn std::error::Error + Send + Sync>),
+    /// Spotify asked for requests to stop for longer than is worth waiting
+    #[error("Rate limited by Spotify for {}s", retry_after.as_secs())]
+    RateLimited { retry_after: Duration },
+    /// The playlist, track or other object requested doesn't exist, or isn't visible to the user
+    #[error("{url} wasn't found")]
+    NotFound { url: String },
+    /// The token hasn't been granted a scope the request needs
+    #[error("Not allowed to request {url}, the token is missing a scope it needs")]
+    InsufficientScope { url: String },
+    /// The request couldn't be sent, or no response arrived
+    #[error("Failed to request {url}")]
+    Network {
+        url: String,
+        #[source]
+        source: reqwest::Error,
+    },
+    /// Spotify responded with an error not covered by the other variants
+    #[error("Got {status} response from {url}")]
+    Status { url: String, status: StatusCode },
+    /// The response wasn't what was expected
+    #[error("Failed to parse response from {url}")]
+    Deserialize {
+        url: String,
+        /// Start of the response body
+        body_snippet: String,
+        #[source]
+        source: serde_json::Error,
+    },
+    /// Anything else, such as failing to read the stored token
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl Error {
+    /// Error for a response that failed with `status`.
+    pub(crate) fn from_status(url: &str, status: StatusCode) -> Self {
+        let url = url.to_string();
+
+        match status {
+            StatusCode::UNAUTHORIZED => {
+                Self::Auth(format!("Got {status} response from {url}").into())
+            }
+            StatusCode::FORBIDDEN => Self::InsufficientScope { url },
+            StatusCode::NOT_FOUND => Self::NotFound { url },
+            status => Self::Status { url, status },
+        }
+    }
+
+    /// Error for a response body that couldn't be parsed.
+    pub(crate) fn deserialize(url: &str, body: &str, source: serde_json::Error) -> Self {
+        let end = body
+            .char_indices()
+            .nth(MAX_BODY_SNIPPET)
+            .map_or(body.len(), |(i, _)| i);
+
+        Self::Deserialize {
+            url: url.to_string(),
+            body_snippet: body[..end].to_string(),
+            source,
+        }
+    }
+
+    /// Code the CLI exits with when failing with this error, following sysexits.h.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            // EX_NOPERM
+            Self::Auth(_) | Self::InsufficientScope { .. } => 77,
+            // EX_TEMPFAIL, running again later may well work
+            Self::RateLimited { .. } | Self::Network { .. } => 75,
+            // EX_NOINPUT
+            Self::NotFound { .. } => 66,
+            // EX_PROTOCOL
+            Self::Status { .. } | Self::Deserialize { .. } => 76,
+            Self::Other(_) => 1,
+        }
+    }
+
+    /// Name of the kind of error, for telling failures apart without parsing their message.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Auth(_) => "auth",
+            Self::RateLimited { .. } => "rate_limited",
+            Self::NotFound { .. } => "not_found",
+            Self::InsufficientScope { .. } => "insufficient_scope",
+            Self::Network { .. } => "network",
+            Self::Status { .. } => "status",
+            Self::Deserialize { .. } => "deserialize",
+            Self::Other(_) => "other",
+        }
+    }
+
+    /// What the user can do about the error, printed by the CLI beneath it.
+    pub fn hint(&self) -> Option<&'static str> {
+        Some(match self {
+            Self::Auth(_) => {
+                "Log out with `spotify-backup logout` and run the command again in a terminal to \
+                 log in, or check SPOTIFY_REFRESH_TOKEN if set"
+            }
+            Self::InsufficientScope { .. } => {
+                "Log out with `spotify-backup logout` and log in again to grant the missing scope"
+            }
+            Self::RateLimited { .. } => {
+                "Try again later, or with a lower --concurrency or --rate-limit"
+            }
+            Self::Network { .. } => "Check your connection, or set --proxy if you need one",
+            Self::NotFound { .. } => {
+                "Check the ID, playlists that are private to someone else can't be backed up"
+            }
+            Self::Status { .. } | Self::Deserialize { .. } | Self::Other(_) => return None,
+        })
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    /// The typed error somewhere in the chain of `error`, or `error` itself as [`Error::Other`].
+    /// Typed errors keep none of the context added to them, which is in their fields instead.
+    fn from(error: anyhow::Error) -> Self {
+        match error.downcast::<Self>() {
+            Ok(error) => error,
+            Err(error) => Self::Other(error),
+        }
+    }
+}
@@ -0,0 +1,314 @@
+use std::{collections::HashMap, io::Write, path::Path};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::info;
+
+use crate::{
+    atomic,
+    cli::Args,
+    encryption, manifest, parts,
+    profile::Profile,
+    storage::{self, Storage},
+    writer::{self, FinishWrite},
+    GetPlaylistsResponseItem, Ownership,
+};
+
+/// Name of the index written alongside everything else.
+const INDEX_FILE: &str = "index.json";
+
+/// Table of contents of a backup of the whole library, saying what's in each file.
+#[derive(Serialize, Debug)]
+struct Index {
+    schema_version: u32,
+    /// RFC 3339 timestamps of when the run started and finished
+    started_at: String,
+    finished_at: String,
+    account: Account,
+    profile: FileRef,
+    liked: FileRef,
+    playlists: Vec<PlaylistRef>,
+    albums: FileRef,
+    shows: FileRef,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Account {
+    id: String,
+    display_name: Option<String>,
+}
+
+/// A file of the backup, as the index refers to it.
+#[derive(Serialize, Debug)]
+struct FileRef {
+    file: String,
+    /// Number of items in the file, as Spotify counts them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    count: Option<u32>,
+    /// RFC 3339 timestamp of when the file was last written, missing if it never has been
+    written_at: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct PlaylistRef {
+    id: String,
+    name: String,
+    ownership: Ownership,
+    #[serde(flatten)]
+    file: FileRef,
+}
+
+#[derive(Deserialize, Debug)]
+struct SavedAlbum {
+    added_at: String,
+    album: Album,
+}
+
+#[derive(Deserialize, Debug)]
+struct Album {
+    name: String,
+    uri: String,
+    artists: Vec<Artist>,
+    #[serde(default)]
+    release_date: Option<String>,
+    #[serde(default)]
+    total_tracks: Option<u32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Artist {
+    name: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct SavedShow {
+    added_at: String,
+    show: Show,
+}
+
+#[derive(Deserialize, Debug)]
+struct Show {
+    name: String,
+    uri: String,
+    #[serde(default)]
+    publisher: Option<String>,
+    #[serde(default)]
+    total_episodes: Option<u32>,
+}
+
+/// A saved album, as written to `albums.json`.
+#[derive(Serialize, Debug)]
+struct AlbumItem {
+    name: String,
+    artists: Vec<String>,
+    uri: String,
+    release_date: Option<String>,
+    total_tracks: Option<u32>,
+    added_at: String,
+}
+
+/// A saved show, as written to `shows.json`.
+#[derive(Serialize, Debug)]
+struct ShowItem {
+    name: String,
+    publisher: Option<String>,
+    uri: String,
+    total_episodes: Option<u32>,
+    added_at: String,
+}
+
+/// Backs up the whole library into the output directory: the user's profile, liked songs, every
+/// playlist (bar followed ones with `--owned-only`), saved albums and saved shows, then writes an
+/// index of what's in each file. A playlist that fails to back up doesn't stop the rest from being
+/// written, and keeps the time it was last written in the index.
+pub async fn run(args: &Args) -> Result<()> {
+    let dir = args
+        .output
+        .as_deref()
+        .context("Everything is written into a directory, pass --output")?;
+    let started_at = chrono::Utc::now();
+
+    let profile = Profile::new(&args.profile)?;
+    let client = crate::cli::build_client(&profile, args).await?;
+
+    let me: Value = client
+        .get_json("https://api.spotify.com/v1/me")
+        .await
+        .context("Failed to fetch current user")?;
+    let account: Account = serde_json::from_value(me.clone())?;
+
+    let playlists = crate::cli::fetch_all::<GetPlaylistsResponseItem>(
+        &client,
+        args.api.first_page_url("me/playlists", 50),
+    )
+    .await
+    .context("Failed to fetch playlists")?;
+    let playlists = crate::cli::included_playlists(args, &account.id, playlists);
+    let ids: Vec<_> = playlists.iter().map(|v| v.id.clone()).collect();
+
+    // the tracks are backed up as they would be by `liked` and `playlist`, and anything that
+    // failed is reported once the rest has been written
+    let backed_up = crate::cli::backup_library(args, true, &ids, false, false).await;
+
+    let liked = crate::fetch_page::<Value>(&client, args.api.first_page_url("me/tracks", 1))
+        .await
+        .context("Failed to count liked songs")?
+        .total;
+    let albums: Vec<_> =
+        crate::cli::fetch_all::<SavedAlbum>(&client, args.api.first_page_url("me/albums", 50))
+            .await
+            .context("Failed to fetch saved albums")?
+            .into_iter()
+            .map(|v| AlbumItem {
+                name: v.album.name,
+                artists: v.album.artists.into_iter().map(|v| v.name).collect(),
+                uri: v.album.uri,
+                release_date: v.album.release_date,
+                total_tracks: v.album.total_tracks,
+                added_at: v.added_at,
+            })
+            .collect();
+    let shows: Vec<_> =
+        crate::cli::fetch_all::<SavedShow>(&client, args.api.first_page_url("me/shows", 50))
+            .await
+            .context("Failed to fetch saved shows")?
+            .into_iter()
+            .map(|v| ShowItem {
+                name: v.show.name,
+                publisher: v.show.publisher,
+                uri: v.show.uri,
+                total_episodes: v.show.total_episodes,
+                added_at: v.added_at,
+            })
+            .collect();
+
+    let mut written = Vec::new();
+    let profile_ref = write(args, dir, "profile", &me, None, &mut written).await?;
+    let albums_ref = write(
+        args,
+        dir,
+        "albums",
+        &serde_json::json!({ "schema_version": manifest::SCHEMA_VERSION, "albums": albums }),
+        Some(albums.len() as u32),
+        &mut written,
+    )
+    .await?;
+    let shows_ref = write(
+        args,
+        dir,
+        "shows",
+        &serde_json::json!({ "schema_version": manifest::SCHEMA_VERSION, "shows": shows }),
+        Some(shows.len() as u32),
+        &mut written,
+    )
+    .await?;
+
+    // backups of tracks are listed in the manifest, with when each was last written
+    let written_at: HashMap<_, _> = manifest::read(dir)
+        .await?
+        .map(|v| v.files)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|v| (v.name, v.created_at))
+        .collect();
+    let track_file = |name: String, count| {
+        // a backup split into parts is found through their index
+        let index = parts::index_name(&name);
+        let file = if written_at.contains_key(&index) {
+            index
+        } else {
+            crate::cli::file_name(args, name)
+        };
+        FileRef {
+            written_at: written_at.get(&file).cloned(),
+            file,
+            count: Some(count),
+        }
+    };
+
+    let index = Index {
+        schema_version: manifest::SCHEMA_VERSION,
+        started_at: started_at.to_rfc3339(),
+        finished_at: chrono::Utc::now().to_rfc3339(),
+        profile: profile_ref,
+        liked: track_file(crate::cli::liked_job(args).0, liked),
+        playlists: playlists
+            .iter()
+            .zip(crate::cli::playlist_jobs(args, &ids))
+            .map(|(playlist, (name, _))| PlaylistRef {
+                id: playlist.id.clone(),
+                name: playlist.name.clone(),
+                ownership: playlist.ownership(&account.id),
+                file: track_file(name, playlist.tracks.total),
+            })
+            .collect(),
+        albums: albums_ref,
+        shows: shows_ref,
+        account,
+    };
+
+    let mut data = serde_json::to_vec_pretty(&index)?;
+    data.push(b'\n');
+    atomic::write(&dir.join(INDEX_FILE), &data)
+        .await
+        .with_context(|| format!("Failed to write {INDEX_FILE}"))?;
+    info!("Wrote {}", dir.join(INDEX_FILE).display());
+    written.push((INDEX_FILE.to_string(), data));
+
+    if let Some(destination) = &args.upload {
+        let storage =
+            storage::Destination::from_url(destination, &args.storage, &profile, &args.auth)?;
+
+        for (name, data) in written {
+            info!("Uploading {name} to {destination}...");
+            storage
+                .put(&name, &mut data.as_slice())
+                .await
+                .context("Failed to upload backup")?;
+        }
+    }
+
+    backed_up
+}
+
+/// Writes `value` as JSON into `dir`, compressed and encrypted like the backups of tracks, keeping
+/// what was written in `written` for uploading.
+async fn write(
+    args: &Args,
+    dir: &Path,
+    name: &str,
+    value: &impl Serialize,
+    count: Option<u32>,
+    written: &mut Vec<(String, Vec<u8>)>,
+) -> Result<FileRef> {
+    let file = crate::cli::file_name(args, format!("{name}.json"));
+
+    let mut writer: Box<dyn FinishWrite> = Box::new(writer::Sink::new(std::io::sink(), true));
+    if !args.encrypt.is_empty() {
+        writer = encryption::writer(writer, &args.encrypt).context("Failed to encrypt backup")?;
+    }
+    if let Some(compression) = args.compress {
+        writer = compression.writer(writer)?;
+    }
+
+    if args.pretty {
+        serde_json::to_writer_pretty(&mut writer, value)?;
+    } else {
+        serde_json::to_writer(&mut writer, value)?;
+    }
+    writer.write_all(b"\n")?;
+    let data = writer.finish()?.data.unwrap_or_default();
+
+    atomic::write(&dir.join(&file), &data)
+        .await
+        .with_context(|| format!("Failed to write {file}"))?;
+    info!("Wrote {}", dir.join(&file).display());
+    written.push((file.clone(), data));
+
+    Ok(FileRef {
+        file,
+        count,
+        written_at: Some(chrono::Utc::now().to_rfc3339()),
+    })
+}
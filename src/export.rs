@@ -0,0 +1,568 @@
+//! Formats backups are written in. Each is an [`Exporter`], which tracks are streamed through as
+//! their pages arrive, so library users can write backups in formats of their own too.
+
+use std::{collections::HashMap, io::Write};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    output::{self, Field, Selected, Template},
+    Output,
+};
+
+/// Version of the format backups are written in, bumped whenever the output changes shape. Version
+/// 1 wrote JSON backups as a bare array of tracks, 2 wraps it in an object alongside the version.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// Writes the tracks of a backup in one format. [`Exporter::begin`] is called once before the
+/// first track, [`Exporter::write_item`] for every track in order, then [`Exporter::finish`] once
+/// after the last. Everything is written to the writer passed to each call, beneath which any
+/// compression and encryption are applied.
+pub trait Exporter {
+    /// Writes anything that comes before the first track.
+    fn begin(&mut self, _writer: &mut dyn Write) -> Result<()> {
+        Ok(())
+    }
+
+    /// Writes a track, or holds onto it for formats that can only be written once every track is
+    /// known.
+    fn write_item(&mut self, writer: &mut dyn Write, output: &Output) -> Result<()>;
+
+    /// Writes anything that comes after the last track.
+    fn finish(&mut self, _writer: &mut dyn Write) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// How many tracks each person added to a playlist, written after the tracks of JSON backups
+/// with the `added_by` or `added_by_name` fields so collaborative playlists keep who added what.
+#[derive(Default)]
+struct Contributors {
+    contributors: Vec<Contributor>,
+    /// Position in `contributors` of each person, by user ID
+    index: HashMap<String, usize>,
+}
+
+#[derive(Serialize, Clone)]
+struct Contributor {
+    id: String,
+    name: Option<String>,
+    tracks: u64,
+}
+
+impl Contributors {
+    /// Counts contributors only if the backup is written with who added each track.
+    fn for_fields(fields: &[Field]) -> Option<Self> {
+        fields
+            .iter()
+            .any(|v| matches!(v, Field::AddedBy | Field::AddedByName))
+            .then(Self::default)
+    }
+
+    fn add(&mut self, output: &Output) {
+        let Some(id) = &output.added_by else {
+            return;
+        };
+
+        let i = *self.index.entry(id.clone()).or_insert_with(|| {
+            self.contributors.push(Contributor {
+                id: id.clone(),
+                name: output.added_by_name.clone(),
+                tracks: 0,
+            });
+            self.contributors.len() - 1
+        });
+        self.contributors[i].tracks += 1;
+    }
+
+    /// Every contributor, most tracks first, or nothing for liked songs which have none.
+    fn sorted(&self) -> Option<Vec<Contributor>> {
+        if self.contributors.is_empty() {
+            return None;
+        }
+
+        let mut contributors = self.contributors.clone();
+        contributors.sort_by_key(|v| std::cmp::Reverse(v.tracks));
+
+        Some(contributors)
+    }
+}
+
+/// A JSON object with the schema version and an array of tracks, streamed a track at a time.
+pub struct JsonExporter<'a> {
+    fields: &'a [Field],
+    pretty: bool,
+    first: bool,
+    contributors: Option<Contributors>,
+}
+
+impl<'a> JsonExporter<'a> {
+    pub fn new(fields: &'a [Field], pretty: bool) -> Self {
+        Self {
+            fields,
+            pretty,
+            first: true,
+            contributors: Contributors::for_fields(fields),
+        }
+    }
+}
+
+impl Exporter for JsonExporter<'_> {
+    fn begin(&mut self, writer: &mut dyn Write) -> Result<()> {
+        if self.pretty {
+            write!(
+                writer,
+                "{{\n  \"schema_version\": {},\n  \"tracks\": [",
+                SCHEMA_VERSION
+            )?;
+        } else {
+            write!(
+                writer,
+                "{{\"schema_version\":{},\"tracks\":[",
+                SCHEMA_VERSION
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn write_item(&mut self, writer: &mut dyn Write, output: &Output) -> Result<()> {
+        let fields = self.fields;
+
+        if self.pretty {
+            // every track is indented to sit inside the array, keeping one field per line so a
+            // changed track only shows up as the lines that changed in a diff
+            let json = serde_json::to_string_pretty(&Selected { output, fields })?;
+            let separator = if self.first { "\n    " } else { ",\n    " };
+
+            writer.write_all(separator.as_bytes())?;
+            writer.write_all(json.replace('\n', "\n    ").as_bytes())?;
+        } else {
+            if !self.first {
+                writer.write_all(b",")?;
+            }
+
+            serde_json::to_writer(writer, &Selected { output, fields })?;
+        }
+        self.first = false;
+        if let Some(contributors) = &mut self.contributors {
+            contributors.add(output);
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self, writer: &mut dyn Write) -> Result<()> {
+        match (self.pretty, self.first) {
+            (true, false) => writer.write_all(b"\n  ]")?,
+            _ => writer.write_all(b"]")?,
+        }
+
+        if let Some(contributors) = self.contributors.as_ref().and_then(Contributors::sorted) {
+            if self.pretty {
+                let json = serde_json::to_string_pretty(&contributors)?;
+                write!(
+                    writer,
+                    ",\n  \"contributors\": {}",
+                    json.replace('\n', "\n  ")
+                )?;
+            } else {
+                write!(
+                    writer,
+                    ",\"contributors\":{}",
+                    serde_json::to_string(&contributors)?
+                )?;
+            }
+        }
+
+        if self.pretty {
+            writer.write_all(b"\n}\n")?;
+        } else {
+            writer.write_all(b"}\n")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A JSON object with the tracks nested under their albums, as written with `--group-by album`.
+/// Tracks are collected until the last one, then written under their albums in the order each
+/// album first appears.
+pub struct AlbumsExporter<'a> {
+    fields: &'a [Field],
+    pretty: bool,
+    albums: Vec<Vec<Output>>,
+    /// Position in `albums` of each album, by URI (or name, for local files)
+    index: HashMap<String, usize>,
+    contributors: Option<Contributors>,
+}
+
+impl<'a> AlbumsExporter<'a> {
+    pub fn new(fields: &'a [Field], pretty: bool) -> Self {
+        Self {
+            fields,
+            pretty,
+            albums: Vec::new(),
+            index: HashMap::new(),
+            contributors: Contributors::for_fields(fields),
+        }
+    }
+}
+
+/// Tracks of one album, as written with `--group-by album`.
+#[derive(Serialize)]
+struct AlbumGroup<'a> {
+    name: &'a str,
+    art: &'a str,
+    uri: Option<&'a str>,
+    release_date: Option<&'a str>,
+    tracks: Vec<Selected<'a>>,
+}
+
+/// A JSON backup with its tracks nested under their albums.
+#[derive(Serialize)]
+struct Albums<'a> {
+    schema_version: u32,
+    albums: Vec<AlbumGroup<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    contributors: Option<Vec<Contributor>>,
+}
+
+impl Exporter for AlbumsExporter<'_> {
+    fn write_item(&mut self, _writer: &mut dyn Write, output: &Output) -> Result<()> {
+        let key = output.album.uri.as_ref().unwrap_or(&output.album.name);
+        let i = *self.index.entry(key.clone()).or_insert_with(|| {
+            self.albums.push(Vec::new());
+            self.albums.len() - 1
+        });
+        self.albums[i].push(output.clone());
+        if let Some(contributors) = &mut self.contributors {
+            contributors.add(output);
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self, mut writer: &mut dyn Write) -> Result<()> {
+        // the album is written once for all its tracks, which are numbered instead
+        let numbers = [Field::DiscNumber, Field::TrackNumber];
+        let mut track_fields = numbers.to_vec();
+        track_fields.extend(
+            self.fields
+                .iter()
+                .filter(|v| !numbers.contains(v) && **v != Field::Album),
+        );
+
+        for tracks in self.albums.iter_mut() {
+            tracks.sort_by_key(|v| (v.disc_number, v.track_number));
+        }
+
+        let albums = Albums {
+            schema_version: SCHEMA_VERSION,
+            albums: self
+                .albums
+                .iter()
+                .map(|tracks| {
+                    let album = &tracks[0];
+                    AlbumGroup {
+                        name: &album.album.name,
+                        art: &album.album.art,
+                        uri: album.album.uri.as_deref(),
+                        release_date: album.release_date.as_deref(),
+                        tracks: tracks
+                            .iter()
+                            .map(|output| Selected {
+                                output,
+                                fields: &track_fields,
+                            })
+                            .collect(),
+                    }
+                })
+                .collect(),
+            contributors: self.contributors.as_ref().and_then(Contributors::sorted),
+        };
+
+        if self.pretty {
+            serde_json::to_writer_pretty(&mut writer, &albums)?;
+        } else {
+            serde_json::to_writer(&mut writer, &albums)?;
+        }
+        writer.write_all(b"\n")?;
+
+        Ok(())
+    }
+}
+
+/// A JSON object per line for each track, without the schema version, for tools that read a
+/// track at a time (eg. `jq -c`).
+pub struct NdjsonExporter<'a> {
+    fields: &'a [Field],
+}
+
+impl<'a> NdjsonExporter<'a> {
+    pub fn new(fields: &'a [Field]) -> Self {
+        Self { fields }
+    }
+}
+
+impl Exporter for NdjsonExporter<'_> {
+    fn write_item(&mut self, mut writer: &mut dyn Write, output: &Output) -> Result<()> {
+        let fields = self.fields;
+        serde_json::to_writer(&mut writer, &Selected { output, fields })?;
+        writer.write_all(b"\n")?;
+
+        Ok(())
+    }
+}
+
+/// A line of text per track, filled in from `--template`.
+pub struct TemplateExporter<'a> {
+    template: &'a Template,
+}
+
+impl<'a> TemplateExporter<'a> {
+    pub fn new(template: &'a Template) -> Self {
+        Self { template }
+    }
+}
+
+impl Exporter for TemplateExporter<'_> {
+    fn write_item(&mut self, writer: &mut dyn Write, output: &Output) -> Result<()> {
+        self.template.write(writer, output)
+    }
+}
+
+/// Aligned columns of the track, artists, album, duration and when each track was added. Tracks
+/// are collected until the last one, so the columns can be sized to fit every row.
+pub struct TableExporter {
+    table: comfy_table::Table,
+}
+
+impl TableExporter {
+    /// Starts a table whose rows are truncated to fit the terminal when `fit` is set, otherwise
+    /// they're written in full.
+    pub fn new(fit: bool) -> Self {
+        // the names are cut short to make room rather than the duration and date
+        Self {
+            table: output::table(
+                &["Track", "Artists", "Album", "Duration", "Added"],
+                fit,
+                &[3, 4],
+            ),
+        }
+    }
+}
+
+impl Exporter for TableExporter {
+    fn write_item(&mut self, _writer: &mut dyn Write, output: &Output) -> Result<()> {
+        let duration = output.duration_ms.map(|v| {
+            let seconds = v / 1000;
+            format!("{}:{:02}", seconds / 60, seconds % 60)
+        });
+        let added = output.added_at.as_deref().and_then(|v| v.get(..10));
+
+        output::add_row(
+            &mut self.table,
+            [
+                output.name.as_str(),
+                &output.artists.join(", "),
+                &output.album.name,
+                duration.as_deref().unwrap_or_default(),
+                added.unwrap_or_default(),
+            ],
+        );
+
+        Ok(())
+    }
+
+    fn finish(&mut self, writer: &mut dyn Write) -> Result<()> {
+        writeln!(writer, "{}", self.table)?;
+
+        Ok(())
+    }
+}
+
+/// The fields of a track as columns, for the tabular formats. Fields holding an object (the
+/// album) get a column for each of its keys (eg. `album_name`), and lists are joined with `; `.
+fn columns(output: &Output, fields: &[Field]) -> Result<Vec<(String, Value)>> {
+    let Value::Object(object) = serde_json::to_value(Selected { output, fields })? else {
+        anyhow::bail!("Track wasn't serialized as an object");
+    };
+
+    let mut columns = Vec::new();
+    for (key, value) in object {
+        match value {
+            Value::Object(nested) => {
+                columns.extend(nested.into_iter().map(|(k, v)| (format!("{key}_{k}"), v)));
+            }
+            Value::Array(values) => {
+                let values: Vec<_> = values
+                    .iter()
+                    .map(|v| match v {
+                        Value::String(v) => v.clone(),
+                        v => v.to_string(),
+                    })
+                    .collect();
+                columns.push((key, Value::String(values.join("; "))));
+            }
+            value => columns.push((key, value)),
+        }
+    }
+
+    Ok(columns)
+}
+
+/// Header of a tabular backup with no tracks, which can't be worked out from the first track.
+fn empty_header(fields: &[Field]) -> Vec<String> {
+    fields
+        .iter()
+        .flat_map(|v| match v {
+            Field::Album => vec!["album_art".to_string(), "album_name".to_string()],
+            v => vec![v.name().to_string()],
+        })
+        .collect()
+}
+
+/// A row of comma-separated values per track, under a header naming the columns.
+pub struct CsvExporter<'a> {
+    fields: &'a [Field],
+    /// Columns of the first track, written as the header above it
+    header: Option<Vec<String>>,
+}
+
+impl<'a> CsvExporter<'a> {
+    pub fn new(fields: &'a [Field]) -> Self {
+        Self {
+            fields,
+            header: None,
+        }
+    }
+}
+
+impl Exporter for CsvExporter<'_> {
+    fn write_item(&mut self, writer: &mut dyn Write, output: &Output) -> Result<()> {
+        let columns = columns(output, self.fields)?;
+        let mut csv = csv::Writer::from_writer(writer);
+
+        if self.header.is_none() {
+            let header: Vec<_> = columns.iter().map(|(k, _)| k.clone()).collect();
+            csv.write_record(&header)?;
+            self.header = Some(header);
+        }
+
+        csv.write_record(columns.into_iter().map(|(_, v)| match v {
+            Value::String(v) => v,
+            Value::Null => String::new(),
+            v => v.to_string(),
+        }))?;
+        csv.flush().context("Failed to write CSV")?;
+
+        Ok(())
+    }
+
+    fn finish(&mut self, writer: &mut dyn Write) -> Result<()> {
+        if self.header.is_none() {
+            let mut csv = csv::Writer::from_writer(writer);
+            csv.write_record(empty_header(self.fields))?;
+            csv.flush().context("Failed to write CSV")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// An SQLite database with a `tracks` table holding a row per track, in playlist order by their
+/// `position`. The database is built in memory and written out once the last track is in.
+pub struct SqliteExporter<'a> {
+    fields: &'a [Field],
+    connection: rusqlite::Connection,
+    /// Columns of the table, once it's been created from the first track
+    columns: Option<Vec<String>>,
+    position: u64,
+}
+
+impl<'a> SqliteExporter<'a> {
+    pub fn new(fields: &'a [Field]) -> Result<Self> {
+        let connection =
+            rusqlite::Connection::open_in_memory().context("Failed to create SQLite database")?;
+
+        Ok(Self {
+            fields,
+            connection,
+            columns: None,
+            position: 0,
+        })
+    }
+
+    fn create_table(&mut self, columns: Vec<String>) -> Result<()> {
+        let definitions: Vec<_> = columns.iter().map(|v| format!(", \"{v}\"")).collect();
+        self.connection
+            .execute_batch(&format!(
+                "CREATE TABLE tracks (position INTEGER PRIMARY KEY{});
+                 CREATE TABLE metadata (key TEXT PRIMARY KEY, value);
+                 INSERT INTO metadata VALUES ('schema_version', {});",
+                definitions.concat(),
+                SCHEMA_VERSION,
+            ))
+            .context("Failed to create SQLite tables")?;
+        self.columns = Some(columns);
+
+        Ok(())
+    }
+}
+
+impl Exporter for SqliteExporter<'_> {
+    fn write_item(&mut self, _writer: &mut dyn Write, output: &Output) -> Result<()> {
+        let columns = columns(output, self.fields)?;
+        if self.columns.is_none() {
+            self.create_table(columns.iter().map(|(k, _)| k.clone()).collect())?;
+        }
+
+        let values: Vec<_> = columns
+            .into_iter()
+            .map(|(_, v)| match v {
+                Value::Null => rusqlite::types::Value::Null,
+                Value::Bool(v) => rusqlite::types::Value::Integer(v.into()),
+                Value::Number(v) => match v.as_i64() {
+                    Some(v) => rusqlite::types::Value::Integer(v),
+                    None => rusqlite::types::Value::Real(v.as_f64().unwrap_or_default()),
+                },
+                Value::String(v) => rusqlite::types::Value::Text(v),
+                v => rusqlite::types::Value::Text(v.to_string()),
+            })
+            .collect();
+
+        let placeholders = "?, ".repeat(values.len());
+        self.connection
+            .execute(
+                &format!(
+                    "INSERT INTO tracks VALUES (?, {})",
+                    placeholders.trim_end_matches(", ")
+                ),
+                rusqlite::params_from_iter(
+                    std::iter::once(rusqlite::types::Value::Integer(self.position as i64))
+                        .chain(values),
+                ),
+            )
+            .context("Failed to insert track into SQLite database")?;
+        self.position += 1;
+
+        Ok(())
+    }
+
+    fn finish(&mut self, writer: &mut dyn Write) -> Result<()> {
+        if self.columns.is_none() {
+            self.create_table(empty_header(self.fields))?;
+        }
+
+        let data = self
+            .connection
+            .serialize(rusqlite::MAIN_DB)
+            .context("Failed to serialize SQLite database")?;
+        writer.write_all(&data)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,313 @@
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+
+use crate::Output;
+
+/// A condition tracks have to meet to be written to a backup, given to `--filter` as `key=value`.
+#[derive(Debug, Clone)]
+pub enum Filter {
+    /// One of the track's artists has this name, ignoring case
+    Artist(String),
+    /// The track's album has this name, ignoring case
+    Album(String),
+    /// The album was released in a year within this range
+    Year(Option<i32>, Option<i32>),
+    /// The track is at least this many milliseconds long
+    MinDuration(u64),
+    /// The track is at most this many milliseconds long
+    MaxDuration(u64),
+    /// The track is (or isn't) marked as explicit
+    Explicit(bool),
+    /// The track was added at or after this time
+    AddedAfter(DateTime<Utc>),
+    /// The track was added before this time
+    AddedBefore(DateTime<Utc>),
+}
+
+/// Earliest time tracks were added to be written with `--since`.
+#[derive(Debug, Clone, Copy)]
+pub enum Since {
+    Time(DateTime<Utc>),
+    /// The last time the same backup succeeded, or the beginning of time if it never has
+    LastRun,
+}
+
+impl FromStr for Since {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "last-run" => Ok(Self::LastRun),
+            _ => parse_time(s)
+                .map(Self::Time)
+                .context("Expected last-run, a date or an RFC 3339 timestamp"),
+        }
+    }
+}
+
+/// Keys that can be filtered on, for error messages.
+const KEYS: &str =
+    "artist, album, year, min-duration, max-duration, explicit, added-after, added-before";
+
+impl Filter {
+    /// Whether `output` meets the condition. Tracks missing the field being filtered on never do.
+    pub fn matches(&self, output: &Output) -> bool {
+        match self {
+            Self::Artist(name) => output
+                .artists
+                .iter()
+                .any(|v| v.to_lowercase() == name.to_lowercase()),
+            Self::Album(name) => output.album.name.to_lowercase() == name.to_lowercase(),
+            Self::Year(from, to) => {
+                let year = output
+                    .release_date
+                    .as_deref()
+                    .and_then(|v| v.get(..4))
+                    .and_then(|v| v.parse::<i32>().ok());
+
+                year.is_some_and(|year| {
+                    from.is_none_or(|from| year >= from) && to.is_none_or(|to| year <= to)
+                })
+            }
+            Self::MinDuration(ms) => output.duration_ms.is_some_and(|v| v >= *ms),
+            Self::MaxDuration(ms) => output.duration_ms.is_some_and(|v| v <= *ms),
+            Self::Explicit(explicit) => output.explicit == Some(*explicit),
+            Self::AddedAfter(time) => added_at(output).is_some_and(|v| v >= *time),
+            Self::AddedBefore(time) => added_at(output).is_some_and(|v| v < *time),
+        }
+    }
+}
+
+fn added_at(output: &Output) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(output.added_at.as_deref()?)
+        .ok()
+        .map(|v| v.to_utc())
+}
+
+impl FromStr for Filter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (key, value) = s
+            .split_once('=')
+            .with_context(|| format!("Expected key=value, where key is one of {KEYS}"))?;
+
+        Ok(match key {
+            "artist" => Self::Artist(value.to_string()),
+            "album" => Self::Album(value.to_string()),
+            "year" => match value.split_once("..") {
+                Some((from, to)) => Self::Year(parse_year(from)?, parse_year(to)?),
+                None => {
+                    let year = parse_year(value)?.context("Expected a year (eg. 2023)")?;
+                    Self::Year(Some(year), Some(year))
+                }
+            },
+            "min-duration" => Self::MinDuration(parse_duration(value)?),
+            "max-duration" => Self::MaxDuration(parse_duration(value)?),
+            "explicit" => Self::Explicit(
+                value
+                    .parse()
+                    .context("Expected explicit=true or explicit=false")?,
+            ),
+            "added-after" => Self::AddedAfter(parse_time(value)?),
+            "added-before" => Self::AddedBefore(parse_time(value)?),
+            _ => anyhow::bail!("Unknown filter {key}, expected one of {KEYS}"),
+        })
+    }
+}
+
+/// Parses one end of a year range, which may be left open.
+fn parse_year(value: &str) -> Result<Option<i32>> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+
+    value
+        .parse()
+        .map(Some)
+        .with_context(|| format!("Invalid year {value}, expected eg. 2023 or 2020..2023"))
+}
+
+/// Parses a duration given as seconds or minutes and seconds (eg. 90 or 1:30), into milliseconds.
+fn parse_duration(value: &str) -> Result<u64> {
+    let invalid = || format!("Invalid duration {value}, expected seconds or minutes:seconds");
+
+    let seconds: u64 = match value.split_once(':') {
+        Some((minutes, seconds)) => {
+            let minutes: u64 = minutes.parse().with_context(invalid)?;
+            let seconds: u64 = seconds.parse().with_context(invalid)?;
+            minutes * 60 + seconds
+        }
+        None => value.parse().with_context(invalid)?,
+    };
+
+    Ok(seconds * 1000)
+}
+
+/// Parses a date (taken as midnight UTC) or an RFC 3339 timestamp.
+fn parse_time(value: &str) -> Result<DateTime<Utc>> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_time(Default::default()).and_utc());
+    }
+
+    DateTime::parse_from_rfc3339(value)
+        .map(|v| v.to_utc())
+        .with_context(|| format!("Invalid time {value}, expected eg. 2023-01-01"))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn matches(filter: &str, output: &Output) -> bool {
+        filter.parse::<Filter>().unwrap().matches(output)
+    }
+
+    #[test]
+    fn takes_values_after_the_first_equals_sign_as_they_are() {
+        let Filter::Album(name) = "album=Stop Making Sense=Live".parse().unwrap() else {
+            panic!("Not an album filter");
+        };
+        assert_eq!(name, "Stop Making Sense=Live");
+
+        let Filter::Artist(name) = "artist= Earth, Wind & Fire".parse().unwrap() else {
+            panic!("Not an artist filter");
+        };
+        assert_eq!(name, " Earth, Wind & Fire");
+    }
+
+    #[test]
+    fn compares_names_ignoring_case() {
+        let output = Output::example(json!({}));
+
+        assert!(matches("artist=boards of CANADA", &output));
+        assert!(!matches("artist=Boards of", &output));
+        assert!(matches("album=music has the right to children", &output));
+        assert!(!matches("album=Geogaddi", &output));
+    }
+
+    #[test]
+    fn includes_both_ends_of_year_ranges() {
+        let output = Output::example(json!({}));
+
+        for filter in [
+            "year=1998",
+            "year=1998..2000",
+            "year=1990..1998",
+            "year=..1998",
+            "year=1998..",
+        ] {
+            assert!(matches(filter, &output), "{filter}");
+        }
+        for filter in ["year=1999", "year=1999..2000", "year=..1997", "year=1999.."] {
+            assert!(!matches(filter, &output), "{filter}");
+        }
+    }
+
+    #[test]
+    fn includes_the_limits_of_durations() {
+        let output = Output::example(json!({}));
+
+        assert!(matches("min-duration=151", &output));
+        assert!(matches("max-duration=2:31", &output));
+        assert!(!matches("min-duration=2:32", &output));
+        assert!(!matches("max-duration=150", &output));
+    }
+
+    #[test]
+    fn includes_added_after_and_excludes_added_before() {
+        let output = Output::example(json!({"added_at": "2023-01-01T00:00:00Z"}));
+
+        assert!(matches("added-after=2023-01-01", &output));
+        assert!(!matches("added-before=2023-01-01", &output));
+        assert!(matches("added-before=2023-01-01T00:00:01Z", &output));
+        // timestamps in other time zones are compared in UTC
+        assert!(matches("added-after=2023-01-01T01:00:00+01:00", &output));
+        assert!(!matches("added-after=2022-12-31T23:00:00-02:00", &output));
+    }
+
+    #[test]
+    fn leaves_out_tracks_missing_the_field() {
+        let local =
+            Output::example(json!({"release_date": null, "duration_ms": null, "explicit": null}));
+
+        for filter in [
+            "year=..3000",
+            "min-duration=0",
+            "max-duration=9999",
+            "explicit=false",
+        ] {
+            assert!(!matches(filter, &local), "{filter}");
+        }
+        assert!(!matches(
+            "added-after=2000-01-01",
+            &Output::example(json!({"added_at": null}))
+        ));
+    }
+
+    #[test]
+    fn tracks_have_to_meet_every_filter() {
+        let filters: Vec<Filter> = ["artist=Boards of Canada", "year=1998", "explicit=true"]
+            .iter()
+            .map(|v| v.parse().unwrap())
+            .collect();
+        let meets_all = |output: &Output| filters.iter().all(|v| v.matches(output));
+
+        assert!(!meets_all(&Output::example(json!({}))));
+        assert!(meets_all(&Output::example(json!({"explicit": true}))));
+    }
+
+    #[test]
+    fn explains_invalid_filters() {
+        for (filter, error) in [
+            (
+                "artist",
+                "Expected key=value, where key is one of artist, album, year",
+            ),
+            (
+                "genre=ambient",
+                "Unknown filter genre, expected one of artist, album, year",
+            ),
+            ("Artist=Burial", "Unknown filter Artist"),
+            ("year=", "Expected a year (eg. 2023)"),
+            (
+                "year=nineties",
+                "Invalid year nineties, expected eg. 2023 or 2020..2023",
+            ),
+            ("year=1990..199x", "Invalid year 199x"),
+            (
+                "min-duration=2m",
+                "Invalid duration 2m, expected seconds or minutes:seconds",
+            ),
+            ("max-duration=1:xx", "Invalid duration 1:xx"),
+            ("explicit=yes", "Expected explicit=true or explicit=false"),
+            (
+                "added-after=2023-13-01",
+                "Invalid time 2023-13-01, expected eg. 2023-01-01",
+            ),
+            ("added-before=yesterday", "Invalid time yesterday"),
+        ] {
+            let e = filter.parse::<Filter>().unwrap_err();
+            assert!(e.to_string().starts_with(error), "{filter}: {e}");
+        }
+    }
+
+    #[test]
+    fn parses_since() {
+        assert!(matches!("last-run".parse(), Ok(Since::LastRun)));
+        let Ok(Since::Time(time)) = "2024-01-01".parse() else {
+            panic!("Not a time");
+        };
+        assert_eq!(time.to_rfc3339(), "2024-01-01T00:00:00+00:00");
+
+        let e = "last-week".parse::<Since>().unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Expected last-run, a date or an RFC 3339 timestamp"
+        );
+    }
+}
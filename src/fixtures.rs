@@ -0,0 +1,305 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use reqwest::{
+    header::{CONTENT_TYPE, ETAG, RETRY_AFTER},
+    Method, Response,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::api::BASE_URL;
+
+/// Fields of response bodies that are blanked before they're recorded.
+const SENSITIVE_FIELDS: &[&str] = &["email", "birthdate", "access_token", "refresh_token"];
+
+/// Fields holding a user, which don't always say so with their `type`.
+const USER_FIELDS: &[&str] = &["owner", "added_by"];
+
+/// A response from the Spotify API recorded with `--record`, to be replayed with `--replay`. Only
+/// what the client reads of it is kept, so the access token and other request headers never are.
+#[derive(Serialize, Deserialize, Debug)]
+struct Fixture {
+    /// Request the response was to, for finding fixtures by hand
+    method: String,
+    url: String,
+    status: u16,
+    etag: Option<String>,
+    retry_after: Option<String>,
+    body: Value,
+}
+
+/// Where the response to a request is recorded in `dir`, named after a hash of the request.
+fn path(dir: &Path, method: &Method, url: &str, body: Option<&Value>) -> PathBuf {
+    let mut hash = Sha256::new();
+    hash.update(method.as_str());
+    hash.update(url);
+    if let Some(body) = body {
+        hash.update(body.to_string());
+    }
+
+    dir.join(format!("{}.json", hex::encode(hash.finalize())))
+}
+
+/// Writes `resp` to `dir` with anything identifying the user blanked and users' IDs replaced,
+/// returning a response with the same status and original body for the client to carry on with.
+/// Requests for a user are recorded under the replaced ID, so a replay asking for the user it
+/// read from a recorded body finds them.
+pub async fn record(
+    dir: &Path,
+    method: &Method,
+    url: &str,
+    request_body: Option<&Value>,
+    resp: Response,
+) -> Result<Response> {
+    let header = |name| {
+        resp.headers()
+            .get(name)
+            .and_then(|v: &reqwest::header::HeaderValue| v.to_str().ok())
+            .map(str::to_string)
+    };
+    let status = resp.status().as_u16();
+    let etag = header(ETAG);
+    let retry_after = header(RETRY_AFTER);
+    let data = resp
+        .bytes()
+        .await
+        .with_context(|| format!("Failed to read response from {url}"))?;
+
+    // bodies that aren't JSON (eg. empty ones) are kept as a string
+    let body = serde_json::from_slice(&data)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&data).into_owned()));
+    let mut fixture = Fixture {
+        method: method.to_string(),
+        url: sanitize_url(url),
+        status,
+        etag,
+        retry_after,
+        body: body.clone(),
+    };
+    sanitize(&mut fixture.body, false);
+
+    tokio::fs::create_dir_all(dir)
+        .await
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+    crate::atomic::write(
+        &path(dir, method, &fixture.url, request_body),
+        serde_json::to_vec_pretty(&fixture)?,
+    )
+    .await
+    .with_context(|| format!("Failed to record response from {url}"))?;
+
+    response(&Fixture { body, ..fixture })
+}
+
+/// The response recorded in `dir` for a request.
+pub async fn replay(
+    dir: &Path,
+    method: &Method,
+    url: &str,
+    request_body: Option<&Value>,
+) -> Result<Response> {
+    let path = path(dir, method, url, request_body);
+    let data = tokio::fs::read(&path).await.with_context(|| {
+        format!(
+            "No response to {method} {url} was recorded in {}",
+            dir.display()
+        )
+    })?;
+    let fixture: Fixture = serde_json::from_slice(&data)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+    response(&fixture)
+}
+
+fn response(fixture: &Fixture) -> Result<Response> {
+    let body = match &fixture.body {
+        Value::String(v) => v.clone(),
+        v => v.to_string(),
+    };
+
+    let mut builder = http::Response::builder()
+        .status(fixture.status)
+        .header(CONTENT_TYPE, "application/json");
+    if let Some(etag) = &fixture.etag {
+        builder = builder.header(ETAG, etag);
+    }
+    if let Some(retry_after) = &fixture.retry_after {
+        builder = builder.header(RETRY_AFTER, retry_after);
+    }
+
+    Ok(builder.body(body)?.into())
+}
+
+/// Blanks the fields of a body that identify the user or let someone act as them, and replaces
+/// the ID of every user in it with a pseudonym. `user` is whether the body is known to be a user,
+/// as the ones in playlists don't always say so with their `type`.
+fn sanitize(value: &mut Value, user: bool) {
+    match value {
+        Value::Object(object) => {
+            if user || object.get("type").and_then(Value::as_str) == Some("user") {
+                sanitize_user(object);
+            }
+
+            for (key, value) in object.iter_mut() {
+                if SENSITIVE_FIELDS.contains(&key.as_str()) {
+                    *value = Value::String(String::new());
+                } else {
+                    sanitize(value, USER_FIELDS.contains(&key.as_str()));
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(|v| sanitize(v, false)),
+        _ => {}
+    }
+}
+
+/// Replaces the ID of `user` with a pseudonym wherever it's given, and leaves out their name and
+/// pictures.
+fn sanitize_user(user: &mut serde_json::Map<String, Value>) {
+    let Some(id) = user.get("id").and_then(Value::as_str) else {
+        return;
+    };
+    let id = pseudonym(id);
+
+    user.insert("id".to_string(), id.clone().into());
+    for (key, value) in [
+        ("uri", format!("spotify:user:{id}")),
+        ("href", format!("{BASE_URL}/users/{id}")),
+    ] {
+        if user.contains_key(key) {
+            user.insert(key.to_string(), value.into());
+        }
+    }
+    if let Some(Value::Object(urls)) = user.get_mut("external_urls") {
+        urls.insert(
+            "spotify".to_string(),
+            format!("https://open.spotify.com/user/{id}").into(),
+        );
+    }
+    if user.contains_key("display_name") {
+        user.insert("display_name".to_string(), Value::Null);
+    }
+    if user.contains_key("images") {
+        user.insert("images".to_string(), Value::Array(Vec::new()));
+    }
+}
+
+/// `url` with the ID of the user it's for, if any, replaced with their pseudonym.
+fn sanitize_url(url: &str) -> String {
+    let Some((start, rest)) = url.split_once("/users/") else {
+        return url.to_string();
+    };
+    let end = rest.find(['/', '?']).unwrap_or(rest.len());
+    let id = percent_encoding::percent_decode_str(&rest[..end]).decode_utf8_lossy();
+
+    format!("{start}/users/{}{}", pseudonym(&id), &rest[end..])
+}
+
+/// Stands in for a user's ID in recordings, always the same for the same user so that the
+/// playlists they own and the tracks they added still go together.
+fn pseudonym(id: &str) -> String {
+    format!("user-{}", &hex::encode(Sha256::digest(id))[..12])
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn sanitize_blanks_tokens_and_replaces_user_ids() {
+        let mut body = json!({
+            "access_token": "BQD-secret",
+            "refresh_token": "AQC-secret",
+            "items": [{
+                "owner": { "id": "alice", "display_name": "Alice Smith" },
+                "added_by": {
+                    "id": "alice",
+                    "uri": "spotify:user:alice",
+                    "href": "https://api.spotify.com/v1/users/alice",
+                    "external_urls": { "spotify": "https://open.spotify.com/user/alice" },
+                },
+            }],
+        });
+        sanitize(&mut body, false);
+
+        let text = body.to_string();
+        assert!(!text.contains("secret"), "{text}");
+        assert!(!text.contains("alice"), "{text}");
+        assert!(!text.contains("Alice"), "{text}");
+
+        // the same user is still the same user
+        let id = pseudonym("alice");
+        assert_eq!(body["items"][0]["owner"]["id"], id);
+        assert_eq!(body["items"][0]["added_by"]["id"], id);
+        assert_eq!(
+            body["items"][0]["added_by"]["uri"],
+            format!("spotify:user:{id}")
+        );
+        assert_eq!(
+            sanitize_url("https://api.spotify.com/v1/users/alice?fields=id"),
+            format!("https://api.spotify.com/v1/users/{id}?fields=id")
+        );
+    }
+
+    #[test]
+    fn sanitize_replaces_users_by_type() {
+        let mut me = json!({
+            "id": "alice",
+            "type": "user",
+            "email": "alice@example.com",
+            "display_name": "Alice Smith",
+            "images": [{ "url": "https://i.scdn.co/image/alice" }],
+            "country": "NL",
+        });
+        sanitize(&mut me, false);
+
+        assert_eq!(
+            me,
+            json!({
+                "id": pseudonym("alice"),
+                "type": "user",
+                "email": "",
+                "display_name": null,
+                "images": [],
+                "country": "NL",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn record_writes_sanitized_fixture_that_replays() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = "https://api.spotify.com/v1/users/alice";
+        let resp: Response = http::Response::builder()
+            .status(200)
+            .header(ETAG, "\"1\"")
+            .body(r#"{"id":"alice","type":"user","display_name":"Alice"}"#)
+            .unwrap()
+            .into();
+
+        let resp = record(dir.path(), &Method::GET, url, None, resp)
+            .await
+            .unwrap();
+        // the client carries on with what Spotify sent
+        assert_eq!(resp.json::<Value>().await.unwrap()["display_name"], "Alice");
+
+        for entry in std::fs::read_dir(dir.path()).unwrap() {
+            let text = std::fs::read_to_string(entry.unwrap().path()).unwrap();
+            assert!(!text.contains("alice"), "{text}");
+            assert!(!text.contains("Alice"), "{text}");
+        }
+
+        let url = format!("https://api.spotify.com/v1/users/{}", pseudonym("alice"));
+        let replayed: Value = replay(dir.path(), &Method::GET, &url, None)
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(replayed["id"], pseudonym("alice"));
+    }
+}
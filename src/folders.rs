@@ -0,0 +1,242 @@
+use std::{collections::HashMap, io::IsTerminal, path::Path};
+
+use anyhow::{Context, Result};
+use reqwest::header::{ACCEPT, AUTHORIZATION, COOKIE};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::{cli::Args, output, profile::Profile, GetPlaylistsResponseItem};
+
+/// Endpoint the web player gets its access token from, given the `sp_dc` cookie of a signed in
+/// session. Unlike the tokens of the official API, these can read the rootlist.
+const TOKEN_URL: &str =
+    "https://open.spotify.com/get_access_token?reason=transport&productType=web_player";
+
+/// Endpoint the Spotify clients read the user's library of playlists from, folders included.
+const ROOTLIST_URL: &str = "https://spclient.wg.spotify.com/playlist/v2/user";
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct WebPlayerToken {
+    access_token: String,
+    #[serde(default)]
+    is_anonymous: bool,
+}
+
+/// The user's playlists and folders in the order the clients show them, with folders flattened
+/// into `start-group` and `end-group` markers around their contents.
+#[derive(Deserialize, Debug)]
+struct Rootlist {
+    contents: RootlistContents,
+}
+
+#[derive(Deserialize, Debug)]
+struct RootlistContents {
+    #[serde(default)]
+    items: Vec<RootlistItem>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RootlistItem {
+    uri: String,
+}
+
+/// A folder or a playlist in the folder tree.
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Node {
+    Folder {
+        id: String,
+        name: String,
+        children: Vec<Node>,
+    },
+    Playlist {
+        id: String,
+        /// Missing for playlists no longer in the library returned by the official API
+        name: Option<String>,
+    },
+}
+
+/// Prints the tree of folders the user's playlists are organised into, read from the rootlist
+/// the Spotify clients use, with either the web player session of `sp_dc` or a saved response.
+pub async fn run(args: &Args, sp_dc: Option<&str>, rootlist: Option<&Path>) -> Result<()> {
+    let profile = Profile::new(&args.profile)?;
+    let client = crate::cli::build_client(&profile, args).await?;
+
+    let playlists: Vec<GetPlaylistsResponseItem> =
+        crate::cli::fetch_all(&client, args.api.first_page_url("me/playlists", 50))
+            .await
+            .context("Failed to fetch playlists")?;
+    let names: HashMap<_, _> = playlists.into_iter().map(|v| (v.id, v.name)).collect();
+
+    let rootlist = match (sp_dc, rootlist) {
+        (_, Some(path)) => read_rootlist(path).await?,
+        (Some(sp_dc), None) => {
+            let user = crate::fetch_current_user(&client).await?;
+            fetch_rootlist(sp_dc, &user.id).await?
+        }
+        (None, None) => anyhow::bail!("Either --sp-dc or --rootlist has to be given"),
+    };
+
+    let tree = build_tree(rootlist, &names);
+    let terminal = std::io::stdout().is_terminal();
+
+    match args.format {
+        output::Format::Json => {
+            let json = if args.pretty || terminal {
+                serde_json::to_string_pretty(&tree)?
+            } else {
+                serde_json::to_string(&tree)?
+            };
+            println!("{json}");
+        }
+        output::Format::Table => {
+            let mut table = output::table(&["Folder", "Name", "ID"], terminal, &[]);
+            add_rows(&mut table, &tree, &mut Vec::new());
+            println!("{table}");
+        }
+        _ => {
+            anyhow::bail!("Folders can only be written as JSON or a table")
+        }
+    }
+
+    Ok(())
+}
+
+async fn read_rootlist(path: &Path) -> Result<Rootlist> {
+    let data = tokio::fs::read(path)
+        .await
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+
+    serde_json::from_slice(&data).with_context(|| {
+        format!(
+            "{} isn't a rootlist response, save the response to the rootlist request instead",
+            path.display()
+        )
+    })
+}
+
+async fn fetch_rootlist(sp_dc: &str, user: &str) -> Result<Rootlist> {
+    let client = crate::http::builder().build()?;
+
+    let token: WebPlayerToken = client
+        .get(TOKEN_URL)
+        .header(COOKIE, format!("sp_dc={sp_dc}"))
+        .send()
+        .await
+        .and_then(|v| v.error_for_status())
+        .context("Failed to get a web player token, the unofficial endpoint may have changed")?
+        .json()
+        .await
+        .context("Failed to parse web player token")?;
+    if token.is_anonymous {
+        anyhow::bail!("The sp_dc cookie isn't signed in, copy it again from open.spotify.com");
+    }
+
+    let url = format!(
+        "{ROOTLIST_URL}/{}/rootlist?decorate=revision,attributes&from=0&length=100000",
+        percent_encoding::utf8_percent_encode(user, percent_encoding::NON_ALPHANUMERIC)
+    );
+    debug!(url, "Fetching {url}...");
+
+    client
+        .get(&url)
+        .header(AUTHORIZATION, format!("Bearer {}", token.access_token))
+        .header(ACCEPT, "application/json")
+        .send()
+        .await
+        .and_then(|v| v.error_for_status())
+        .context("Failed to fetch folders, the unofficial endpoint may have changed")?
+        .json()
+        .await
+        .context("Failed to parse folders, the unofficial endpoint may have changed")
+}
+
+/// Nests the playlists of `rootlist` into the folders whose markers they're between.
+fn build_tree(rootlist: Rootlist, names: &HashMap<String, String>) -> Vec<Node> {
+    // folders still open, each with the nodes read into it so far, below the top level
+    let mut open: Vec<(String, String, Vec<Node>)> = Vec::new();
+    let mut top = Vec::new();
+
+    for item in rootlist.contents.items {
+        let mut parts = item.uri.splitn(4, ':');
+        let node = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some("spotify"), Some("start-group"), Some(id), name) => {
+                open.push((
+                    id.to_string(),
+                    folder_name(name.unwrap_or_default()),
+                    Vec::new(),
+                ));
+                continue;
+            }
+            (Some("spotify"), Some("end-group"), Some(id), _) => {
+                let Some((open_id, name, children)) = open.pop() else {
+                    warn!("Folder {id} was closed without being opened, ignoring it");
+                    continue;
+                };
+                if open_id != id {
+                    warn!("Folder {open_id} was closed by {id}, the folders may be misnested");
+                }
+
+                Node::Folder {
+                    id: open_id,
+                    name,
+                    children,
+                }
+            }
+            (Some("spotify"), Some("playlist"), Some(id), None) => Node::Playlist {
+                id: id.to_string(),
+                name: names.get(id).cloned(),
+            },
+            _ => {
+                debug!(uri = item.uri, "Skipping {} in rootlist", item.uri);
+                continue;
+            }
+        };
+
+        match open.last_mut() {
+            Some((_, _, children)) => children.push(node),
+            None => top.push(node),
+        }
+    }
+
+    // a truncated rootlist can leave folders open, which are kept with what they held so far
+    while let Some((id, name, children)) = open.pop() {
+        warn!("Folder {name} was never closed, the rootlist may be truncated");
+        let node = Node::Folder { id, name, children };
+        match open.last_mut() {
+            Some((_, _, children)) => children.push(node),
+            None => top.push(node),
+        }
+    }
+
+    top
+}
+
+/// Name of a folder as it's encoded in its `start-group` URI, with spaces as `+`.
+fn folder_name(encoded: &str) -> String {
+    percent_encoding::percent_decode_str(&encoded.replace('+', " "))
+        .decode_utf8_lossy()
+        .into_owned()
+}
+
+/// Adds a row for every playlist under `nodes`, with the path of folders it's in.
+fn add_rows(table: &mut comfy_table::Table, nodes: &[Node], path: &mut Vec<String>) {
+    for node in nodes {
+        match node {
+            Node::Folder { name, children, .. } => {
+                path.push(name.clone());
+                add_rows(table, children, path);
+                path.pop();
+            }
+            Node::Playlist { id, name } => output::add_row(
+                table,
+                [
+                    path.join(" / ").as_str(),
+                    name.as_deref().unwrap_or_default(),
+                    id.as_str(),
+                ],
+            ),
+        }
+    }
+}
@@ -0,0 +1,263 @@
+use anyhow::{Context, Result};
+use reqwest::{Method, Url};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    map::{Candidate, Service, Track},
+    sync::Destination,
+};
+
+/// Most search results scored for each track.
+const MAX_RESULTS: &str = "5";
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct FunkwhaleArgs {
+    /// URL of the Funkwhale pod (eg. https://funkwhale.example.com)
+    #[arg(long, env = "FUNKWHALE_URL", global = true)]
+    pub funkwhale_url: Option<Url>,
+    /// Access token of an application created in the pod's settings, with the read:libraries and
+    /// write:playlists scopes
+    #[arg(long, env = "FUNKWHALE_TOKEN", hide_env_values = true, global = true)]
+    pub funkwhale_token: Option<String>,
+}
+
+/// A Funkwhale pod, with the user whose token is used owning the playlists.
+pub struct Funkwhale {
+    http: reqwest::Client,
+    url: Url,
+    token: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Page<T> {
+    results: Vec<T>,
+}
+
+#[derive(Deserialize, Debug)]
+struct FunkwhaleTrack {
+    id: u64,
+    title: String,
+    /// Artist of pods before 1.4
+    artist: Option<Artist>,
+    /// Every artist credited, on pods from 1.4
+    #[serde(default)]
+    artist_credit: Vec<ArtistCredit>,
+    album: Option<Album>,
+    #[serde(default)]
+    uploads: Vec<Upload>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Artist {
+    name: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ArtistCredit {
+    artist: Artist,
+}
+
+#[derive(Deserialize, Debug)]
+struct Album {
+    id: u64,
+    title: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Upload {
+    /// Length in seconds
+    duration: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Playlist {
+    id: u64,
+    name: String,
+}
+
+#[derive(Serialize, Debug)]
+struct CreatePlaylist<'a> {
+    name: &'a str,
+    privacy_level: &'a str,
+}
+
+#[derive(Serialize, Debug)]
+struct AddTracks {
+    tracks: Vec<u64>,
+    allow_duplicates: bool,
+}
+
+impl Funkwhale {
+    pub async fn connect(funkwhale: &FunkwhaleArgs) -> Result<Self> {
+        let url = funkwhale
+            .funkwhale_url
+            .clone()
+            .context("Missing Funkwhale pod URL, set FUNKWHALE_URL")?;
+        let token = funkwhale
+            .funkwhale_token
+            .clone()
+            .context("Missing Funkwhale token, set FUNKWHALE_TOKEN")?;
+
+        let pod = Self {
+            http: crate::http::client(),
+            url,
+            token,
+        };
+        pod.send(Method::GET, "api/v1/users/me/", &[], None::<&()>)
+            .await
+            .context("Failed to connect to Funkwhale pod")?;
+
+        Ok(pod)
+    }
+
+    async fn request<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        params: &[(&str, &str)],
+        body: Option<&impl Serialize>,
+    ) -> Result<T> {
+        self.send(method, path, params, body)
+            .await?
+            .json()
+            .await
+            .context("Failed to parse response from Funkwhale")
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        params: &[(&str, &str)],
+        body: Option<&impl Serialize>,
+    ) -> Result<reqwest::Response> {
+        let url = self.url.join(path)?;
+
+        let mut request = self
+            .http
+            .request(method, url)
+            .query(params)
+            .bearer_auth(&self.token);
+        if let Some(body) = body {
+            request = request.json(body);
+        }
+
+        request
+            .send()
+            .await
+            .context("Failed to send request to Funkwhale")?
+            .error_for_status()
+            .context("Failed to query Funkwhale")
+    }
+}
+
+impl Service for Funkwhale {
+    fn name(&self) -> &'static str {
+        "funkwhale"
+    }
+
+    /// Searches the pod for tracks that can be played, by the track's artist and title.
+    async fn search(&self, track: &Track) -> Result<Vec<Candidate>> {
+        let query = match track.artists.first() {
+            Some(artist) => format!("{artist} {}", track.name),
+            None => track.name.clone(),
+        };
+        let page: Page<FunkwhaleTrack> = self
+            .request(
+                Method::GET,
+                "api/v1/tracks/",
+                &[
+                    ("q", query.as_str()),
+                    ("playable", "true"),
+                    ("page_size", MAX_RESULTS),
+                ],
+                None::<&()>,
+            )
+            .await?;
+
+        Ok(page
+            .results
+            .into_iter()
+            .map(|v| {
+                let artists = match v.artist_credit.is_empty() {
+                    true => v.artist.into_iter().map(|v| v.name).collect(),
+                    false => v.artist_credit.into_iter().map(|v| v.artist.name).collect(),
+                };
+
+                Candidate {
+                    id: v.id.to_string(),
+                    title: v.title,
+                    artists,
+                    album_id: v.album.as_ref().map(|v| v.id.to_string()),
+                    album: v.album.map(|v| v.title),
+                    duration_ms: v.uploads.first().and_then(|v| v.duration).map(|v| v * 1000),
+                    by_isrc: false,
+                }
+            })
+            .collect())
+    }
+}
+
+impl Destination for Funkwhale {
+    async fn replace_playlist(&self, name: &str, ids: &[String]) -> Result<bool> {
+        let playlists: Page<Playlist> = self
+            .request(
+                Method::GET,
+                "api/v1/playlists/",
+                &[("scope", "me"), ("q", name)],
+                None::<&()>,
+            )
+            .await
+            .context("Failed to list Funkwhale playlists")?;
+        let existing = playlists.results.into_iter().find(|v| v.name == name);
+
+        let (id, created) = match existing {
+            Some(playlist) => {
+                self.send(
+                    Method::DELETE,
+                    &format!("api/v1/playlists/{}/clear/", playlist.id),
+                    &[],
+                    None::<&()>,
+                )
+                .await
+                .with_context(|| format!("Failed to clear Funkwhale playlist {name}"))?;
+
+                (playlist.id, false)
+            }
+            None => {
+                let body = CreatePlaylist {
+                    name,
+                    privacy_level: "me",
+                };
+                let playlist: Playlist = self
+                    .request(Method::POST, "api/v1/playlists/", &[], Some(&body))
+                    .await
+                    .with_context(|| format!("Failed to create Funkwhale playlist {name}"))?;
+
+                (playlist.id, true)
+            }
+        };
+
+        if !ids.is_empty() {
+            let body = AddTracks {
+                tracks: ids
+                    .iter()
+                    .map(|v| v.parse())
+                    .collect::<Result<_, _>>()
+                    .context("Funkwhale track IDs are numbers")?,
+                // a playlist can list the same track twice
+                allow_duplicates: true,
+            };
+            self.send(
+                Method::POST,
+                &format!("api/v1/playlists/{id}/add/"),
+                &[],
+                Some(&body),
+            )
+            .await
+            .with_context(|| format!("Failed to add tracks to Funkwhale playlist {name}"))?;
+        }
+
+        Ok(created)
+    }
+}
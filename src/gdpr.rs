@@ -0,0 +1,258 @@
+use std::{collections::HashMap, io::Read, path::Path, time::Duration};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::Deserialize;
+use tracing::{debug, info};
+
+use crate::scrobble::{self, Play};
+
+/// How far apart two plays of the same track can be logged and still be the same play. The
+/// account data only has the minute a track finished, and the listening history and the extended
+/// history don't agree to the second.
+const SAME_PLAY: chrono::Duration = chrono::Duration::seconds(60);
+
+/// A play from either kind of streaming history in Spotify's privacy data download.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum Entry {
+    Extended(ExtendedEntry),
+    Account(AccountEntry),
+}
+
+/// A play from the extended streaming history (`Streaming_History_Audio_*.json`, or
+/// `endsong_*.json` in older downloads), which has every play since the account was created.
+#[derive(Deserialize, Debug)]
+struct ExtendedEntry {
+    /// When the track stopped playing
+    ts: DateTime<Utc>,
+    ms_played: u64,
+    master_metadata_track_name: Option<String>,
+    master_metadata_album_artist_name: Option<String>,
+    master_metadata_album_album_name: Option<String>,
+    spotify_track_uri: Option<String>,
+    episode_name: Option<String>,
+    episode_show_name: Option<String>,
+    spotify_episode_uri: Option<String>,
+}
+
+/// A play from the account data (`StreamingHistory*.json`), which only has the last year and no
+/// URIs.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct AccountEntry {
+    /// When the track stopped playing, in UTC to the minute (eg. 2023-01-01 12:34)
+    end_time: String,
+    ms_played: u64,
+    artist_name: Option<String>,
+    track_name: Option<String>,
+    podcast_name: Option<String>,
+    episode_name: Option<String>,
+}
+
+impl Entry {
+    /// The play as it's logged by scrobble-log, or `None` for plays of something that's neither a
+    /// track nor an episode (eg. an audiobook).
+    fn into_play(self) -> Result<Option<Play>> {
+        let play = match self {
+            Self::Extended(v) => {
+                let (uri, name, artists, album) =
+                    match (v.master_metadata_track_name, v.episode_name) {
+                        (Some(name), _) => (
+                            v.spotify_track_uri,
+                            name,
+                            v.master_metadata_album_artist_name,
+                            v.master_metadata_album_album_name,
+                        ),
+                        (None, Some(name)) => {
+                            (v.spotify_episode_uri, name, None, v.episode_show_name)
+                        }
+                        (None, None) => return Ok(None),
+                    };
+
+                Play {
+                    played_at: v.ts,
+                    uri: uri.unwrap_or_default(),
+                    name,
+                    artists: artists.into_iter().collect(),
+                    album,
+                    duration_ms: None,
+                    ms_played: Some(v.ms_played),
+                    context: None,
+                }
+            }
+            Self::Account(v) => {
+                let played_at = NaiveDateTime::parse_from_str(&v.end_time, "%Y-%m-%d %H:%M")
+                    .with_context(|| format!("Invalid endTime {:?}", v.end_time))?
+                    .and_utc();
+                let (name, artists, album) = match (v.track_name, v.episode_name) {
+                    (Some(name), _) => (name, v.artist_name, None),
+                    (None, Some(name)) => (name, None, v.podcast_name),
+                    (None, None) => return Ok(None),
+                };
+
+                Play {
+                    played_at,
+                    uri: String::new(),
+                    name,
+                    artists: artists.into_iter().collect(),
+                    album,
+                    duration_ms: None,
+                    ms_played: Some(v.ms_played),
+                    context: None,
+                }
+            }
+        };
+
+        Ok(Some(play))
+    }
+}
+
+/// Imports the streaming history from Spotify's privacy data download at `path`, either the zip
+/// file or the directory it was extracted into, into the play log at `log`. Plays shorter than
+/// `min_played` aren't imported, and neither are plays already in the log.
+pub async fn import(path: &Path, log: &Path, min_played: Duration) -> Result<()> {
+    let path = path.to_path_buf();
+    let files = tokio::task::spawn_blocking(move || read_history(&path)).await??;
+    anyhow::ensure!(
+        !files.is_empty(),
+        "No streaming history found, expected StreamingHistory*.json, Streaming_History_Audio_*.json \
+         or endsong_*.json files"
+    );
+
+    let mut imported = Vec::new();
+    for (name, data) in &files {
+        let entries: Vec<Entry> =
+            serde_json::from_slice(data).with_context(|| format!("Failed to parse {name}"))?;
+        debug!("Read {} play(s) from {name}", entries.len());
+
+        for entry in entries {
+            if let Some(play) = entry
+                .into_play()
+                .with_context(|| format!("Failed to read {name}"))?
+            {
+                imported.push(play);
+            }
+        }
+    }
+
+    let min_played = u64::try_from(min_played.as_millis()).unwrap_or(u64::MAX);
+    let read = imported.len();
+    imported.retain(|v| v.ms_played.unwrap_or_default() >= min_played);
+    let short = read - imported.len();
+
+    let mut plays = if tokio::fs::try_exists(log).await.unwrap_or_default() {
+        scrobble::read_plays(log).await?
+    } else {
+        Vec::new()
+    };
+    let logged = plays.len();
+
+    // both kinds of history can be in the same download and overlap each other, as well as what
+    // was already logged, so plays are compared with every one kept so far
+    imported.sort_by_key(|v| v.played_at);
+    let mut seen: HashMap<(String, String), Vec<DateTime<Utc>>> = HashMap::new();
+    for play in &plays {
+        seen.entry(key(play)).or_default().push(play.played_at);
+    }
+    let mut duplicates = 0;
+    for play in imported {
+        let times = seen.entry(key(&play)).or_default();
+        if times
+            .iter()
+            .any(|v| (*v - play.played_at).abs() <= SAME_PLAY)
+        {
+            duplicates += 1;
+            continue;
+        }
+        times.push(play.played_at);
+        plays.push(play);
+    }
+    let added = plays.len() - logged;
+
+    // the log stays in the order the plays were made, as scrobble-log appends them
+    plays.sort_by_key(|v| v.played_at);
+    let mut data = Vec::new();
+    for play in &plays {
+        serde_json::to_writer(&mut data, play).context("Failed to serialize play")?;
+        data.push(b'\n');
+    }
+    crate::atomic::write(log, data)
+        .await
+        .with_context(|| format!("Failed to write {}", log.display()))?;
+
+    info!(
+        "Imported {added} play(s) from {} file(s) into {}, skipping {duplicates} already logged \
+         and {short} shorter than --min-played",
+        files.len(),
+        log.display()
+    );
+
+    Ok(())
+}
+
+/// Plays of the same track or episode have the same key, whichever history they came from: the
+/// name along with the first artist of a track, or the show of an episode.
+fn key(play: &Play) -> (String, String) {
+    let by = if play.uri.starts_with("spotify:episode:") || play.artists.is_empty() {
+        play.album.as_ref()
+    } else {
+        play.artists.first()
+    };
+
+    (
+        play.name.to_lowercase(),
+        by.map(|v| v.to_lowercase()).unwrap_or_default(),
+    )
+}
+
+/// Whether the file named `name` holds streaming history, rather than the rest of the account's
+/// data (eg. Userdata.json or the video history).
+fn is_history(name: &str) -> bool {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+
+    name.ends_with(".json")
+        && (name.starts_with("StreamingHistory")
+            || name.starts_with("Streaming_History_Audio_")
+            || name.starts_with("endsong"))
+}
+
+/// Reads every file of streaming history in the zip file or directory at `path`, with its name.
+fn read_history(path: &Path) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut files = Vec::new();
+
+    if path.is_dir() {
+        for entry in walkdir::WalkDir::new(path).sort_by_file_name() {
+            let entry = entry.with_context(|| format!("Failed to read {}", path.display()))?;
+            let name = entry.file_name().to_string_lossy();
+            if entry.file_type().is_file() && is_history(&name) {
+                let data = std::fs::read(entry.path())
+                    .with_context(|| format!("Failed to read {}", entry.path().display()))?;
+                files.push((name.into_owned(), data));
+            }
+        }
+    } else {
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        let mut archive = zip::ZipArchive::new(file)
+            .with_context(|| format!("{} isn't a zip file or a directory", path.display()))?;
+
+        let mut names: Vec<_> = archive
+            .file_names()
+            .filter(|v| is_history(v))
+            .map(str::to_string)
+            .collect();
+        names.sort();
+
+        for name in names {
+            let mut data = Vec::new();
+            archive
+                .by_name(&name)?
+                .read_to_end(&mut data)
+                .with_context(|| format!("Failed to extract {name}"))?;
+            files.push((name, data));
+        }
+    }
+
+    Ok(files)
+}
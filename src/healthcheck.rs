@@ -0,0 +1,75 @@
+use std::time::Duration;
+
+use reqwest::Url;
+use tracing::{debug, warn};
+
+/// Most a ping body can be, as healthchecks.io ignores anything past it.
+const MAX_BODY: usize = 100_000;
+
+/// A run being monitored by a healthchecks.io-style check, pinged when it starts and again with
+/// its outcome and logs when it finishes.
+pub struct Healthcheck {
+    url: Url,
+    client: reqwest::Client,
+}
+
+impl Healthcheck {
+    /// Pings `<url>/start` and starts recording log lines to send with the final ping.
+    pub async fn start(url: &Url) -> Self {
+        let check = Self {
+            url: url.clone(),
+            client: crate::http::client(),
+        };
+
+        check.ping("start", String::new()).await;
+        crate::progress::record_logs();
+
+        check
+    }
+
+    /// Pings `<url>` if the run succeeded or `<url>/fail` if it failed with `error`, along with
+    /// the run's log.
+    pub async fn finish(self, error: Option<&anyhow::Error>) {
+        let mut log = crate::progress::take_recorded_logs();
+        if let Some(e) = error {
+            log.push_str(&format!("Error: {e:#}\n"));
+        }
+
+        // the end of the log is where anything went wrong
+        if log.len() > MAX_BODY {
+            let mut start = log.len() - MAX_BODY;
+            while !log.is_char_boundary(start) {
+                start += 1;
+            }
+            log.drain(..start);
+        }
+
+        let endpoint = if error.is_some() { "fail" } else { "" };
+        self.ping(endpoint, log).await;
+    }
+
+    /// Pings `endpoint` of the check. Failing to do so is only logged, so a monitoring service
+    /// that's down doesn't fail the backup.
+    async fn ping(&self, endpoint: &str, body: String) {
+        let mut url = self.url.clone();
+        if !endpoint.is_empty() {
+            if let Ok(mut segments) = url.path_segments_mut() {
+                segments.pop_if_empty().push(endpoint);
+            }
+        }
+
+        let response = self
+            .client
+            .post(url.clone())
+            .timeout(Duration::from_secs(10))
+            .body(body)
+            .send()
+            .await
+            .and_then(|v| v.error_for_status());
+
+        match response {
+            Ok(_) => debug!(%url, "Pinged healthcheck"),
+            Err(e) => warn!("Failed to ping healthcheck: {e}"),
+        }
+    }
+}
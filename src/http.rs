@@ -0,0 +1,137 @@
+use std::{path::PathBuf, sync::OnceLock, time::Duration};
+
+use anyhow::{Context, Result};
+use reqwest::{Certificate, ClientBuilder, NoProxy, Proxy, Url};
+use tracing::warn;
+
+/// Settings every HTTP client is built with, set once at startup by [`init`].
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+/// How requests reach Spotify and upload destinations.
+#[derive(clap::Args, Debug, Clone)]
+pub struct HttpArgs {
+    /// Sends every request through the given proxy (eg. http://proxy:3128 or
+    /// socks5h://localhost:1080) instead of the one in HTTPS_PROXY, HTTP_PROXY or ALL_PROXY
+    #[arg(
+        long,
+        env = "SPOTIFY_BACKUP_PROXY",
+        hide_env_values = true,
+        global = true
+    )]
+    pub proxy: Option<Url>,
+    /// PEM file of CA certificates to trust on top of the system's, for TLS-intercepting proxies,
+    /// may be given multiple times
+    #[arg(long, env = "SPOTIFY_BACKUP_CA_CERT", global = true)]
+    pub ca_cert: Vec<PathBuf>,
+    /// Doesn't verify TLS certificates at all, which leaves tokens open to interception and should
+    /// only be used for testing
+    #[arg(long, env = "SPOTIFY_BACKUP_INSECURE", global = true)]
+    pub insecure: bool,
+    /// How long connecting to a server may take before the request fails
+    #[arg(
+        long,
+        env = "SPOTIFY_BACKUP_CONNECT_TIMEOUT",
+        value_parser = humantime::parse_duration,
+        default_value = "10s",
+        global = true
+    )]
+    pub connect_timeout: Duration,
+    /// How long a server may go without sending anything before the request fails
+    #[arg(
+        long,
+        env = "SPOTIFY_BACKUP_READ_TIMEOUT",
+        value_parser = humantime::parse_duration,
+        default_value = "60s",
+        global = true
+    )]
+    pub read_timeout: Duration,
+    /// How long a whole request may take, including sending and receiving its body, before it
+    /// fails (eg. 5m). Unlimited by default, so large uploads aren't cut short
+    #[arg(
+        long,
+        env = "SPOTIFY_BACKUP_TIMEOUT",
+        value_parser = humantime::parse_duration,
+        global = true
+    )]
+    pub timeout: Option<Duration>,
+}
+
+struct Settings {
+    proxy: Option<Proxy>,
+    ca_certs: Vec<Certificate>,
+    insecure: bool,
+    connect_timeout: Duration,
+    read_timeout: Duration,
+    timeout: Option<Duration>,
+}
+
+/// Applies `args` to every HTTP client built from here on.
+pub async fn init(args: &HttpArgs) -> Result<()> {
+    let proxy = match &args.proxy {
+        // hosts in NO_PROXY are still reached directly
+        Some(url) => Some(
+            Proxy::all(url.clone())
+                .with_context(|| format!("Invalid proxy {url}"))?
+                .no_proxy(NoProxy::from_env()),
+        ),
+        None => None,
+    };
+
+    let mut ca_certs = Vec::new();
+    for path in &args.ca_cert {
+        let pem = tokio::fs::read(path)
+            .await
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        ca_certs.extend(
+            Certificate::from_pem_bundle(&pem)
+                .with_context(|| format!("Failed to parse certificates in {}", path.display()))?,
+        );
+    }
+
+    if args.insecure {
+        warn!(
+            "TLS certificates aren't being verified, anyone on the network can read the requests"
+        );
+    }
+
+    let _ = SETTINGS.set(Settings {
+        proxy,
+        ca_certs,
+        insecure: args.insecure,
+        connect_timeout: args.connect_timeout,
+        read_timeout: args.read_timeout,
+        timeout: args.timeout,
+    });
+
+    Ok(())
+}
+
+/// Builder for an HTTP client with the settings given to [`init`]. Without `--proxy`, requests go
+/// through the proxy in `HTTPS_PROXY`, `HTTP_PROXY` or `ALL_PROXY` (including SOCKS proxies).
+pub fn builder() -> ClientBuilder {
+    let mut builder = reqwest::Client::builder();
+    let Some(settings) = SETTINGS.get() else {
+        return builder;
+    };
+
+    if let Some(proxy) = &settings.proxy {
+        builder = builder.proxy(proxy.clone());
+    }
+    for cert in &settings.ca_certs {
+        builder = builder.add_root_certificate(cert.clone());
+    }
+    if let Some(timeout) = settings.timeout {
+        builder = builder.timeout(timeout);
+    }
+
+    builder
+        .danger_accept_invalid_certs(settings.insecure)
+        .connect_timeout(settings.connect_timeout)
+        .read_timeout(settings.read_timeout)
+}
+
+/// HTTP client without any settings beyond those of [`builder`].
+pub fn client() -> reqwest::Client {
+    // only fails if the TLS backend can't be initialised, as with `reqwest::Client::new`
+    builder().build().expect("Failed to build HTTP client")
+}
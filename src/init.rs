@@ -0,0 +1,104 @@
+use anyhow::{Context, Result};
+use dialoguer::{theme::ColorfulTheme, Input, Select};
+
+use crate::{
+    authentication,
+    cli::Args,
+    compression::Compression,
+    config::{Config, ProfileConfig},
+    profile::Profile,
+    Error,
+};
+
+/// Walks through setting up a profile, writes the answers to the config file and performs the
+/// initial authentication.
+pub async fn run(args: &Args) -> Result<()> {
+    anyhow::ensure!(
+        !args.auth.non_interactive,
+        "init asks questions, it can't be run with --non-interactive, write the config file instead"
+    );
+
+    let mut config = Config::load(args.config.as_deref()).await?;
+    let theme = ColorfulTheme::default();
+
+    eprintln!("Setting up spotify-backup, press enter to accept the suggested values.\n");
+
+    let name: String = Input::with_theme(&theme)
+        .with_prompt("Profile name")
+        .default(args.profile.clone())
+        .validate_with(|v: &String| Profile::new(v).map(|_| ()).map_err(|e| e.to_string()))
+        .interact_text()
+        .context("Failed to read profile name")?;
+    let profile = Profile::new(&name)?;
+    let existing = config.resolve(&name);
+
+    let client_id: String = Input::with_theme(&theme)
+        .with_prompt("Client ID of your own Spotify app (leave empty to use the shared app)")
+        .with_initial_text(existing.client_id.unwrap_or_default())
+        .allow_empty(true)
+        .interact_text()
+        .context("Failed to read client ID")?;
+
+    let output: String = Input::with_theme(&theme)
+        .with_prompt("Directory to write backups into (leave empty to print them to stdout)")
+        .with_initial_text(
+            existing
+                .output
+                .map(|v| v.display().to_string())
+                .unwrap_or_default(),
+        )
+        .allow_empty(true)
+        .interact_text()
+        .context("Failed to read output directory")?;
+
+    let compressions = [None, Some(Compression::Zstd), Some(Compression::Gzip)];
+    let compression = Select::with_theme(&theme)
+        .with_prompt("Compression")
+        .items(&["none", "zstd", "gzip"])
+        .default(
+            compressions
+                .iter()
+                .position(|v| *v == existing.compress)
+                .unwrap_or_default(),
+        )
+        .interact()
+        .context("Failed to read compression")?;
+
+    // only the answers are written, any other settings of the profile are kept as they were
+    let profile_config = ProfileConfig {
+        client_id: Some(client_id.trim().to_string()).filter(|v| !v.is_empty()),
+        output: Some(output.trim())
+            .filter(|v| !v.is_empty())
+            .map(Into::into),
+        compress: compressions[compression],
+        ..config.profile(&name).cloned().unwrap_or_default()
+    };
+
+    let mut auth = args.auth.clone();
+    auth.client_id = profile_config
+        .client_id
+        .clone()
+        .unwrap_or_else(|| authentication::CLIENT_ID.to_string());
+
+    config.profiles.insert(name, profile_config);
+    config.save(args.config.as_deref()).await?;
+    eprintln!(
+        "\nWrote {}",
+        Config::path(args.config.as_deref())?.display()
+    );
+
+    let token = authentication::authenticate(&profile, &auth, &[])
+        .await
+        .map_err(|e| Error::Auth(e.into()))?;
+    let user =
+        crate::fetch_current_user(&crate::client_with_token(&token, &args.api, &profile)?).await?;
+
+    eprintln!(
+        "Authenticated profile {} as {} ({}), you're all set",
+        profile.name(),
+        user.display_name.as_deref().unwrap_or("-"),
+        user.id
+    );
+
+    Ok(())
+}
@@ -0,0 +1,248 @@
+use anyhow::{Context, Result};
+use reqwest::{Method, Url};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    dupes,
+    map::{Candidate, Service, Track},
+    sync::Destination,
+};
+
+/// Most search results scored for each track.
+const MAX_RESULTS: &str = "5";
+
+/// Jellyfin gives lengths in ticks of 100 nanoseconds.
+const TICKS_PER_MS: u64 = 10_000;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct JellyfinArgs {
+    /// URL of the Jellyfin server (eg. http://192.168.1.10:8096)
+    #[arg(long, env = "JELLYFIN_URL", global = true)]
+    pub jellyfin_url: Option<Url>,
+    /// API key to authenticate with, created in the server's dashboard under API Keys
+    #[arg(long, env = "JELLYFIN_API_KEY", hide_env_values = true, global = true)]
+    pub jellyfin_api_key: Option<String>,
+    /// Name of the user the playlists belong to, needed if the server has more than one
+    #[arg(long, env = "JELLYFIN_USER", global = true)]
+    pub jellyfin_user: Option<String>,
+}
+
+/// A Jellyfin server, with the user whose library tracks are matched in and who owns the
+/// playlists.
+pub struct Jellyfin {
+    http: reqwest::Client,
+    url: Url,
+    api_key: String,
+    user_id: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct User {
+    id: String,
+    name: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct Items {
+    #[serde(default)]
+    items: Vec<Item>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct Item {
+    id: String,
+    name: String,
+    #[serde(default)]
+    artists: Vec<String>,
+    album: Option<String>,
+    album_id: Option<String>,
+    run_time_ticks: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct CreatePlaylist<'a> {
+    name: &'a str,
+    ids: &'a [String],
+    user_id: &'a str,
+    media_type: &'a str,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct UpdatePlaylist<'a> {
+    ids: &'a [String],
+}
+
+impl Jellyfin {
+    pub async fn connect(jellyfin: &JellyfinArgs) -> Result<Self> {
+        let url = jellyfin
+            .jellyfin_url
+            .clone()
+            .context("Missing Jellyfin server URL, set JELLYFIN_URL")?;
+        let api_key = jellyfin
+            .jellyfin_api_key
+            .clone()
+            .context("Missing Jellyfin API key, set JELLYFIN_API_KEY")?;
+
+        let mut server = Self {
+            http: crate::http::client(),
+            url,
+            api_key,
+            user_id: String::new(),
+        };
+
+        // API keys aren't tied to a user, but searching libraries and owning playlists needs one
+        let users: Vec<User> = server
+            .request(Method::GET, "Users", &[], None::<&()>)
+            .await
+            .context("Failed to connect to Jellyfin server")?;
+        let user = match &jellyfin.jellyfin_user {
+            Some(name) => users
+                .iter()
+                .find(|v| v.name.eq_ignore_ascii_case(name))
+                .with_context(|| format!("Jellyfin server has no user named {name}"))?,
+            None => match users.as_slice() {
+                [user] => user,
+                _ => {
+                    let names: Vec<_> = users.iter().map(|v| v.name.as_str()).collect();
+                    anyhow::bail!(
+                        "Jellyfin server has several users, pick one with --jellyfin-user: {}",
+                        names.join(", ")
+                    )
+                }
+            },
+        };
+        server.user_id = user.id.clone();
+
+        Ok(server)
+    }
+
+    async fn request<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        params: &[(&str, &str)],
+        body: Option<&impl Serialize>,
+    ) -> Result<T> {
+        self.send(method, path, params, body)
+            .await?
+            .json()
+            .await
+            .context("Failed to parse response from Jellyfin")
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        params: &[(&str, &str)],
+        body: Option<&impl Serialize>,
+    ) -> Result<reqwest::Response> {
+        let url = self.url.join(path)?;
+
+        let mut request = self.http.request(method, url).query(params).header(
+            "Authorization",
+            format!(
+                "MediaBrowser Client=\"{}\", Version=\"{}\", Token=\"{}\"",
+                env!("CARGO_PKG_NAME"),
+                env!("CARGO_PKG_VERSION"),
+                self.api_key
+            ),
+        );
+        if let Some(body) = body {
+            request = request.json(body);
+        }
+
+        request
+            .send()
+            .await
+            .context("Failed to send request to Jellyfin")?
+            .error_for_status()
+            .context("Failed to query Jellyfin")
+    }
+
+    /// Items of the user's libraries of the type `kind` (eg. "Audio"), optionally only those whose
+    /// names contain `search`.
+    async fn items(&self, kind: &str, search: Option<&str>, limit: Option<&str>) -> Result<Items> {
+        let mut params = vec![
+            ("userId", self.user_id.as_str()),
+            ("includeItemTypes", kind),
+            ("recursive", "true"),
+        ];
+        params.extend(search.map(|v| ("searchTerm", v)));
+        params.extend(limit.map(|v| ("limit", v)));
+
+        self.request(Method::GET, "Items", &params, None::<&()>)
+            .await
+    }
+}
+
+impl Service for Jellyfin {
+    fn name(&self) -> &'static str {
+        "jellyfin"
+    }
+
+    /// Searches the library for tracks with the track's title in theirs, without versions like
+    /// "(Remastered)" which the files may not have.
+    async fn search(&self, track: &Track) -> Result<Vec<Candidate>> {
+        let title = dupes::normalize_title(&track.name).unwrap_or_else(|| track.name.clone());
+        let items = self.items("Audio", Some(&title), Some(MAX_RESULTS)).await?;
+
+        Ok(items
+            .items
+            .into_iter()
+            .map(|v| Candidate {
+                id: v.id,
+                title: v.name,
+                artists: v.artists,
+                album: v.album,
+                album_id: v.album_id,
+                duration_ms: v.run_time_ticks.map(|v| v / TICKS_PER_MS),
+                by_isrc: false,
+            })
+            .collect())
+    }
+}
+
+impl Destination for Jellyfin {
+    async fn replace_playlist(&self, name: &str, ids: &[String]) -> Result<bool> {
+        let playlists = self
+            .items("Playlist", None, None)
+            .await
+            .context("Failed to list Jellyfin playlists")?;
+        let existing = playlists.items.into_iter().find(|v| v.name == name);
+
+        match existing {
+            // replaces every track of the playlist, which needs Jellyfin 10.9 or later
+            Some(playlist) => {
+                self.send(
+                    Method::POST,
+                    &format!("Playlists/{}", playlist.id),
+                    &[],
+                    Some(&UpdatePlaylist { ids }),
+                )
+                .await
+                .with_context(|| format!("Failed to update Jellyfin playlist {name}"))?;
+
+                Ok(false)
+            }
+            None => {
+                let body = CreatePlaylist {
+                    name,
+                    ids,
+                    user_id: &self.user_id,
+                    media_type: "Audio",
+                };
+                self.send(Method::POST, "Playlists", &[], Some(&body))
+                    .await
+                    .with_context(|| format!("Failed to create Jellyfin playlist {name}"))?;
+
+                Ok(true)
+            }
+        }
+    }
+}
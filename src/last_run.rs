@@ -0,0 +1,59 @@
+use std::{collections::BTreeMap, io::ErrorKind};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, SubsecRound, Utc};
+
+use crate::profile::Profile;
+
+/// Name of the file in a profile's state dir recording when each backup last succeeded.
+const FILE_NAME: &str = "last-run.json";
+
+/// When each backup of a profile last succeeded, keyed by the backup's name without its extension
+/// (eg. `liked` or `playlist-3cEYpjA9oz9GiPac4AsH4n`), for `--since last-run`.
+pub struct LastRuns {
+    runs: BTreeMap<String, DateTime<Utc>>,
+}
+
+impl LastRuns {
+    pub async fn load(profile: &Profile) -> Result<Self> {
+        let path = profile.dir().join(FILE_NAME);
+
+        let runs = match tokio::fs::read(&path).await {
+            Ok(data) => serde_json::from_slice(&data)
+                .with_context(|| format!("Failed to parse {}", path.display()))?,
+            Err(e) if e.kind() == ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+
+        Ok(Self { runs })
+    }
+
+    /// When the backup named `name` last succeeded, if it ever has.
+    pub fn get(&self, name: &str) -> Option<DateTime<Utc>> {
+        self.runs.get(key(name)).copied()
+    }
+
+    /// Records that the backup named `name`, started at `started_at`, succeeded.
+    pub async fn record(
+        &mut self,
+        profile: &Profile,
+        name: &str,
+        started_at: DateTime<Utc>,
+    ) -> Result<()> {
+        // Spotify's timestamps are to the second, so a track added in the same second the run
+        // started is written again by the next run rather than being missed
+        self.runs
+            .insert(key(name).to_string(), started_at.trunc_subsecs(0));
+
+        profile.create_dir().await?;
+        let data =
+            serde_json::to_vec_pretty(&self.runs).context("Failed to serialize last runs")?;
+        crate::atomic::write(&profile.dir().join(FILE_NAME), data).await
+    }
+}
+
+/// Name a backup's state is stored under, which stays the same whether it's compressed, encrypted
+/// or written in another format.
+pub fn key(name: &str) -> &str {
+    name.split_once('.').map_or(name, |(v, _)| v)
+}
@@ -0,0 +1,474 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::IsTerminal,
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use md5::{Digest, Md5};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::{
+    authentication::AuthArgs, cli::Args, dupes, migrate, output, profile::Profile,
+    token_store::TokenStore,
+};
+
+const API_URL: &str = "https://ws.audioscrobbler.com/2.0/";
+
+/// Page the user allows access to their Last.fm account on.
+const AUTH_URL: &str = "https://www.last.fm/api/auth/";
+
+/// Name the Last.fm session is stored under, alongside the profile's Spotify token.
+const SESSION_FILE: &str = "lastfm-session.json";
+
+/// Most loved tracks Last.fm returns in one page.
+const PAGE_SIZE: u32 = 1000;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct LastfmArgs {
+    /// API key of your Last.fm API account (https://www.last.fm/api/account/create)
+    #[arg(long, env = "LASTFM_API_KEY", global = true)]
+    pub api_key: Option<String>,
+    /// Shared secret of your Last.fm API account, needed to sign in and love tracks
+    #[arg(long, env = "LASTFM_API_SECRET", hide_env_values = true, global = true)]
+    pub api_secret: Option<String>,
+}
+
+/// A signed in Last.fm account, which never expires unless access is revoked.
+#[derive(Serialize, Deserialize, Debug)]
+struct Session {
+    name: String,
+    key: String,
+}
+
+/// Liked songs and loved tracks missing from the other service, matched by title and artist.
+#[derive(Serialize, Debug)]
+struct Report {
+    liked: usize,
+    loved: usize,
+    liked_not_loved: Vec<LikedTrack>,
+    loved_not_liked: Vec<LovedTrack>,
+}
+
+#[derive(Serialize, Debug)]
+struct LikedTrack {
+    name: String,
+    artists: Vec<String>,
+    uri: String,
+}
+
+#[derive(Serialize, Debug)]
+struct LovedTrack {
+    name: String,
+    artist: String,
+    url: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct LovedTrackItem {
+    name: String,
+    url: String,
+    artist: LovedTrackArtist,
+}
+
+#[derive(Deserialize, Debug)]
+struct LovedTrackArtist {
+    name: String,
+}
+
+struct Client {
+    http: reqwest::Client,
+    api_key: String,
+    api_secret: Option<String>,
+}
+
+impl Client {
+    fn new(args: &LastfmArgs) -> Result<Self> {
+        Ok(Self {
+            http: crate::http::client(),
+            api_key: args
+                .api_key
+                .clone()
+                .context("Missing Last.fm API key, set LASTFM_API_KEY")?,
+            api_secret: args.api_secret.clone(),
+        })
+    }
+
+    /// Calls an API method, signing the call when `signed` is set, which methods acting on the
+    /// user's behalf or handing out sessions need. Signed calls that change something are POSTed.
+    async fn call(
+        &self,
+        method: &str,
+        params: &[(&str, &str)],
+        signed: bool,
+        post: bool,
+    ) -> Result<Value> {
+        let mut params: BTreeMap<&str, &str> = params.iter().copied().collect();
+        params.insert("method", method);
+        params.insert("api_key", &self.api_key);
+
+        let signature = if signed {
+            let secret = self
+                .api_secret
+                .as_deref()
+                .context("Missing Last.fm API secret, set LASTFM_API_SECRET")?;
+
+            // every parameter in order of name, each name followed by its value, then the secret
+            let mut hasher = Md5::new();
+            for (name, value) in &params {
+                hasher.update(name);
+                hasher.update(value);
+            }
+            hasher.update(secret);
+            Some(hex::encode(hasher.finalize()))
+        } else {
+            None
+        };
+        if let Some(signature) = &signature {
+            params.insert("api_sig", signature);
+        }
+        // the format isn't part of the signature
+        params.insert("format", "json");
+
+        let request = if post {
+            self.http.post(API_URL).form(&params)
+        } else {
+            self.http.get(API_URL).query(&params)
+        };
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("Failed to call {method}"))?;
+
+        // errors come back as JSON too, with a more useful message than the status
+        let status = response.status();
+        let body: Value = response
+            .json()
+            .await
+            .with_context(|| format!("Failed to read response to {method} ({status})"))?;
+        if let Some(error) = body.get("error") {
+            anyhow::bail!(
+                "Last.fm returned error {error} for {method}: {}",
+                body["message"].as_str().unwrap_or_default()
+            );
+        }
+        anyhow::ensure!(status.is_success(), "Got {status} response to {method}");
+
+        Ok(body)
+    }
+
+    /// Every track `user` has loved, most recently loved first.
+    async fn loved_tracks(&self, user: &str) -> Result<Vec<LovedTrackItem>> {
+        let limit = PAGE_SIZE.to_string();
+        let mut tracks = Vec::new();
+        let mut page = 1;
+
+        loop {
+            let response = self
+                .call(
+                    "user.getLovedTracks",
+                    &[
+                        ("user", user),
+                        ("limit", &limit),
+                        ("page", &page.to_string()),
+                    ],
+                    false,
+                    false,
+                )
+                .await
+                .context("Failed to fetch loved tracks")?;
+            let loved = &response["lovedtracks"];
+
+            // a page of one track holds the track rather than an array of it
+            let items = match &loved["track"] {
+                Value::Array(items) => items.clone(),
+                item @ Value::Object(_) => vec![item.clone()],
+                _ => Vec::new(),
+            };
+            for item in items {
+                tracks.push(serde_json::from_value(item).context("Failed to parse loved track")?);
+            }
+
+            // numbers are sent as strings
+            let total_pages: u32 = loved["@attr"]["totalPages"]
+                .as_str()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or_default();
+            if page >= total_pages {
+                break;
+            }
+            page += 1;
+        }
+
+        Ok(tracks)
+    }
+}
+
+fn session_store(profile: &Profile, auth: &AuthArgs) -> TokenStore {
+    let store = TokenStore::new(auth.token_store, profile, SESSION_FILE);
+
+    match &auth.token_passphrase {
+        Some(Some(passphrase)) => store.with_passphrase(passphrase.clone()),
+        _ => store,
+    }
+}
+
+/// Signs in to Last.fm through the browser and stores the session in the profile, so tracks can be
+/// loved on the user's behalf.
+pub async fn login(args: &Args, lastfm: &LastfmArgs) -> Result<()> {
+    anyhow::ensure!(
+        !args.auth.non_interactive,
+        "Signing in to Last.fm needs a browser, it can't be done with --non-interactive"
+    );
+
+    let profile = Profile::new(&args.profile)?;
+    let client = Client::new(lastfm)?;
+
+    let response = client
+        .call("auth.getToken", &[], true, false)
+        .await
+        .context("Failed to start signing in to Last.fm")?;
+    let token = response["token"]
+        .as_str()
+        .context("Last.fm didn't return a token")?;
+
+    let url = Url::parse_with_params(AUTH_URL, [("api_key", &*client.api_key), ("token", token)])?;
+    let opened = !args.auth.no_browser && {
+        eprintln!("Opening Last.fm for authentication...");
+        webbrowser::open(url.as_str())
+            .inspect_err(|e| warn!("Failed to open browser: {e}"))
+            .is_ok()
+    };
+    if !opened {
+        eprintln!("Open the following URL in a browser to authenticate with Last.fm:\n\n{url}\n");
+    }
+    eprintln!("Press Enter once access has been allowed");
+
+    tokio::task::spawn_blocking(|| std::io::stdin().read_line(&mut String::new()))
+        .await?
+        .context("Failed to read from stdin")?;
+
+    let mut response = client
+        .call("auth.getSession", &[("token", token)], true, false)
+        .await
+        .context("Failed to sign in to Last.fm, was access allowed?")?;
+    let session: Session = serde_json::from_value(response["session"].take())
+        .context("Failed to parse Last.fm session")?;
+
+    profile.create_dir().await?;
+    session_store(&profile, &args.auth)
+        .write(&serde_json::to_string(&session)?)
+        .await?;
+
+    eprintln!("Signed in to Last.fm as {}", session.name);
+
+    Ok(())
+}
+
+/// Compares liked songs, from the backup at `path` or otherwise fetched from Spotify, with the
+/// tracks `user` (or the signed in user) has loved on Last.fm, printing those missing from either.
+/// With `sync` the liked songs that aren't loved are then loved.
+pub async fn compare(
+    args: &Args,
+    lastfm: &LastfmArgs,
+    path: Option<&Path>,
+    user: Option<&str>,
+    sync: bool,
+) -> Result<()> {
+    let profile = Profile::new(&args.profile)?;
+    let client = Client::new(lastfm)?;
+
+    let session: Option<Session> = match session_store(&profile, &args.auth).read().await? {
+        Some(data) => Some(serde_json::from_str(&data).context("Failed to parse Last.fm session")?),
+        None => None,
+    };
+    anyhow::ensure!(
+        !sync || session.is_some(),
+        "Loving tracks needs a Last.fm session, sign in with `spotify-backup lastfm login`"
+    );
+    let user = user
+        .or(session.as_ref().map(|v| v.name.as_str()))
+        .context("No Last.fm user, pass --user or sign in with `spotify-backup lastfm login`")?;
+
+    let liked = match path {
+        Some(path) => read_liked(args, path).await?,
+        None => fetch_liked(args, &profile).await?,
+    };
+    let loved = client.loved_tracks(user).await?;
+
+    let liked_keys: HashSet<_> = liked
+        .iter()
+        .flat_map(|track| track.artists.iter().map(|artist| key(&track.name, artist)))
+        .collect();
+    let loved_keys: HashSet<_> = loved
+        .iter()
+        .map(|track| key(&track.name, &track.artist.name))
+        .collect();
+
+    let report = Report {
+        liked: liked.len(),
+        loved: loved.len(),
+        loved_not_liked: loved
+            .into_iter()
+            .filter(|track| !liked_keys.contains(&key(&track.name, &track.artist.name)))
+            .map(|track| LovedTrack {
+                name: track.name,
+                artist: track.artist.name,
+                url: track.url,
+            })
+            .collect(),
+        liked_not_loved: liked
+            .into_iter()
+            .filter(|track| {
+                !track
+                    .artists
+                    .iter()
+                    .any(|artist| loved_keys.contains(&key(&track.name, artist)))
+            })
+            .collect(),
+    };
+
+    print(args, &report)?;
+
+    if let Some(session) = session.filter(|_| sync) {
+        for track in &report.liked_not_loved {
+            // Last.fm scrobbles tracks under their first artist
+            let Some(artist) = track.artists.first() else {
+                continue;
+            };
+
+            client
+                .call(
+                    "track.love",
+                    &[
+                        ("track", &track.name),
+                        ("artist", artist),
+                        ("sk", &session.key),
+                    ],
+                    true,
+                    true,
+                )
+                .await
+                .with_context(|| format!("Failed to love {}", track.uri))?;
+        }
+
+        info!(
+            "Loved {} track(s) on Last.fm as {}",
+            report.liked_not_loved.len(),
+            session.name
+        );
+    }
+
+    Ok(())
+}
+
+/// Title and artist a track is matched on, ignoring case and versions like "(Remastered 2011)".
+fn key(name: &str, artist: &str) -> (String, String) {
+    let name = dupes::normalize_title(name).unwrap_or_else(|| name.to_lowercase());
+    (name, artist.to_lowercase())
+}
+
+async fn read_liked(args: &Args, path: &Path) -> Result<Vec<LikedTrack>> {
+    let data = crate::cli::read_backup(path, &args.identity).await?;
+    let tracks = migrate::tracks(&data)
+        .with_context(|| format!("Failed to read tracks from {}", path.display()))?
+        .with_context(|| format!("{} isn't a JSON backup", path.display()))?;
+
+    tracks
+        .iter()
+        .enumerate()
+        .map(|(i, track)| {
+            let (Some(name), Some(artists)) = (track["name"].as_str(), track["artists"].as_array())
+            else {
+                anyhow::bail!(
+                    "Track {i} of {} has no name or artists, back it up with name and artists in \
+                     --fields",
+                    path.display()
+                );
+            };
+
+            Ok(LikedTrack {
+                name: name.to_string(),
+                artists: artists
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(str::to_string)
+                    .collect(),
+                uri: track["uri"].as_str().unwrap_or_default().to_string(),
+            })
+        })
+        .collect()
+}
+
+async fn fetch_liked(args: &Args, profile: &Profile) -> Result<Vec<LikedTrack>> {
+    let client = crate::cli::build_client(profile, args).await?;
+
+    let liked = crate::cli::fetch_sources(&client, args, &[], false, true)
+        .await?
+        .pop()
+        .map(|v| v.items)
+        .unwrap_or_default();
+
+    Ok(liked
+        .into_iter()
+        .map(|v| LikedTrack {
+            name: v.track.name,
+            artists: v.track.artists.into_iter().map(|v| v.name).collect(),
+            uri: v.track.uri,
+        })
+        .collect())
+}
+
+fn print(args: &Args, report: &Report) -> Result<()> {
+    let terminal = std::io::stdout().is_terminal();
+
+    match args.format {
+        output::Format::Json => {
+            let json = if args.pretty || terminal {
+                serde_json::to_string_pretty(report)?
+            } else {
+                serde_json::to_string(report)?
+            };
+            println!("{json}");
+        }
+        output::Format::Table => {
+            let mut summary = output::table(&["Library", "Tracks"], terminal, &[1]);
+            for (name, count) in [
+                ("Liked on Spotify", report.liked),
+                ("Loved on Last.fm", report.loved),
+                ("Liked, not loved", report.liked_not_loved.len()),
+                ("Loved, not liked", report.loved_not_liked.len()),
+            ] {
+                output::add_row(&mut summary, [name, &count.to_string()]);
+            }
+            println!("{summary}");
+
+            if !report.liked_not_loved.is_empty() {
+                let mut table = output::table(&["Unloved track", "Artists", "URI"], terminal, &[2]);
+                for track in &report.liked_not_loved {
+                    output::add_row(
+                        &mut table,
+                        [track.name.as_str(), &track.artists.join(", "), &track.uri],
+                    );
+                }
+                println!("\n{table}");
+            }
+
+            if !report.loved_not_liked.is_empty() {
+                let mut table = output::table(&["Unliked track", "Artist", "URL"], terminal, &[2]);
+                for track in &report.loved_not_liked {
+                    output::add_row(&mut table, [track.name.as_str(), &track.artist, &track.url]);
+                }
+                println!("\n{table}");
+            }
+        }
+        _ => {
+            anyhow::bail!("The Last.fm comparison can only be printed as JSON or a table")
+        }
+    }
+
+    Ok(())
+}
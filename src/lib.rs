@@ -0,0 +1,516 @@
+//! Backs up Spotify playlists and liked songs. [`SpotifyClient`] fetches the library for programs
+//! embedding the crate, and [`export`] writes it in the formats backups are. The `spotify-backup`
+//! binary is a thin wrapper around the `cli` module, which is only built with the `cli` feature.
+
+#[cfg(feature = "cli")]
+mod annotations;
+pub mod api;
+#[cfg(feature = "cli")]
+mod apple;
+mod atomic;
+pub mod authentication;
+#[cfg(feature = "cli")]
+mod beets;
+#[cfg(feature = "cli")]
+mod charts;
+#[cfg(feature = "cli")]
+pub mod cli;
+mod client;
+#[cfg(feature = "cli")]
+mod collection;
+#[cfg(feature = "cli")]
+mod compare;
+#[cfg(feature = "cli")]
+mod completions;
+#[cfg(feature = "cli")]
+mod compression;
+#[cfg(feature = "cli")]
+mod config;
+#[cfg(feature = "cli")]
+mod coverage;
+#[cfg(feature = "cli")]
+mod daemon;
+#[cfg(feature = "cli")]
+mod deezer;
+// only partly used by the library, the rest is there for the command line
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
+mod desktop;
+#[cfg(feature = "cli")]
+mod dupes;
+#[cfg(feature = "cli")]
+mod encryption;
+mod error;
+#[cfg(feature = "cli")]
+mod everything;
+pub mod export;
+#[cfg(feature = "cli")]
+mod filter;
+mod fixtures;
+#[cfg(feature = "folders")]
+mod folders;
+#[cfg(feature = "cli")]
+mod funkwhale;
+#[cfg(feature = "cli")]
+mod gdpr;
+#[cfg(feature = "cli")]
+mod healthcheck;
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
+mod http;
+#[cfg(feature = "cli")]
+mod init;
+#[cfg(feature = "cli")]
+mod jellyfin;
+#[cfg(feature = "cli")]
+mod last_run;
+#[cfg(feature = "cli")]
+mod lastfm;
+#[cfg(feature = "cli")]
+mod link;
+#[cfg(feature = "cli")]
+mod listenbrainz;
+#[cfg(feature = "cli")]
+mod local;
+#[cfg(feature = "cli")]
+mod logging;
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
+mod lyrics;
+#[cfg(feature = "cli")]
+mod manifest;
+#[cfg(feature = "cli")]
+mod map;
+#[cfg(feature = "cli")]
+mod merge;
+mod metadata_cache;
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
+mod metrics;
+#[cfg(feature = "cli")]
+mod migrate;
+#[cfg(feature = "cli")]
+mod musicbrainz;
+#[cfg(feature = "cli")]
+mod normalize;
+#[cfg(feature = "cli")]
+mod notify;
+pub mod observer;
+pub mod output;
+pub mod pagination;
+#[cfg(feature = "cli")]
+mod parts;
+#[cfg(feature = "tui")]
+mod picker;
+#[cfg(feature = "cli")]
+mod plex;
+pub mod profile;
+#[cfg(feature = "cli")]
+mod progress;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "cli")]
+mod resolve;
+#[cfg(feature = "cli")]
+mod schema;
+#[cfg(feature = "cli")]
+mod scrobble;
+#[cfg(feature = "cli")]
+mod search;
+#[cfg(feature = "serve")]
+mod serve;
+#[cfg(feature = "cli")]
+mod service;
+#[cfg(feature = "cli")]
+mod similar;
+#[cfg(feature = "cli")]
+mod smart;
+#[cfg(feature = "cli")]
+mod stats;
+#[cfg(feature = "cli")]
+pub mod storage;
+#[cfg(feature = "cli")]
+mod subsonic;
+#[cfg(feature = "cli")]
+mod summary;
+#[cfg(feature = "cli")]
+mod sync;
+#[cfg(feature = "cli")]
+mod tidal;
+mod token_store;
+#[cfg(feature = "tui")]
+mod tui;
+#[cfg(feature = "cli")]
+mod unavailable;
+pub mod usage;
+#[cfg(feature = "cli")]
+mod validate;
+#[cfg(feature = "cli")]
+mod verify_live;
+#[cfg(feature = "cli")]
+mod writer;
+#[cfg(feature = "cli")]
+mod youtube;
+#[cfg(feature = "cli")]
+mod ytdlp;
+
+use anyhow::{Context, Result};
+use reqwest::header::HeaderMap;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::debug;
+
+pub use client::SpotifyClient;
+pub use error::Error;
+pub use export::Exporter;
+pub use observer::Observer;
+pub use pagination::{Page, Paginator};
+
+fn client_with_token(
+    token: &str,
+    args: &api::ApiArgs,
+    profile: &profile::Profile,
+) -> Result<api::Client> {
+    let mut headers = HeaderMap::new();
+    headers.insert("Authorization", format!("Bearer {token}").parse()?);
+
+    let http = http::builder().default_headers(headers).build()?;
+
+    Ok(api::Client::new(http, args.clone(), profile))
+}
+
+async fn fetch_page<T: DeserializeOwned>(client: &api::Client, url: String) -> Result<Page<T>> {
+    debug!(url, "Fetching {url}...");
+
+    client.get_json(&url).await
+}
+
+async fn fetch_current_user(client: &api::Client) -> Result<GetCurrentUserResponse> {
+    client
+        .get_json("https://api.spotify.com/v1/me")
+        .await
+        .context("Failed to fetch current user")
+}
+
+/// Every field a track can be written with, serialized with only the requested ones through
+/// [`output::Selected`].
+#[derive(Clone)]
+pub struct Output {
+    pub album: OutputAlbum,
+    pub name: String,
+    pub artists: Vec<String>,
+    pub uri: String,
+    pub id: Option<String>,
+    pub added_at: Option<String>,
+    pub added_by: Option<String>,
+    pub added_by_name: Option<String>,
+    pub duration_ms: Option<u64>,
+    pub explicit: Option<bool>,
+    pub popularity: Option<u32>,
+    pub isrc: Option<String>,
+    pub release_date: Option<String>,
+    pub genres: Option<Vec<String>>,
+    pub playable: Option<bool>,
+    pub disc_number: Option<u32>,
+    pub track_number: Option<u32>,
+    pub recording_mbid: Option<String>,
+    pub release_mbid: Option<String>,
+    pub lyrics: Option<lyrics::Lyrics>,
+    pub tags: Option<Vec<String>>,
+    pub rating: Option<u8>,
+    pub note: Option<String>,
+}
+
+impl From<GetPlaylistTracksResponseItem> for Output {
+    /// The track as it's written to backups, without the genres, MusicBrainz IDs, lyrics, name of
+    /// whoever added it and annotations that are looked up elsewhere.
+    fn from(v: GetPlaylistTracksResponseItem) -> Self {
+        Self {
+            album: OutputAlbum {
+                art: v
+                    .track
+                    .album
+                    .images
+                    .first()
+                    .map(|v| v.url.to_string())
+                    .unwrap_or_default(),
+                name: v.track.album.name,
+                uri: v.track.album.uri,
+            },
+            name: v.track.name,
+            artists: v.track.artists.into_iter().map(|v| v.name).collect(),
+            uri: v.track.uri,
+            id: v.track.id,
+            added_at: v.added_at,
+            added_by: v.added_by.map(|v| v.id).filter(|v| !v.is_empty()),
+            added_by_name: None,
+            duration_ms: v.track.duration_ms,
+            explicit: v.track.explicit,
+            popularity: v.track.popularity,
+            isrc: v.track.external_ids.and_then(|v| v.isrc),
+            release_date: v.track.album.release_date,
+            genres: None,
+            playable: v.track.is_playable,
+            disc_number: v.track.disc_number,
+            track_number: v.track.track_number,
+            recording_mbid: None,
+            release_mbid: None,
+            lyrics: None,
+            tags: None,
+            rating: None,
+            note: None,
+        }
+    }
+}
+
+#[cfg(test)]
+impl Output {
+    /// Roygbiv by Boards of Canada as listed in a playlist, with `fields` of the item Spotify
+    /// returns replaced (`added_at` and `release_date` where they are, the rest on the track).
+    pub(crate) fn example(fields: serde_json::Value) -> Self {
+        let mut item = serde_json::json!({
+            "added_at": "2023-06-15T12:00:00Z",
+            "track": {
+                "name": "Roygbiv",
+                "artists": [{"id": "2VAvhf61GgLYmC6C8anyX1", "name": "Boards of Canada"}],
+                "album": {
+                    "images": [],
+                    "name": "Music Has the Right to Children",
+                    "release_date": "1998-04-20",
+                },
+                "uri": "spotify:track:6ZLqX6C0qKfMnCUHYWCk0W",
+                "duration_ms": 151000,
+                "explicit": false,
+                "external_ids": {"isrc": "GBBPW9800012"},
+            },
+        });
+        for (key, value) in fields.as_object().into_iter().flatten() {
+            match key.as_str() {
+                "added_at" => item["added_at"] = value.clone(),
+                "release_date" => item["track"]["album"]["release_date"] = value.clone(),
+                _ => item["track"][key] = value.clone(),
+            }
+        }
+
+        serde_json::from_value::<GetPlaylistTracksResponseItem>(item)
+            .unwrap()
+            .into()
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct OutputAlbum {
+    pub art: String,
+    pub name: String,
+    /// Only used to group tracks by album, as it isn't one of the fields written
+    #[serde(skip)]
+    pub uri: Option<String>,
+}
+
+/// A playlist as listed by the `playlists` command.
+#[derive(Serialize)]
+pub struct OutputPlaylist<'a> {
+    id: &'a str,
+    name: &'a str,
+    owner: Option<&'a str>,
+    /// Only known when the playlists were fetched along with the user's ID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ownership: Option<Ownership>,
+    tracks: u32,
+    snapshot_id: Option<&'a str>,
+    /// The user's own tags, rating and note, only listed with `--with-annotations`
+    #[cfg(feature = "cli")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    annotation: Option<&'a annotations::Annotation>,
+}
+
+impl<'a> OutputPlaylist<'a> {
+    /// The playlist as listed for the user with the ID `user_id`, along with whose it is.
+    pub fn for_user(playlist: &'a GetPlaylistsResponseItem, user_id: &str) -> Self {
+        Self {
+            ownership: Some(playlist.ownership(user_id)),
+            ..Self::from(playlist)
+        }
+    }
+}
+
+impl<'a> From<&'a GetPlaylistsResponseItem> for OutputPlaylist<'a> {
+    fn from(playlist: &'a GetPlaylistsResponseItem) -> Self {
+        Self {
+            id: &playlist.id,
+            name: &playlist.name,
+            owner: playlist.owner.as_ref().map(|v| v.name()),
+            ownership: None,
+            tracks: playlist.tracks.total,
+            snapshot_id: playlist.snapshot_id.as_deref(),
+            #[cfg(feature = "cli")]
+            annotation: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetCurrentUserResponse {
+    pub id: String,
+    pub display_name: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetPlaylistsResponseItem {
+    pub id: String,
+    pub name: String,
+    pub tracks: GetPlaylistsResponseItemTracks,
+    #[serde(default)]
+    pub owner: Option<GetPlaylistsResponseItemOwner>,
+    /// Changes whenever the playlist does
+    #[serde(default)]
+    pub snapshot_id: Option<String>,
+    /// Whether anyone invited can add tracks, not just the owner
+    #[serde(default)]
+    pub collaborative: bool,
+}
+
+impl GetPlaylistsResponseItem {
+    /// Whose the playlist is, from the point of view of the user with the ID `user_id`.
+    pub fn ownership(&self, user_id: &str) -> Ownership {
+        if self.collaborative {
+            Ownership::Collaborative
+        } else if self.owner.as_ref().is_some_and(|v| v.id == user_id) {
+            Ownership::Owned
+        } else {
+            Ownership::Followed
+        }
+    }
+}
+
+/// Whose a playlist in the user's library is, so their own can be backed up separately from those
+/// they only follow.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Ownership {
+    /// Created by the user
+    Owned,
+    /// Anyone invited can add tracks to it, whoever created it
+    Collaborative,
+    /// Created by someone else, and followed by the user
+    Followed,
+}
+
+impl Ownership {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Owned => "owned",
+            Self::Collaborative => "collaborative",
+            Self::Followed => "followed",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetPlaylistsResponseItemOwner {
+    pub id: String,
+    pub display_name: Option<String>,
+}
+
+impl GetPlaylistsResponseItemOwner {
+    /// The owner's display name, or their ID if they haven't set one.
+    fn name(&self) -> &str {
+        self.display_name.as_deref().unwrap_or(&self.id)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetPlaylistsResponseItemTracks {
+    pub total: u32,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetPlaylistTracksResponseItem {
+    #[serde(default)]
+    pub added_at: Option<String>,
+    /// Missing for liked songs, and blank for tracks added before Spotify kept track of it
+    #[serde(default)]
+    pub added_by: Option<GetPlaylistTracksResponseItemAddedBy>,
+    pub track: GetPlaylistTracksResponseItemTrack,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetPlaylistTracksResponseItemTrack {
+    pub artists: Vec<GetPlaylistTracksResponseItemTrackArtist>,
+    pub name: String,
+    pub album: GetPlaylistTracksResponseItemTrackAlbum,
+    pub uri: String,
+    /// Missing for local files
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub duration_ms: Option<u64>,
+    #[serde(default)]
+    pub explicit: Option<bool>,
+    #[serde(default)]
+    pub popularity: Option<u32>,
+    #[serde(default)]
+    pub external_ids: Option<GetPlaylistTracksResponseItemTrackExternalIds>,
+    #[serde(default)]
+    pub disc_number: Option<u32>,
+    #[serde(default)]
+    pub track_number: Option<u32>,
+    /// Whether the track can be played in the market it was requested for
+    #[serde(default)]
+    pub is_playable: Option<bool>,
+    /// Why the track can't be played, if it can't
+    #[serde(default)]
+    pub restrictions: Option<GetPlaylistTracksResponseItemTrackRestrictions>,
+    /// The track as it's stored in the playlist or library, when Spotify relinked it to another
+    /// one that's available in the market it was requested for
+    #[serde(default)]
+    pub linked_from: Option<GetPlaylistTracksResponseItemTrackLinkedFrom>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetPlaylistTracksResponseItemAddedBy {
+    pub id: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetPlaylistTracksResponseItemTrackRestrictions {
+    pub reason: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetPlaylistTracksResponseItemTrackLinkedFrom {
+    pub uri: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetPlaylistTracksResponseItemTrackExternalIds {
+    pub isrc: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetPlaylistTracksResponseItemTrackAlbum {
+    pub images: Vec<GetPlaylistTracksResponseItemTrackAlbumImage>,
+    pub name: String,
+    /// Missing for local files
+    #[serde(default)]
+    pub uri: Option<String>,
+    #[serde(default)]
+    pub release_date: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetPlaylistTracksResponseItemTrackAlbumImage {
+    pub url: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetPlaylistTracksResponseItemTrackArtist {
+    /// Missing for local files
+    pub id: Option<String>,
+    pub name: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetUserResponse {
+    pub display_name: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetArtistResponse {
+    pub genres: Vec<String>,
+}
@@ -0,0 +1,306 @@
+use std::{collections::BTreeMap, fmt, str::FromStr, sync::OnceLock};
+
+use anyhow::{Context, Result};
+use reqwest::Url;
+
+/// IDs of the playlists given names in the config file, by their name.
+static ALIASES: OnceLock<BTreeMap<String, String>> = OnceLock::new();
+
+/// Kind of object a Spotify link or URI points to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Track,
+    Album,
+    Playlist,
+    Artist,
+    Episode,
+    Show,
+}
+
+impl Kind {
+    const ALL: [Self; 6] = [
+        Self::Track,
+        Self::Album,
+        Self::Playlist,
+        Self::Artist,
+        Self::Episode,
+        Self::Show,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Track => "track",
+            Self::Album => "album",
+            Self::Playlist => "playlist",
+            Self::Artist => "artist",
+            Self::Episode => "episode",
+            Self::Show => "show",
+        }
+    }
+
+    /// The name with its indefinite article, eg. "an album".
+    fn with_article(self) -> String {
+        match self {
+            Self::Album | Self::Artist | Self::Episode => format!("an {}", self.name()),
+            _ => format!("a {}", self.name()),
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|v| v.name() == name)
+    }
+}
+
+/// An object on Spotify, parsed from an open.spotify.com link (eg.
+/// https://open.spotify.com/playlist/3cEYpjA9oz9GiPac4AsH4n?si=...) or a URI (eg.
+/// spotify:playlist:3cEYpjA9oz9GiPac4AsH4n).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    pub kind: Kind,
+    pub id: String,
+}
+
+impl FromStr for Link {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+
+        let parts: Vec<String> = if let Some(uri) = s.strip_prefix("spotify:") {
+            uri.split(':').map(str::to_string).collect()
+        } else {
+            let url = Url::parse(s).context("Not a Spotify link or URI")?;
+            // shortened links only say what they point to once they've been followed
+            anyhow::ensure!(
+                url.host_str() != Some("spotify.link"),
+                "Shortened spotify.link links aren't supported, open it in a browser and pass the \
+                 open.spotify.com link it goes to instead"
+            );
+            anyhow::ensure!(
+                url.host_str() == Some("open.spotify.com"),
+                "Not an open.spotify.com link"
+            );
+            url.path_segments()
+                .into_iter()
+                .flatten()
+                .filter(|v| !v.is_empty())
+                .map(str::to_string)
+                .collect()
+        };
+
+        // the kind and ID are last, after any locale (eg. /intl-de/track/...) or owner (eg.
+        // spotify:user:<user>:playlist:...)
+        let [.., kind, id] = parts.as_slice() else {
+            anyhow::bail!("Not a link to a track, album, playlist, artist, episode or show");
+        };
+        let kind = Kind::from_name(kind).with_context(|| {
+            format!("Links to a {kind} aren't supported, only tracks, albums, playlists, artists, episodes and shows")
+        })?;
+        anyhow::ensure!(
+            !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric()),
+            "Invalid ID {id:?}"
+        );
+
+        Ok(Self {
+            kind,
+            id: id.clone(),
+        })
+    }
+}
+
+impl fmt::Display for Link {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "spotify:{}:{}", self.kind.name(), self.id)
+    }
+}
+
+/// Parses a playlist given as an ID, link, URI or the name of an alias in the config file,
+/// returning its ID. Links to anything else are easily copied by mistake, so they're rejected
+/// saying what was passed instead.
+pub fn parse_playlist_id(v: &str) -> Result<String> {
+    match ALIASES.get().and_then(|aliases| aliases.get(v.trim())) {
+        Some(id) => Ok(id.clone()),
+        None => parse_playlist(v),
+    }
+}
+
+/// Lets the playlists in `aliases`, from their names to their ID, link or URI, be given by name
+/// wherever a playlist is. Fails if any of them isn't a playlist.
+pub fn set_aliases(aliases: &BTreeMap<String, String>) -> Result<()> {
+    let _ = ALIASES.set(resolve_aliases(aliases)?);
+
+    Ok(())
+}
+
+fn resolve_aliases(aliases: &BTreeMap<String, String>) -> Result<BTreeMap<String, String>> {
+    aliases
+        .iter()
+        .map(|(name, playlist)| {
+            let id = parse_playlist(playlist)
+                .with_context(|| format!("Alias {name} isn't a playlist"))?;
+            Ok((name.clone(), id))
+        })
+        .collect()
+}
+
+/// Parses a playlist given as an ID, link or URI, returning its ID.
+fn parse_playlist(v: &str) -> Result<String> {
+    let v = v.trim();
+
+    // a bare ID
+    if !v.contains([':', '/']) {
+        anyhow::ensure!(
+            !v.is_empty() && v.chars().all(|c| c.is_ascii_alphanumeric()),
+            "Invalid playlist {v:?}, pass its ID (eg. 3cEYpjA9oz9GiPac4AsH4n), its \
+             open.spotify.com link or its spotify:playlist: URI"
+        );
+        return Ok(v.to_string());
+    }
+
+    // liked songs are listed alongside playlists in the apps, but aren't one
+    anyhow::ensure!(
+        !v.contains("/collection") && !v.ends_with(":collection"),
+        "Liked songs aren't a playlist, back them up with `spotify-backup liked`"
+    );
+
+    let link: Link = v.parse()?;
+    anyhow::ensure!(
+        link.kind == Kind::Playlist,
+        "That's a link to {}, not a playlist, copy the link from the playlist's Share menu instead",
+        link.kind.with_article()
+    );
+    Ok(link.id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_links_to_every_kind() {
+        for (v, kind) in [
+            ("spotify:track:6kTBsC2hcEBTWmAnl2s2bQ", Kind::Track),
+            (
+                "https://open.spotify.com/album/6kTBsC2hcEBTWmAnl2s2bQ?si=x",
+                Kind::Album,
+            ),
+            (
+                "https://open.spotify.com/intl-ja/playlist/6kTBsC2hcEBTWmAnl2s2bQ",
+                Kind::Playlist,
+            ),
+            ("spotify:artist:6kTBsC2hcEBTWmAnl2s2bQ", Kind::Artist),
+            (
+                "https://open.spotify.com/episode/6kTBsC2hcEBTWmAnl2s2bQ",
+                Kind::Episode,
+            ),
+            ("spotify:show:6kTBsC2hcEBTWmAnl2s2bQ", Kind::Show),
+        ] {
+            let link: Link = v.parse().unwrap();
+            assert_eq!(link.kind, kind, "{v}");
+            assert_eq!(link.id, "6kTBsC2hcEBTWmAnl2s2bQ", "{v}");
+            // printed as a URI, which parses back to the same link
+            assert_eq!(link.to_string().parse::<Link>().unwrap(), link);
+        }
+
+        let link: Link = "https://open.spotify.com/track/6kTBsC2hcEBTWmAnl2s2bQ"
+            .parse()
+            .unwrap();
+        assert_eq!(link.to_string(), "spotify:track:6kTBsC2hcEBTWmAnl2s2bQ");
+    }
+
+    #[test]
+    fn parses_playlist_ids() {
+        let id = "3cEYpjA9oz9GiPac4AsH4n";
+
+        for v in [
+            "3cEYpjA9oz9GiPac4AsH4n",
+            "  3cEYpjA9oz9GiPac4AsH4n\n",
+            "spotify:playlist:3cEYpjA9oz9GiPac4AsH4n",
+            "spotify:user:someone:playlist:3cEYpjA9oz9GiPac4AsH4n",
+            "https://open.spotify.com/playlist/3cEYpjA9oz9GiPac4AsH4n",
+            "https://open.spotify.com/playlist/3cEYpjA9oz9GiPac4AsH4n?si=d2b0c5e6f1a84e7b",
+            "https://open.spotify.com/playlist/3cEYpjA9oz9GiPac4AsH4n/",
+            "https://open.spotify.com/intl-de/playlist/3cEYpjA9oz9GiPac4AsH4n?si=abc",
+            "https://open.spotify.com/intl-pt/playlist/3cEYpjA9oz9GiPac4AsH4n#top",
+            "https://open.spotify.com/user/someone/playlist/3cEYpjA9oz9GiPac4AsH4n",
+        ] {
+            assert_eq!(parse_playlist_id(v).unwrap(), id, "{v:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_playlists() {
+        for (v, error) in [
+            ("", "Invalid playlist \"\""),
+            ("not an id", "Invalid playlist \"not an id\""),
+            ("abc-def", "Invalid playlist \"abc-def\""),
+            ("spotify:playlist:", "Invalid ID \"\""),
+            ("spotify:playlist:abc$", "Invalid ID \"abc$\""),
+            ("spotify:playlist", "Not a link to a track"),
+            (
+                "spotify:podcast:3cEYpjA9oz9GiPac4AsH4n",
+                "Links to a podcast aren't supported",
+            ),
+            (
+                "spotify:track:3cEYpjA9oz9GiPac4AsH4n",
+                "That's a link to a track, not a playlist",
+            ),
+            (
+                "https://open.spotify.com/album/3cEYpjA9oz9GiPac4AsH4n",
+                "That's a link to an album, not a playlist",
+            ),
+            (
+                "https://open.spotify.com/intl-fr/artist/3cEYpjA9oz9GiPac4AsH4n",
+                "That's a link to an artist, not a playlist",
+            ),
+            (
+                "https://open.spotify.com/collection/tracks",
+                "Liked songs aren't a playlist",
+            ),
+            (
+                "spotify:user:someone:collection",
+                "Liked songs aren't a playlist",
+            ),
+            (
+                "https://spotify.link/AbCdEf",
+                "Shortened spotify.link links",
+            ),
+            (
+                "https://example.com/playlist/3cEYpjA9oz9GiPac4AsH4n",
+                "Not an open.spotify.com link",
+            ),
+            (
+                "open.spotify.com/playlist/3cEYpjA9oz9GiPac4AsH4n",
+                "Not a Spotify link or URI",
+            ),
+        ] {
+            let e = parse_playlist_id(v).unwrap_err();
+            assert!(e.to_string().starts_with(error), "{v:?}: {e}");
+        }
+    }
+
+    #[test]
+    fn resolves_aliases() {
+        let aliases = BTreeMap::from([
+            (
+                "workout".to_string(),
+                "https://open.spotify.com/playlist/3cEYpjA9oz9GiPac4AsH4n?si=abc".to_string(),
+            ),
+            (
+                "road-trip".to_string(),
+                "spotify:playlist:37i9dQZF1DXcBWIGoYBM5M".to_string(),
+            ),
+        ]);
+
+        let resolved = resolve_aliases(&aliases).unwrap();
+        assert_eq!(resolved["workout"], "3cEYpjA9oz9GiPac4AsH4n");
+        assert_eq!(resolved["road-trip"], "37i9dQZF1DXcBWIGoYBM5M");
+
+        let aliases = BTreeMap::from([(
+            "song".to_string(),
+            "spotify:track:3cEYpjA9oz9GiPac4AsH4n".to_string(),
+        )]);
+        let e = resolve_aliases(&aliases).unwrap_err();
+        assert_eq!(e.to_string(), "Alias song isn't a playlist");
+    }
+}
@@ -0,0 +1,183 @@
+use std::{io::IsTerminal, path::Path, time::Duration};
+
+use anyhow::{Context, Result};
+use reqwest::StatusCode;
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::{
+    cli::Args,
+    profile::Profile,
+    scrobble::{self, Play},
+};
+
+const SUBMIT_URL: &str = "https://api.listenbrainz.org/1/submit-listens";
+
+/// Most listens ListenBrainz accepts in one submission.
+const MAX_LISTENS: usize = 1000;
+
+/// A submission of listens, in the shape ListenBrainz's API takes them.
+#[derive(Serialize, Debug)]
+struct Submission<'a> {
+    /// "import" for listens from the past, as opposed to what's playing now
+    listen_type: &'static str,
+    payload: &'a [Listen],
+}
+
+#[derive(Serialize, Debug)]
+struct Listen {
+    /// When the track started playing, in seconds since the epoch
+    listened_at: i64,
+    track_metadata: TrackMetadata,
+}
+
+#[derive(Serialize, Debug)]
+struct TrackMetadata {
+    artist_name: String,
+    track_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    release_name: Option<String>,
+    additional_info: AdditionalInfo,
+}
+
+#[derive(Serialize, Debug)]
+struct AdditionalInfo {
+    artist_names: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration_ms: Option<u64>,
+    /// Link to the track on Spotify, missing for local files
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spotify_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    origin_url: Option<String>,
+    media_player: &'static str,
+    music_service: &'static str,
+    submission_client: &'static str,
+    submission_client_version: &'static str,
+}
+
+/// Converts the plays logged to `path` by `scrobble-log`, or the listening history if no log is
+/// given, to ListenBrainz listens, then prints them as a submission or submits them with `token`.
+/// Podcast episodes and plays without an artist aren't music ListenBrainz can take, so they're
+/// left out.
+pub async fn run(
+    args: &Args,
+    path: Option<&Path>,
+    submit: bool,
+    token: Option<&str>,
+) -> Result<()> {
+    let plays = match path {
+        Some(path) => scrobble::read_plays(path).await?,
+        None => {
+            let profile = Profile::new(&args.profile)?;
+            let client = crate::cli::build_client(&profile, args).await?;
+            scrobble::recently_played(&client).await?
+        }
+    };
+
+    let total = plays.len();
+    let listens: Vec<_> = plays.into_iter().filter_map(listen).collect();
+    if listens.len() < total {
+        info!(
+            "Left out {} episode(s) and play(s) without an artist",
+            total - listens.len()
+        );
+    }
+
+    if !submit {
+        let submission = Submission {
+            listen_type: "import",
+            payload: &listens,
+        };
+        let json = if args.pretty || std::io::stdout().is_terminal() {
+            serde_json::to_string_pretty(&submission)?
+        } else {
+            serde_json::to_string(&submission)?
+        };
+        println!("{json}");
+
+        return Ok(());
+    }
+
+    let token = token.context("Missing ListenBrainz user token, set LISTENBRAINZ_TOKEN")?;
+    let client = crate::http::client();
+
+    for chunk in listens.chunks(MAX_LISTENS) {
+        submit_chunk(&client, token, chunk).await?;
+    }
+
+    // ListenBrainz ignores listens it already has, so submitting the same log again is harmless
+    info!("Submitted {} listen(s) to ListenBrainz", listens.len());
+
+    Ok(())
+}
+
+fn listen(play: Play) -> Option<Listen> {
+    if play.uri.starts_with("spotify:episode:") || play.artists.is_empty() {
+        return None;
+    }
+
+    // Spotify logs when a track finished, ListenBrainz when it started
+    let played = play.ms_played.or(play.duration_ms).unwrap_or_default();
+    let duration = chrono::Duration::milliseconds(played as i64);
+    let link = play
+        .uri
+        .strip_prefix("spotify:track:")
+        .map(|id| format!("https://open.spotify.com/track/{id}"));
+
+    Some(Listen {
+        listened_at: (play.played_at - duration).timestamp(),
+        track_metadata: TrackMetadata {
+            artist_name: play.artists.join(", "),
+            track_name: play.name,
+            release_name: play.album,
+            additional_info: AdditionalInfo {
+                artist_names: play.artists,
+                duration_ms: play.duration_ms,
+                spotify_id: link.clone(),
+                origin_url: link,
+                media_player: "Spotify",
+                music_service: "spotify.com",
+                submission_client: env!("CARGO_PKG_NAME"),
+                submission_client_version: env!("CARGO_PKG_VERSION"),
+            },
+        },
+    })
+}
+
+/// Submits `listens`, waiting out the rate limit if it's been hit.
+async fn submit_chunk(client: &reqwest::Client, token: &str, listens: &[Listen]) -> Result<()> {
+    let submission = Submission {
+        listen_type: "import",
+        payload: listens,
+    };
+
+    loop {
+        let response = client
+            .post(SUBMIT_URL)
+            .header("Authorization", format!("Token {token}"))
+            .json(&submission)
+            .send()
+            .await
+            .context("Failed to send listens to ListenBrainz")?;
+
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            let wait = response
+                .headers()
+                .get("X-RateLimit-Reset-In")
+                .and_then(|v| v.to_str().ok()?.parse().ok())
+                .unwrap_or(10);
+            warn!("Rate limited by ListenBrainz, retrying in {wait}s");
+            tokio::time::sleep(Duration::from_secs(wait)).await;
+            continue;
+        }
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!("Got {status} response when submitting listens to ListenBrainz: {body}");
+        }
+
+        return Ok(());
+    }
+}
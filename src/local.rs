@@ -0,0 +1,262 @@
+use std::{
+    io::IsTerminal,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use futures::StreamExt;
+use serde::Serialize;
+use symphonia::core::{
+    formats::FormatOptions,
+    io::MediaSourceStream,
+    meta::{MetadataOptions, StandardTagKey, Tag},
+    probe::Hint,
+};
+use tracing::{debug, info};
+
+use crate::{
+    cli::Args,
+    collection::{self, Collection, File},
+    map, output,
+};
+
+/// Extensions of the files read as part of the collection.
+const AUDIO_EXTENSIONS: &[&str] = &[
+    "aac", "flac", "m4a", "mp3", "mp4", "oga", "ogg", "opus", "wav",
+];
+
+/// M3U playlists written from backups, with the tracks that couldn't be found in the collection.
+#[derive(Serialize, Debug)]
+struct Report {
+    playlists: Vec<PlaylistReport>,
+}
+
+#[derive(Serialize, Debug)]
+struct PlaylistReport {
+    name: String,
+    /// Path the M3U playlist was written to
+    path: PathBuf,
+    tracks: usize,
+    matched: usize,
+    unmatched: Vec<Unmatched>,
+}
+
+#[derive(Serialize, Debug)]
+struct Unmatched {
+    name: String,
+    artists: Vec<String>,
+    album: Option<String>,
+    uri: String,
+}
+
+/// Matches the tracks of each backup in `paths` against the audio files in `music_dir` by their
+/// tags, writing an M3U playlist of the files found for each into `playlist_dir` and printing the
+/// tracks that weren't.
+pub async fn run(
+    args: &Args,
+    paths: &[PathBuf],
+    music_dir: &Path,
+    playlist_dir: &Path,
+    min_confidence: f64,
+) -> Result<()> {
+    // the playlists are read from elsewhere, so the files have to be given by absolute paths
+    let music_dir = tokio::fs::canonicalize(music_dir)
+        .await
+        .with_context(|| format!("Failed to open music directory {}", music_dir.display()))?;
+
+    info!("Reading tags of the files in {}...", music_dir.display());
+    let collection = scan(music_dir.clone()).await?;
+    info!("Found {} audio file(s)", collection.len());
+
+    tokio::fs::create_dir_all(playlist_dir)
+        .await
+        .with_context(|| format!("Failed to create {}", playlist_dir.display()))?;
+
+    let mut report = Report {
+        playlists: Vec::new(),
+    };
+    for path in paths {
+        let tracks = map::read_tracks(args, path).await?;
+
+        // backups are named like playlist-<id>.json.zst, which is the best there is to go on
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let name = file_name.split('.').next().unwrap_or_default().to_string();
+
+        let mut matched = Vec::new();
+        let mut unmatched = Vec::new();
+        for track in &tracks {
+            match collection.find(track, min_confidence) {
+                Some(found) => matched.push((track, found.file)),
+                None => unmatched.push(Unmatched {
+                    name: track.name.clone(),
+                    artists: track.artists.clone(),
+                    album: track.album.clone(),
+                    uri: track.uri.clone(),
+                }),
+            }
+        }
+
+        let mut m3u = Vec::new();
+        collection::write_m3u(&mut m3u, matched.iter().copied())?;
+        let m3u_path = playlist_dir.join(format!("{name}.m3u8"));
+        crate::atomic::write(&m3u_path, m3u).await?;
+
+        report.playlists.push(PlaylistReport {
+            name,
+            path: m3u_path,
+            tracks: tracks.len(),
+            matched: matched.len(),
+            unmatched,
+        });
+    }
+
+    print(args, &report)
+}
+
+/// Reads the tags of every audio file under `dir`, skipping those that can't be read.
+async fn scan(dir: PathBuf) -> Result<Collection> {
+    let paths = tokio::task::spawn_blocking(move || {
+        walkdir::WalkDir::new(dir)
+            .follow_links(true)
+            .into_iter()
+            .filter_map(|v| v.ok())
+            .filter(|v| v.file_type().is_file())
+            .map(|v| v.into_path())
+            .filter(|v| {
+                v.extension()
+                    .and_then(|v| v.to_str())
+                    .is_some_and(|v| AUDIO_EXTENSIONS.contains(&v.to_lowercase().as_str()))
+            })
+            .collect::<Vec<_>>()
+    })
+    .await?;
+
+    let concurrency = std::thread::available_parallelism().map_or(4, |v| v.get());
+    let files = futures::stream::iter(paths)
+        .map(|path| tokio::task::spawn_blocking(move || read_tags(&path)))
+        .buffered(concurrency)
+        .filter_map(|v| async move { v.ok().flatten() })
+        .collect()
+        .await;
+
+    Ok(Collection::new(files))
+}
+
+/// Reads the tags and length of the audio file at `path`, falling back to the name of the file
+/// for its title when it has none.
+fn read_tags(path: &Path) -> Option<File> {
+    let source = match std::fs::File::open(path) {
+        Ok(v) => v,
+        Err(e) => {
+            debug!("Skipping {}: {e}", path.display());
+            return None;
+        }
+    };
+    let stream = MediaSourceStream::new(Box::new(source), Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|v| v.to_str()) {
+        hint.with_extension(extension);
+    }
+
+    let mut probed = match symphonia::default::get_probe().format(
+        &hint,
+        stream,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    ) {
+        Ok(v) => v,
+        Err(e) => {
+            debug!("Skipping {}: {e}", path.display());
+            return None;
+        }
+    };
+
+    // ID3 tags come before the container and are read while probing, others are in the container
+    let mut tags: Vec<Tag> = Vec::new();
+    if let Some(revision) = probed.metadata.get().as_ref().and_then(|v| v.current()) {
+        tags.extend_from_slice(revision.tags());
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        tags.extend_from_slice(revision.tags());
+    }
+
+    let tag = |key: StandardTagKey| {
+        tags.iter()
+            .find(|v| v.std_key == Some(key))
+            .map(|v| v.value.to_string())
+            .filter(|v| !v.is_empty())
+    };
+    let duration_ms = probed.format.default_track().and_then(|track| {
+        let params = &track.codec_params;
+        let time = params.time_base?.calc_time(params.n_frames?);
+        Some(time.seconds * 1000 + (time.frac * 1000.0) as u64)
+    });
+
+    Some(File {
+        path: path.to_path_buf(),
+        title: tag(StandardTagKey::TrackTitle).unwrap_or_else(|| {
+            path.file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned()
+        }),
+        artists: tags
+            .iter()
+            .filter(|v| v.std_key == Some(StandardTagKey::Artist))
+            .map(|v| v.value.to_string())
+            .collect(),
+        album: tag(StandardTagKey::Album),
+        duration_ms,
+        recording_mbid: tag(StandardTagKey::MusicBrainzRecordingId),
+        isrc: tag(StandardTagKey::IdentIsrc),
+    })
+}
+
+fn print(args: &Args, report: &Report) -> Result<()> {
+    let terminal = std::io::stdout().is_terminal();
+
+    match args.format {
+        output::Format::Json => {
+            let json = if args.pretty || terminal {
+                serde_json::to_string_pretty(report)?
+            } else {
+                serde_json::to_string(report)?
+            };
+            println!("{json}");
+        }
+        output::Format::Table => {
+            let mut table = output::table(&["Playlist", "Track", "Artists", "URI"], terminal, &[3]);
+            for playlist in &report.playlists {
+                for track in &playlist.unmatched {
+                    output::add_row(
+                        &mut table,
+                        [
+                            playlist.name.as_str(),
+                            &track.name,
+                            &track.artists.join(", "),
+                            &track.uri,
+                        ],
+                    );
+                }
+            }
+            if report.playlists.iter().any(|v| !v.unmatched.is_empty()) {
+                println!("{table}");
+            }
+
+            for playlist in &report.playlists {
+                println!(
+                    "{}: matched {} of {} track(s), written to {}",
+                    playlist.name,
+                    playlist.matched,
+                    playlist.tracks,
+                    playlist.path.display()
+                );
+            }
+        }
+        _ => {
+            anyhow::bail!("match-local results can only be written as JSON or a table")
+        }
+    }
+
+    Ok(())
+}
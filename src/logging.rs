@@ -0,0 +1,102 @@
+use std::io::IsTerminal;
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable lines
+    Text,
+    /// A JSON object per event, with a timestamp and any fields of the event
+    Json,
+}
+
+/// Options controlling what's logged to stderr.
+#[derive(clap::Args, Debug, Clone)]
+pub struct LogArgs {
+    /// Logs more detail (eg. the timing of every request), pass twice for even more
+    #[arg(
+        short,
+        long,
+        action = clap::ArgAction::Count,
+        env = "SPOTIFY_BACKUP_VERBOSE",
+        global = true
+    )]
+    pub verbose: u8,
+    /// Only logs warnings and errors, and hides progress bars
+    #[arg(
+        short,
+        long,
+        env = "SPOTIFY_BACKUP_QUIET",
+        conflicts_with = "verbose",
+        global = true
+    )]
+    pub quiet: bool,
+    /// Format of the log lines written to stderr
+    #[arg(
+        long,
+        value_enum,
+        env = "SPOTIFY_BACKUP_LOG_FORMAT",
+        default_value_t = LogFormat::Text,
+        global = true
+    )]
+    pub log_format: LogFormat,
+    /// Prints how many requests were sent to Spotify, how much was downloaded and how long was
+    /// spent waiting out rate limiting, along with the slowest endpoints, when the command finishes
+    #[arg(long, env = "SPOTIFY_BACKUP_STATS", global = true)]
+    pub stats: bool,
+}
+
+impl LogArgs {
+    fn level(&self) -> &'static str {
+        match (self.quiet, self.verbose) {
+            (true, _) => "warn",
+            (false, 0) => "info",
+            (false, 1) => "debug",
+            (false, _) => "trace",
+        }
+    }
+}
+
+/// Sets up logging to stderr at the requested verbosity. Only our own events are logged below the
+/// warn level, `RUST_LOG` overrides the filter entirely for debugging dependencies.
+pub fn init(args: &LogArgs) -> Result<()> {
+    let filter = match std::env::var("RUST_LOG") {
+        Ok(v) => EnvFilter::try_new(v).context("Invalid RUST_LOG filter")?,
+        // symphonia logs audio files it can't read as errors, which match-local skips and logs
+        // itself
+        Err(_) => EnvFilter::new(format!(
+            "warn,symphonia=off,spotify_backup={}",
+            args.level()
+        )),
+    };
+
+    if args.quiet {
+        crate::progress::hide();
+    }
+
+    let layer = tracing_subscriber::fmt::layer().with_writer(|| crate::progress::LogWriter);
+    let layer = match args.log_format {
+        LogFormat::Text => layer
+            .with_ansi(std::io::stderr().is_terminal())
+            .with_target(false)
+            .without_time()
+            .boxed(),
+        // fields of the backup being run are included alongside the event's own, so every line
+        // can be attributed to a playlist
+        LogFormat::Json => layer
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .boxed(),
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(layer)
+        .init();
+
+    Ok(())
+}
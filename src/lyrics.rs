@@ -0,0 +1,230 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use futures::StreamExt;
+use reqwest::{StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::api;
+
+/// Tree of the metadata cache lyrics are kept in, by Spotify track ID.
+const CACHE_KIND: &str = "lyrics";
+
+/// Directory in the output directory sidecar lyrics are written into.
+pub const DIR: &str = "lyrics";
+
+/// How far the length of a search result may be from the track's, in seconds, for it to be taken
+/// as the same recording.
+const MAX_DURATION_DIFFERENCE: f64 = 2.0;
+
+/// Provider lyrics are fetched from by default.
+pub const DEFAULT_PROVIDER: &str = "https://lrclib.net";
+
+/// LRCLIB asks clients to identify themselves.
+const USER_AGENT: &str = concat!(
+    env!("CARGO_PKG_NAME"),
+    "/",
+    env!("CARGO_PKG_VERSION"),
+    " (https://github.com/w4/spotify-backup)"
+);
+
+/// Where lyrics fetched with `--with-lyrics` are stored.
+#[derive(ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// In a `lyrics` field of each track in the backup
+    Embed,
+    /// In a .lrc (or .txt, for lyrics without timings) file per track in a lyrics directory next
+    /// to the backup
+    Sidecar,
+}
+
+/// Lyrics of a track, time-synced in LRC format and as plain text, either of which the provider
+/// may not have.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Lyrics {
+    pub synced: Option<String>,
+    pub plain: Option<String>,
+}
+
+impl Lyrics {
+    /// The synced lyrics if there are any, otherwise the plain ones.
+    pub fn text(&self) -> Option<&str> {
+        self.synced.as_deref().or(self.plain.as_deref())
+    }
+}
+
+/// What a track's lyrics are looked up by.
+pub struct Query<'a> {
+    /// Spotify ID, missing for local files which aren't looked up
+    pub id: Option<&'a str>,
+    pub name: &'a str,
+    pub artist: Option<&'a str>,
+    pub album: &'a str,
+    pub duration_ms: Option<u64>,
+}
+
+/// A record as returned by an LRCLIB-compatible provider.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Record {
+    duration: Option<f64>,
+    plain_lyrics: Option<String>,
+    synced_lyrics: Option<String>,
+}
+
+impl From<Record> for Lyrics {
+    fn from(record: Record) -> Self {
+        Self {
+            synced: record.synced_lyrics.filter(|v| !v.is_empty()),
+            plain: record.plain_lyrics.filter(|v| !v.is_empty()),
+        }
+    }
+}
+
+/// Fetches the lyrics of each track from `provider`, an LRCLIB-compatible API, by its exact
+/// title, artist, album and length, falling back to searching for its title and artist. Lyrics are
+/// kept in the metadata cache by track ID, including for tracks the provider has none for, so
+/// each track is only looked up once. Lookups that fail are logged and left empty, and with
+/// `--offline` only the cache is read.
+pub async fn lookup(
+    client: &api::Client,
+    provider: &Url,
+    queries: &[Query<'_>],
+) -> Vec<Option<Lyrics>> {
+    let http = crate::http::builder()
+        .user_agent(USER_AGENT)
+        .build()
+        .expect("Failed to build HTTP client");
+    let cache = client.metadata_cache();
+
+    futures::stream::iter(queries)
+        .map(|query| {
+            let http = &http;
+
+            async move {
+                let id = query.id?;
+
+                let cached = cache.and_then(|cache| {
+                    cache
+                        .get(CACHE_KIND, id)
+                        .inspect_err(|e| warn!("Failed to read lyrics of {id} from cache: {e:#}"))
+                        .ok()
+                        .flatten()
+                        .and_then(|v| serde_json::from_value(v).ok())
+                });
+                if cached.is_some() || client.offline() {
+                    return cached;
+                }
+
+                match lookup_one(http, provider, query).await {
+                    Ok(lyrics) => {
+                        if let Some(cache) = cache {
+                            let value = serde_json::to_value(&lyrics).unwrap_or_default();
+                            if let Err(e) = cache.insert(CACHE_KIND, id, &value) {
+                                warn!("Failed to cache lyrics of {id}: {e:#}");
+                            }
+                        }
+                        Some(lyrics)
+                    }
+                    // not cached, so it's tried again on the next run
+                    Err(e) => {
+                        warn!("Failed to fetch lyrics of {id}: {e:#}");
+                        None
+                    }
+                }
+            }
+        })
+        .buffered(client.concurrency())
+        .collect()
+        .await
+}
+
+async fn lookup_one(http: &reqwest::Client, provider: &Url, query: &Query<'_>) -> Result<Lyrics> {
+    let Some(artist) = query.artist else {
+        return Ok(Lyrics::default());
+    };
+
+    if let Some(duration_ms) = query.duration_ms {
+        let duration = (duration_ms / 1000).to_string();
+        let url = provider.join("api/get")?;
+        let params = [
+            ("track_name", query.name),
+            ("artist_name", artist),
+            ("album_name", query.album),
+            ("duration", &duration),
+        ];
+
+        if let Some(record) = get::<Record>(http, url, &params).await? {
+            debug!(id = query.id, "Found lyrics by signature");
+            return Ok(record.into());
+        }
+    }
+
+    let url = provider.join("api/search")?;
+    let params = [("track_name", query.name), ("artist_name", artist)];
+    let records = get::<Vec<Record>>(http, url, &params)
+        .await?
+        .unwrap_or_default();
+
+    // results can be other recordings of the same song, so only those the same length are taken
+    let duration = query.duration_ms.map(|v| v as f64 / 1000.0);
+    let record = records
+        .into_iter()
+        .filter(|v| match (duration, v.duration) {
+            (Some(a), Some(b)) => (a - b).abs() <= MAX_DURATION_DIFFERENCE,
+            _ => true,
+        })
+        .max_by_key(|v| (v.synced_lyrics.is_some(), v.plain_lyrics.is_some()));
+
+    Ok(record.map(Lyrics::from).unwrap_or_default())
+}
+
+/// Fetches `url` with the given query parameters, returning `None` if the provider has nothing
+/// there.
+async fn get<T: serde::de::DeserializeOwned>(
+    http: &reqwest::Client,
+    url: Url,
+    params: &[(&str, &str)],
+) -> Result<Option<T>> {
+    let response = http
+        .get(url)
+        .query(params)
+        .send()
+        .await
+        .context("Failed to send request to lyrics provider")?;
+
+    match response.status() {
+        StatusCode::NOT_FOUND => Ok(None),
+        status if !status.is_success() => {
+            anyhow::bail!("Got {status} response from lyrics provider")
+        }
+        _ => response
+            .json()
+            .await
+            .map(Some)
+            .context("Failed to parse response from lyrics provider"),
+    }
+}
+
+/// Writes the lyrics of a track into `dir`'s lyrics directory, as `<id>.lrc` if they're synced or
+/// `<id>.txt` if they're only plain text. Files already there are replaced.
+pub async fn write_sidecar(dir: &Path, id: &str, lyrics: &Lyrics) -> Result<()> {
+    let (extension, text) = match (&lyrics.synced, &lyrics.plain) {
+        (Some(synced), _) => ("lrc", synced),
+        (None, Some(plain)) => ("txt", plain),
+        (None, None) => return Ok(()),
+    };
+
+    let dir = dir.join(DIR);
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    let path = dir.join(format!("{id}.{extension}"));
+    crate::atomic::write(&path, text.as_bytes())
+        .await
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
@@ -0,0 +1,143 @@
+use std::{io::ErrorKind, path::Path};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use crate::writer::Written;
+
+/// Name of the manifest written alongside the backup files in an output directory.
+pub const FILE_NAME: &str = "manifest.json";
+
+pub use crate::export::SCHEMA_VERSION;
+
+/// Index of every file in a backup directory along with its checksum, so the backup can be checked
+/// for bit-rot or truncation long after it was written.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Manifest {
+    pub schema_version: u32,
+    /// RFC 3339 timestamp of the last time the manifest was updated
+    pub updated_at: String,
+    /// Spotify user ID of the account the backup was taken from
+    pub account_id: String,
+    pub files: Vec<ManifestFile>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ManifestFile {
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+    /// RFC 3339 timestamp of when the file was written
+    pub created_at: String,
+}
+
+impl ManifestFile {
+    pub fn new(name: String, written: &Written) -> Self {
+        Self {
+            name,
+            size: written.size,
+            sha256: written.sha256.clone(),
+            created_at: chrono::Utc::now().to_rfc3339(),
+        }
+    }
+}
+
+/// Reads the manifest from a backup directory, if one has been written.
+pub async fn read(dir: &Path) -> Result<Option<Manifest>> {
+    let data = match tokio::fs::read(dir.join(FILE_NAME)).await {
+        Ok(v) => v,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).context("Failed to read manifest"),
+    };
+
+    serde_json::from_slice(&data)
+        .map(Some)
+        .context("Failed to parse manifest")
+}
+
+/// Adds `added` to the manifest in `dir`, replacing any previous entries with the same names, and
+/// drops the entries of the `removed` files. Returns the serialized manifest that was written.
+pub async fn update(
+    dir: &Path,
+    account_id: &str,
+    added: Vec<ManifestFile>,
+    removed: &[String],
+) -> Result<Vec<u8>> {
+    let mut files = match read(dir).await? {
+        // a manifest from a different account describes a different backup, start over
+        Some(manifest) if manifest.account_id == account_id => manifest.files,
+        _ => Vec::new(),
+    };
+
+    files.retain(|v| !removed.contains(&v.name) && !added.iter().any(|file| file.name == v.name));
+    files.extend(added);
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let manifest = Manifest {
+        schema_version: SCHEMA_VERSION,
+        updated_at: chrono::Utc::now().to_rfc3339(),
+        account_id: account_id.to_string(),
+        files,
+    };
+
+    write(dir, &manifest).await
+}
+
+/// Writes `manifest` to `dir`, returning the serialized manifest that was written.
+pub async fn write(dir: &Path, manifest: &Manifest) -> Result<Vec<u8>> {
+    let serialized = serde_json::to_vec_pretty(manifest).context("Failed to serialize manifest")?;
+    crate::atomic::write(&dir.join(FILE_NAME), &serialized)
+        .await
+        .context("Failed to write manifest")?;
+
+    Ok(serialized)
+}
+
+/// Checks every file listed in the manifest in `dir` against its recorded size and checksum,
+/// printing the result for each and returning the number of files that failed.
+pub async fn verify(dir: &Path) -> Result<usize> {
+    let manifest = read(dir)
+        .await?
+        .with_context(|| format!("No {FILE_NAME} found in {}", dir.display()))?;
+
+    if manifest.schema_version > SCHEMA_VERSION {
+        warn!(
+            "Manifest was written with a newer schema version ({}), results may be inaccurate",
+            manifest.schema_version
+        );
+    }
+
+    let mut failures = 0;
+
+    for file in &manifest.files {
+        match check(dir, file).await {
+            None => println!("OK       {}", file.name),
+            Some(status) => {
+                failures += 1;
+                println!("FAILED   {}: {status}", file.name);
+            }
+        }
+    }
+
+    Ok(failures)
+}
+
+/// Checks `file` in `dir` against its recorded size and checksum, returning what's wrong with it if
+/// anything.
+pub async fn check(dir: &Path, file: &ManifestFile) -> Option<String> {
+    match tokio::fs::read(dir.join(&file.name)).await {
+        Ok(data) if data.len() as u64 != file.size => Some(format!(
+            "SIZE MISMATCH (expected {}, got {})",
+            file.size,
+            data.len()
+        )),
+        Ok(data) if hex::encode(Sha256::digest(&data)) != file.sha256 => {
+            Some("CHECKSUM MISMATCH".to_string())
+        }
+        Ok(_) => None,
+        Err(e) if e.kind() == ErrorKind::NotFound => Some("MISSING".to_string()),
+        Err(e) => Some(format!("UNREADABLE ({e})")),
+    }
+}
@@ -0,0 +1,521 @@
+use std::{
+    collections::BTreeMap,
+    io::IsTerminal,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use futures::StreamExt;
+use serde::Serialize;
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::{apple, cli::Args, deezer, dupes, migrate, output, tidal, youtube};
+
+/// Searches run at once, which is kept low as the services matched against don't publish a rate
+/// limit.
+const CONCURRENCY: usize = 4;
+
+/// How far apart the lengths of two tracks may be, in seconds, while still counting as a perfect
+/// match, as services often round or pad them differently.
+const DURATION_TOLERANCE: f64 = 2.0;
+
+/// Difference in length, in seconds, past which the length counts for nothing.
+const MAX_DURATION_DIFFERENCE: f64 = 15.0;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct MapArgs {
+    /// Path to the backup file
+    pub path: PathBuf,
+    /// Writes the mapping as CSV rather than in `--format`, the same as `--format csv`
+    #[arg(long)]
+    pub csv: bool,
+    /// Lowest confidence, from 0 to 1, a match needs to be kept
+    #[arg(long, default_value_t = 0.6, value_parser = crate::similar::parse_threshold)]
+    pub min_confidence: f64,
+}
+
+/// A service tracks can be mapped to.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// YouTube Music, by searching
+    Youtube,
+    /// Deezer, by ISRC or searching
+    Deezer,
+    /// Tidal, by ISRC or searching, with the credentials of a developer app
+    Tidal,
+    /// Apple Music, by ISRC or searching
+    Apple,
+}
+
+impl Kind {
+    pub fn service(self, args: &Args, tidal: &tidal::TidalArgs) -> Result<AnyService> {
+        Ok(match self {
+            Self::Youtube => AnyService::Youtube(youtube::YoutubeMusic::new()),
+            Self::Deezer => AnyService::Deezer(deezer::Deezer::new()),
+            Self::Tidal => AnyService::Tidal(tidal::Tidal::new(args, tidal)?),
+            Self::Apple => AnyService::Apple(apple::AppleMusic::new(args)),
+        })
+    }
+}
+
+/// A track read from a backup, to be found on another service.
+pub struct Track {
+    pub uri: String,
+    pub name: String,
+    pub artists: Vec<String>,
+    pub album: Option<String>,
+    pub duration_ms: Option<u64>,
+    pub isrc: Option<String>,
+    /// MusicBrainz recording ID, only in backups written with --musicbrainz
+    pub recording_mbid: Option<String>,
+}
+
+impl Track {
+    /// The track as fetched from Spotify, rather than read from a backup.
+    pub fn fetched(track: &crate::GetPlaylistTracksResponseItemTrack) -> Self {
+        Self {
+            uri: track.uri.clone(),
+            name: track.name.clone(),
+            artists: track.artists.iter().map(|v| v.name.clone()).collect(),
+            album: Some(track.album.name.clone()),
+            duration_ms: track.duration_ms,
+            isrc: track.external_ids.as_ref().and_then(|v| v.isrc.clone()),
+            recording_mbid: None,
+        }
+    }
+}
+
+/// A result of searching another service for a track.
+#[derive(Serialize, Debug, Clone)]
+pub struct Candidate {
+    /// ID of the track on the service (eg. a YouTube video ID)
+    pub id: String,
+    pub title: String,
+    pub artists: Vec<String>,
+    pub album: Option<String>,
+    /// ID of the album on the service (eg. a YouTube Music browse ID)
+    pub album_id: Option<String>,
+    #[serde(skip)]
+    pub duration_ms: Option<u64>,
+    /// Whether the result was found by the track's ISRC, which makes it the same recording
+    #[serde(skip)]
+    pub by_isrc: bool,
+}
+
+/// The best match for a track, if it was confident enough.
+#[derive(Serialize, Debug)]
+struct Mapping {
+    uri: String,
+    name: String,
+    artists: Vec<String>,
+    #[serde(rename = "match")]
+    found: Option<Candidate>,
+    /// How likely the best result is the same recording, from 0 to 1, even if it wasn't confident
+    /// enough to be matched
+    confidence: f64,
+}
+
+/// A track's IDs on each service it was mapped to, `None` where it wasn't matched.
+#[derive(Serialize, Debug)]
+struct ConcordanceRow {
+    uri: String,
+    name: String,
+    artists: Vec<String>,
+    isrc: Option<String>,
+    #[serde(flatten)]
+    services: BTreeMap<&'static str, Option<ServiceId>>,
+}
+
+#[derive(Serialize, Debug)]
+struct ServiceId {
+    id: String,
+    confidence: f64,
+}
+
+/// A service tracks can be looked up on.
+pub trait Service {
+    /// Name of the service, used as a prefix for its columns in CSV output (eg. "youtube").
+    fn name(&self) -> &'static str;
+
+    /// Looks up `track`, by its ISRC where the service supports it and otherwise by searching,
+    /// returning the results worth scoring.
+    async fn search(&self, track: &Track) -> Result<Vec<Candidate>>;
+}
+
+/// One of the services tracks can be mapped to, for mapping to several at once.
+pub enum AnyService {
+    Youtube(youtube::YoutubeMusic),
+    Deezer(deezer::Deezer),
+    Tidal(tidal::Tidal),
+    Apple(apple::AppleMusic),
+}
+
+impl Service for AnyService {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Youtube(v) => v.name(),
+            Self::Deezer(v) => v.name(),
+            Self::Tidal(v) => v.name(),
+            Self::Apple(v) => v.name(),
+        }
+    }
+
+    async fn search(&self, track: &Track) -> Result<Vec<Candidate>> {
+        match self {
+            Self::Youtube(v) => v.search(track).await,
+            Self::Deezer(v) => v.search(track).await,
+            Self::Tidal(v) => v.search(track).await,
+            Self::Apple(v) => v.search(track).await,
+        }
+    }
+}
+
+/// Finds the best match on `service` for every track in the backup, scoring each result by how
+/// closely its title, artists and length match the track, and prints the mapping as JSON, a table
+/// or as CSV. Tracks whose best result scores below the minimum confidence are left unmatched.
+pub async fn run(args: &Args, service: &impl Service, map: &MapArgs) -> Result<()> {
+    let tracks = read_tracks(args, &map.path).await?;
+    info!("Finding {} track(s) on {}...", tracks.len(), service.name());
+
+    let mappings: Vec<_> = futures::stream::iter(&tracks)
+        .map(|track| async move {
+            let (confidence, best) = best_match(service, track).await;
+
+            Mapping {
+                uri: track.uri.clone(),
+                name: track.name.clone(),
+                artists: track.artists.clone(),
+                found: best.filter(|_| confidence >= map.min_confidence),
+                confidence,
+            }
+        })
+        .buffered(CONCURRENCY)
+        .collect()
+        .await;
+
+    let matched = mappings.iter().filter(|v| v.found.is_some()).count();
+    info!("Matched {matched} of {} track(s)", mappings.len());
+
+    if map.csv || args.format == output::Format::Csv {
+        print_csv(service.name(), &mappings)
+    } else {
+        print(args, &mappings)
+    }
+}
+
+/// Maps every track in the backup to each of `services` at once, printing a row per track with
+/// its ID on each of them, for joining libraries kept on several services.
+pub async fn concordance(args: &Args, services: &[AnyService], map: &MapArgs) -> Result<()> {
+    let tracks = read_tracks(args, &map.path).await?;
+    let names: Vec<_> = services.iter().map(|v| v.name()).collect();
+    info!(
+        "Finding {} track(s) on {}...",
+        tracks.len(),
+        names.join(", ")
+    );
+
+    let rows: Vec<_> = futures::stream::iter(&tracks)
+        .map(|track| async move {
+            let found = futures::future::join_all(services.iter().map(|service| async move {
+                let (confidence, best) = best_match(service, track).await;
+                let id = best
+                    .filter(|_| confidence >= map.min_confidence)
+                    .map(|v| ServiceId {
+                        id: v.id,
+                        confidence,
+                    });
+
+                (service.name(), id)
+            }))
+            .await;
+
+            ConcordanceRow {
+                uri: track.uri.clone(),
+                name: track.name.clone(),
+                artists: track.artists.clone(),
+                isrc: track.isrc.clone(),
+                services: found.into_iter().collect(),
+            }
+        })
+        .buffered(CONCURRENCY)
+        .collect()
+        .await;
+
+    for name in &names {
+        let matched = rows
+            .iter()
+            .filter(|v| v.services.get(name).is_some_and(Option::is_some))
+            .count();
+        info!("Matched {matched} of {} track(s) on {name}", rows.len());
+    }
+
+    if map.csv || args.format == output::Format::Csv {
+        print_concordance_csv(&names, &rows)
+    } else {
+        print_concordance(args, &names, &rows)
+    }
+}
+
+/// The result of looking up `track` on `service` most likely to be the same recording, with how
+/// likely it is. One failed lookup shouldn't lose the rest, so it's logged and left unmatched.
+pub async fn best_match(service: &impl Service, track: &Track) -> (f64, Option<Candidate>) {
+    let candidates = service
+        .search(track)
+        .await
+        .inspect_err(|e| {
+            warn!(
+                "Failed to look up {} on {}: {e:#}",
+                track.uri,
+                service.name()
+            )
+        })
+        .unwrap_or_default();
+
+    candidates
+        .into_iter()
+        .map(|v| (confidence(track, &v), v))
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+        .map_or((0.0, None), |(confidence, v)| (confidence, Some(v)))
+}
+
+/// Reads the tracks to match from a backup, leaving out podcast episodes which can't be matched
+/// to music.
+pub async fn read_tracks(args: &Args, path: &Path) -> Result<Vec<Track>> {
+    let data = crate::cli::read_backup(path, &args.identity).await?;
+    let tracks = migrate::tracks(&data)
+        .with_context(|| format!("Failed to read tracks from {}", path.display()))?
+        .with_context(|| format!("{} isn't a JSON backup", path.display()))?;
+
+    let mut read = Vec::new();
+    for (i, track) in tracks.iter().enumerate() {
+        let uri = track["uri"].as_str().with_context(|| {
+            format!(
+                "Track {i} of {} has no URI, back it up with uri in --fields",
+                path.display()
+            )
+        })?;
+        if uri.starts_with("spotify:episode:") {
+            continue;
+        }
+
+        read.push(Track {
+            uri: uri.to_string(),
+            name: track["name"].as_str().unwrap_or_default().to_string(),
+            artists: track["artists"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+                .map(str::to_string)
+                .collect(),
+            album: track["album"]["name"].as_str().map(str::to_string),
+            duration_ms: track["duration_ms"].as_u64(),
+            isrc: track["isrc"].as_str().map(str::to_string),
+            recording_mbid: track["recording_mbid"].as_str().map(str::to_string),
+        });
+    }
+
+    Ok(read)
+}
+
+/// How likely `candidate` is the same recording as `track`, from 0 to 1, weighing up how similar
+/// their titles (ignoring versions like "Remastered"), artists and lengths are. Results found by
+/// ISRC are certain.
+pub fn confidence(track: &Track, candidate: &Candidate) -> f64 {
+    if candidate.by_isrc {
+        return 1.0;
+    }
+
+    let normalize =
+        |name: &str| dupes::normalize_title(name).unwrap_or_else(|| name.to_lowercase());
+    let title = strsim::jaro_winkler(&normalize(&track.name), &normalize(&candidate.title));
+
+    // services credit featured artists differently, so the best matching pair is what counts
+    let artist = track
+        .artists
+        .iter()
+        .flat_map(|a| {
+            candidate
+                .artists
+                .iter()
+                .map(move |b| strsim::jaro_winkler(&a.to_lowercase(), &b.to_lowercase()))
+        })
+        .fold(0.0, f64::max);
+
+    // an unknown length neither helps nor hurts
+    let duration = match (track.duration_ms, candidate.duration_ms) {
+        (Some(a), Some(b)) => {
+            let difference = (a as f64 - b as f64).abs() / 1000.0;
+            1.0 - ((difference - DURATION_TOLERANCE).max(0.0)
+                / (MAX_DURATION_DIFFERENCE - DURATION_TOLERANCE))
+                .min(1.0)
+        }
+        _ => 0.5,
+    };
+
+    let confidence = title * 0.5 + artist * 0.3 + duration * 0.2;
+    (confidence * 100.0).round() / 100.0
+}
+
+fn print(args: &Args, mappings: &[Mapping]) -> Result<()> {
+    let terminal = std::io::stdout().is_terminal();
+
+    match args.format {
+        output::Format::Json => {
+            let json = if args.pretty || terminal {
+                serde_json::to_string_pretty(mappings)?
+            } else {
+                serde_json::to_string(mappings)?
+            };
+            println!("{json}");
+        }
+        output::Format::Table => {
+            let mut table = output::table(
+                &["Track", "Artists", "Match", "ID", "Confidence"],
+                terminal,
+                &[3, 4],
+            );
+            for mapping in mappings {
+                let (title, id) = match &mapping.found {
+                    Some(v) => (v.title.as_str(), v.id.as_str()),
+                    None => ("", ""),
+                };
+
+                output::add_row(
+                    &mut table,
+                    [
+                        mapping.name.as_str(),
+                        &mapping.artists.join(", "),
+                        title,
+                        id,
+                        &format!("{:.2}", mapping.confidence),
+                    ],
+                );
+            }
+            println!("{table}");
+        }
+        _ => {
+            anyhow::bail!("Mappings can only be written as JSON, a table or CSV")
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes a row per track, with the match's columns left empty for tracks that weren't matched.
+fn print_csv(service: &str, mappings: &[Mapping]) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(std::io::stdout());
+
+    writer.write_record([
+        "spotify_uri".to_string(),
+        "name".to_string(),
+        "artists".to_string(),
+        format!("{service}_id"),
+        format!("{service}_title"),
+        format!("{service}_artists"),
+        format!("{service}_album"),
+        format!("{service}_album_id"),
+        "confidence".to_string(),
+    ])?;
+
+    for mapping in mappings {
+        let found = mapping.found.as_ref();
+
+        writer.write_record([
+            mapping.uri.as_str(),
+            &mapping.name,
+            &mapping.artists.join(", "),
+            found.map(|v| v.id.as_str()).unwrap_or_default(),
+            found.map(|v| v.title.as_str()).unwrap_or_default(),
+            &found.map(|v| v.artists.join(", ")).unwrap_or_default(),
+            found.and_then(|v| v.album.as_deref()).unwrap_or_default(),
+            found
+                .and_then(|v| v.album_id.as_deref())
+                .unwrap_or_default(),
+            &format!("{:.2}", mapping.confidence),
+        ])?;
+    }
+
+    writer.flush().context("Failed to write CSV")
+}
+
+fn print_concordance(args: &Args, names: &[&str], rows: &[ConcordanceRow]) -> Result<()> {
+    let terminal = std::io::stdout().is_terminal();
+
+    match args.format {
+        output::Format::Json => {
+            let json = if args.pretty || terminal {
+                serde_json::to_string_pretty(rows)?
+            } else {
+                serde_json::to_string(rows)?
+            };
+            println!("{json}");
+        }
+        output::Format::Table => {
+            let columns: Vec<_> = ["Track", "Artists", "ISRC"]
+                .into_iter()
+                .chain(names.iter().copied())
+                .collect();
+            let keep: Vec<_> = (2..columns.len()).collect();
+            let mut table = output::table(&columns, terminal, &keep);
+
+            for row in rows {
+                let mut cells = vec![
+                    row.name.clone(),
+                    row.artists.join(", "),
+                    row.isrc.clone().unwrap_or_default(),
+                ];
+                cells.extend(names.iter().map(|name| match row.services.get(name) {
+                    Some(Some(v)) => v.id.clone(),
+                    _ => String::new(),
+                }));
+
+                output::add_row(&mut table, cells.iter().map(String::as_str));
+            }
+            println!("{table}");
+        }
+        _ => {
+            anyhow::bail!("Concordances can only be written as JSON, a table or CSV")
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes a row per track with an ID and confidence column for each service, left empty for the
+/// services it wasn't matched on.
+fn print_concordance_csv(names: &[&str], rows: &[ConcordanceRow]) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(std::io::stdout());
+
+    let mut header: Vec<_> = ["spotify_uri", "name", "artists", "isrc"]
+        .map(String::from)
+        .into();
+    for name in names {
+        header.push(format!("{name}_id"));
+        header.push(format!("{name}_confidence"));
+    }
+    writer.write_record(&header)?;
+
+    for row in rows {
+        let mut record = vec![
+            row.uri.clone(),
+            row.name.clone(),
+            row.artists.join(", "),
+            row.isrc.clone().unwrap_or_default(),
+        ];
+        for name in names {
+            match row.services.get(name) {
+                Some(Some(v)) => {
+                    record.push(v.id.clone());
+                    record.push(format!("{:.2}", v.confidence));
+                }
+                _ => record.extend([String::new(), String::new()]),
+            }
+        }
+        writer.write_record(&record)?;
+    }
+
+    writer.flush().context("Failed to write CSV")
+}
@@ -0,0 +1,328 @@
+use std::{
+    collections::HashMap,
+    io::{IsTerminal, Write},
+    path::PathBuf,
+};
+
+use anyhow::{Context, Result};
+use chrono::DateTime;
+use serde_json::{json, Map, Value};
+use tracing::info;
+
+use crate::{cli::Args, manifest, migrate, output};
+
+/// Which copy of a track found in several backups is kept.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Strategy {
+    /// Keeps the copy from the earliest file given
+    #[default]
+    First,
+    /// Keeps the copy from the latest file given
+    Last,
+    /// Keeps the copy from the earliest file given, filling in fields it's missing or has as null
+    /// from later ones
+    Fill,
+}
+
+/// Prints the union of the tracks in the backups at `paths` as a JSON backup, in the order they're
+/// first seen. Tracks are the same if they share a URI, or failing that an ISRC, so a track
+/// Spotify has relinked in one of the backups isn't kept twice. Whichever copy `strategy` picks is
+/// kept, with the earliest `added_at` of any of them.
+pub async fn run(args: &Args, paths: &[PathBuf], strategy: Strategy) -> Result<()> {
+    anyhow::ensure!(
+        args.format == output::Format::Json,
+        "Backups can only be merged into JSON, pass --format json"
+    );
+
+    let mut merged = Merged::default();
+    for path in paths {
+        let data = crate::cli::read_backup(path, &args.identity).await?;
+        merged
+            .add(&data, strategy)
+            .with_context(|| format!("Failed to merge {}", path.display()))?;
+    }
+
+    info!(
+        "Merged {} track(s) from {} file(s) into {}",
+        merged.total,
+        paths.len(),
+        merged.tracks.len()
+    );
+
+    let backup = json!({
+        "schema_version": manifest::SCHEMA_VERSION,
+        "tracks": merged.tracks,
+    });
+
+    let mut stdout = std::io::stdout().lock();
+    if args.pretty || stdout.is_terminal() {
+        serde_json::to_writer_pretty(&mut stdout, &backup)?;
+    } else {
+        serde_json::to_writer(&mut stdout, &backup)?;
+    }
+    writeln!(stdout)?;
+
+    Ok(())
+}
+
+/// Tracks merged so far, in the order they were first seen.
+#[derive(Default)]
+struct Merged {
+    tracks: Vec<Map<String, Value>>,
+    /// Index of the track seen under each URI
+    by_uri: HashMap<String, usize>,
+    /// Index of the track seen with each ISRC
+    by_isrc: HashMap<String, usize>,
+    /// Number of tracks across every backup added
+    total: usize,
+}
+
+impl Merged {
+    /// Merges in the tracks of a decrypted and decompressed JSON backup of any schema version.
+    fn add(&mut self, data: &[u8], strategy: Strategy) -> Result<()> {
+        let tracks = migrate::tracks(data)
+            .context("Failed to read tracks")?
+            .context("Not a JSON backup")?;
+
+        for (i, track) in tracks.into_iter().enumerate() {
+            let Value::Object(track) = track else {
+                anyhow::bail!("Track {i} isn't an object");
+            };
+            self.total += 1;
+
+            let uri = string(&track, "uri");
+            let isrc = string(&track, "isrc");
+
+            let existing = uri
+                .as_ref()
+                .and_then(|v| self.by_uri.get(v))
+                .or_else(|| isrc.as_ref().and_then(|v| self.by_isrc.get(v)))
+                .copied();
+
+            let index = match existing {
+                Some(index) => {
+                    merge(&mut self.tracks[index], track, strategy);
+                    index
+                }
+                None => {
+                    self.tracks.push(track);
+                    self.tracks.len() - 1
+                }
+            };
+
+            // every URI a track has been seen under is remembered, as is its ISRC
+            if let Some(uri) = uri {
+                self.by_uri.entry(uri).or_insert(index);
+            }
+            if let Some(isrc) = isrc {
+                self.by_isrc.entry(isrc).or_insert(index);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn string(track: &Map<String, Value>, key: &str) -> Option<String> {
+    track.get(key).and_then(Value::as_str).map(str::to_string)
+}
+
+/// Merges a later copy of a track into the one already kept.
+fn merge(kept: &mut Map<String, Value>, track: Map<String, Value>, strategy: Strategy) {
+    let added_at = earliest(kept.get("added_at"), track.get("added_at"));
+
+    match strategy {
+        Strategy::First => {}
+        Strategy::Last => *kept = track,
+        Strategy::Fill => {
+            for (key, value) in track {
+                match kept.get_mut(&key) {
+                    Some(kept @ Value::Null) => *kept = value,
+                    Some(_) => {}
+                    None => {
+                        kept.insert(key, value);
+                    }
+                }
+            }
+        }
+    }
+
+    // only if it was one of the fields written, so every track keeps the same fields
+    if let (Some(kept), Some(added_at)) = (kept.get_mut("added_at"), added_at) {
+        *kept = added_at;
+    }
+}
+
+/// Whichever of two `added_at` values is earlier, ignoring any that are null or can't be parsed.
+fn earliest(a: Option<&Value>, b: Option<&Value>) -> Option<Value> {
+    let parse = |v: Option<&Value>| {
+        let v = v?.as_str()?;
+        Some((DateTime::parse_from_rfc3339(v).ok()?, v.to_string()))
+    };
+
+    match (parse(a), parse(b)) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+    .map(|(_, v)| Value::String(v))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merged(backups: &[Value], strategy: Strategy) -> Vec<Value> {
+        let mut merged = Merged::default();
+        for backup in backups {
+            merged.add(backup.to_string().as_bytes(), strategy).unwrap();
+        }
+
+        merged.tracks.into_iter().map(Value::Object).collect()
+    }
+
+    fn backup(tracks: Value) -> Value {
+        json!({"schema_version": manifest::SCHEMA_VERSION, "tracks": tracks})
+    }
+
+    #[test]
+    fn keeps_tracks_in_several_backups_once() {
+        let tracks = merged(
+            &[
+                backup(json!([
+                    {"name": "Roygbiv", "uri": "spotify:track:a"},
+                    {"name": "Archangel", "uri": "spotify:track:b"},
+                ])),
+                backup(json!([
+                    {"name": "Archangel", "uri": "spotify:track:b"},
+                    {"name": "Jóga", "uri": "spotify:track:c"},
+                ])),
+            ],
+            Strategy::First,
+        );
+
+        let names: Vec<_> = tracks.iter().map(|v| v["name"].as_str().unwrap()).collect();
+        assert_eq!(names, ["Roygbiv", "Archangel", "Jóga"]);
+    }
+
+    #[test]
+    fn matches_relinked_tracks_by_isrc() {
+        let tracks = merged(
+            &[
+                backup(json!([{"uri": "spotify:track:old", "isrc": "GBBPW9800012"}])),
+                backup(json!([{"uri": "spotify:track:new", "isrc": "GBBPW9800012"}])),
+                // seen under either URI from then on
+                backup(json!([{"uri": "spotify:track:new"}, {"uri": "spotify:track:old"}])),
+            ],
+            Strategy::First,
+        );
+
+        assert_eq!(
+            tracks,
+            [json!({"uri": "spotify:track:old", "isrc": "GBBPW9800012"})]
+        );
+    }
+
+    #[test]
+    fn keeps_earliest_added_at() {
+        let copy = |name: &str, added_at: Value| {
+            backup(json!([{"uri": "spotify:track:a", "name": name, "added_at": added_at}]))
+        };
+        let backups = [
+            copy("First", json!("2024-03-01T00:00:00Z")),
+            // earlier once in UTC, though later as written
+            copy("Second", json!("2024-03-01T01:00:00+02:00")),
+            copy("Third", json!("not a date")),
+            copy("Fourth", Value::Null),
+        ];
+
+        for (strategy, name) in [
+            (Strategy::First, "First"),
+            (Strategy::Last, "Fourth"),
+            (Strategy::Fill, "First"),
+        ] {
+            let tracks = merged(&backups, strategy);
+
+            assert_eq!(tracks.len(), 1);
+            assert_eq!(tracks[0]["name"], name, "{strategy:?}");
+            assert_eq!(
+                tracks[0]["added_at"], "2024-03-01T01:00:00+02:00",
+                "{strategy:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn only_sets_added_at_if_it_was_written() {
+        let tracks = merged(
+            &[
+                backup(json!([{"uri": "spotify:track:a"}])),
+                backup(json!([{"uri": "spotify:track:a", "added_at": "2024-03-01T00:00:00Z"}])),
+            ],
+            Strategy::First,
+        );
+
+        assert_eq!(tracks, [json!({"uri": "spotify:track:a"})]);
+    }
+
+    #[test]
+    fn fills_in_missing_fields_from_later_copies() {
+        let tracks = merged(
+            &[
+                backup(json!([{"uri": "spotify:track:a", "name": "Roygbiv", "isrc": null}])),
+                backup(json!([{"uri": "spotify:track:a", "name": "Other", "isrc": "X"}])),
+                backup(json!([{"uri": "spotify:track:a", "popularity": 50}])),
+            ],
+            Strategy::Fill,
+        );
+
+        assert_eq!(
+            tracks,
+            [json!({"uri": "spotify:track:a", "name": "Roygbiv", "isrc": "X", "popularity": 50})]
+        );
+    }
+
+    #[test]
+    fn reads_backups_of_every_schema_version() {
+        let tracks = merged(
+            &[
+                // version 1, a bare array
+                json!([{"uri": "spotify:track:a", "added_at": "2024-01-01T00:00:00Z"}]),
+                backup(json!([{"uri": "spotify:track:a", "added_at": "2023-01-01T00:00:00Z"}])),
+                // grouped by album
+                json!({
+                    "schema_version": manifest::SCHEMA_VERSION,
+                    "albums": [{
+                        "name": "Untrue",
+                        "art": "",
+                        "tracks": [{"uri": "spotify:track:b", "added_at": "2024-01-01T00:00:00Z"}],
+                    }],
+                }),
+            ],
+            Strategy::First,
+        );
+
+        assert_eq!(tracks.len(), 2);
+        assert_eq!(tracks[0]["added_at"], "2023-01-01T00:00:00Z");
+        assert_eq!(tracks[1]["album"]["name"], "Untrue");
+    }
+
+    #[test]
+    fn rejects_backups_it_cant_read() {
+        for (data, error) in [
+            (
+                json!({"schema_version": manifest::SCHEMA_VERSION + 1, "tracks": []}).to_string(),
+                "newer schema version",
+            ),
+            ("spotify:track:a\n".to_string(), "Not a JSON backup"),
+            (
+                backup(json!(["spotify:track:a"])).to_string(),
+                "Track 0 isn't an object",
+            ),
+        ] {
+            let e = Merged::default()
+                .add(data.as_bytes(), Strategy::First)
+                .unwrap_err();
+            assert!(format!("{e:#}").contains(error), "{data}: {e:#}");
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+/// Name of the directory in a profile's state dir the metadata cache is kept in.
+pub const DIR: &str = "metadata-cache";
+
+/// On-disk cache of Spotify objects (tracks, albums, artists) keyed by their ID, so metadata that
+/// rarely changes isn't fetched again on every run. Objects are stored as the raw JSON returned by
+/// the API, in a tree per kind of object.
+#[derive(Clone)]
+pub struct MetadataCache {
+    db: sled::Db,
+}
+
+impl MetadataCache {
+    pub fn open(dir: &Path) -> Result<Self> {
+        let db = sled::open(dir)
+            .with_context(|| format!("Failed to open metadata cache at {}", dir.display()))?;
+
+        Ok(Self { db })
+    }
+
+    /// Returns the cached JSON of the object with the given ID, if there is one.
+    pub fn get(&self, kind: &str, id: &str) -> Result<Option<serde_json::Value>> {
+        let Some(data) = self
+            .db
+            .open_tree(kind)
+            .context("Failed to open metadata cache tree")?
+            .get(id)
+            .context("Failed to read from metadata cache")?
+        else {
+            return Ok(None);
+        };
+
+        serde_json::from_slice(&data)
+            .map(Some)
+            .context("Failed to parse cached metadata")
+    }
+
+    pub fn insert(&self, kind: &str, id: &str, value: &serde_json::Value) -> Result<()> {
+        let data = serde_json::to_vec(value).context("Failed to serialize metadata")?;
+
+        self.db
+            .open_tree(kind)
+            .context("Failed to open metadata cache tree")?
+            .insert(id, data)
+            .context("Failed to write to metadata cache")?;
+
+        Ok(())
+    }
+
+    /// Number of cached objects of each kind, along with the size of the cache on disk.
+    pub fn stats(&self) -> Result<(Vec<(String, usize)>, u64)> {
+        let mut counts = Vec::new();
+
+        for name in self.db.tree_names() {
+            // sled always has a default tree, which isn't used
+            if name == self.db.name() {
+                continue;
+            }
+
+            let tree = self
+                .db
+                .open_tree(&name)
+                .context("Failed to open metadata cache tree")?;
+            counts.push((String::from_utf8_lossy(&name).into_owned(), tree.len()));
+        }
+
+        let size = self
+            .db
+            .size_on_disk()
+            .context("Failed to read metadata cache size")?;
+
+        Ok((counts, size))
+    }
+
+    /// Removes every cached object.
+    pub fn clear(&self) -> Result<()> {
+        for name in self.db.tree_names() {
+            if name == self.db.name() {
+                continue;
+            }
+
+            self.db
+                .drop_tree(&name)
+                .context("Failed to clear metadata cache")?;
+        }
+
+        self.db.flush().context("Failed to flush metadata cache")?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,201 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, PoisonError,
+    },
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use http_body_util::Full;
+use hyper::{body::Bytes, header, server::conn::http1, service::service_fn, Method};
+use hyper_util::rt::TokioIo;
+use tokio::net::TcpListener;
+use tracing::{info, warn};
+
+/// Content type of the Prometheus text format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+static API_REQUESTS: AtomicU64 = AtomicU64::new(0);
+static RATE_LIMITED: AtomicU64 = AtomicU64::new(0);
+static TRACKS_EXPORTED: AtomicU64 = AtomicU64::new(0);
+static BACKUP_FAILURES: AtomicU64 = AtomicU64::new(0);
+static RUNS: Mutex<BTreeMap<String, RunStats>> = Mutex::new(BTreeMap::new());
+
+/// How the scheduled runs of a daemon job have gone.
+#[derive(Default)]
+struct RunStats {
+    runs: u64,
+    failures: u64,
+    last_run: Option<DateTime<Utc>>,
+    last_success: Option<DateTime<Utc>>,
+    last_duration: Duration,
+}
+
+/// Counts a request sent to the Spotify API, including retries.
+pub fn api_request() {
+    API_REQUESTS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Counts a 429 response from the Spotify API.
+pub fn rate_limited() {
+    RATE_LIMITED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Counts a backup that was written, and the tracks in it.
+pub fn backup_succeeded(tracks: usize) {
+    TRACKS_EXPORTED.fetch_add(tracks as u64, Ordering::Relaxed);
+}
+
+/// Counts a backup that failed.
+pub fn backup_failed() {
+    BACKUP_FAILURES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records a finished scheduled run of the daemon job named `job`.
+pub fn run_finished(job: &str, started_at: DateTime<Utc>, duration: Duration, success: bool) {
+    let mut runs = RUNS.lock().unwrap_or_else(PoisonError::into_inner);
+    let stats = runs.entry(job.to_string()).or_default();
+
+    stats.runs += 1;
+    stats.last_run = Some(started_at);
+    stats.last_duration = duration;
+    if success {
+        stats.last_success = Some(started_at);
+    } else {
+        stats.failures += 1;
+    }
+}
+
+/// Serves the metrics at `/metrics` on `addr` in the Prometheus text format until the process is
+/// stopped.
+pub async fn serve(addr: SocketAddr) -> Result<()> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to listen on {addr}"))?;
+    info!("Serving metrics at http://{addr}/metrics");
+
+    loop {
+        let (stream, _) = listener
+            .accept()
+            .await
+            .context("Failed to accept TCP connection")?;
+
+        // each connection is served on its own task, so a slow scraper can't hold up the next
+        tokio::spawn(async move {
+            let service = service_fn(|req| async move {
+                let response = if req.method() == Method::GET && req.uri().path() == "/metrics" {
+                    hyper::Response::builder()
+                        .header(header::CONTENT_TYPE, CONTENT_TYPE)
+                        .body(Full::<Bytes>::from(render()))
+                } else {
+                    hyper::Response::builder()
+                        .status(404)
+                        .body(Full::<Bytes>::from("Not found, metrics are at /metrics"))
+                };
+
+                response.context("Failed to build response")
+            });
+
+            if let Err(e) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                warn!("Failed to serve metrics request: {e}");
+            }
+        });
+    }
+}
+
+/// Renders every metric in the Prometheus text exposition format.
+pub fn render() -> String {
+    let mut out = String::new();
+
+    let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, f64)]| {
+        let _ = writeln!(out, "# HELP spotify_backup_{name} {help}");
+        let _ = writeln!(out, "# TYPE spotify_backup_{name} {kind}");
+        for (labels, value) in samples {
+            let _ = writeln!(out, "spotify_backup_{name}{labels} {value}");
+        }
+    };
+
+    let counter = |v: &AtomicU64| [(String::new(), v.load(Ordering::Relaxed) as f64)];
+    metric(
+        "api_requests_total",
+        "counter",
+        "Requests sent to the Spotify API, including retries",
+        &counter(&API_REQUESTS),
+    );
+    metric(
+        "api_rate_limited_total",
+        "counter",
+        "Responses from the Spotify API asking to slow down (429)",
+        &counter(&RATE_LIMITED),
+    );
+    metric(
+        "tracks_exported_total",
+        "counter",
+        "Tracks written to successful backups",
+        &counter(&TRACKS_EXPORTED),
+    );
+    metric(
+        "backup_failures_total",
+        "counter",
+        "Playlists or liked songs that failed to back up",
+        &counter(&BACKUP_FAILURES),
+    );
+
+    let runs = RUNS.lock().unwrap_or_else(PoisonError::into_inner);
+    let per_job = |value: fn(&RunStats) -> Option<f64>| {
+        runs.iter()
+            .filter_map(|(job, stats)| {
+                Some((format!("{{job=\"{}\"}}", escape(job)), value(stats)?))
+            })
+            .collect::<Vec<_>>()
+    };
+
+    metric(
+        "runs_total",
+        "counter",
+        "Scheduled runs that have finished",
+        &per_job(|v| Some(v.runs as f64)),
+    );
+    metric(
+        "run_failures_total",
+        "counter",
+        "Scheduled runs that failed",
+        &per_job(|v| Some(v.failures as f64)),
+    );
+    metric(
+        "last_run_timestamp_seconds",
+        "gauge",
+        "When the last scheduled run started",
+        &per_job(|v| Some(v.last_run?.timestamp() as f64)),
+    );
+    metric(
+        "last_success_timestamp_seconds",
+        "gauge",
+        "When the last successful scheduled run started",
+        &per_job(|v| Some(v.last_success?.timestamp() as f64)),
+    );
+    metric(
+        "last_run_duration_seconds",
+        "gauge",
+        "How long the last scheduled run took",
+        &per_job(|v| Some(v.last_duration.as_secs_f64())),
+    );
+
+    out
+}
+
+/// Escapes a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,236 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+
+use crate::{compression::Compression, encryption, manifest, writer};
+
+/// Upgrades a backup file, or every file in a backup directory's manifest, to the current schema
+/// version, keeping each file's compression and re-encrypting encrypted ones to `recipients`.
+/// Checksums in the manifest are updated to match, and a directory's manifest is marked as being
+/// at the current version once every file in it is.
+pub async fn run(path: &Path, identities: &[PathBuf], recipients: &[String]) -> Result<()> {
+    let metadata = tokio::fs::metadata(path)
+        .await
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+
+    let (dir, names) = if metadata.is_dir() {
+        let manifest = manifest::read(path)
+            .await?
+            .with_context(|| format!("No {} found in {}", manifest::FILE_NAME, path.display()))?;
+
+        (path, manifest.files.into_iter().map(|v| v.name).collect())
+    } else {
+        let name = path
+            .file_name()
+            .context("Backup path has no file name")?
+            .to_string_lossy()
+            .to_string();
+
+        (path.parent().unwrap_or(Path::new("")), vec![name])
+    };
+
+    let mut migrated = Vec::new();
+    let mut failures = 0;
+
+    for name in &names {
+        match migrate_file(&dir.join(name), identities, recipients).await {
+            Ok(Some(written)) => {
+                println!("MIGRATED {name}");
+                migrated.push((name, written));
+            }
+            Ok(None) => println!("CURRENT  {name}"),
+            Err(e) => {
+                failures += 1;
+                println!("FAILED   {name}: {e:#}");
+            }
+        }
+    }
+
+    // files that are listed in a manifest would otherwise fail verification from here on
+    if let Some(mut manifest) = manifest::read(dir).await? {
+        for file in &mut manifest.files {
+            if let Some((_, written)) = migrated.iter().find(|(name, _)| **name == file.name) {
+                file.size = written.size;
+                file.sha256.clone_from(&written.sha256);
+            }
+        }
+        if metadata.is_dir() && failures == 0 {
+            manifest.schema_version = manifest::SCHEMA_VERSION;
+        }
+        if !migrated.is_empty() || metadata.is_dir() {
+            manifest.updated_at = chrono::Utc::now().to_rfc3339();
+            manifest::write(dir, &manifest).await?;
+        }
+    }
+
+    println!(
+        "{} file(s), {} migrated, {failures} failed",
+        names.len(),
+        migrated.len()
+    );
+    anyhow::ensure!(failures == 0, "{failures} file(s) failed to migrate");
+
+    Ok(())
+}
+
+/// Rewrites the backup at `path` in the current schema, returning what was written, or `None` if
+/// it's already current or in a text format, which has no schema.
+async fn migrate_file(
+    path: &Path,
+    identities: &[PathBuf],
+    recipients: &[String],
+) -> Result<Option<writer::Written>> {
+    rewrite(path, identities, recipients, upgrade).await
+}
+
+/// Rewrites the backup at `path` with what `edit` makes of it once decrypted and decompressed,
+/// keeping its compression and re-encrypting it to `recipients` if it was encrypted. Returns what
+/// was written, or `None` if `edit` left it as it was.
+pub async fn rewrite(
+    path: &Path,
+    identities: &[PathBuf],
+    recipients: &[String],
+    edit: impl FnOnce(&[u8]) -> Result<Option<Vec<u8>>>,
+) -> Result<Option<writer::Written>> {
+    let mut data = tokio::fs::read(path)
+        .await
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+
+    let encrypted = encryption::is_encrypted(&data);
+    if encrypted {
+        data = encryption::decrypt(&data, identities).context("Failed to decrypt backup")?;
+    }
+    let compression = Compression::detect(&data);
+    let data = crate::compression::decompress(data)?;
+
+    let Some(data) = edit(&data)? else {
+        return Ok(None);
+    };
+
+    anyhow::ensure!(
+        !encrypted || !recipients.is_empty(),
+        "Backup is encrypted, pass --encrypt to re-encrypt it once rewritten"
+    );
+
+    // kept in memory so the original is only replaced once the whole file has been written
+    let mut writer: Box<dyn writer::FinishWrite> =
+        Box::new(writer::Sink::new(std::io::sink(), true));
+    if encrypted {
+        writer = encryption::writer(writer, recipients)?;
+    }
+    if let Some(compression) = compression {
+        writer = compression.writer(writer)?;
+    }
+    writer.write_all(&data)?;
+    let written = writer.finish()?;
+
+    crate::atomic::write(path, written.data.as_deref().unwrap_or_default()).await?;
+
+    Ok(Some(written))
+}
+
+/// Upgrades a decrypted and decompressed backup to the current schema, returning `None` if there's
+/// nothing to upgrade.
+fn upgrade(data: &[u8]) -> Result<Option<Vec<u8>>> {
+    let trimmed = data.trim_ascii();
+
+    match trimmed.first() {
+        // version 1, a bare array of tracks
+        Some(b'[') => {
+            serde_json::from_slice::<Vec<Value>>(trimmed).context("Failed to parse backup")?;
+
+            // the array is spliced in as-is rather than re-serialized so each track keeps the
+            // order of its fields, and is indented to match a pretty backup written by this version
+            let tracks = String::from_utf8_lossy(trimmed);
+            let upgraded = if tracks.contains('\n') {
+                format!(
+                    "{{\n  \"schema_version\": {},\n  \"tracks\": {}\n}}\n",
+                    manifest::SCHEMA_VERSION,
+                    tracks.replace('\n', "\n  ")
+                )
+            } else {
+                format!(
+                    "{{\"schema_version\":{},\"tracks\":{tracks}}}\n",
+                    manifest::SCHEMA_VERSION
+                )
+            };
+
+            Ok(Some(upgraded.into_bytes()))
+        }
+        Some(b'{') => {
+            let backup: Value =
+                serde_json::from_slice(trimmed).context("Failed to parse backup")?;
+            let version = backup
+                .get("schema_version")
+                .and_then(Value::as_u64)
+                .context("Backup has no schema_version")?;
+
+            anyhow::ensure!(
+                version <= u64::from(manifest::SCHEMA_VERSION),
+                "Backup was written with a newer schema version ({version}) than this version \
+                 understands ({})",
+                manifest::SCHEMA_VERSION
+            );
+
+            Ok(None)
+        }
+        // text backups
+        _ => Ok(None),
+    }
+}
+
+/// Reads the tracks out of a decrypted and decompressed JSON backup written in any schema version,
+/// returning `None` for backups in a text format.
+pub fn tracks(data: &[u8]) -> Result<Option<Vec<Value>>> {
+    let data = match upgrade(data)? {
+        Some(upgraded) => upgraded,
+        None => data.to_vec(),
+    };
+    if !data.trim_ascii_start().starts_with(b"{") {
+        return Ok(None);
+    }
+
+    let mut backup: Value = serde_json::from_slice(&data).context("Failed to parse backup")?;
+    match (
+        backup.get_mut("tracks").map(Value::take),
+        backup.get_mut("albums").map(Value::take),
+    ) {
+        (Some(Value::Array(tracks)), _) => Ok(Some(tracks)),
+        (None, Some(albums)) => flatten_albums(albums)
+            .map(Some)
+            .context("Backup's albums are malformed"),
+        _ => anyhow::bail!("Backup has no tracks"),
+    }
+}
+
+/// Tracks of a backup written with `--group-by album`, each given back the album it was nested
+/// under as its album field. Returns `None` if the albums aren't an array of objects with tracks.
+pub fn flatten_albums(albums: Value) -> Option<Vec<Value>> {
+    let Value::Array(albums) = albums else {
+        return None;
+    };
+
+    let mut flattened = Vec::new();
+    for mut album in albums {
+        let Some(Value::Array(tracks)) = album.get_mut("tracks").map(Value::take) else {
+            return None;
+        };
+        let art = album.get("art").cloned().unwrap_or_default();
+        let name = album.get("name").cloned().unwrap_or_default();
+
+        for mut track in tracks {
+            let track_fields = track.as_object_mut()?;
+            track_fields.insert(
+                "album".to_string(),
+                serde_json::json!({ "art": art, "name": name }),
+            );
+            flattened.push(track);
+        }
+    }
+
+    Some(flattened)
+}
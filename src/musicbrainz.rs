@@ -0,0 +1,247 @@
+use std::{collections::HashMap, num::NonZeroU32, sync::LazyLock, time::Duration};
+
+use anyhow::{Context, Result};
+use governor::{DefaultDirectRateLimiter, Quota, RateLimiter};
+use reqwest::{StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::{api, dupes};
+
+const BASE_URL: &str = "https://musicbrainz.org/ws/2";
+
+/// Tree of the metadata cache lookups are kept in, by Spotify URI.
+const CACHE_KIND: &str = "musicbrainz";
+
+/// Lowest score (out of 100) a search result needs to be taken as the same recording.
+const MIN_SEARCH_SCORE: u32 = 90;
+
+/// Times a request turned away for going over the rate limit is retried.
+const RETRIES: u32 = 3;
+
+/// MusicBrainz allows one request per second from each client, shared by every backup in the run.
+static RATE_LIMITER: LazyLock<DefaultDirectRateLimiter> =
+    LazyLock::new(|| RateLimiter::direct(Quota::per_second(NonZeroU32::MIN)));
+
+/// MusicBrainz turns away requests that don't identify the application making them.
+const USER_AGENT: &str = concat!(
+    env!("CARGO_PKG_NAME"),
+    "/",
+    env!("CARGO_PKG_VERSION"),
+    " ( https://github.com/w4/spotify-backup )"
+);
+
+/// MusicBrainz IDs of a track, either of which is missing if it couldn't be found.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Mbids {
+    pub recording: Option<String>,
+    pub release: Option<String>,
+}
+
+/// What a track is looked up by.
+pub struct Query<'a> {
+    pub uri: &'a str,
+    pub isrc: Option<&'a str>,
+    pub name: &'a str,
+    pub artist: Option<&'a str>,
+    pub album: &'a str,
+}
+
+#[derive(Deserialize, Debug)]
+struct RecordingsResponse {
+    #[serde(default)]
+    recordings: Vec<Recording>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Recording {
+    id: String,
+    title: String,
+    /// Only set on search results
+    score: Option<u32>,
+    #[serde(default)]
+    releases: Vec<Release>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Release {
+    id: String,
+    title: String,
+    status: Option<String>,
+}
+
+/// Looks up the MusicBrainz IDs of each track, by ISRC or failing that by searching for its title,
+/// artist and album. Results are kept in the metadata cache, including tracks that weren't found,
+/// so each track is only looked up once. Lookups that fail are logged and left empty, and with
+/// `--offline` only the cache is read.
+pub async fn lookup(client: &api::Client, queries: &[Query<'_>]) -> Vec<Mbids> {
+    let http = crate::http::builder()
+        .user_agent(USER_AGENT)
+        .build()
+        .expect("Failed to build HTTP client");
+    let cache = client.metadata_cache();
+
+    let mut found = HashMap::new();
+    let mut mbids = Vec::with_capacity(queries.len());
+
+    for query in queries {
+        if let Some(v) = found.get(query.uri) {
+            mbids.push(Mbids::clone(v));
+            continue;
+        }
+
+        let cached = cache.and_then(|cache| {
+            cache
+                .get(CACHE_KIND, query.uri)
+                .inspect_err(|e| warn!("Failed to read {} from cache: {e:#}", query.uri))
+                .ok()
+                .flatten()
+                .and_then(|v| serde_json::from_value(v).ok())
+        });
+
+        let result = match cached {
+            Some(v) => v,
+            None if client.offline() => Mbids::default(),
+            None => match lookup_one(&http, query).await {
+                Ok(v) => {
+                    if let Some(cache) = cache {
+                        let value = serde_json::to_value(&v).unwrap_or_default();
+                        if let Err(e) = cache.insert(CACHE_KIND, query.uri, &value) {
+                            warn!("Failed to cache MusicBrainz IDs of {}: {e:#}", query.uri);
+                        }
+                    }
+                    v
+                }
+                // not cached, so it's tried again on the next run
+                Err(e) => {
+                    warn!("Failed to look up {} on MusicBrainz: {e:#}", query.uri);
+                    Mbids::default()
+                }
+            },
+        };
+
+        found.insert(query.uri, result.clone());
+        mbids.push(result);
+    }
+
+    mbids
+}
+
+async fn lookup_one(http: &reqwest::Client, query: &Query<'_>) -> Result<Mbids> {
+    // podcasts aren't in MusicBrainz
+    if query.uri.starts_with("spotify:episode:") {
+        return Ok(Mbids::default());
+    }
+
+    if let Some(isrc) = query.isrc {
+        let url = format!("{BASE_URL}/isrc/{isrc}?inc=releases&fmt=json");
+        if let Some(response) = get(http, &url).await? {
+            if let Some(mbids) = pick(query, response.recordings) {
+                debug!(uri = query.uri, isrc, "Found on MusicBrainz by ISRC");
+                return Ok(mbids);
+            }
+        }
+    }
+
+    let Some(artist) = query.artist else {
+        return Ok(Mbids::default());
+    };
+
+    let mut search = format!(
+        "recording:{} AND artist:{}",
+        phrase(query.name),
+        phrase(artist)
+    );
+    // not required, but ranks the recording on the right release higher
+    if !query.album.is_empty() {
+        search.push_str(&format!(" release:{}", phrase(query.album)));
+    }
+    let url = Url::parse_with_params(
+        &format!("{BASE_URL}/recording"),
+        [("query", search.as_str()), ("limit", "5"), ("fmt", "json")],
+    )?;
+
+    let recordings = get(http, url.as_str())
+        .await?
+        .map(|v| v.recordings)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|v| v.score.unwrap_or_default() >= MIN_SEARCH_SCORE)
+        .collect();
+
+    Ok(pick(query, recordings).unwrap_or_default())
+}
+
+/// Picks the recording matching the track's title, and its release matching the track's album,
+/// falling back to the first of each (official releases first).
+fn pick(query: &Query<'_>, recordings: Vec<Recording>) -> Option<Mbids> {
+    let title = dupes::normalize_title(query.name);
+    let recording = match recordings
+        .iter()
+        .position(|v| title.is_some() && dupes::normalize_title(&v.title) == title)
+    {
+        Some(i) => recordings.into_iter().nth(i)?,
+        None => recordings.into_iter().next()?,
+    };
+
+    let release = recording
+        .releases
+        .iter()
+        .find(|v| v.title.eq_ignore_ascii_case(query.album))
+        .or_else(|| {
+            recording
+                .releases
+                .iter()
+                .find(|v| v.status.as_deref() == Some("Official"))
+        })
+        .or(recording.releases.first())
+        .map(|v| v.id.clone());
+
+    Some(Mbids {
+        recording: Some(recording.id),
+        release,
+    })
+}
+
+/// `value` as a quoted phrase in a Lucene search query.
+fn phrase(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Fetches `url`, returning `None` if MusicBrainz has nothing there, waiting for the rate limit
+/// before each request and backing off when turned away for going over it anyway.
+async fn get(http: &reqwest::Client, url: &str) -> Result<Option<RecordingsResponse>> {
+    let mut attempt = 0;
+
+    loop {
+        RATE_LIMITER.until_ready().await;
+
+        let response = http
+            .get(url)
+            .send()
+            .await
+            .context("Failed to send request to MusicBrainz")?;
+
+        match response.status() {
+            StatusCode::NOT_FOUND => return Ok(None),
+            StatusCode::SERVICE_UNAVAILABLE | StatusCode::TOO_MANY_REQUESTS
+                if attempt < RETRIES =>
+            {
+                attempt += 1;
+                let wait = Duration::from_secs(2_u64.pow(attempt));
+                debug!("Rate limited by MusicBrainz, retrying in {wait:?}");
+                tokio::time::sleep(wait).await;
+            }
+            status if !status.is_success() => {
+                anyhow::bail!("Got {status} response from MusicBrainz");
+            }
+            _ => {
+                return response
+                    .json()
+                    .await
+                    .map(Some)
+                    .context("Failed to parse response from MusicBrainz");
+            }
+        }
+    }
+}
@@ -0,0 +1,664 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::IsTerminal,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use dialoguer::{theme::ColorfulTheme, Confirm};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use crate::{
+    api::{self, BASE_URL},
+    cli::Args,
+    manifest, migrate, output,
+    profile::Profile,
+    GetPlaylistsResponseItem, Ownership,
+};
+
+/// Most tracks Spotify adds to a playlist in one request.
+const MAX_TRACKS_PER_REQUEST: usize = 100;
+
+/// A recording found under several URIs, through Spotify relinking it or its ISRC, and the one
+/// URI it's collapsed to.
+#[derive(Serialize, Debug)]
+struct Group {
+    /// URI the others are rewritten to
+    canonical: String,
+    name: String,
+    artists: Vec<String>,
+    isrc: Option<String>,
+    /// Every URI the recording was found under, canonical one first
+    uris: Vec<String>,
+    occurrences: Vec<Occurrence>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct Occurrence {
+    /// Playlist ID, missing for liked songs
+    id: Option<String>,
+    playlist: String,
+    /// Zero-based position of the track in the playlist
+    position: usize,
+    /// URI the track is stored under in the playlist or library
+    uri: String,
+    /// URI Spotify relinked the track to, if it did
+    relinked_to: Option<String>,
+    #[serde(skip)]
+    name: String,
+    #[serde(skip)]
+    artists: Vec<String>,
+    #[serde(skip)]
+    isrc: Option<String>,
+    #[serde(skip)]
+    playable: Option<bool>,
+}
+
+impl Occurrence {
+    /// URI Spotify plays the track as in the market it was fetched for.
+    fn played(&self) -> &str {
+        self.relinked_to.as_deref().unwrap_or(&self.uri)
+    }
+}
+
+/// Finds the tracks in liked songs with `liked` and the playlists given by `ids` (or every
+/// playlist with `all`) that are the same recording under different URIs, because Spotify relinked
+/// them to another release available in the market or they share an ISRC, and prints them grouped
+/// with the URI each group is collapsed to. The backups in `rewrite` have their tracks rewritten to
+/// the canonical URIs, and with `apply` so do the user's playlists on Spotify, once confirmed.
+pub async fn run(
+    args: &Args,
+    ids: &[String],
+    all: bool,
+    liked: bool,
+    rewrite: &[PathBuf],
+    apply: bool,
+    yes: bool,
+) -> Result<()> {
+    let profile = Profile::new(&args.profile)?;
+    let client = crate::cli::build_client(&profile, args).await?;
+
+    let sources = crate::cli::fetch_sources(&client, args, ids, all, liked).await?;
+    let snapshots: HashMap<String, String> = sources
+        .iter()
+        .filter_map(|v| Some((v.id.clone()?, v.snapshot_id.clone()?)))
+        .collect();
+    let occurrences: Vec<Occurrence> = sources
+        .into_iter()
+        .flat_map(|source| {
+            source
+                .items
+                .into_iter()
+                .enumerate()
+                .map(move |(position, v)| {
+                    let relinked_to = v.track.linked_from.is_some().then(|| v.track.uri.clone());
+
+                    Occurrence {
+                        id: source.id.clone(),
+                        playlist: source.name.clone(),
+                        position,
+                        uri: v.track.linked_from.map_or(v.track.uri, |v| v.uri),
+                        relinked_to,
+                        name: v.track.name,
+                        artists: v.track.artists.into_iter().map(|v| v.name).collect(),
+                        isrc: v.track.external_ids.and_then(|v| v.isrc),
+                        playable: v.track.is_playable,
+                    }
+                })
+        })
+        // local files are never relinked and have no ISRC
+        .filter(|v| !v.uri.starts_with("spotify:local:"))
+        .collect();
+
+    let groups = groups(&occurrences);
+    print(args, &groups)?;
+
+    let canonical: HashMap<&str, &str> = groups
+        .iter()
+        .flat_map(|group| {
+            group
+                .uris
+                .iter()
+                .filter(|v| **v != group.canonical)
+                .map(|v| (v.as_str(), group.canonical.as_str()))
+        })
+        .collect();
+
+    for path in rewrite {
+        rewrite_backup(args, path, &canonical)
+            .await
+            .with_context(|| format!("Failed to rewrite {}", path.display()))?;
+    }
+
+    if apply {
+        apply_to_playlists(args, &client, &groups, &snapshots, yes).await?;
+    }
+
+    Ok(())
+}
+
+/// Groups the occurrences of tracks that are the same recording: those Spotify relinked along
+/// with what they were relinked to, and those sharing an ISRC. Only groups of more than one URI
+/// are returned.
+fn groups(occurrences: &[Occurrence]) -> Vec<Group> {
+    let mut sets = Sets::default();
+    let mut by_isrc: HashMap<&str, &str> = HashMap::new();
+    for occurrence in occurrences {
+        sets.union(&occurrence.uri, occurrence.played());
+        if let Some(isrc) = &occurrence.isrc {
+            let first = by_isrc.entry(isrc).or_insert(&occurrence.uri);
+            sets.union(first, &occurrence.uri);
+        }
+    }
+
+    let mut by_root: BTreeMap<usize, Vec<&Occurrence>> = BTreeMap::new();
+    for occurrence in occurrences {
+        by_root
+            .entry(sets.find(&occurrence.uri))
+            .or_default()
+            .push(occurrence);
+    }
+
+    let mut groups: Vec<Group> = by_root
+        .into_values()
+        .filter_map(|occurrences| {
+            let mut uris: Vec<&str> = occurrences
+                .iter()
+                .flat_map(|v| [v.uri.as_str(), v.played()])
+                .collect();
+            uris.sort();
+            uris.dedup();
+            if uris.len() < 2 {
+                return None;
+            }
+
+            let canonical = canonical(&uris, &occurrences);
+            uris.sort_by_key(|v| *v != canonical);
+            let first = occurrences[0];
+
+            Some(Group {
+                canonical: canonical.to_string(),
+                name: first.name.clone(),
+                artists: first.artists.clone(),
+                isrc: occurrences.iter().find_map(|v| v.isrc.clone()),
+                uris: uris.into_iter().map(str::to_string).collect(),
+                occurrences: occurrences.into_iter().cloned().collect(),
+            })
+        })
+        .collect();
+
+    groups.sort_by(|a, b| (&a.name, &a.canonical).cmp(&(&b.name, &b.canonical)));
+    groups
+}
+
+/// The URI of a group that the others are collapsed to: one that's playable in the market, and
+/// of those the one most tracks are already stored under, so the fewest have to be rewritten.
+fn canonical<'a>(uris: &[&'a str], occurrences: &[&Occurrence]) -> &'a str {
+    let playable = |uri: &str| {
+        occurrences
+            .iter()
+            .any(|v| v.played() == uri && v.playable != Some(false))
+    };
+    let stored = |uri: &str| occurrences.iter().filter(|v| v.uri == uri).count();
+
+    uris.iter()
+        .copied()
+        // ties go to the first URI in order, so the same one is picked on every run
+        .max_by_key(|v| (playable(v), stored(v), std::cmp::Reverse(*v)))
+        .unwrap_or_default()
+}
+
+/// Disjoint sets of URIs, for joining the URIs of the same recording into groups.
+#[derive(Default)]
+struct Sets {
+    ids: HashMap<String, usize>,
+    parents: Vec<usize>,
+}
+
+impl Sets {
+    fn find(&mut self, uri: &str) -> usize {
+        let mut id = match self.ids.get(uri) {
+            Some(id) => *id,
+            None => {
+                let id = self.parents.len();
+                self.ids.insert(uri.to_string(), id);
+                self.parents.push(id);
+                id
+            }
+        };
+
+        while self.parents[id] != id {
+            self.parents[id] = self.parents[self.parents[id]];
+            id = self.parents[id];
+        }
+        id
+    }
+
+    fn union(&mut self, a: &str, b: &str) {
+        let (a, b) = (self.find(a), self.find(b));
+        self.parents[b] = a;
+    }
+}
+
+/// Rewrites the URIs of the tracks in the JSON backup at `path` found in `canonical` to the URI
+/// they map to, updating the checksums in its directory's manifest if there is one.
+async fn rewrite_backup(args: &Args, path: &Path, canonical: &HashMap<&str, &str>) -> Result<()> {
+    let mut rewritten = 0;
+    let written = migrate::rewrite(path, &args.identity, &args.encrypt, |data| {
+        let trimmed = data.trim_ascii();
+        anyhow::ensure!(
+            trimmed.starts_with(b"{") || trimmed.starts_with(b"["),
+            "Only JSON backups can be rewritten"
+        );
+        let mut backup: Value =
+            serde_json::from_slice(trimmed).context("Failed to parse backup")?;
+
+        // tracks are in a bare array in version 1 backups, and nested in their albums in grouped
+        // ones
+        let tracks: Vec<&mut Value> = match &mut backup {
+            Value::Array(tracks) => tracks.iter_mut().collect(),
+            Value::Object(backup) if backup.contains_key("tracks") => backup
+                .get_mut("tracks")
+                .and_then(Value::as_array_mut)
+                .context("Backup's tracks are malformed")?
+                .iter_mut()
+                .collect(),
+            Value::Object(backup) => backup
+                .get_mut("albums")
+                .and_then(Value::as_array_mut)
+                .context("Backup has no tracks")?
+                .iter_mut()
+                .filter_map(|v| v.get_mut("tracks")?.as_array_mut())
+                .flatten()
+                .collect(),
+            _ => anyhow::bail!("Backup has no tracks"),
+        };
+
+        for track in tracks {
+            let Some(uri) = track
+                .get("uri")
+                .and_then(Value::as_str)
+                .and_then(|v| canonical.get(v))
+            else {
+                continue;
+            };
+            let uri = uri.to_string();
+
+            if track.get("id").is_some_and(Value::is_string) {
+                track["id"] = uri.rsplit(':').next().unwrap_or_default().into();
+            }
+            track["uri"] = uri.into();
+            rewritten += 1;
+        }
+        if rewritten == 0 {
+            return Ok(None);
+        }
+
+        // written back the way it was, pretty-printed or not
+        let mut data = if trimmed.contains(&b'\n') {
+            serde_json::to_vec_pretty(&backup)?
+        } else {
+            serde_json::to_vec(&backup)?
+        };
+        data.push(b'\n');
+        Ok(Some(data))
+    })
+    .await?;
+
+    let Some(written) = written else {
+        info!("{} has no tracks to rewrite", path.display());
+        return Ok(());
+    };
+    info!("Rewrote {rewritten} track(s) in {}", path.display());
+
+    // the file would otherwise fail verification from here on
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    if let Some(mut manifest) = manifest::read(dir).await? {
+        if let Some(file) = manifest.files.iter_mut().find(|v| v.name == name) {
+            file.size = written.size;
+            file.sha256.clone_from(&written.sha256);
+            manifest.updated_at = chrono::Utc::now().to_rfc3339();
+            manifest::write(dir, &manifest).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Replaces the tracks in the user's playlists that aren't stored under their group's canonical
+/// URI with the canonical one, in the same position, once the user confirms. Followed playlists
+/// can't be changed and liked songs would be reordered, so both are left as they are. A playlist
+/// is only changed if it's still at the snapshot in `snapshots` it was at when its tracks were
+/// fetched, so the positions are still right.
+async fn apply_to_playlists(
+    args: &Args,
+    client: &api::Client,
+    groups: &[Group],
+    snapshots: &HashMap<String, String>,
+    yes: bool,
+) -> Result<()> {
+    let user = crate::fetch_current_user(client).await?;
+    let playlists: Vec<GetPlaylistsResponseItem> =
+        crate::cli::fetch_all(client, args.api.first_page_url("me/playlists", 50))
+            .await
+            .context("Failed to fetch playlists")?;
+    let ownership: HashMap<_, _> = playlists
+        .iter()
+        .map(|v| (v.id.as_str(), v.ownership(&user.id)))
+        .collect();
+
+    // the name of each playlist with tracks to replace, and the tracks
+    let mut changes: BTreeMap<&str, (&str, Vec<Replacement>)> = BTreeMap::new();
+    let mut liked = 0;
+    for group in groups {
+        for occurrence in group
+            .occurrences
+            .iter()
+            .filter(|v| v.uri != group.canonical)
+        {
+            match &occurrence.id {
+                None => liked += 1,
+                Some(id) if ownership.get(id.as_str()) == Some(&Ownership::Followed) => {
+                    warn!(
+                        "Leaving {} in {} as it is, followed playlists can't be changed",
+                        occurrence.uri, occurrence.playlist
+                    );
+                }
+                Some(id) => changes
+                    .entry(id)
+                    .or_insert_with(|| (&occurrence.playlist, Vec::new()))
+                    .1
+                    .push(Replacement {
+                        position: occurrence.position,
+                        uri: &occurrence.uri,
+                        canonical: &group.canonical,
+                    }),
+            }
+        }
+    }
+    if liked > 0 {
+        warn!(
+            "Leaving {liked} track(s) in liked songs as they are, as liking them again would move \
+             them to the top"
+        );
+    }
+
+    let count: usize = changes.values().map(|(_, v)| v.len()).sum();
+    if count == 0 {
+        info!("No playlists have tracks to replace");
+        return Ok(());
+    }
+
+    if !yes {
+        anyhow::ensure!(
+            !args.auth.non_interactive && std::io::stdin().is_terminal(),
+            "Changing playlists has to be confirmed, pass --yes to change them without asking"
+        );
+        let confirmed = Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!(
+                "Replace {count} track(s) in {} playlist(s) with their canonical URIs? They'll \
+                 show as added today",
+                changes.len()
+            ))
+            .default(false)
+            .interact()?;
+        if !confirmed {
+            info!("Left the playlists as they are");
+            return Ok(());
+        }
+    }
+
+    for (id, (name, mut tracks)) in changes {
+        tracks.sort_by_key(|v| v.position);
+        if let Err(e) = replace_tracks(client, id, snapshots.get(id), &tracks).await {
+            // what was there before is listed so it can be put back by hand
+            for track in &tracks {
+                warn!("{name} had {} at #{}", track.uri, track.position + 1);
+            }
+            return Err(e.context(format!("Failed to replace tracks in playlist {name}")));
+        }
+        info!("Replaced {} track(s) in {name}", tracks.len());
+    }
+
+    Ok(())
+}
+
+/// A track in a playlist that's replaced with its canonical URI.
+struct Replacement<'a> {
+    position: usize,
+    uri: &'a str,
+    canonical: &'a str,
+}
+
+#[derive(Deserialize, Debug)]
+struct Snapshot {
+    snapshot_id: String,
+}
+
+/// Replaces the tracks at the given positions in the playlist with the ID `id`, sorted by
+/// position, with their canonical URI, failing without changing anything if the playlist isn't at
+/// `snapshot` anymore. The canonical URIs are added before the ones they replace first, so nothing
+/// is lost if a request fails partway, and the replaced ones are removed after. Spotify removes
+/// every copy of a URI at once, which are all being replaced.
+async fn replace_tracks(
+    client: &api::Client,
+    id: &str,
+    snapshot: Option<&String>,
+    tracks: &[Replacement<'_>],
+) -> Result<()> {
+    let url = format!("{BASE_URL}/playlists/{id}/tracks");
+
+    if let Some(snapshot) = snapshot {
+        let current: Snapshot = client
+            .get_json_uncached(&format!("{BASE_URL}/playlists/{id}?fields=snapshot_id"))
+            .await?
+            .context("Failed to fetch the playlist's snapshot")?;
+        anyhow::ensure!(
+            current.snapshot_id == *snapshot,
+            "The playlist changed since its tracks were fetched, run normalize again"
+        );
+    }
+
+    // tracks at consecutive positions are added in one request
+    let mut runs: Vec<(usize, Vec<&str>)> = Vec::new();
+    for track in tracks {
+        match runs.last_mut() {
+            Some((start, uris))
+                if *start + uris.len() == track.position && uris.len() < MAX_TRACKS_PER_REQUEST =>
+            {
+                uris.push(track.canonical)
+            }
+            _ => runs.push((track.position, vec![track.canonical])),
+        }
+    }
+
+    // each run goes in front of the tracks it replaces, which have moved down by the ones added
+    // before it
+    let mut added = 0;
+    let mut snapshot = None;
+    for (position, uris) in runs {
+        let response: Snapshot = client
+            .post_json(&url, &json!({ "uris": uris, "position": position + added }))
+            .await
+            .with_context(|| format!("Failed after adding {added} of {} track(s)", tracks.len()))?;
+        added += uris.len();
+        snapshot = Some(response.snapshot_id);
+    }
+
+    let mut removed: Vec<_> = tracks.iter().map(|v| v.uri).collect();
+    removed.sort();
+    removed.dedup();
+    for chunk in removed.chunks(MAX_TRACKS_PER_REQUEST) {
+        let tracks: Vec<_> = chunk.iter().map(|v| json!({ "uri": v })).collect();
+        let response: Snapshot = client
+            .delete_json(&url, &json!({ "tracks": tracks, "snapshot_id": snapshot }))
+            .await
+            .context(
+                "Failed to remove the replaced tracks, which are still there along with the \
+                 canonical ones",
+            )?;
+        snapshot = Some(response.snapshot_id);
+    }
+
+    Ok(())
+}
+
+fn print(args: &Args, groups: &[Group]) -> Result<()> {
+    let terminal = std::io::stdout().is_terminal();
+
+    match args.format {
+        output::Format::Json => {
+            let json = if args.pretty || terminal {
+                serde_json::to_string_pretty(groups)?
+            } else {
+                serde_json::to_string(groups)?
+            };
+            println!("{json}");
+        }
+        output::Format::Table => {
+            let mut table = output::table(
+                &["Track", "Artists", "Canonical", "Rewritten"],
+                terminal,
+                &[2, 3],
+            );
+            for group in groups {
+                let rewritten = group
+                    .occurrences
+                    .iter()
+                    .filter(|v| v.uri != group.canonical)
+                    .map(|v| format!("{} #{}", v.playlist, v.position + 1))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+
+                output::add_row(
+                    &mut table,
+                    [
+                        group.name.as_str(),
+                        &group.artists.join(", "),
+                        &group.canonical,
+                        &rewritten,
+                    ],
+                );
+            }
+            println!("{table}");
+        }
+        _ => {
+            anyhow::bail!("Normalized tracks can only be listed as JSON or a table")
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn occurrence(position: usize, uri: &str, relinked_to: Option<&str>) -> Occurrence {
+        Occurrence {
+            id: Some("pl".to_string()),
+            playlist: "Driving".to_string(),
+            position,
+            uri: uri.to_string(),
+            relinked_to: relinked_to.map(str::to_string),
+            name: "Song".to_string(),
+            artists: vec!["Artist".to_string()],
+            isrc: None,
+            playable: Some(true),
+        }
+    }
+
+    fn with_isrc(mut occurrence: Occurrence, isrc: &str) -> Occurrence {
+        occurrence.isrc = Some(isrc.to_string());
+        occurrence
+    }
+
+    #[test]
+    fn groups_relinked_tracks_with_what_they_were_relinked_to() {
+        let groups = groups(&[
+            occurrence(0, "spotify:track:old", Some("spotify:track:new")),
+            occurrence(1, "spotify:track:other", None),
+        ]);
+
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].canonical, "spotify:track:new");
+        assert_eq!(groups[0].uris, ["spotify:track:new", "spotify:track:old"]);
+        assert_eq!(groups[0].occurrences.len(), 1);
+    }
+
+    #[test]
+    fn groups_tracks_sharing_an_isrc() {
+        let groups = groups(&[
+            with_isrc(occurrence(0, "spotify:track:b", None), "X"),
+            with_isrc(occurrence(1, "spotify:track:a", None), "X"),
+            with_isrc(occurrence(2, "spotify:track:c", None), "Y"),
+        ]);
+
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].isrc.as_deref(), Some("X"));
+        // equally playable and stored, so the first in order wins
+        assert_eq!(groups[0].canonical, "spotify:track:a");
+    }
+
+    #[test]
+    fn joins_relinking_and_isrc_into_one_group() {
+        let groups = groups(&[
+            with_isrc(
+                occurrence(0, "spotify:track:old", Some("spotify:track:new")),
+                "X",
+            ),
+            with_isrc(occurrence(1, "spotify:track:reissue", None), "X"),
+        ]);
+
+        assert_eq!(groups.len(), 1);
+        // the reissue is stored and playable, where the relinked track is only played
+        assert_eq!(
+            groups[0].uris,
+            [
+                "spotify:track:reissue",
+                "spotify:track:new",
+                "spotify:track:old"
+            ]
+        );
+    }
+
+    #[test]
+    fn prefers_the_uri_most_tracks_are_stored_under() {
+        let groups = groups(&[
+            with_isrc(occurrence(0, "spotify:track:a", None), "X"),
+            with_isrc(occurrence(1, "spotify:track:b", None), "X"),
+            with_isrc(occurrence(2, "spotify:track:b", None), "X"),
+        ]);
+
+        assert_eq!(groups[0].canonical, "spotify:track:b");
+    }
+
+    #[test]
+    fn prefers_a_playable_uri_over_a_stored_one() {
+        let mut unplayable = with_isrc(occurrence(0, "spotify:track:a", None), "X");
+        unplayable.playable = Some(false);
+        let groups = groups(&[
+            unplayable.clone(),
+            Occurrence {
+                position: 1,
+                ..unplayable
+            },
+            with_isrc(occurrence(2, "spotify:track:b", None), "X"),
+        ]);
+
+        assert_eq!(groups[0].canonical, "spotify:track:b");
+    }
+
+    #[test]
+    fn leaves_out_tracks_under_one_uri() {
+        let groups = groups(&[
+            occurrence(0, "spotify:track:a", None),
+            occurrence(1, "spotify:track:a", None),
+        ]);
+
+        assert!(groups.is_empty());
+    }
+}
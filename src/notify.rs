@@ -0,0 +1,303 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    io::ErrorKind,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use indicatif::DecimalBytes;
+use reqwest::Url;
+use serde::Serialize;
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::profile::{Profile, DEFAULT_PROFILE};
+
+/// Directory in a profile's state dir holding the tracks of each backup's previous run, for
+/// counting the tracks added and removed since.
+const TRACKS_DIR: &str = "tracks";
+
+/// Most failures listed in a chat message before the rest are only counted.
+const MAX_LISTED_FAILURES: usize = 10;
+
+/// Longest message Discord accepts.
+const MAX_DISCORD_MESSAGE: usize = 2000;
+
+/// Where a summary of each run is sent once it's finished.
+#[derive(clap::Args, Debug, Clone)]
+pub struct NotifyArgs {
+    /// POSTs a JSON summary to the given URL after each run, with how long it took, the tracks
+    /// added to and removed from each backup since its last run, and any errors
+    #[arg(long, env = "SPOTIFY_BACKUP_NOTIFY_WEBHOOK", global = true)]
+    pub notify_webhook: Option<Url>,
+    /// Posts a message summarising each run to a Discord webhook URL
+    #[arg(
+        long,
+        env = "SPOTIFY_BACKUP_NOTIFY_DISCORD",
+        hide_env_values = true,
+        global = true
+    )]
+    pub notify_discord: Option<Url>,
+    /// Posts a message summarising each run to a Slack incoming webhook URL
+    #[arg(
+        long,
+        env = "SPOTIFY_BACKUP_NOTIFY_SLACK",
+        hide_env_values = true,
+        global = true
+    )]
+    pub notify_slack: Option<Url>,
+    /// Raises a desktop notification when a long backup finishes or authentication is needed. Needs
+    /// `notify-send` (from libnotify) on Linux and the BSDs, and isn't supported on Windows
+    #[arg(long, env = "SPOTIFY_BACKUP_NOTIFY_DESKTOP", global = true)]
+    pub notify_desktop: bool,
+}
+
+impl NotifyArgs {
+    /// Whether a summary is sent anywhere, and so has to be put together.
+    pub fn is_enabled(&self) -> bool {
+        self.notify_webhook.is_some()
+            || self.notify_discord.is_some()
+            || self.notify_slack.is_some()
+    }
+}
+
+/// Summary of a run, POSTed as JSON to `--notify-webhook` and formatted as a message for chat
+/// webhooks once it's finished.
+#[derive(Serialize, Debug)]
+pub struct Summary {
+    profile: String,
+    success: bool,
+    started_at: DateTime<Utc>,
+    duration_secs: f64,
+    backups: Vec<BackupSummary>,
+    /// Kind of destination backups were uploaded to (eg. S3), if they were
+    uploaded_to: Option<&'static str>,
+    /// Errors that stopped the run, as opposed to failing a single backup
+    errors: Vec<String>,
+    #[serde(skip)]
+    started: Instant,
+}
+
+#[derive(Serialize, Debug)]
+struct BackupSummary {
+    name: String,
+    tracks: Option<usize>,
+    /// Tracks that weren't in the previous run of the backup, missing if it hasn't run before
+    added: Option<usize>,
+    /// Tracks that were in the previous run of the backup but aren't anymore
+    removed: Option<usize>,
+    /// Size of the backup as written, after compression and encryption
+    size: Option<u64>,
+    error: Option<String>,
+}
+
+impl Summary {
+    pub fn new(profile: &str) -> Self {
+        Self {
+            profile: profile.to_string(),
+            success: true,
+            started_at: Utc::now(),
+            duration_secs: 0.0,
+            backups: Vec::new(),
+            uploaded_to: None,
+            errors: Vec::new(),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn uploading_to(&mut self, destination: &'static str) {
+        self.uploaded_to = Some(destination);
+    }
+
+    /// Records a backup that succeeded, comparing its tracks with those of its previous run.
+    pub async fn succeeded(
+        &mut self,
+        profile: &Profile,
+        name: &str,
+        uris: &[String],
+        size: u64,
+    ) -> Result<()> {
+        let previous = diff_tracks(profile, name, uris).await?;
+
+        self.backups.push(BackupSummary {
+            name: name.to_string(),
+            tracks: Some(uris.len()),
+            added: previous.map(|v| v.0),
+            removed: previous.map(|v| v.1),
+            size: Some(size),
+            error: None,
+        });
+
+        Ok(())
+    }
+
+    pub fn failed(&mut self, name: &str, error: &anyhow::Error) {
+        self.success = false;
+        self.backups.push(BackupSummary {
+            name: name.to_string(),
+            tracks: None,
+            added: None,
+            removed: None,
+            size: None,
+            error: Some(format!("{error:#}")),
+        });
+    }
+
+    /// Sends the summary everywhere asked for by `args`, along with the error the run failed with
+    /// if it did. Failing to do so is only logged, so a webhook that's down doesn't fail the
+    /// backup.
+    pub async fn send(mut self, args: &NotifyArgs, error: Option<&anyhow::Error>) {
+        self.duration_secs = self.started.elapsed().as_secs_f64();
+
+        if let Some(e) = error {
+            self.success = false;
+
+            // failed backups are already listed with their errors
+            if self.backups.iter().all(|v| v.error.is_none()) {
+                self.errors.push(format!("{e:#}"));
+            }
+        }
+
+        let client = crate::http::client();
+
+        if let Some(url) = &args.notify_webhook {
+            post(&client, url, &self).await;
+        }
+
+        if let Some(url) = &args.notify_discord {
+            let mut message = self.message();
+            if message.len() > MAX_DISCORD_MESSAGE {
+                let mut end = MAX_DISCORD_MESSAGE - '…'.len_utf8();
+                while !message.is_char_boundary(end) {
+                    end -= 1;
+                }
+                message.truncate(end);
+                message.push('…');
+            }
+
+            post(&client, url, &json!({ "content": message })).await;
+        }
+
+        if let Some(url) = &args.notify_slack {
+            post(&client, url, &json!({ "text": self.message() })).await;
+        }
+    }
+
+    /// Formats the summary as a short message for chat (eg. "Backup OK: 3 of 10 backup(s)
+    /// changed, +12/−2 tracks, 14.20 MB uploaded to S3"), listing any failures beneath it.
+    fn message(&self) -> String {
+        let succeeded: Vec<_> = self.backups.iter().filter(|v| v.error.is_none()).collect();
+        let failures: Vec<_> = self
+            .backups
+            .iter()
+            .filter_map(|v| Some((Some(v.name.as_str()), v.error.as_deref()?)))
+            .chain(self.errors.iter().map(|v| (None, v.as_str())))
+            .collect();
+
+        // a backup that hasn't run before counts as changed
+        let changed = succeeded
+            .iter()
+            .filter(|v| v.added.is_none_or(|v| v > 0) || v.removed.is_some_and(|v| v > 0))
+            .count();
+        let added: usize = succeeded.iter().filter_map(|v| v.added).sum();
+        let removed: usize = succeeded.iter().filter_map(|v| v.removed).sum();
+        let size = DecimalBytes(succeeded.iter().filter_map(|v| v.size).sum());
+
+        let mut parts = Vec::new();
+        if succeeded.len() < self.backups.len() {
+            parts.push(format!(
+                "{} of {} backup(s) failed",
+                self.backups.len() - succeeded.len(),
+                self.backups.len()
+            ));
+        }
+        if !succeeded.is_empty() {
+            parts.push(format!(
+                "{changed} of {} backup(s) changed",
+                succeeded.len()
+            ));
+            parts.push(format!("+{added}/−{removed} tracks"));
+            parts.push(match self.uploaded_to {
+                Some(destination) => format!("{size} uploaded to {destination}"),
+                None => format!("{size} written"),
+            });
+        }
+
+        let mut message = match self.success {
+            true => "Backup OK".to_string(),
+            false => "Backup failed".to_string(),
+        };
+        if self.profile != DEFAULT_PROFILE {
+            message.push_str(&format!(" ({})", self.profile));
+        }
+        if !parts.is_empty() {
+            message.push_str(&format!(": {}", parts.join(", ")));
+        }
+
+        for (name, error) in failures.iter().take(MAX_LISTED_FAILURES) {
+            match name {
+                Some(name) => message.push_str(&format!("\n• {name}: {error}")),
+                None => message.push_str(&format!("\n• {error}")),
+            }
+        }
+        if failures.len() > MAX_LISTED_FAILURES {
+            message.push_str(&format!(
+                "\n…and {} more",
+                failures.len() - MAX_LISTED_FAILURES
+            ));
+        }
+
+        message
+    }
+}
+
+async fn post(client: &reqwest::Client, url: &Url, body: &impl Serialize) {
+    let response = client
+        .post(url.clone())
+        .timeout(Duration::from_secs(30))
+        .json(body)
+        .send()
+        .await
+        .and_then(|v| v.error_for_status());
+
+    match response {
+        Ok(_) => info!("Sent summary to {}", url.host_str().unwrap_or_default()),
+        Err(e) => warn!("Failed to send summary to webhook: {e}"),
+    }
+}
+
+/// Compares `uris` with the tracks of the previous run of the backup named `name`, returning the
+/// number added and removed, and stores them for the next run.
+async fn diff_tracks(
+    profile: &Profile,
+    name: &str,
+    uris: &[String],
+) -> Result<Option<(usize, usize)>> {
+    let dir = profile.dir().join(TRACKS_DIR);
+    let path = dir.join(format!("{}.json", crate::last_run::key(name)));
+
+    let previous: Option<HashSet<String>> = match tokio::fs::read(&path).await {
+        Ok(data) => Some(
+            serde_json::from_slice(&data)
+                .with_context(|| format!("Failed to parse {}", path.display()))?,
+        ),
+        Err(e) if e.kind() == ErrorKind::NotFound => None,
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+
+    let current: BTreeSet<&String> = uris.iter().collect();
+    let diff = previous.map(|previous| {
+        let added = current.iter().filter(|v| !previous.contains(**v)).count();
+        let removed = previous.iter().filter(|v| !current.contains(v)).count();
+        (added, removed)
+    });
+
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+    let data = serde_json::to_vec(&current).context("Failed to serialize tracks")?;
+    crate::atomic::write(&path, data).await?;
+
+    Ok(diff)
+}
@@ -0,0 +1,34 @@
+use std::time::Duration;
+
+use crate::{Error, Output};
+
+/// Receives events while the library is fetched, for reporting progress without parsing the
+/// logs. Every method does nothing by default, so only the events of interest need implementing.
+/// They're called from whichever task is fetching, so should return quickly rather than block it.
+///
+/// Register one with [`crate::SpotifyClient::with_observer`]. The CLI's progress bars and
+/// `--progress json` events are reported through one for each backup.
+pub trait Observer: Send + Sync {
+    /// A page of `count` items arrived from `url`, out of `total` items across every page of its
+    /// endpoint. Pages are fetched concurrently, so may arrive out of order.
+    fn on_page_fetched(&self, url: &str, count: u64, total: u64) {
+        let _ = (url, count, total);
+    }
+
+    /// A request to `url` failed with `error`, and will be sent again for the `attempt`th time
+    /// once `delay` has passed.
+    fn on_retry(&self, url: &str, attempt: u32, delay: Duration, error: &Error) {
+        let _ = (url, attempt, delay, error);
+    }
+
+    /// Spotify asked for requests to stop, so the one to `url` will be retried once
+    /// `retry_after` has passed.
+    fn on_rate_limited(&self, url: &str, retry_after: Duration) {
+        let _ = (url, retry_after);
+    }
+
+    /// A track was written to a backup or export, after any filtering.
+    fn on_item(&self, item: &Output) {
+        let _ = item;
+    }
+}
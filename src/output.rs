@@ -0,0 +1,539 @@
+use std::{fmt, io::Write, str::FromStr};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::{ser::SerializeMap, Deserialize, Serialize, Serializer};
+
+use crate::Output;
+
+/// Format backups are written in.
+#[derive(ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// A JSON array holding an object per track
+    Json,
+    /// A line of text per track, filled in from `--template`
+    Template,
+    /// Aligned columns for reading in a terminal, fitted to its width
+    Table,
+    /// Comma-separated values with a header, a row per track
+    Csv,
+    /// A JSON object per line for each track
+    Ndjson,
+    /// An SQLite database with a table of tracks
+    Sqlite,
+}
+
+impl Format {
+    /// Extension of backups written in this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Template | Self::Table => "txt",
+            Self::Csv => "csv",
+            Self::Ndjson => "ndjson",
+            Self::Sqlite => "sqlite",
+        }
+    }
+}
+
+/// A field of each track written to a backup.
+#[derive(ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+    /// The album's name and cover art URL
+    Album,
+    /// Track name
+    Name,
+    /// Names of the track's artists
+    Artists,
+    /// Spotify URI (eg. spotify:track:...)
+    Uri,
+    /// Spotify ID, missing for local files
+    Id,
+    /// When the track was added to the playlist or liked
+    #[value(name = "added_at")]
+    AddedAt,
+    /// Spotify user ID of whoever added the track to the playlist, missing for liked songs
+    #[value(name = "added_by")]
+    AddedBy,
+    /// Display name of whoever added the track to the playlist, looked up from their profile
+    #[value(name = "added_by_name")]
+    AddedByName,
+    /// Length of the track in milliseconds
+    #[value(name = "duration_ms")]
+    DurationMs,
+    /// Whether the track has explicit lyrics
+    Explicit,
+    /// Spotify's popularity score, from 0 to 100
+    Popularity,
+    /// International Standard Recording Code, for matching the track on other services
+    Isrc,
+    /// When the track's album was released, as precise as Spotify knows it (eg. 1981, 1981-12 or
+    /// 1981-12-15)
+    #[value(name = "release_date")]
+    ReleaseDate,
+    /// Genres of the track's artists, looked up in bulk
+    Genres,
+    /// Whether the track can be played in the market it was fetched for (see `--market`)
+    Playable,
+    /// Which disc of its album the track is on, starting from 1
+    #[value(name = "disc_number")]
+    DiscNumber,
+    /// Position of the track on its disc, starting from 1
+    #[value(name = "track_number")]
+    TrackNumber,
+    /// MusicBrainz ID of the recording, looked up by ISRC or failing that by title and artist
+    #[value(name = "recording_mbid")]
+    RecordingMbid,
+    /// MusicBrainz ID of a release the recording is on, preferring one named like the album
+    #[value(name = "release_mbid")]
+    ReleaseMbid,
+    /// Lyrics of the track, time-synced in LRC format where the provider has them (see
+    /// `--with-lyrics`)
+    Lyrics,
+    /// Your own tags on the track (see `annotate` and `--with-annotations`)
+    Tags,
+    /// Your own rating of the track, from 1 to 5
+    Rating,
+    /// Your own note on the track
+    Note,
+}
+
+/// Fields written when `--fields` isn't given.
+pub const DEFAULT_FIELDS: &[Field] = &[Field::Album, Field::Name, Field::Artists, Field::Uri];
+
+impl Field {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Album => "album",
+            Self::Name => "name",
+            Self::Artists => "artists",
+            Self::Uri => "uri",
+            Self::Id => "id",
+            Self::AddedAt => "added_at",
+            Self::AddedBy => "added_by",
+            Self::AddedByName => "added_by_name",
+            Self::DurationMs => "duration_ms",
+            Self::Explicit => "explicit",
+            Self::Popularity => "popularity",
+            Self::Isrc => "isrc",
+            Self::ReleaseDate => "release_date",
+            Self::Genres => "genres",
+            Self::Playable => "playable",
+            Self::DiscNumber => "disc_number",
+            Self::TrackNumber => "track_number",
+            Self::RecordingMbid => "recording_mbid",
+            Self::ReleaseMbid => "release_mbid",
+            Self::Lyrics => "lyrics",
+            Self::Tags => "tags",
+            Self::Rating => "rating",
+            Self::Note => "note",
+        }
+    }
+}
+
+/// Serializes only the given fields of a track, in the order they were given.
+pub struct Selected<'a> {
+    pub output: &'a Output,
+    pub fields: &'a [Field],
+}
+
+impl Serialize for Selected<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let output = self.output;
+        let mut map = serializer.serialize_map(Some(self.fields.len()))?;
+
+        for field in self.fields {
+            let key = field.name();
+
+            match field {
+                Field::Album => map.serialize_entry(key, &output.album)?,
+                Field::Name => map.serialize_entry(key, &output.name)?,
+                Field::Artists => map.serialize_entry(key, &output.artists)?,
+                Field::Uri => map.serialize_entry(key, &output.uri)?,
+                Field::Id => map.serialize_entry(key, &output.id)?,
+                Field::AddedAt => map.serialize_entry(key, &output.added_at)?,
+                Field::AddedBy => map.serialize_entry(key, &output.added_by)?,
+                Field::AddedByName => map.serialize_entry(key, &output.added_by_name)?,
+                Field::DurationMs => map.serialize_entry(key, &output.duration_ms)?,
+                Field::Explicit => map.serialize_entry(key, &output.explicit)?,
+                Field::Popularity => map.serialize_entry(key, &output.popularity)?,
+                Field::Isrc => map.serialize_entry(key, &output.isrc)?,
+                Field::ReleaseDate => map.serialize_entry(key, &output.release_date)?,
+                Field::Genres => map.serialize_entry(key, &output.genres)?,
+                Field::Playable => map.serialize_entry(key, &output.playable)?,
+                Field::DiscNumber => map.serialize_entry(key, &output.disc_number)?,
+                Field::TrackNumber => map.serialize_entry(key, &output.track_number)?,
+                Field::RecordingMbid => map.serialize_entry(key, &output.recording_mbid)?,
+                Field::ReleaseMbid => map.serialize_entry(key, &output.release_mbid)?,
+                Field::Lyrics => {
+                    map.serialize_entry(key, &output.lyrics.as_ref().and_then(|v| v.text()))?
+                }
+                Field::Tags => map.serialize_entry(key, &output.tags)?,
+                Field::Rating => map.serialize_entry(key, &output.rating)?,
+                Field::Note => map.serialize_entry(key, &output.note)?,
+            }
+        }
+
+        map.end()
+    }
+}
+
+/// What tracks are sorted by with `--sort`.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sort {
+    /// When the track was added to the playlist or liked, oldest first
+    #[value(name = "added_at")]
+    AddedAt,
+    /// Track name
+    Name,
+    /// Name of the track's first artist
+    Artist,
+    /// Album name
+    Album,
+    /// Length of the track, shortest first
+    Duration,
+}
+
+/// Order tracks are written in, instead of the order Spotify returns them in.
+#[derive(Debug, Clone, Copy)]
+pub struct Order {
+    pub by: Sort,
+    pub reverse: bool,
+}
+
+impl Order {
+    /// Sorts `outputs`, keeping tracks that compare equal in the order they were in.
+    pub fn sort(self, outputs: &mut [Output]) {
+        let key = |output: &Output| match self.by {
+            // timestamps are ISO 8601 in UTC, so they sort correctly as strings
+            Sort::AddedAt => SortKey::Text(output.added_at.clone().unwrap_or_default()),
+            Sort::Name => SortKey::Text(output.name.to_lowercase()),
+            Sort::Artist => SortKey::Text(
+                output
+                    .artists
+                    .first()
+                    .map(|v| v.to_lowercase())
+                    .unwrap_or_default(),
+            ),
+            Sort::Album => SortKey::Text(output.album.name.to_lowercase()),
+            Sort::Duration => SortKey::Number(output.duration_ms.unwrap_or_default()),
+        };
+
+        if self.reverse {
+            outputs.sort_by_cached_key(|v| std::cmp::Reverse(key(v)));
+        } else {
+            outputs.sort_by_cached_key(key);
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum SortKey {
+    Text(String),
+    Number(u64),
+}
+
+/// What tracks are grouped by with `--group-by`.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupBy {
+    /// Nests tracks under their album, in disc and track order
+    Album,
+}
+
+/// A line written for each track, with `{field}` placeholders replaced by the track's fields (eg.
+/// `{artists} — {name} ({album})`). Literal braces are written as `{{` and `}}`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(try_from = "String", into = "String")]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Text(String),
+    Field(Field),
+}
+
+impl Template {
+    /// Fields used by the template's placeholders.
+    pub fn fields(&self) -> Vec<Field> {
+        let mut fields = Vec::new();
+
+        for part in &self.parts {
+            if let Part::Field(field) = part {
+                if !fields.contains(field) {
+                    fields.push(*field);
+                }
+            }
+        }
+
+        fields
+    }
+
+    /// Writes the line for `output`, followed by a newline.
+    pub fn write(&self, mut writer: impl Write, output: &Output) -> Result<()> {
+        let mut line = String::new();
+
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => line.push_str(text),
+                Part::Field(field) => line.push_str(&field.text(output)),
+            }
+        }
+
+        line.push('\n');
+        writer.write_all(line.as_bytes())?;
+
+        Ok(())
+    }
+}
+
+impl FromStr for Template {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = s.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let (name, rest) = chars
+                        .as_str()
+                        .split_once('}')
+                        .context("Unclosed { in template, write {{ for a literal brace")?;
+                    let field = Field::from_str(name, false).map_err(|_| {
+                        let names: Vec<_> =
+                            Field::value_variants().iter().map(|v| v.name()).collect();
+                        anyhow::anyhow!(
+                            "Unknown placeholder {{{name}}} in template, expected one of {}",
+                            names.join(", ")
+                        )
+                    })?;
+
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Field(field));
+                    chars = rest.chars();
+                }
+                '}' => anyhow::bail!("Unmatched }} in template, write }}}} for a literal brace"),
+                c => text.push(c),
+            }
+        }
+
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+
+        Ok(Self { parts })
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => f.write_str(&text.replace('{', "{{").replace('}', "}}"))?,
+                Part::Field(field) => write!(f, "{{{}}}", field.name())?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl TryFrom<String> for Template {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+impl From<Template> for String {
+    fn from(value: Template) -> Self {
+        value.to_string()
+    }
+}
+
+impl Field {
+    /// The field's value as plain text, for templates. Lists are joined with commas and missing
+    /// values are left empty.
+    fn text(self, output: &Output) -> String {
+        let optional = |v: Option<String>| v.unwrap_or_default();
+
+        match self {
+            Self::Album => output.album.name.clone(),
+            Self::Name => output.name.clone(),
+            Self::Artists => output.artists.join(", "),
+            Self::Uri => output.uri.clone(),
+            Self::Id => optional(output.id.clone()),
+            Self::AddedAt => optional(output.added_at.clone()),
+            Self::AddedBy => optional(output.added_by.clone()),
+            Self::AddedByName => optional(output.added_by_name.clone()),
+            Self::DurationMs => optional(output.duration_ms.map(|v| v.to_string())),
+            Self::Explicit => optional(output.explicit.map(|v| v.to_string())),
+            Self::Popularity => optional(output.popularity.map(|v| v.to_string())),
+            Self::Isrc => optional(output.isrc.clone()),
+            Self::ReleaseDate => optional(output.release_date.clone()),
+            Self::Genres => output.genres.as_deref().unwrap_or_default().join(", "),
+            Self::Playable => optional(output.playable.map(|v| v.to_string())),
+            Self::DiscNumber => optional(output.disc_number.map(|v| v.to_string())),
+            Self::TrackNumber => optional(output.track_number.map(|v| v.to_string())),
+            Self::RecordingMbid => optional(output.recording_mbid.clone()),
+            Self::ReleaseMbid => optional(output.release_mbid.clone()),
+            Self::Lyrics => optional(
+                output
+                    .lyrics
+                    .as_ref()
+                    .and_then(|v| v.text().map(Into::into)),
+            ),
+            Self::Tags => output.tags.as_deref().unwrap_or_default().join(", "),
+            Self::Rating => optional(output.rating.map(|v| v.to_string())),
+            Self::Note => optional(output.note.clone()),
+        }
+    }
+}
+
+/// Starts a table with the given columns, whose rows are truncated to a single line fitting the
+/// terminal when `fit` is set, apart from the columns in `keep` which are always written in full.
+pub fn table(columns: &[&str], fit: bool, keep: &[usize]) -> comfy_table::Table {
+    let mut header = comfy_table::Row::from(columns);
+    header.max_height(1);
+
+    let mut table = comfy_table::Table::new();
+    table
+        .load_preset(comfy_table::presets::NOTHING)
+        .set_style(comfy_table::TableComponent::HeaderLines, '─')
+        .set_style(comfy_table::TableComponent::MiddleHeaderIntersections, '─')
+        .set_truncation_indicator("…")
+        .set_header(header);
+
+    if fit {
+        table.set_content_arrangement(comfy_table::ContentArrangement::Dynamic);
+
+        for &i in keep {
+            if let Some(column) = table.column_mut(i) {
+                column.set_constraint(comfy_table::ColumnConstraint::ContentWidth);
+            }
+        }
+    }
+
+    table
+}
+
+/// Adds a row to a table started with [`table`], truncated to a single line.
+pub fn add_row<'a>(table: &mut comfy_table::Table, cells: impl IntoIterator<Item = &'a str>) {
+    let mut row = comfy_table::Row::from(cells);
+    row.max_height(1);
+    table.add_row(row);
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn line(template: &str, output: &Output) -> String {
+        let mut line = Vec::new();
+        template
+            .parse::<Template>()
+            .unwrap()
+            .write(&mut line, output)
+            .unwrap();
+        String::from_utf8(line).unwrap()
+    }
+
+    #[test]
+    fn fills_in_placeholders() {
+        let output = Output::example(json!({
+            "artists": [{"name": "Boards of Canada"}, {"name": "Hell Interface"}],
+        }));
+
+        assert_eq!(
+            line("{artists} — {name} ({album})", &output),
+            "Boards of Canada, Hell Interface — Roygbiv (Music Has the Right to Children)\n"
+        );
+        assert_eq!(
+            line("{uri}\t{isrc}\t{duration_ms}", &output),
+            "spotify:track:6ZLqX6C0qKfMnCUHYWCk0W\tGBBPW9800012\t151000\n"
+        );
+        assert_eq!(line("just text", &output), "just text\n");
+        assert_eq!(line("", &output), "\n");
+    }
+
+    #[test]
+    fn leaves_missing_fields_empty() {
+        let output = Output::example(json!({"external_ids": null, "popularity": null}));
+
+        assert_eq!(
+            line("[{isrc}] [{popularity}] {name}", &output),
+            "[] [] Roygbiv\n"
+        );
+    }
+
+    #[test]
+    fn lists_fields_used_once_each() {
+        let template: Template = "{name} {artists} {name} {{name}}".parse().unwrap();
+
+        assert_eq!(template.fields(), [Field::Name, Field::Artists]);
+    }
+
+    #[test]
+    fn writes_doubled_braces_literally() {
+        let output = Output::example(json!({}));
+
+        assert_eq!(line("{{{name}}}", &output), "{Roygbiv}\n");
+        assert_eq!(line("{{name}} }}{{", &output), "{name} }{\n");
+    }
+
+    #[test]
+    fn prints_as_it_was_given() {
+        for template in ["{artists} — {name}", "{{{name}}} {{}}", "{{", "plain"] {
+            assert_eq!(
+                template.parse::<Template>().unwrap().to_string(),
+                template,
+                "{template}"
+            );
+        }
+    }
+
+    #[test]
+    fn explains_invalid_templates() {
+        for (template, error) in [
+            (
+                "{title}",
+                "Unknown placeholder {title} in template, expected one of album, name, artists",
+            ),
+            ("{Name}", "Unknown placeholder {Name} in template"),
+            ("{}", "Unknown placeholder {} in template"),
+            ("{ name }", "Unknown placeholder { name } in template"),
+            (
+                "{name",
+                "Unclosed { in template, write {{ for a literal brace",
+            ),
+            ("{name} {", "Unclosed { in template"),
+            (
+                "name}",
+                "Unmatched } in template, write }} for a literal brace",
+            ),
+            ("{{name}", "Unmatched } in template"),
+        ] {
+            let e = template.parse::<Template>().unwrap_err();
+            assert!(e.to_string().starts_with(error), "{template}: {e}");
+        }
+    }
+}
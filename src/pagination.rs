@@ -0,0 +1,194 @@
+use std::{
+    pin::Pin,
+    task::{Context as TaskContext, Poll},
+};
+
+use anyhow::{Context, Result};
+use futures::{stream::BoxStream, Stream, StreamExt, TryStreamExt};
+use reqwest::Url;
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::{api, Error};
+
+/// A page of a paginated endpoint.
+#[derive(Deserialize, Debug)]
+pub struct Page<T> {
+    /// Number of items across every page
+    pub total: u32,
+    #[serde(default)]
+    pub offset: u32,
+    pub items: Vec<T>,
+}
+
+/// Every item of a paginated endpoint, as a stream yielding them in order as their pages arrive.
+/// Nothing is fetched until the stream is first polled. The first page tells us how many items
+/// there are, so the pages after it are fetched concurrently, a few ahead of the one being read.
+/// Dropping the stream cancels the requests in flight.
+pub struct Paginator<T> {
+    pages: BoxStream<'static, Result<Page<T>, Error>>,
+    /// Items of the last page fetched that haven't been yielded yet
+    items: std::vec::IntoIter<T>,
+}
+
+impl<T: DeserializeOwned + Send + 'static> Paginator<T> {
+    /// Pages through the endpoint `first_url` is the first page of, which sets the page size with
+    /// its `limit`.
+    pub fn new(client: &api::Client, first_url: String) -> Self {
+        let client = client.clone();
+
+        let pages = futures::stream::once(async move {
+            let first_page = fetch_page::<T>(&client, first_url.clone()).await?;
+            let urls = page_urls(&first_url, first_page.total)?;
+            let concurrency = client.concurrency();
+
+            let rest = futures::stream::iter(urls)
+                .map(move |url| {
+                    let client = client.clone();
+                    async move { fetch_page::<T>(&client, url).await }
+                })
+                .buffered(concurrency);
+
+            Ok::<_, Error>(futures::stream::once(async { Ok(first_page) }).chain(rest))
+        })
+        .try_flatten()
+        .boxed();
+
+        Self {
+            pages,
+            items: Vec::new().into_iter(),
+        }
+    }
+}
+
+impl<T> Paginator<T> {
+    /// The pages rather than the items on them, for when the total or where each page starts is
+    /// needed. Any items of a page already partly read are dropped.
+    pub fn pages(self) -> BoxStream<'static, Result<Page<T>, Error>> {
+        self.pages
+    }
+}
+
+// neither the boxed stream nor the items are ever pinned in place
+impl<T> Unpin for Paginator<T> {}
+
+impl<T> Stream for Paginator<T> {
+    type Item = Result<T, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(item) = self.items.next() {
+                return Poll::Ready(Some(Ok(item)));
+            }
+
+            match futures::ready!(self.pages.poll_next_unpin(cx)) {
+                Some(Ok(page)) => self.items = page.items.into_iter(),
+                Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+/// Fetches the page at `url`, telling the client's observer about it.
+async fn fetch_page<T: DeserializeOwned>(
+    client: &api::Client,
+    url: String,
+) -> Result<Page<T>, Error> {
+    let page = crate::fetch_page::<T>(client, url.clone()).await?;
+    client.observe(|v| v.on_page_fetched(&url, page.items.len() as u64, page.total.into()));
+
+    Ok(page)
+}
+
+/// Builds the URLs of every page after the first, based on the `limit` of the first page's URL.
+fn page_urls(first_url: &str, total: u32) -> Result<Vec<String>> {
+    let url = Url::parse(first_url).context("Invalid page URL")?;
+    let limit = url
+        .query_pairs()
+        .find(|(key, _)| key == "limit")
+        .and_then(|(_, v)| v.parse().ok())
+        .unwrap_or(50)
+        .max(1);
+
+    let query: Vec<_> = url
+        .query_pairs()
+        .filter(|(key, _)| key != "offset")
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect();
+
+    Ok((limit..total)
+        .step_by(limit as usize)
+        .map(|offset| {
+            let mut url = url.clone();
+            url.query_pairs_mut()
+                .clear()
+                .extend_pairs(&query)
+                .append_pair("offset", &offset.to_string());
+            url.to_string()
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIRST: &str = "https://api.spotify.com/v1/me/tracks?offset=0&limit=50&market=from_token";
+
+    fn offsets(first_url: &str, total: u32) -> Vec<u32> {
+        page_urls(first_url, total)
+            .unwrap()
+            .iter()
+            .map(|v| {
+                let url = Url::parse(v).unwrap();
+                let offset = url.query_pairs().find(|(key, _)| key == "offset").unwrap();
+                offset.1.parse().unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fetches_nothing_more_when_everything_fits_on_the_first_page() {
+        assert!(offsets(FIRST, 0).is_empty());
+        assert!(offsets(FIRST, 1).is_empty());
+        assert!(offsets(FIRST, 50).is_empty());
+    }
+
+    #[test]
+    fn fetches_each_page_after_the_first() {
+        assert_eq!(offsets(FIRST, 51), [50]);
+        assert_eq!(offsets(FIRST, 100), [50]);
+        assert_eq!(offsets(FIRST, 150), [50, 100]);
+        assert_eq!(offsets(FIRST, 199), [50, 100, 150]);
+    }
+
+    #[test]
+    fn pages_by_the_limit_of_the_first_url() {
+        let first = "https://api.spotify.com/v1/me/tracks?limit=2";
+        assert_eq!(offsets(first, 5), [2, 4]);
+        assert_eq!(offsets(first, 6), [2, 4]);
+
+        // Spotify's default page size, and a limit of 0 taken as 1 rather than never moving on
+        assert_eq!(
+            offsets("https://api.spotify.com/v1/me/tracks", 120),
+            [50, 100]
+        );
+        assert_eq!(
+            offsets("https://api.spotify.com/v1/me/tracks?limit=0", 3),
+            [1, 2]
+        );
+    }
+
+    #[test]
+    fn keeps_the_rest_of_the_query() {
+        assert_eq!(
+            page_urls(FIRST, 60).unwrap(),
+            ["https://api.spotify.com/v1/me/tracks?limit=50&market=from_token&offset=50"]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_urls() {
+        assert!(page_urls("me/tracks?limit=50", 100).is_err());
+    }
+}
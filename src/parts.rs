@@ -0,0 +1,691 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    atomic::{self, TmpFile},
+    cli::Args,
+    encryption, manifest,
+    output::{self, Field},
+    writer::{FinishWrite, Written},
+    Exporter, Output,
+};
+
+/// Extension of the index listing the parts of a split backup, added to the name it would have
+/// had whole.
+const INDEX_EXTENSION: &str = "parts.json";
+
+/// An amount of bytes, given as a number with an optional K, M or G suffix (eg. 500M).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct Size(pub u64);
+
+impl FromStr for Size {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+            Some(i) => s.split_at(i),
+            None => (s, ""),
+        };
+        let multiplier = match unit.trim().to_ascii_uppercase().as_str() {
+            "" | "B" => 1,
+            "K" | "KB" | "KIB" => 1 << 10,
+            "M" | "MB" | "MIB" => 1 << 20,
+            "G" | "GB" | "GIB" => 1 << 30,
+            _ => anyhow::bail!("Unknown unit {unit}, expected K, M or G"),
+        };
+        let number: u64 = number
+            .parse()
+            .with_context(|| format!("Expected a size such as 500M, got {s}"))?;
+        anyhow::ensure!(number > 0, "Size has to be more than 0");
+
+        number
+            .checked_mul(multiplier)
+            .map(Self)
+            .context("Size is too large")
+    }
+}
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            v if v % (1 << 30) == 0 => write!(f, "{}G", v >> 30),
+            v if v % (1 << 20) == 0 => write!(f, "{}M", v >> 20),
+            v if v % (1 << 10) == 0 => write!(f, "{}K", v >> 10),
+            v => write!(f, "{v}"),
+        }
+    }
+}
+
+impl TryFrom<String> for Size {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+impl From<Size> for String {
+    fn from(value: Size) -> Self {
+        value.to_string()
+    }
+}
+
+/// Index of the parts a backup was split into, written in place of the backup as
+/// `<name>.parts.json` (eg. `liked.json.parts.json`).
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Index {
+    pub schema_version: u32,
+    /// Format each part is written in, as its extension (eg. json)
+    pub format: String,
+    /// Number of tracks across every part
+    pub tracks: usize,
+    /// The parts in order, each holding the tracks that follow those of the one before
+    pub parts: Vec<Part>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Part {
+    pub name: String,
+    pub tracks: usize,
+    pub size: u64,
+    pub sha256: String,
+}
+
+impl Part {
+    fn written(&self) -> Written {
+        Written {
+            size: self.size,
+            sha256: self.sha256.clone(),
+            data: None,
+        }
+    }
+}
+
+/// Whether the file `name` is the index of a split backup.
+pub fn is_index(name: &str) -> bool {
+    name.ends_with(&format!(".{INDEX_EXTENSION}"))
+}
+
+/// Splits the name of a backup (eg. `liked.json`) into the part before the extension of its format
+/// and the extension.
+fn split_name(name: &str) -> (&str, &str) {
+    name.rsplit_once('.').unwrap_or((name, ""))
+}
+
+/// Name of the index of the backup `name` once it's been split.
+pub fn index_name(name: &str) -> String {
+    format!("{name}.{INDEX_EXTENSION}")
+}
+
+/// Name of the `n`th part (counting from 1) of the backup `name`, before compression and
+/// encryption.
+fn part_name(name: &str, n: usize) -> String {
+    match split_name(name) {
+        (stem, "") => format!("{stem}.part-{n:03}"),
+        (stem, extension) => format!("{stem}.part-{n:03}.{extension}"),
+    }
+}
+
+/// Whether tracks written in `format` can be split across files that are each readable alone.
+pub fn can_split(args: &Args) -> bool {
+    args.group_by.is_none()
+        && matches!(
+            args.format,
+            output::Format::Json
+                | output::Format::Ndjson
+                | output::Format::Csv
+                | output::Format::Template
+        )
+}
+
+/// An [`Exporter`] writing tracks into numbered parts in a directory, each in the format that was
+/// asked for and readable on its own, starting a new part whenever the current one reaches
+/// `--split-size` bytes (before compression) or `--split-tracks` tracks. Parts are written to
+/// temporary files, and only moved into place by [`Writer::commit`]; the writer it's given is
+/// left untouched.
+pub struct Writer<'a> {
+    args: &'a Args,
+    fields: &'a [Field],
+    dir: &'a Path,
+    /// Name the backup would be written under whole, without compression and encryption
+    name: &'a str,
+    current: Option<Current<'a>>,
+    finished: Vec<(TmpFile, Part)>,
+}
+
+/// The part tracks are currently written to.
+struct Current<'a> {
+    tmp: TmpFile,
+    name: String,
+    writer: Counted,
+    exporter: Box<dyn Exporter + 'a>,
+    tracks: usize,
+}
+
+/// Writer keeping count of the bytes written through it, before they're compressed.
+struct Counted {
+    inner: Box<dyn FinishWrite>,
+    written: u64,
+}
+
+impl Write for Counted {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.written += n as u64;
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Files a backup wrote once its parts were moved into place, and those of a previous run of it
+/// that were deleted since they're no longer part of it.
+pub struct Committed {
+    pub files: Vec<(String, Written)>,
+    pub removed: Vec<String>,
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(args: &'a Args, fields: &'a [Field], dir: &'a Path, name: &'a str) -> Self {
+        Self {
+            args,
+            fields,
+            dir,
+            name,
+            current: None,
+            finished: Vec::new(),
+        }
+    }
+
+    /// Whether the current part has reached either limit, and the next track starts a new one.
+    fn is_full(&self) -> bool {
+        self.current.as_ref().is_some_and(|v| {
+            self.args
+                .split_size
+                .is_some_and(|max| v.writer.written >= max.0)
+                || self
+                    .args
+                    .split_tracks
+                    .is_some_and(|max| v.tracks >= max.get())
+        })
+    }
+
+    fn start_part(&mut self) -> Result<()> {
+        let name = crate::cli::file_name(self.args, part_name(self.name, self.finished.len() + 1));
+        let tmp = TmpFile::new(self.dir.join(&name));
+        let inner = crate::cli::open_backup(self.args, tmp.path())
+            .with_context(|| format!("Failed to create {name}"))?;
+        let mut writer = Counted { inner, written: 0 };
+
+        let mut exporter = self.args.exporter(self.fields)?;
+        exporter.begin(&mut writer)?;
+
+        self.current = Some(Current {
+            tmp,
+            name,
+            writer,
+            exporter,
+            tracks: 0,
+        });
+
+        Ok(())
+    }
+
+    fn finish_part(&mut self) -> Result<()> {
+        let Some(mut current) = self.current.take() else {
+            return Ok(());
+        };
+
+        current.exporter.finish(&mut current.writer)?;
+        let written = current.writer.inner.finish()?;
+        self.finished.push((
+            current.tmp,
+            Part {
+                name: current.name,
+                tracks: current.tracks,
+                size: written.size,
+                sha256: written.sha256,
+            },
+        ));
+
+        Ok(())
+    }
+
+    /// Moves the parts into place along with their index, deleting whatever was left of a previous
+    /// run of the backup that isn't part of it anymore. A backup that fit in one part is written
+    /// under its usual name, without an index.
+    pub async fn commit(mut self) -> Result<Committed> {
+        let whole = crate::cli::file_name(self.args, self.name.to_string());
+        let index_name = index_name(self.name);
+        let previous = previous_files(self.args, self.dir, self.name).await?;
+
+        let mut files = Vec::new();
+        if self.finished.len() == 1 {
+            let (tmp, part) = self.finished.remove(0);
+            tmp.commit_as(self.dir.join(&whole)).await?;
+            files.push((whole, part.written()));
+        } else {
+            let mut parts = Vec::new();
+            for (tmp, part) in self.finished {
+                tmp.commit().await?;
+                files.push((part.name.clone(), part.written()));
+                parts.push(part);
+            }
+
+            let index = Index {
+                schema_version: manifest::SCHEMA_VERSION,
+                format: split_name(self.name).1.to_string(),
+                tracks: parts.iter().map(|v| v.tracks).sum(),
+                parts,
+            };
+            let mut data = serde_json::to_vec_pretty(&index)?;
+            data.push(b'\n');
+            atomic::write(&self.dir.join(&index_name), &data)
+                .await
+                .with_context(|| format!("Failed to write {index_name}"))?;
+            files.push((
+                index_name,
+                Written {
+                    size: data.len() as u64,
+                    sha256: hex::encode(Sha256::digest(&data)),
+                    data: Some(data),
+                },
+            ));
+        }
+
+        let kept: Vec<_> = files.iter().map(|(name, _)| name.clone()).collect();
+        let removed = remove_stale(self.dir, previous, &kept).await?;
+
+        Ok(Committed { files, removed })
+    }
+}
+
+impl Exporter for Writer<'_> {
+    fn write_item(&mut self, _writer: &mut dyn Write, output: &Output) -> Result<()> {
+        if self.is_full() {
+            self.finish_part()?;
+        }
+        if self.current.is_none() {
+            self.start_part()?;
+        }
+
+        if let Some(current) = &mut self.current {
+            current.exporter.write_item(&mut current.writer, output)?;
+            current.tracks += 1;
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self, _writer: &mut dyn Write) -> Result<()> {
+        // a backup without any tracks still gets a file, as it would if it wasn't split
+        if self.current.is_none() && self.finished.is_empty() {
+            self.start_part()?;
+        }
+
+        self.finish_part()
+    }
+}
+
+/// Every file a previous run of the backup `name` could have left in `dir`: the whole backup, or
+/// the index of its parts and each part it lists.
+pub async fn previous_files(args: &Args, dir: &Path, name: &str) -> Result<Vec<String>> {
+    let index_name = index_name(name);
+    let index = read_index(&dir.join(&index_name)).await?;
+
+    let mut files = vec![crate::cli::file_name(args, name.to_string()), index_name];
+    files.extend(index.into_iter().flat_map(|v| v.parts).map(|v| v.name));
+
+    Ok(files)
+}
+
+/// Deletes each of `files` in `dir` that wasn't written again as one of `kept`, so a backup that's
+/// now split replaces the whole file, and one that isn't replaces the parts it was split into.
+/// Returns the names of the files deleted.
+pub async fn remove_stale(dir: &Path, files: Vec<String>, kept: &[String]) -> Result<Vec<String>> {
+    let mut removed = Vec::new();
+
+    for name in files.into_iter().filter(|v| !kept.contains(v)) {
+        match tokio::fs::remove_file(dir.join(&name)).await {
+            Ok(()) => removed.push(name),
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("Failed to delete {name}")),
+        }
+    }
+
+    Ok(removed)
+}
+
+async fn read_index(path: &Path) -> Result<Option<Index>> {
+    let data = match tokio::fs::read(path).await {
+        Ok(v) => v,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+
+    serde_json::from_slice(&data)
+        .map(Some)
+        .with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// Index of the split backup `path` refers to, which is either the index itself or the name the
+/// backup would have had if it wasn't split (eg. `liked.json` for `liked.json.parts.json`).
+pub async fn find_index(path: &Path) -> Result<Option<PathBuf>> {
+    let Some(name) = path.file_name().map(|v| v.to_string_lossy()) else {
+        return Ok(None);
+    };
+
+    if is_index(&name) {
+        return Ok(Some(path.to_path_buf()));
+    }
+    if tokio::fs::try_exists(path).await.unwrap_or(true) {
+        return Ok(None);
+    }
+
+    // the extensions of compression and encryption aren't part of the index's name
+    let mut name = name.as_ref();
+    for extension in [encryption::EXTENSION, "zst", "gz"] {
+        name = name
+            .strip_suffix(extension)
+            .and_then(|v| v.strip_suffix('.'))
+            .unwrap_or(name);
+    }
+
+    let index = path.with_file_name(index_name(name));
+    Ok(tokio::fs::try_exists(&index)
+        .await
+        .unwrap_or_default()
+        .then_some(index))
+}
+
+/// Reads every part of the split backup indexed at `path`, decrypting and decompressing each,
+/// and joins them back into the backup they were split from. JSON backups get a single array of
+/// tracks (and of contributors, counted across the parts), CSV backups a single header.
+pub async fn read(path: &Path, identities: &[PathBuf]) -> Result<Vec<u8>> {
+    let index = read_index(path)
+        .await?
+        .with_context(|| format!("{} doesn't exist", path.display()))?;
+    anyhow::ensure!(
+        index.schema_version <= manifest::SCHEMA_VERSION,
+        "{} was written with a newer schema version ({}) than this version understands ({})",
+        path.display(),
+        index.schema_version,
+        manifest::SCHEMA_VERSION
+    );
+    let dir = path.parent().unwrap_or(Path::new(""));
+
+    let mut joined = Vec::new();
+    let mut tracks = Vec::new();
+    let mut contributors: Vec<Value> = Vec::new();
+
+    for (i, part) in index.parts.iter().enumerate() {
+        let data = crate::cli::read_file(&dir.join(&part.name), identities).await?;
+
+        match index.format.as_str() {
+            "json" => {
+                let mut backup: Value = serde_json::from_slice(&data)
+                    .with_context(|| format!("Failed to parse {}", part.name))?;
+                let Some(Value::Array(part_tracks)) = backup.get_mut("tracks").map(Value::take)
+                else {
+                    anyhow::bail!("{} has no tracks", part.name);
+                };
+                tracks.extend(part_tracks);
+
+                if let Some(Value::Array(part_contributors)) = backup.get_mut("contributors") {
+                    add_contributors(&mut contributors, part_contributors);
+                }
+            }
+            // every part starts with the header
+            "csv" if i > 0 => {
+                let start = data
+                    .iter()
+                    .position(|&v| v == b'\n')
+                    .map_or(data.len(), |v| v + 1);
+                joined.extend_from_slice(&data[start..]);
+            }
+            _ => joined.extend(data),
+        }
+    }
+
+    if index.format != "json" {
+        return Ok(joined);
+    }
+
+    contributors.sort_by_key(|v| std::cmp::Reverse(v["tracks"].as_u64()));
+    let mut backup = serde_json::json!({
+        "schema_version": index.schema_version,
+        "tracks": tracks,
+    });
+    if !contributors.is_empty() {
+        backup["contributors"] = Value::Array(contributors);
+    }
+
+    let mut data = serde_json::to_vec(&backup)?;
+    data.push(b'\n');
+
+    Ok(data)
+}
+
+/// Adds the contributors of a part to those counted so far, summing the tracks of anyone in both.
+fn add_contributors(contributors: &mut Vec<Value>, part: &[Value]) {
+    let index: HashMap<_, _> = contributors
+        .iter()
+        .enumerate()
+        .filter_map(|(i, v)| Some((v["id"].as_str()?.to_string(), i)))
+        .collect();
+
+    for contributor in part {
+        let existing = contributor["id"].as_str().and_then(|v| index.get(v));
+        match existing {
+            Some(&i) => {
+                let tracks = contributors[i]["tracks"].as_u64().unwrap_or_default()
+                    + contributor["tracks"].as_u64().unwrap_or_default();
+                contributors[i]["tracks"] = tracks.into();
+            }
+            None => contributors.push(contributor.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!("500".parse::<Size>().unwrap(), Size(500));
+        assert_eq!("64K".parse::<Size>().unwrap(), Size(64 << 10));
+        assert_eq!("500m".parse::<Size>().unwrap(), Size(500 << 20));
+        assert_eq!(" 2 GiB ".parse::<Size>().unwrap(), Size(2 << 30));
+        assert_eq!("10MB".parse::<Size>().unwrap().to_string(), "10M");
+
+        for invalid in ["", "M", "0", "0G", "5T", "1.5G", "99999999999G"] {
+            assert!(invalid.parse::<Size>().is_err(), "{invalid} was parsed");
+        }
+    }
+
+    #[test]
+    fn names_parts() {
+        assert_eq!(split_name("liked.json"), ("liked", "json"));
+        assert_eq!(split_name("liked"), ("liked", ""));
+        assert_eq!(part_name("liked.ndjson", 1), "liked.part-001.ndjson");
+        assert_eq!(
+            part_name("playlist-abc.csv", 12),
+            "playlist-abc.part-012.csv"
+        );
+        assert_eq!(part_name("liked", 3), "liked.part-003");
+        assert_eq!(index_name("liked.json"), "liked.json.parts.json");
+        assert!(is_index("liked.json.parts.json"));
+        assert!(!is_index("liked.json"));
+    }
+
+    #[tokio::test]
+    async fn finds_index_of_compressed_and_encrypted_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = dir.path().join("liked.json.parts.json");
+        std::fs::write(&index, "{}").unwrap();
+        std::fs::write(dir.path().join("whole.json"), "{}").unwrap();
+
+        for name in [
+            "liked.json",
+            "liked.json.gz",
+            "liked.json.zst",
+            "liked.json.zst.age",
+            "liked.json.parts.json",
+        ] {
+            assert_eq!(
+                find_index(&dir.path().join(name)).await.unwrap().as_ref(),
+                Some(&index),
+                "{name}"
+            );
+        }
+        // a backup that exists whole is read as it is, even with an index next to it
+        assert_eq!(
+            find_index(&dir.path().join("whole.json")).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            find_index(&dir.path().join("other.json")).await.unwrap(),
+            None
+        );
+    }
+
+    /// Writes `parts` into `dir` along with an index of them in `format`, returning the index's
+    /// path.
+    fn write_parts(dir: &Path, format: &str, parts: &[(&str, &[u8])]) -> PathBuf {
+        let index = Index {
+            schema_version: manifest::SCHEMA_VERSION,
+            format: format.to_string(),
+            tracks: 0,
+            parts: parts
+                .iter()
+                .map(|(name, data)| {
+                    std::fs::write(dir.join(name), data).unwrap();
+                    Part {
+                        name: name.to_string(),
+                        tracks: 0,
+                        size: data.len() as u64,
+                        sha256: hex::encode(Sha256::digest(data)),
+                    }
+                })
+                .collect(),
+        };
+
+        let path = dir.join(index_name(&format!("backup.{format}")));
+        std::fs::write(&path, serde_json::to_vec(&index).unwrap()).unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn reads_json_parts_into_one_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let part = |tracks: &[&str], contributors: Value| {
+            serde_json::to_vec(&json!({
+                "schema_version": manifest::SCHEMA_VERSION,
+                "tracks": tracks.iter().map(|v| json!({"name": v})).collect::<Vec<_>>(),
+                "contributors": contributors,
+            }))
+            .unwrap()
+        };
+        let index = write_parts(
+            dir.path(),
+            "json",
+            &[
+                (
+                    "backup.part-001.json",
+                    &part(
+                        &["a", "b"],
+                        json!([{"id": "alice", "tracks": 1}, {"id": "bob", "tracks": 1}]),
+                    ),
+                ),
+                (
+                    "backup.part-002.json",
+                    &part(&["c", "d"], json!([{"id": "bob", "tracks": 2}])),
+                ),
+            ],
+        );
+
+        let backup: Value = serde_json::from_slice(&read(&index, &[]).await.unwrap()).unwrap();
+        assert_eq!(
+            backup,
+            json!({
+                "schema_version": manifest::SCHEMA_VERSION,
+                "tracks": [{"name": "a"}, {"name": "b"}, {"name": "c"}, {"name": "d"}],
+                "contributors": [{"id": "bob", "tracks": 3}, {"id": "alice", "tracks": 1}],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_csv_parts_with_one_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = write_parts(
+            dir.path(),
+            "csv",
+            &[
+                ("backup.part-001.csv", b"name,artists\na,x\nb,y\n"),
+                ("backup.part-002.csv", b"name,artists\nc,z\n"),
+            ],
+        );
+
+        assert_eq!(
+            read(&index, &[]).await.unwrap(),
+            b"name,artists\na,x\nb,y\nc,z\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn refuses_index_from_newer_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("backup.json.parts.json");
+        let index = json!({
+            "schema_version": manifest::SCHEMA_VERSION + 1,
+            "format": "json",
+            "tracks": 0,
+            "parts": [],
+        });
+        std::fs::write(&path, index.to_string()).unwrap();
+
+        let err = read(&path, &[]).await.unwrap_err();
+        assert!(err.to_string().contains("newer schema version"), "{err}");
+    }
+
+    #[test]
+    fn sums_contributors_across_parts() {
+        let mut contributors = vec![json!({"id": "alice", "tracks": 2})];
+
+        add_contributors(
+            &mut contributors,
+            &[
+                json!({"id": "bob", "tracks": 1}),
+                json!({"id": "alice", "tracks": 3}),
+            ],
+        );
+        add_contributors(&mut contributors, &[json!({"id": "bob", "tracks": 4})]);
+
+        assert_eq!(
+            contributors,
+            [
+                json!({"id": "alice", "tracks": 5}),
+                json!({"id": "bob", "tracks": 5}),
+            ]
+        );
+    }
+}
@@ -0,0 +1,172 @@
+use std::{
+    collections::BTreeSet,
+    io::{self, IsTerminal},
+};
+
+use anyhow::{Context, Result};
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+use ratatui::{
+    backend::CrosstermBackend,
+    crossterm::{
+        event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+        execute,
+        terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
+    },
+    layout::{Constraint, Layout},
+    style::{Style, Stylize},
+    text::Line,
+    widgets::{List, ListState},
+    Terminal,
+};
+
+/// Lets the user fuzzy search `items` and pick one or more of them, returning the indices of the
+/// ones picked. Drawn on stderr, so stdout can still be redirected to a file.
+pub fn pick(prompt: &str, items: &[String]) -> Result<Vec<usize>> {
+    anyhow::ensure!(
+        io::stderr().is_terminal(),
+        "Picking interactively needs a terminal"
+    );
+
+    terminal::enable_raw_mode().context("Failed to set up terminal")?;
+    execute!(io::stderr(), EnterAlternateScreen).context("Failed to set up terminal")?;
+
+    let result = Terminal::new(CrosstermBackend::new(io::stderr()))
+        .context("Failed to set up terminal")
+        .and_then(|mut terminal| Picker::new(items).run(&mut terminal, prompt));
+
+    let _ = execute!(io::stderr(), LeaveAlternateScreen);
+    let _ = terminal::disable_raw_mode();
+
+    result
+}
+
+struct Picker<'a> {
+    items: &'a [String],
+    query: String,
+    /// Indices of the items matching the query, best match first
+    matches: Vec<usize>,
+    picked: BTreeSet<usize>,
+    list: ListState,
+    matcher: SkimMatcherV2,
+}
+
+impl<'a> Picker<'a> {
+    fn new(items: &'a [String]) -> Self {
+        let mut picker = Self {
+            items,
+            query: String::new(),
+            matches: Vec::new(),
+            picked: BTreeSet::new(),
+            list: ListState::default(),
+            matcher: SkimMatcherV2::default().smart_case(),
+        };
+        picker.update_matches();
+        picker
+    }
+
+    fn run(
+        mut self,
+        terminal: &mut Terminal<CrosstermBackend<io::Stderr>>,
+        prompt: &str,
+    ) -> Result<Vec<usize>> {
+        loop {
+            terminal
+                .draw(|frame| {
+                    let [input, list, help] = Layout::vertical([
+                        Constraint::Length(1),
+                        Constraint::Min(1),
+                        Constraint::Length(1),
+                    ])
+                    .areas(frame.area());
+
+                    frame.render_widget(
+                        Line::from_iter([
+                            format!("{prompt}> ").bold(),
+                            self.query.as_str().into(),
+                            format!("  {}/{}", self.matches.len(), self.items.len()).dim(),
+                        ]),
+                        input,
+                    );
+
+                    let items = self.matches.iter().map(|&i| {
+                        let mark = if self.picked.contains(&i) { "* " } else { "  " };
+                        format!("{mark}{}", self.items[i])
+                    });
+                    frame.render_stateful_widget(
+                        List::new(items).highlight_style(Style::new().reversed()),
+                        list,
+                        &mut self.list,
+                    );
+
+                    frame.render_widget(
+                        Line::raw("type to search  ↑/↓ move  tab pick  enter done  esc cancel")
+                            .reversed(),
+                        help,
+                    );
+                })
+                .context("Failed to draw picker")?;
+
+            let Event::Key(key) = event::read().context("Failed to read key press")? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+
+            match key.code {
+                KeyCode::Esc => anyhow::bail!("Cancelled"),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    anyhow::bail!("Cancelled")
+                }
+                KeyCode::Enter => {
+                    // picks the highlighted item if nothing was picked with tab
+                    if self.picked.is_empty() {
+                        self.picked.extend(self.highlighted());
+                    }
+
+                    return Ok(self.picked.into_iter().collect());
+                }
+                KeyCode::Tab => {
+                    if let Some(i) = self.highlighted() {
+                        if !self.picked.remove(&i) {
+                            self.picked.insert(i);
+                        }
+                    }
+                    self.list.select_next();
+                }
+                KeyCode::Up => self.list.select_previous(),
+                KeyCode::Down => self.list.select_next(),
+                KeyCode::Backspace => {
+                    self.query.pop();
+                    self.update_matches();
+                }
+                KeyCode::Char(c) => {
+                    self.query.push(c);
+                    self.update_matches();
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn highlighted(&self) -> Option<usize> {
+        self.list
+            .selected()
+            .and_then(|v| self.matches.get(v))
+            .copied()
+    }
+
+    fn update_matches(&mut self) {
+        let mut scored: Vec<_> = self
+            .items
+            .iter()
+            .enumerate()
+            .filter_map(|(i, item)| Some((self.matcher.fuzzy_match(item, &self.query)?, i)))
+            .collect();
+        // ties keep the order the items were given in
+        scored.sort_by_key(|&(score, i)| (std::cmp::Reverse(score), i));
+
+        self.matches = scored.into_iter().map(|(_, i)| i).collect();
+        self.list.select((!self.matches.is_empty()).then_some(0));
+    }
+}
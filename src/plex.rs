@@ -0,0 +1,296 @@
+use anyhow::{Context, Result};
+use reqwest::{Method, Url};
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::{
+    dupes,
+    map::{Candidate, Service, Track},
+    sync::Destination,
+};
+
+/// Most tracks added to a playlist in one request, keeping the URL listing them a sensible length.
+const MAX_ITEMS: usize = 200;
+
+/// Type Plex gives music libraries, and the type number of tracks in them.
+const MUSIC_LIBRARY: &str = "artist";
+const TRACK_TYPE: &str = "10";
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct PlexArgs {
+    /// URL of the Plex server (eg. http://192.168.1.10:32400)
+    #[arg(long, env = "PLEX_URL", global = true)]
+    pub plex_url: Option<Url>,
+    /// Plex token to authenticate with (https://support.plex.tv/articles/204059436)
+    #[arg(long, env = "PLEX_TOKEN", hide_env_values = true, global = true)]
+    pub plex_token: Option<String>,
+    /// Name of the music library to match tracks in, needed if the server has more than one
+    #[arg(long, env = "PLEX_LIBRARY", global = true)]
+    pub plex_library: Option<String>,
+}
+
+/// Every Plex response is wrapped in a `MediaContainer`.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct Response<T> {
+    media_container: T,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Identity {
+    machine_identifier: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Sections {
+    #[serde(default, rename = "Directory")]
+    directories: Vec<Section>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Section {
+    key: String,
+    #[serde(rename = "type")]
+    kind: String,
+    title: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Items {
+    #[serde(default, rename = "Metadata")]
+    metadata: Vec<Item>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Item {
+    rating_key: String,
+    title: String,
+    /// Artist of the track when it's not the album artist
+    original_title: Option<String>,
+    /// Album artist of a track
+    grandparent_title: Option<String>,
+    /// Album of a track
+    parent_title: Option<String>,
+    parent_rating_key: Option<String>,
+    /// Length of a track in milliseconds
+    duration: Option<u64>,
+    #[serde(default)]
+    smart: bool,
+}
+
+/// A Plex server, with the music library tracks are matched in.
+pub struct Plex {
+    http: reqwest::Client,
+    url: Url,
+    token: String,
+    machine_identifier: String,
+    library: String,
+}
+
+impl Plex {
+    pub async fn connect(plex: &PlexArgs) -> Result<Self> {
+        let url = plex
+            .plex_url
+            .clone()
+            .context("Missing Plex server URL, set PLEX_URL")?;
+        let token = plex
+            .plex_token
+            .clone()
+            .context("Missing Plex token, set PLEX_TOKEN")?;
+
+        let mut server = Self {
+            http: crate::http::client(),
+            url,
+            token,
+            machine_identifier: String::new(),
+            library: String::new(),
+        };
+
+        let identity: Identity = server
+            .request(Method::GET, "identity", &[])
+            .await
+            .context("Failed to connect to Plex server")?;
+        server.machine_identifier = identity.machine_identifier;
+
+        let sections: Sections = server.request(Method::GET, "library/sections", &[]).await?;
+        let libraries: Vec<_> = sections
+            .directories
+            .into_iter()
+            .filter(|v| v.kind == MUSIC_LIBRARY)
+            .collect();
+        let library = match &plex.plex_library {
+            Some(name) => libraries
+                .iter()
+                .find(|v| v.title.eq_ignore_ascii_case(name))
+                .with_context(|| format!("Plex server has no music library named {name}"))?,
+            None => match libraries.as_slice() {
+                [library] => library,
+                [] => anyhow::bail!("Plex server has no music library"),
+                _ => {
+                    let names: Vec<_> = libraries.iter().map(|v| v.title.as_str()).collect();
+                    anyhow::bail!(
+                        "Plex server has several music libraries, pick one with --plex-library: {}",
+                        names.join(", ")
+                    )
+                }
+            },
+        };
+        server.library = library.key.clone();
+
+        Ok(server)
+    }
+
+    async fn request<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        params: &[(&str, &str)],
+    ) -> Result<T> {
+        let response = self.send(method, path, params).await?;
+
+        let response: Response<T> = response
+            .json()
+            .await
+            .context("Failed to parse response from Plex")?;
+
+        Ok(response.media_container)
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        params: &[(&str, &str)],
+    ) -> Result<reqwest::Response> {
+        let url = self.url.join(path)?;
+
+        self.http
+            .request(method, url)
+            .query(params)
+            .header("Accept", "application/json")
+            .header("X-Plex-Token", &self.token)
+            .header("X-Plex-Product", env!("CARGO_PKG_NAME"))
+            .header("X-Plex-Client-Identifier", env!("CARGO_PKG_NAME"))
+            .send()
+            .await
+            .context("Failed to send request to Plex")?
+            .error_for_status()
+            .context("Failed to query Plex")
+    }
+
+    /// URI Plex refers to tracks of its own library by when adding them to playlists.
+    fn items_uri(&self, rating_keys: &[String]) -> String {
+        format!(
+            "server://{}/com.plexapp.plugins.library/library/metadata/{}",
+            self.machine_identifier,
+            rating_keys.join(",")
+        )
+    }
+}
+
+impl Destination for Plex {
+    async fn replace_playlist(&self, name: &str, rating_keys: &[String]) -> Result<bool> {
+        let playlists: Items = self
+            .request(Method::GET, "playlists", &[("playlistType", "audio")])
+            .await
+            .context("Failed to list Plex playlists")?;
+        // smart playlists are made of rules rather than tracks, so aren't touched
+        let existing = playlists
+            .metadata
+            .into_iter()
+            .find(|v| v.title == name && !v.smart);
+
+        let mut chunks = rating_keys.chunks(MAX_ITEMS);
+        let (key, created) = match existing {
+            Some(playlist) => {
+                self.send(
+                    Method::DELETE,
+                    &format!("playlists/{}/items", playlist.rating_key),
+                    &[],
+                )
+                .await
+                .with_context(|| format!("Failed to clear Plex playlist {name}"))?;
+
+                (playlist.rating_key, false)
+            }
+            None => {
+                // Plex won't create an empty playlist, so it's created with the first tracks
+                let Some(first) = chunks.next() else {
+                    return Ok(false);
+                };
+                let uri = self.items_uri(first);
+                let created: Items = self
+                    .request(
+                        Method::POST,
+                        "playlists",
+                        &[
+                            ("type", "audio"),
+                            ("title", name),
+                            ("smart", "0"),
+                            ("uri", &uri),
+                        ],
+                    )
+                    .await
+                    .with_context(|| format!("Failed to create Plex playlist {name}"))?;
+                let playlist = created
+                    .metadata
+                    .into_iter()
+                    .next()
+                    .context("Plex didn't return the playlist it created")?;
+
+                (playlist.rating_key, true)
+            }
+        };
+
+        for chunk in chunks {
+            let uri = self.items_uri(chunk);
+            self.send(
+                Method::PUT,
+                &format!("playlists/{key}/items"),
+                &[("uri", &uri)],
+            )
+            .await
+            .with_context(|| format!("Failed to add tracks to Plex playlist {name}"))?;
+        }
+
+        Ok(created)
+    }
+}
+
+impl Service for Plex {
+    fn name(&self) -> &'static str {
+        "plex"
+    }
+
+    /// Searches the library for tracks with the track's title in theirs, without versions like
+    /// "(Remastered)" which the files may not have.
+    async fn search(&self, track: &Track) -> Result<Vec<Candidate>> {
+        let title = dupes::normalize_title(&track.name).unwrap_or_else(|| track.name.clone());
+        let items: Items = self
+            .request(
+                Method::GET,
+                &format!("library/sections/{}/all", self.library),
+                &[("type", TRACK_TYPE), ("title", &title)],
+            )
+            .await?;
+
+        Ok(items
+            .metadata
+            .into_iter()
+            .map(|v| Candidate {
+                id: v.rating_key,
+                title: v.title,
+                artists: v
+                    .original_title
+                    .or(v.grandparent_title)
+                    .into_iter()
+                    .collect(),
+                album: v.parent_title,
+                album_id: v.parent_rating_key,
+                duration_ms: v.duration,
+                by_isrc: false,
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,161 @@
+use std::{
+    fs::TryLockError,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use anyhow::{Context, Result};
+use tracing::info;
+
+pub const DEFAULT_PROFILE: &str = "default";
+
+const LOCK_FILE: &str = "run.lock";
+
+/// Directory given by `--state-dir`, used instead of the platform's data dir.
+static STATE_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Names of every token state a profile may hold.
+pub const TOKEN_FILES: &[&str] = &[
+    "token.json",
+    "gdrive-token.json",
+    "dropbox-token.json",
+    "lastfm-session.json",
+];
+
+/// A named set of credentials and state, allowing several Spotify accounts to be backed up from
+/// the same machine.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    name: String,
+    dir: PathBuf,
+}
+
+impl Profile {
+    pub fn new(name: &str) -> Result<Self> {
+        anyhow::ensure!(
+            !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+            "Invalid profile name {name:?}, only letters, numbers, - and _ are allowed"
+        );
+
+        Ok(Self {
+            name: name.to_string(),
+            dir: build_profiles_dir_path()?.join(name),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Directory holding the profile's tokens and state.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Takes an exclusive lock on the profile, held until the returned file is dropped, so
+    /// overlapping runs don't race on its tokens, caches and output. Fails straight away if another
+    /// run holds the lock, unless `wait` is set.
+    pub async fn lock(&self, wait: bool) -> Result<std::fs::File> {
+        self.create_dir().await?;
+
+        let path = self.dir.join(LOCK_FILE);
+        let name = self.name.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&path)
+                .context("Failed to open profile lock file")?;
+
+            match file.try_lock() {
+                Ok(()) => {}
+                Err(TryLockError::WouldBlock) if wait => {
+                    info!("Waiting for another run using profile {name} to finish...");
+                    file.lock().context("Failed to lock profile")?;
+                }
+                Err(TryLockError::WouldBlock) => anyhow::bail!(
+                    "Another run is already using profile {name}, pass --wait to wait for it to \
+                     finish"
+                ),
+                Err(TryLockError::Error(e)) => return Err(e).context("Failed to lock profile"),
+            }
+
+            Ok(file)
+        })
+        .await
+        .context("Failed to wait for profile lock")?
+    }
+
+    /// Creates the profile's state directory, moving over state written before profiles existed
+    /// into the default profile.
+    pub async fn create_dir(&self) -> Result<()> {
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .context("Failed to create profile state dir")?;
+
+        if self.name == DEFAULT_PROFILE {
+            let base = build_state_dir_path()?;
+
+            for file in TOKEN_FILES {
+                let legacy = base.join(file);
+
+                if legacy.exists() && !self.dir.join(file).exists() {
+                    tokio::fs::rename(&legacy, self.dir.join(file))
+                        .await
+                        .with_context(|| format!("Failed to migrate {}", legacy.display()))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Lists the names of every profile that has been created.
+pub async fn list() -> Result<Vec<String>> {
+    let mut entries = match tokio::fs::read_dir(build_profiles_dir_path()?).await {
+        Ok(v) => v,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).context("Failed to read profiles dir"),
+    };
+
+    let mut out = Vec::new();
+
+    while let Some(entry) = entries.next_entry().await? {
+        if entry.file_type().await?.is_dir() {
+            out.push(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+
+    out.sort();
+
+    Ok(out)
+}
+
+/// Keeps every profile's state in `dir` from here on, instead of the platform's data dir.
+pub fn set_state_dir(dir: &Path) -> Result<()> {
+    let dir =
+        std::path::absolute(dir).with_context(|| format!("Failed to resolve {}", dir.display()))?;
+    let _ = STATE_DIR.set(dir);
+
+    Ok(())
+}
+
+fn build_profiles_dir_path() -> Result<PathBuf> {
+    Ok(build_state_dir_path()?.join("profiles"))
+}
+
+fn build_state_dir_path() -> Result<PathBuf> {
+    if let Some(dir) = STATE_DIR.get() {
+        return Ok(dir.clone());
+    }
+
+    let base = dirs::data_local_dir().context("Unsupported operating system, no data dir")?;
+    Ok(base.join("spotify-backup"))
+}
@@ -0,0 +1,361 @@
+use std::{
+    io::{self, IsTerminal, Write},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, LazyLock, Mutex, PoisonError,
+    },
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use serde::Serialize;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::{Error, Observer};
+
+/// Every progress bar is drawn through this, so log lines can be printed above the bars rather
+/// than through them. Bars are only drawn when stderr is a terminal, so logs of scheduled runs
+/// aren't filled with escape codes.
+static BARS: LazyLock<MultiProgress> = LazyLock::new(|| {
+    if io::stderr().is_terminal() {
+        MultiProgress::new()
+    } else {
+        MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+    }
+});
+
+/// Stops progress bars from being drawn at all.
+pub fn hide() {
+    BARS.set_draw_target(ProgressDrawTarget::hidden());
+}
+
+/// When set, log lines are sent here instead of being written to stderr, for when something else
+/// (eg. the TUI) owns the terminal.
+static CAPTURED_LOGS: Mutex<Option<UnboundedSender<String>>> = Mutex::new(None);
+
+/// Sends log lines to `sender` rather than stderr until called again with `None`.
+#[cfg(feature = "tui")]
+pub fn capture_logs(sender: Option<UnboundedSender<String>>) {
+    *CAPTURED_LOGS.lock().unwrap_or_else(PoisonError::into_inner) = sender;
+}
+
+static RECORDED_LOGS: Mutex<Option<String>> = Mutex::new(None);
+
+/// Starts keeping a copy of the log lines written, alongside writing them as usual.
+pub fn record_logs() {
+    *RECORDED_LOGS.lock().unwrap_or_else(PoisonError::into_inner) = Some(String::new());
+}
+
+/// Stops keeping a copy of the log lines written, returning those written since [`record_logs`]
+/// without their colour codes.
+pub fn take_recorded_logs() -> String {
+    RECORDED_LOGS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take()
+        .unwrap_or_default()
+}
+
+/// Writes log output to stderr, clearing the progress bars while it's written and redrawing them
+/// afterwards.
+pub struct LogWriter;
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(recorded) = &mut *RECORDED_LOGS.lock().unwrap_or_else(PoisonError::into_inner) {
+            recorded.push_str(&strip_ansi(&String::from_utf8_lossy(buf)));
+        }
+
+        if let Some(sender) = &*CAPTURED_LOGS.lock().unwrap_or_else(PoisonError::into_inner) {
+            let _ = sender.send(String::from_utf8_lossy(buf).trim_end().to_string());
+            return Ok(buf.len());
+        }
+
+        BARS.suspend(|| io::stderr().write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressFormat {
+    /// Progress bars, drawn when stderr is a terminal
+    Bars,
+    /// Newline-delimited JSON events, for wrapping the tool in another program
+    Json,
+}
+
+/// Options controlling how progress is reported while backing up.
+#[derive(clap::Args, Debug, Clone)]
+pub struct ProgressArgs {
+    /// How progress is reported while backing up
+    #[arg(
+        long,
+        value_enum,
+        env = "SPOTIFY_BACKUP_PROGRESS",
+        default_value_t = ProgressFormat::Bars,
+        global = true
+    )]
+    pub progress: ProgressFormat,
+    /// File JSON progress events are written to instead of stderr (eg. /dev/fd/3)
+    #[arg(long, env = "SPOTIFY_BACKUP_PROGRESS_FILE", global = true)]
+    pub progress_file: Option<PathBuf>,
+}
+
+/// A progress event, written as a single line of JSON with `--progress json`.
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Started {
+        file: String,
+    },
+    Page {
+        file: String,
+        fetched: u64,
+        total: u64,
+    },
+    Retrying {
+        file: String,
+        url: String,
+        attempt: u32,
+        delay_ms: u64,
+        error: String,
+    },
+    RateLimited {
+        file: String,
+        url: String,
+        retry_after_ms: u64,
+    },
+    Finished {
+        file: String,
+        error: Option<String>,
+    },
+    Done {
+        total: usize,
+        failed: usize,
+    },
+}
+
+/// Where progress events are sent instead of drawing bars.
+enum Events {
+    Json(Mutex<Box<dyn Write + Send>>),
+    #[cfg(feature = "tui")]
+    Channel(UnboundedSender<Event>),
+}
+
+/// Progress reporting for a run backing up one or more playlists.
+pub struct Progress {
+    shared: Arc<Shared>,
+    jobs: usize,
+}
+
+/// What the progress of every backup in a run is reported to.
+struct Shared {
+    /// Bar counting finished backups, only shown when there's more than one
+    overall: Option<ProgressBar>,
+    /// Destination of progress events, when they were asked for instead of bars
+    events: Option<Events>,
+    failed: AtomicUsize,
+}
+
+impl Progress {
+    pub fn new(jobs: usize, args: &ProgressArgs) -> Result<Self> {
+        let events = match (args.progress, &args.progress_file) {
+            (ProgressFormat::Bars, _) => None,
+            (ProgressFormat::Json, None) => Some(Box::new(io::stderr()) as Box<dyn Write + Send>),
+            (ProgressFormat::Json, Some(path)) => Some(Box::new(
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("Failed to open {}", path.display()))?,
+            ) as Box<dyn Write + Send>),
+        };
+
+        Ok(Self::with_events(
+            jobs,
+            events.map(|v| Events::Json(Mutex::new(v))),
+        ))
+    }
+
+    /// Sends progress events to `sender` rather than drawing bars.
+    #[cfg(feature = "tui")]
+    pub fn channel(jobs: usize, sender: UnboundedSender<Event>) -> Self {
+        Self::with_events(jobs, Some(Events::Channel(sender)))
+    }
+
+    fn with_events(jobs: usize, events: Option<Events>) -> Self {
+        let overall = (jobs > 1 && events.is_none()).then(|| {
+            BARS.add(
+                ProgressBar::new(jobs as u64)
+                    .with_style(style("{prefix:>30} [{bar:40}] {pos}/{len} backups"))
+                    .with_prefix("Total"),
+            )
+        });
+
+        Self {
+            shared: Arc::new(Shared {
+                overall,
+                events,
+                failed: AtomicUsize::new(0),
+            }),
+            jobs,
+        }
+    }
+
+    /// Starts reporting the progress of a single backup, which is done by observing the client
+    /// fetching it.
+    pub fn job(&self, name: &str) -> Job {
+        let shared = &self.shared;
+        let bar = if shared.events.is_some() {
+            ProgressBar::hidden()
+        } else {
+            let bar = ProgressBar::new(0)
+                .with_style(style("{prefix:>30} [{bar:40}] {pos}/{len} tracks"))
+                .with_prefix(name.to_string());
+
+            match &shared.overall {
+                Some(overall) => BARS.insert_before(overall, bar),
+                None => BARS.add(bar),
+            }
+        };
+
+        shared.emit(Event::Started {
+            file: name.to_string(),
+        });
+
+        Job {
+            shared: shared.clone(),
+            name: name.to_string(),
+            bar,
+        }
+    }
+
+    /// Reports the totals once every backup has finished.
+    pub fn finish(self) {
+        self.shared.emit(Event::Done {
+            total: self.jobs,
+            failed: self.shared.failed.load(Ordering::Relaxed),
+        });
+    }
+}
+
+impl Shared {
+    fn emit(&self, event: Event) {
+        // a reader that's gone away shouldn't fail the backup
+        match &self.events {
+            None => {}
+            Some(Events::Json(writer)) => {
+                let mut writer = writer.lock().unwrap_or_else(PoisonError::into_inner);
+                let _ = serde_json::to_writer(&mut *writer, &event)
+                    .map_err(io::Error::from)
+                    .and_then(|()| writer.write_all(b"\n"))
+                    .and_then(|()| writer.flush());
+            }
+            #[cfg(feature = "tui")]
+            Some(Events::Channel(sender)) => {
+                let _ = sender.send(event);
+            }
+        }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if let Some(overall) = &self.shared.overall {
+            overall.finish_and_clear();
+            BARS.remove(overall);
+        }
+    }
+}
+
+/// Progress of a single backup, observing the client fetching it.
+pub struct Job {
+    shared: Arc<Shared>,
+    name: String,
+    bar: ProgressBar,
+}
+
+impl Job {
+    /// Removes the backup's bar, whether it succeeded or not, and counts it towards the total.
+    pub fn finish(&self, error: Option<&anyhow::Error>) {
+        self.bar.finish_and_clear();
+        BARS.remove(&self.bar);
+
+        if let Some(overall) = &self.shared.overall {
+            overall.inc(1);
+        }
+
+        if error.is_some() {
+            self.shared.failed.fetch_add(1, Ordering::Relaxed);
+        }
+
+        self.shared.emit(Event::Finished {
+            file: self.name.clone(),
+            error: error.map(|e| format!("{e:#}")),
+        });
+    }
+}
+
+impl Observer for Job {
+    fn on_page_fetched(&self, _: &str, count: u64, total: u64) {
+        self.bar.set_length(total);
+        self.bar.inc(count);
+
+        self.shared.emit(Event::Page {
+            file: self.name.clone(),
+            fetched: self.bar.position(),
+            total,
+        });
+    }
+
+    fn on_retry(&self, url: &str, attempt: u32, delay: Duration, error: &Error) {
+        self.shared.emit(Event::Retrying {
+            file: self.name.clone(),
+            url: url.to_string(),
+            attempt,
+            delay_ms: delay.as_millis() as u64,
+            // formatted the way anyhow formats a chain of errors with {:#}
+            error: std::iter::successors(Some(error as &dyn std::error::Error), |v| v.source())
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(": "),
+        });
+    }
+
+    fn on_rate_limited(&self, url: &str, retry_after: Duration) {
+        self.shared.emit(Event::RateLimited {
+            file: self.name.clone(),
+            url: url.to_string(),
+            retry_after_ms: retry_after.as_millis() as u64,
+        });
+    }
+}
+
+fn style(template: &str) -> ProgressStyle {
+    ProgressStyle::with_template(template)
+        .expect("progress bar template is valid")
+        .progress_chars("=> ")
+}
+
+/// Removes the colour codes from log lines formatted for a terminal.
+pub fn strip_ansi(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // skips to the end of the escape sequence
+            chars.by_ref().find(char::is_ascii_alphabetic);
+        } else {
+            out.push(c);
+        }
+    }
+
+    out
+}
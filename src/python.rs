@@ -0,0 +1,270 @@
+use std::sync::Arc;
+
+use clap::ValueEnum;
+use futures::{stream::BoxStream, StreamExt, TryStreamExt};
+use pyo3::{
+    create_exception,
+    exceptions::{PyException, PyStopAsyncIteration, PyValueError},
+    prelude::*,
+};
+use pyo3_async_runtimes::tokio::future_into_py;
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::Mutex;
+
+use crate::{
+    client::Options,
+    output::{self, Field, Selected},
+    profile::Profile,
+    Error, GetPlaylistTracksResponseItem, Output, OutputPlaylist, SpotifyClient,
+};
+
+create_exception!(spotify_backup, SpotifyError, PyException);
+create_exception!(spotify_backup, AuthError, SpotifyError);
+create_exception!(spotify_backup, RateLimitedError, SpotifyError);
+create_exception!(spotify_backup, NotFoundError, SpotifyError);
+create_exception!(spotify_backup, InsufficientScopeError, SpotifyError);
+
+/// Raises an [`Error`] as the exception for its kind, with its whole chain as the message.
+fn to_py_err(e: Error) -> PyErr {
+    let kind = match &e {
+        Error::Auth(_) => AuthError::new_err,
+        Error::RateLimited { .. } => RateLimitedError::new_err,
+        Error::NotFound { .. } => NotFoundError::new_err,
+        Error::InsufficientScope { .. } => InsufficientScopeError::new_err,
+        _ => SpotifyError::new_err,
+    };
+
+    kind(format!("{:#}", anyhow::Error::new(e)))
+}
+
+/// Converts anything serializable into the Python objects `json.loads` would give for it.
+fn to_python(value: &impl Serialize) -> PyResult<PyObject> {
+    let json = serde_json::to_string(value).map_err(|e| PyValueError::new_err(e.to_string()))?;
+
+    Python::with_gil(|py| Ok(py.import("json")?.call_method1("loads", (json,))?.unbind()))
+}
+
+/// Parses the names of fields as `--fields` does, defaulting to the same ones.
+fn parse_fields(fields: Option<Vec<String>>) -> PyResult<Arc<[Field]>> {
+    let Some(fields) = fields else {
+        return Ok(output::DEFAULT_FIELDS.into());
+    };
+
+    fields
+        .iter()
+        .map(|v| {
+            Field::from_str(v, true)
+                .map_err(|_| PyValueError::new_err(format!("Unknown field {v}")))
+        })
+        .collect()
+}
+
+/// A track as it's written to backups, with only `fields`.
+fn track(item: GetPlaylistTracksResponseItem, fields: &[Field]) -> Result<Value, Error> {
+    let output = Output::from(item);
+
+    serde_json::to_value(Selected {
+        output: &output,
+        fields,
+    })
+    .map_err(|e| Error::Other(e.into()))
+}
+
+/// Client for reading the library of a profile's user, see `SpotifyClient` in the Rust crate.
+/// Every method fetching from Spotify is a coroutine, and the items are returned as the dicts
+/// written to JSON backups.
+#[pyclass(name = "SpotifyClient", module = "spotify_backup", frozen)]
+struct PySpotifyClient {
+    client: SpotifyClient,
+}
+
+#[pymethods]
+impl PySpotifyClient {
+    /// Authenticates as the user of `profile` with the options set in the environment (eg.
+    /// `SPOTIFY_REFRESH_TOKEN`), logging in through the browser if there's no stored token.
+    #[staticmethod]
+    #[pyo3(signature = (profile = "default"))]
+    fn from_env<'py>(py: Python<'py>, profile: &str) -> PyResult<Bound<'py, PyAny>> {
+        let profile = Profile::new(profile).map_err(|e| SpotifyError::new_err(format!("{e:#}")))?;
+
+        future_into_py(py, async move {
+            let client = SpotifyClient::from_env(&profile).await.map_err(to_py_err)?;
+
+            Ok(Self { client })
+        })
+    }
+
+    /// Makes requests with an access token obtained elsewhere, which has to have been granted the
+    /// `playlist-read-private` and `user-library-read` scopes.
+    #[staticmethod]
+    #[pyo3(signature = (token, profile = "default"))]
+    fn with_token(token: &str, profile: &str) -> PyResult<Self> {
+        let profile = Profile::new(profile).map_err(|e| SpotifyError::new_err(format!("{e:#}")))?;
+        let options = Options::from_env().map_err(to_py_err)?;
+        let client = SpotifyClient::with_token(token, &profile, options.api).map_err(to_py_err)?;
+
+        Ok(Self { client })
+    }
+
+    /// The user the client is authenticated as, with their `id` and `display_name`.
+    fn me<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let client = self.client.clone();
+
+        future_into_py(py, async move {
+            let me = client.me().await.map_err(to_py_err)?;
+
+            to_python(&serde_json::json!({
+                "id": me.id,
+                "display_name": me.display_name,
+            }))
+        })
+    }
+
+    /// Every playlist in the user's library, as `playlists --format json` lists them.
+    fn playlists<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let client = self.client.clone();
+
+        future_into_py(py, async move {
+            let me = client.me().await.map_err(to_py_err)?;
+            let playlists = client.playlists().await.map_err(to_py_err)?;
+            let playlists: Vec<_> = playlists
+                .iter()
+                .map(|v| OutputPlaylist::for_user(v, &me.id))
+                .collect();
+
+            to_python(&playlists)
+        })
+    }
+
+    /// Every playlist in the user's library as an async iterator, yielding them as their pages
+    /// arrive.
+    fn iter_playlists(&self) -> Items {
+        Items::new(
+            self.client
+                .playlists_stream()
+                .and_then(|v| async move {
+                    serde_json::to_value(OutputPlaylist::from(&v))
+                        .map_err(|e| Error::Other(e.into()))
+                })
+                .boxed(),
+        )
+    }
+
+    /// Tracks of the playlist with the ID `id`, in order, with the `fields` given (the same ones
+    /// as `--fields` by default).
+    #[pyo3(signature = (id, fields = None))]
+    fn playlist_tracks<'py>(
+        &self,
+        py: Python<'py>,
+        id: &str,
+        fields: Option<Vec<String>>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let tracks = self.iter_playlist_tracks(id, fields)?;
+
+        future_into_py(py, tracks.collect())
+    }
+
+    /// Tracks of the playlist with the ID `id` as an async iterator.
+    #[pyo3(signature = (id, fields = None))]
+    fn iter_playlist_tracks(&self, id: &str, fields: Option<Vec<String>>) -> PyResult<Items> {
+        let fields = parse_fields(fields)?;
+
+        Ok(Items::new(
+            self.client
+                .playlist_tracks_stream(id)
+                .and_then(move |v| futures::future::ready(track(v, &fields)))
+                .boxed(),
+        ))
+    }
+
+    /// The user's liked songs, most recently liked first.
+    #[pyo3(signature = (fields = None))]
+    fn liked_tracks<'py>(
+        &self,
+        py: Python<'py>,
+        fields: Option<Vec<String>>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let tracks = self.iter_liked_tracks(fields)?;
+
+        future_into_py(py, tracks.collect())
+    }
+
+    /// The user's liked songs as an async iterator.
+    #[pyo3(signature = (fields = None))]
+    fn iter_liked_tracks(&self, fields: Option<Vec<String>>) -> PyResult<Items> {
+        let fields = parse_fields(fields)?;
+
+        Ok(Items::new(
+            self.client
+                .liked_tracks_stream()
+                .and_then(move |v| futures::future::ready(track(v, &fields)))
+                .boxed(),
+        ))
+    }
+}
+
+/// Async iterator over the items of a paginated endpoint, fetching pages as it's iterated.
+#[pyclass(module = "spotify_backup", frozen)]
+struct Items {
+    items: Arc<Mutex<BoxStream<'static, Result<Value, Error>>>>,
+}
+
+impl Items {
+    fn new(items: BoxStream<'static, Result<Value, Error>>) -> Self {
+        Self {
+            items: Arc::new(Mutex::new(items)),
+        }
+    }
+
+    /// Every item left, as a list.
+    async fn collect(self) -> PyResult<PyObject> {
+        let items: Vec<_> = self
+            .items
+            .lock()
+            .await
+            .by_ref()
+            .try_collect()
+            .await
+            .map_err(to_py_err)?;
+
+        to_python(&items)
+    }
+}
+
+#[pymethods]
+impl Items {
+    fn __aiter__(slf: Py<Self>) -> Py<Self> {
+        slf
+    }
+
+    fn __anext__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let items = self.items.clone();
+
+        future_into_py(py, async move {
+            match items.lock().await.try_next().await.map_err(to_py_err)? {
+                Some(item) => to_python(&item),
+                None => Err(PyStopAsyncIteration::new_err(())),
+            }
+        })
+    }
+}
+
+/// The `spotify_backup` Python module, built with `maturin build --features python`.
+#[pymodule]
+fn spotify_backup(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    let py = m.py();
+
+    m.add_class::<PySpotifyClient>()?;
+    m.add_class::<Items>()?;
+    m.add("SpotifyError", py.get_type::<SpotifyError>())?;
+    m.add("AuthError", py.get_type::<AuthError>())?;
+    m.add("RateLimitedError", py.get_type::<RateLimitedError>())?;
+    m.add("NotFoundError", py.get_type::<NotFoundError>())?;
+    m.add(
+        "InsufficientScopeError",
+        py.get_type::<InsufficientScopeError>(),
+    )?;
+
+    Ok(())
+}
@@ -0,0 +1,38 @@
+use std::io::IsTerminal;
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+
+use crate::{
+    api,
+    cli::Args,
+    link::{Kind, Link},
+    profile::Profile,
+};
+
+/// Fetches the object `link` points to and prints it as returned by Spotify.
+pub async fn run(args: &Args, link: &Link) -> Result<()> {
+    let profile = Profile::new(&args.profile)?;
+    let client = crate::cli::build_client(&profile, args).await?;
+
+    let mut url = format!("{}/{}s/{}", api::BASE_URL, link.kind.name(), link.id);
+    // artists are the same in every market
+    if link.kind != Kind::Artist {
+        url.push_str("?market=");
+        url.push_str(args.api.market.as_deref().unwrap_or("from_token"));
+    }
+
+    let object: Value = client
+        .get_json(&url)
+        .await
+        .with_context(|| format!("Failed to fetch {link}"))?;
+
+    let json = if args.pretty || std::io::stdout().is_terminal() {
+        serde_json::to_string_pretty(&object)?
+    } else {
+        serde_json::to_string(&object)?
+    };
+    println!("{json}");
+
+    Ok(())
+}
@@ -0,0 +1,151 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use serde_json::{json, Map, Value};
+
+use crate::{cli::Args, manifest, output};
+
+/// Prints a JSON Schema of the JSON backups written by this version. Every field a track can be
+/// written with is described, with those picked by `--fields` being required.
+pub fn print(args: &Args) -> Result<()> {
+    anyhow::ensure!(
+        args.format == output::Format::Json,
+        "Only JSON backups have a schema, pass --format json"
+    );
+
+    let fields = args.fields();
+
+    let properties: Map<String, Value> = output::Field::value_variants()
+        .iter()
+        .map(|field| {
+            let mut schema = schema(*field);
+            let help = field
+                .to_possible_value()
+                .and_then(|v| v.get_help().cloned());
+            if let (Some(schema), Some(help)) = (schema.as_object_mut(), help) {
+                schema.insert("description".to_string(), help.to_string().into());
+            }
+            (field.name().to_string(), schema)
+        })
+        .collect();
+
+    let tracks = |required: Vec<output::Field>| {
+        json!({
+            "type": "array",
+            "items": {
+                "type": "object",
+                "properties": properties,
+                "required": required.iter().map(|v| v.name()).collect::<Vec<_>>(),
+                "additionalProperties": false,
+            },
+        })
+    };
+
+    let (key, items) = match args.group_by {
+        // albums are written once, with each of their tracks numbered instead
+        Some(output::GroupBy::Album) => {
+            let mut required = vec![output::Field::DiscNumber, output::Field::TrackNumber];
+            required.extend(fields.iter().filter(|v| {
+                !matches!(
+                    v,
+                    output::Field::Album | output::Field::DiscNumber | output::Field::TrackNumber
+                )
+            }));
+
+            let albums = json!({
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "name": { "type": "string" },
+                        "art": { "type": "string", "description": "URL of the album's cover art, empty if it has none" },
+                        "uri": { "type": ["string", "null"], "description": "Spotify URI, missing for local files" },
+                        "release_date": { "type": ["string", "null"] },
+                        "tracks": tracks(required),
+                    },
+                    "required": ["name", "art", "uri", "release_date", "tracks"],
+                    "additionalProperties": false,
+                },
+            });
+            ("albums", albums)
+        }
+        None => ("tracks", tracks(fields)),
+    };
+
+    let schema = json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "spotify-backup tracks",
+        "description": format!(
+            "Tracks of a playlist or liked songs, as backed up by spotify-backup {} (schema version {})",
+            env!("CARGO_PKG_VERSION"),
+            manifest::SCHEMA_VERSION,
+        ),
+        "type": "object",
+        "properties": {
+            "schema_version": { "const": manifest::SCHEMA_VERSION },
+            key: items,
+            "contributors": {
+                "type": "array",
+                "description": "How many tracks each person added to the playlist, most first, written with the added_by or added_by_name fields",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "id": { "type": "string" },
+                        "name": { "type": ["string", "null"] },
+                        "tracks": { "type": "integer", "minimum": 1 },
+                    },
+                    "required": ["id", "name", "tracks"],
+                    "additionalProperties": false,
+                },
+            },
+        },
+        "required": ["schema_version", key],
+        "additionalProperties": false,
+    });
+
+    println!("{}", serde_json::to_string_pretty(&schema)?);
+
+    Ok(())
+}
+
+/// Schema of the value `field` is written with.
+fn schema(field: output::Field) -> Value {
+    use output::Field;
+
+    let nullable = |kind: &str| json!({ "type": [kind, "null"] });
+
+    match field {
+        Field::Album => json!({
+            "type": "object",
+            "properties": {
+                "art": { "type": "string", "description": "URL of the album's cover art, empty if it has none" },
+                "name": { "type": "string" },
+            },
+            "required": ["art", "name"],
+            "additionalProperties": false,
+        }),
+        Field::Name => json!({ "type": "string" }),
+        Field::Artists => json!({ "type": "array", "items": { "type": "string" } }),
+        Field::Uri => json!({ "type": "string", "pattern": "^spotify:(track|episode|local):" }),
+        Field::AddedAt => json!({ "type": ["string", "null"], "format": "date-time" }),
+        Field::DurationMs => nullable("integer"),
+        Field::DiscNumber | Field::TrackNumber => {
+            json!({ "type": ["integer", "null"], "minimum": 1 })
+        }
+        Field::Popularity => json!({ "type": ["integer", "null"], "minimum": 0, "maximum": 100 }),
+        Field::Explicit | Field::Playable => nullable("boolean"),
+        Field::Id
+        | Field::AddedBy
+        | Field::AddedByName
+        | Field::Isrc
+        | Field::ReleaseDate
+        | Field::Lyrics
+        | Field::Note => nullable("string"),
+        Field::RecordingMbid | Field::ReleaseMbid => {
+            json!({ "type": ["string", "null"], "format": "uuid" })
+        }
+        Field::Genres | Field::Tags => {
+            json!({ "type": ["array", "null"], "items": { "type": "string" } })
+        }
+        Field::Rating => json!({ "type": ["integer", "null"], "minimum": 1, "maximum": 5 }),
+    }
+}
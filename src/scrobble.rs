@@ -0,0 +1,276 @@
+use std::{io::ErrorKind, path::Path, time::Duration};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::{io::AsyncWriteExt, time::MissedTickBehavior};
+use tracing::{debug, info, warn};
+
+use crate::{api, cli::Args, profile::Profile, GetPlaylistTracksResponseItemTrack};
+
+/// Most plays Spotify returns from the listening history, which is also as far back as it goes.
+const MAX_RECENTLY_PLAYED: usize = 50;
+
+/// A line of the play log.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Play {
+    /// When the track finished playing, or when the episode started
+    pub played_at: DateTime<Utc>,
+    /// Empty for plays imported from the account data, which has no URIs
+    pub uri: String,
+    pub name: String,
+    #[serde(default)]
+    pub artists: Vec<String>,
+    /// Album of the track, or show of the episode
+    pub album: Option<String>,
+    pub duration_ms: Option<u64>,
+    /// How long it was played for, only known for plays imported from the privacy data download
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ms_played: Option<u64>,
+    /// URI of the playlist, album or artist the track was played from
+    pub context: Option<String>,
+}
+
+/// What's already in the log, so plays aren't logged twice.
+#[derive(Default)]
+struct Logged {
+    /// When the most recently played track in the log was played
+    last_track: Option<DateTime<Utc>>,
+    /// URI of the last episode in the log
+    last_episode: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RecentlyPlayed {
+    items: Vec<RecentlyPlayedItem>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RecentlyPlayedItem {
+    track: GetPlaylistTracksResponseItemTrack,
+    played_at: DateTime<Utc>,
+    context: Option<PlayContext>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PlayContext {
+    uri: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct CurrentlyPlaying {
+    #[serde(default)]
+    is_playing: bool,
+    progress_ms: Option<u64>,
+    currently_playing_type: String,
+    item: Option<Episode>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Episode {
+    uri: String,
+    name: String,
+    duration_ms: Option<u64>,
+    show: Option<Show>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Show {
+    name: String,
+    publisher: Option<String>,
+}
+
+/// Appends each play in the user's listening history to `path` as JSON Lines every `interval`
+/// until the process is stopped. Spotify only keeps the last 50 plays, so anything played between
+/// two checks beyond that is missed. Podcast episodes aren't in the listening history, with
+/// `currently_playing` the episode playing at each check is logged too.
+pub async fn run(
+    args: &Args,
+    path: &Path,
+    interval: Duration,
+    currently_playing: bool,
+) -> Result<()> {
+    anyhow::ensure!(
+        !args.api.offline,
+        "Plays can't be logged with --offline, they're only ever fetched from Spotify"
+    );
+
+    let mut logged = read_log(path).await?;
+    info!("Logging plays to {}", path.display());
+
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        ticker.tick().await;
+
+        // failures are logged, and tried again at the next interval
+        if let Err(e) = poll(args, path, &mut logged, currently_playing).await {
+            warn!("Failed to log plays: {e:#}");
+        }
+    }
+}
+
+/// Appends any plays made since the last check to the log.
+async fn poll(
+    args: &Args,
+    path: &Path,
+    logged: &mut Logged,
+    currently_playing: bool,
+) -> Result<()> {
+    // built for each check so the token is refreshed when it expires
+    let profile = Profile::new(&args.profile)?;
+    let client = crate::cli::build_client(&profile, args).await?;
+
+    let mut plays: Vec<_> = recently_played(&client)
+        .await?
+        .into_iter()
+        .filter(|v| logged.last_track.is_none_or(|last| v.played_at > last))
+        .collect();
+
+    if logged.last_track.is_some() && plays.len() == MAX_RECENTLY_PLAYED {
+        warn!(
+            "Every play in the listening history is new, some played since the last check may \
+             have been missed, check more often with --interval"
+        );
+    }
+    let last_track = plays.last().map(|v| v.played_at).or(logged.last_track);
+    let mut last_episode = logged.last_episode.clone();
+
+    if currently_playing {
+        let playing: Option<CurrentlyPlaying> = client
+            .get_json_uncached(&format!(
+                "{}/me/player/currently-playing?additional_types=episode",
+                api::BASE_URL
+            ))
+            .await?;
+
+        // listening to the same episode across several checks, or sittings, counts as one play
+        let episode = playing
+            .filter(|v| v.is_playing && v.currently_playing_type == "episode")
+            .and_then(|v| Some((v.item?, v.progress_ms)))
+            .filter(|(v, _)| last_episode.as_ref() != Some(&v.uri));
+
+        if let Some((episode, progress_ms)) = episode {
+            last_episode = Some(episode.uri.clone());
+            let progress = chrono::Duration::milliseconds(progress_ms.unwrap_or_default() as i64);
+
+            plays.push(Play {
+                played_at: Utc::now() - progress,
+                artists: episode
+                    .show
+                    .as_ref()
+                    .and_then(|v| v.publisher.clone())
+                    .into_iter()
+                    .collect(),
+                album: episode.show.map(|v| v.name),
+                duration_ms: episode.duration_ms,
+                ms_played: None,
+                context: None,
+                uri: episode.uri,
+                name: episode.name,
+            });
+        }
+    }
+
+    if plays.is_empty() {
+        debug!("Nothing played since the last check");
+        return Ok(());
+    }
+
+    let mut data = Vec::new();
+    for play in &plays {
+        serde_json::to_writer(&mut data, play).context("Failed to serialize play")?;
+        data.push(b'\n');
+    }
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    file.write_all(&data)
+        .await
+        .with_context(|| format!("Failed to write to {}", path.display()))?;
+    file.sync_all()
+        .await
+        .with_context(|| format!("Failed to write to {}", path.display()))?;
+
+    // only once they're written, so plays that failed to be are tried again at the next check
+    logged.last_track = last_track;
+    logged.last_episode = last_episode;
+    info!("Logged {} play(s)", plays.len());
+
+    Ok(())
+}
+
+/// Plays in the user's listening history, oldest first.
+pub async fn recently_played(client: &api::Client) -> Result<Vec<Play>> {
+    let recent: RecentlyPlayed = client
+        .get_json_uncached(&format!(
+            "{}/me/player/recently-played?limit={MAX_RECENTLY_PLAYED}",
+            api::BASE_URL
+        ))
+        .await?
+        .context("Got an empty listening history")?;
+
+    let mut plays: Vec<_> = recent
+        .items
+        .into_iter()
+        .map(|v| Play {
+            played_at: v.played_at,
+            artists: v.track.artists.into_iter().map(|v| v.name).collect(),
+            album: Some(v.track.album.name),
+            duration_ms: v.track.duration_ms,
+            ms_played: None,
+            context: v.context.map(|v| v.uri),
+            uri: v.track.uri,
+            name: v.track.name,
+        })
+        .collect();
+    plays.sort_by_key(|v| v.played_at);
+
+    Ok(plays)
+}
+
+/// Every play logged to `path`, in the order they were logged.
+pub async fn read_plays(path: &Path) -> Result<Vec<Play>> {
+    let data = tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+
+    parse_plays(&data, path)
+}
+
+fn parse_plays(data: &str, path: &Path) -> Result<Vec<Play>> {
+    data.lines()
+        .enumerate()
+        .filter(|(_, v)| !v.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("Failed to parse line {} of {}", i + 1, path.display()))
+        })
+        .collect()
+}
+
+/// Reads what's already been logged to `path`, if anything.
+async fn read_log(path: &Path) -> Result<Logged> {
+    let data = match tokio::fs::read_to_string(path).await {
+        Ok(v) => v,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Logged::default()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+
+    let mut logged = Logged::default();
+
+    for play in parse_plays(&data, path)? {
+        if play.uri.starts_with("spotify:episode:") {
+            logged.last_episode = Some(play.uri);
+        } else {
+            logged.last_track = logged.last_track.max(Some(play.played_at));
+        }
+    }
+
+    Ok(logged)
+}
@@ -0,0 +1,217 @@
+use std::io::IsTerminal;
+
+use anyhow::Result;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::{api, cli::Args, output, profile::Profile, GetPlaylistTracksResponseItemTrack};
+
+/// What to search Spotify for.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Kind {
+    #[default]
+    Track,
+    Album,
+    Artist,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Track => "track",
+            Self::Album => "album",
+            Self::Artist => "artist",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchResponse {
+    tracks: Option<SearchResponsePage>,
+    albums: Option<SearchResponsePage>,
+    artists: Option<SearchResponsePage>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchResponsePage {
+    // Spotify sometimes pads results with nulls
+    items: Vec<Option<SearchResponseItem>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchResponseItem {
+    name: String,
+    uri: String,
+    #[serde(default)]
+    artists: Vec<SearchResponseArtist>,
+    /// Only for tracks
+    album: Option<SearchResponseAlbum>,
+    /// Only for albums
+    release_date: Option<String>,
+    /// Only for artists
+    #[serde(default)]
+    genres: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchResponseArtist {
+    name: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchResponseAlbum {
+    name: String,
+    release_date: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct SearchResult {
+    name: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    artists: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    album: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    release_date: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    genres: Vec<String>,
+    uri: String,
+}
+
+/// Prints up to `limit` of Spotify's results for `query` with their URIs. A track query written
+/// as "artist - title" is searched for as that artist and title, rather than as free text.
+pub async fn run(args: &Args, query: &str, kind: Kind, limit: u32) -> Result<()> {
+    let profile = Profile::new(&args.profile)?;
+    let client = crate::cli::build_client(&profile, args).await?;
+
+    let query = match (kind, query.split_once(" - ")) {
+        (Kind::Track, Some((artist, title))) => {
+            format!("artist:\"{}\" track:\"{}\"", artist.trim(), title.trim())
+        }
+        _ => query.to_string(),
+    };
+
+    let response: SearchResponse = client
+        .get_json(url(args, &query, kind, limit)?.as_str())
+        .await?;
+    let page = match kind {
+        Kind::Track => response.tracks,
+        Kind::Album => response.albums,
+        Kind::Artist => response.artists,
+    };
+
+    let results: Vec<SearchResult> = page
+        .into_iter()
+        .flat_map(|v| v.items)
+        .flatten()
+        .map(|v| SearchResult {
+            name: v.name,
+            artists: v.artists.into_iter().map(|v| v.name).collect(),
+            release_date: v
+                .release_date
+                .or_else(|| v.album.as_ref().and_then(|v| v.release_date.clone())),
+            album: v.album.map(|v| v.name),
+            genres: v.genres,
+            uri: v.uri,
+        })
+        .collect();
+
+    print(args, kind, &results)
+}
+
+/// Searches for tracks matching `query`, which may use Spotify's field filters (eg. `isrc:` or
+/// `artist:`), returning up to `limit` of them.
+pub async fn tracks(
+    client: &api::Client,
+    args: &Args,
+    query: &str,
+    limit: u32,
+) -> Result<Vec<GetPlaylistTracksResponseItemTrack>> {
+    #[derive(Deserialize, Debug)]
+    struct Response {
+        tracks: Page,
+    }
+
+    #[derive(Deserialize, Debug)]
+    struct Page {
+        items: Vec<Option<GetPlaylistTracksResponseItemTrack>>,
+    }
+
+    let response: Response = client
+        .get_json(url(args, query, Kind::Track, limit)?.as_str())
+        .await?;
+
+    Ok(response.tracks.items.into_iter().flatten().collect())
+}
+
+/// URL searching for `kind` in the market given by `--market`.
+fn url(args: &Args, query: &str, kind: Kind, limit: u32) -> Result<Url> {
+    let mut url = Url::parse(&format!("{}/search", api::BASE_URL))?;
+    url.query_pairs_mut()
+        .append_pair("q", query)
+        .append_pair("type", kind.name())
+        .append_pair("limit", &limit.to_string())
+        .append_pair("market", args.api.market.as_deref().unwrap_or("from_token"));
+
+    Ok(url)
+}
+
+fn print(args: &Args, kind: Kind, results: &[SearchResult]) -> Result<()> {
+    let terminal = std::io::stdout().is_terminal();
+
+    match args.format {
+        output::Format::Json => {
+            let json = if args.pretty || terminal {
+                serde_json::to_string_pretty(results)?
+            } else {
+                serde_json::to_string(results)?
+            };
+            println!("{json}");
+        }
+        output::Format::Table => {
+            let mut table = match kind {
+                Kind::Track => output::table(
+                    &["Track", "Artists", "Album", "Released", "URI"],
+                    terminal,
+                    &[3, 4],
+                ),
+                Kind::Album => {
+                    output::table(&["Album", "Artists", "Released", "URI"], terminal, &[2, 3])
+                }
+                Kind::Artist => output::table(&["Artist", "Genres", "URI"], terminal, &[2]),
+            };
+
+            for result in results {
+                let artists = result.artists.join(", ");
+                let released = result.release_date.as_deref().unwrap_or_default();
+
+                match kind {
+                    Kind::Track => output::add_row(
+                        &mut table,
+                        [
+                            result.name.as_str(),
+                            &artists,
+                            result.album.as_deref().unwrap_or_default(),
+                            released,
+                            &result.uri,
+                        ],
+                    ),
+                    Kind::Album => output::add_row(
+                        &mut table,
+                        [result.name.as_str(), &artists, released, &result.uri],
+                    ),
+                    Kind::Artist => output::add_row(
+                        &mut table,
+                        [result.name.as_str(), &result.genres.join(", "), &result.uri],
+                    ),
+                }
+            }
+            println!("{table}");
+        }
+        _ => {
+            anyhow::bail!("Search results can only be printed as JSON or a table")
+        }
+    }
+
+    Ok(())
+}
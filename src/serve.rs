@@ -0,0 +1,514 @@
+use std::{
+    collections::VecDeque,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::{
+    body::{Bytes, Incoming},
+    header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE, HOST, ORIGIN},
+    server::conn::http1,
+    service::service_fn,
+    Method, Request, Response, StatusCode,
+};
+use hyper_util::rt::TokioIo;
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::{
+    net::TcpListener,
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+};
+use tracing::{info, warn};
+
+use crate::{cli::Args, daemon, link, manifest, Error};
+
+/// Most runs kept around for their status to be queried, older ones are forgotten.
+const MAX_RUNS: usize = 100;
+
+/// Largest request body accepted.
+const MAX_BODY: usize = 64 * 1024;
+
+/// Name runs triggered through the API are reported under in metrics.
+const METRICS_JOB: &str = "serve";
+
+/// Page of the web UI, which does everything through the API from the browser.
+const WEB_UI: &str = include_str!("../assets/web-ui.html");
+
+/// Characters left as they are in an RFC 5987 `filename*`, every other byte is percent-encoded.
+const FILENAME: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!')
+    .remove(b'#')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'+')
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'^')
+    .remove(b'_')
+    .remove(b'`')
+    .remove(b'|')
+    .remove(b'~');
+
+/// A backup triggered through the API.
+#[derive(Serialize, Clone, Debug)]
+struct Run {
+    id: u64,
+    status: Status,
+    liked: bool,
+    all: bool,
+    playlists: Vec<String>,
+    queued_at: DateTime<Utc>,
+    started_at: Option<DateTime<Utc>>,
+    finished_at: Option<DateTime<Utc>>,
+    error: Option<String>,
+}
+
+#[derive(Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+/// Body of a request to trigger a backup, backing up the same things as the daemon's options.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct RunRequest {
+    #[serde(default)]
+    liked: bool,
+    #[serde(default)]
+    all: bool,
+    #[serde(default)]
+    playlists: Vec<String>,
+}
+
+struct State {
+    args: Args,
+    /// Output directory backups are written to and served from
+    dir: PathBuf,
+    token: Option<String>,
+    web_ui: bool,
+    runs: Mutex<VecDeque<Run>>,
+    next_id: AtomicU64,
+    queue: UnboundedSender<(u64, daemon::Backups)>,
+}
+
+impl State {
+    fn update(&self, id: u64, f: impl FnOnce(&mut Run)) {
+        let mut runs = self.runs.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(run) = runs.iter_mut().find(|v| v.id == id) {
+            f(run);
+        }
+    }
+}
+
+/// Serves the REST API (and the web UI with `web_ui`) on `addr` until the process is stopped,
+/// running the backups it's asked for one at a time.
+pub async fn run(args: &Args, addr: SocketAddr, token: Option<String>, web_ui: bool) -> Result<()> {
+    // nobody is there to finish logging in when a request needs a token that isn't stored, so
+    // it fails instead of waiting on a browser
+    let mut args = args.clone();
+    args.auth.non_interactive = true;
+
+    let dir = args
+        .output
+        .clone()
+        .context("The API serves backups from the output directory, pass --output")?;
+
+    let (queue, queued) = mpsc::unbounded_channel();
+    let state = Arc::new(State {
+        args: args.clone(),
+        dir,
+        token,
+        web_ui,
+        runs: Mutex::new(VecDeque::new()),
+        next_id: AtomicU64::new(1),
+        queue,
+    });
+
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to listen on {addr}"))?;
+    match web_ui {
+        true => info!("Serving API and web UI at http://{addr}/"),
+        false => info!("Serving API at http://{addr}/"),
+    }
+
+    tokio::try_join!(accept(listener, state.clone()), work(&args, &state, queued))?;
+
+    Ok(())
+}
+
+async fn accept(listener: TcpListener, state: Arc<State>) -> Result<()> {
+    loop {
+        let (stream, _) = listener
+            .accept()
+            .await
+            .context("Failed to accept TCP connection")?;
+        let state = state.clone();
+
+        tokio::spawn(async move {
+            let service = service_fn(|req| {
+                let state = state.clone();
+                async move {
+                    Ok::<_, anyhow::Error>(handle(&state, req).await.unwrap_or_else(|e| {
+                        warn!("Failed to handle API request: {e:#}");
+                        error(StatusCode::INTERNAL_SERVER_ERROR, &format!("{e:#}"))
+                    }))
+                }
+            });
+
+            if let Err(e) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                warn!("Failed to serve API request: {e}");
+            }
+        });
+    }
+}
+
+/// Runs queued backups one after another, as only one backup can use the profile at once.
+async fn work(
+    args: &Args,
+    state: &State,
+    mut queued: UnboundedReceiver<(u64, daemon::Backups)>,
+) -> Result<()> {
+    while let Some((id, backups)) = queued.recv().await {
+        state.update(id, |run| {
+            run.status = Status::Running;
+            run.started_at = Some(Utc::now());
+        });
+
+        let result = daemon::run_logged(args, METRICS_JOB, &backups).await;
+
+        state.update(id, |run| {
+            run.finished_at = Some(Utc::now());
+            match result {
+                Ok(()) => run.status = Status::Succeeded,
+                Err(e) => {
+                    run.status = Status::Failed;
+                    run.error = Some(format!("{e:#}"));
+                }
+            }
+        });
+    }
+
+    Ok(())
+}
+
+async fn handle(state: &State, req: Request<Incoming>) -> Result<Response<Full<Bytes>>> {
+    // the page holds no data of its own, it asks for the token before calling the API
+    if state.web_ui && req.method() == Method::GET && req.uri().path() == "/" {
+        return Ok(Response::builder()
+            .header(CONTENT_TYPE, "text/html; charset=utf-8")
+            .body(Full::from(WEB_UI))?);
+    }
+
+    if !authorized(state, &req) {
+        return Ok(error(
+            StatusCode::UNAUTHORIZED,
+            "Missing or incorrect bearer token",
+        ));
+    }
+
+    // without a token any page open in the browser could otherwise queue backups, since a form
+    // posted to localhost needs no permission. Browsers always say where such requests are from
+    if state.token.is_none() && req.method() != Method::GET && !same_origin(req.headers()) {
+        return Ok(error(
+            StatusCode::FORBIDDEN,
+            "Cross-origin requests need --api-token to be set",
+        ));
+    }
+
+    let path = req.uri().path().trim_matches('/').to_string();
+    let segments: Vec<_> = path.split('/').collect();
+
+    match (req.method(), segments.as_slice()) {
+        (&Method::GET, ["backups"]) => {
+            let runs = state.runs.lock().unwrap_or_else(PoisonError::into_inner);
+            json(StatusCode::OK, &*runs)
+        }
+        (&Method::POST, ["backups"]) => trigger(state, req).await,
+        (&Method::GET, ["backups", id]) => {
+            let runs = state.runs.lock().unwrap_or_else(PoisonError::into_inner);
+            match runs.iter().find(|v| Some(v.id) == id.parse().ok()) {
+                Some(run) => json(StatusCode::OK, run),
+                None => Ok(error(StatusCode::NOT_FOUND, "No such backup")),
+            }
+        }
+        (&Method::GET, ["profiles"]) => {
+            let profiles: Vec<_> = crate::profile::list()
+                .await?
+                .into_iter()
+                .map(|name| {
+                    let active = name == state.args.profile;
+                    serde_json::json!({ "name": name, "active": active })
+                })
+                .collect();
+            json(StatusCode::OK, &profiles)
+        }
+        (&Method::GET, ["playlists"]) => {
+            let (user_id, playlists) = match crate::cli::fetch_playlists(&state.args).await {
+                Ok(v) => v,
+                Err(e) if matches!(e.downcast_ref(), Some(Error::Auth(_))) => {
+                    return Ok(error(
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        "Not authenticated with Spotify, log in by running \
+                         `spotify-backup playlists` in a terminal",
+                    ))
+                }
+                Err(e) => return Err(e),
+            };
+            let playlists: Vec<_> = playlists
+                .iter()
+                .map(|v| crate::OutputPlaylist::for_user(v, &user_id))
+                .collect();
+            json(StatusCode::OK, &playlists)
+        }
+        (&Method::GET, ["snapshots"]) => {
+            let files = manifest::read(&state.dir)
+                .await?
+                .map(|v| v.files)
+                .unwrap_or_default();
+            json(StatusCode::OK, &files)
+        }
+        (&Method::GET, ["snapshots", name]) => download(&state.dir, |v| v.name == *name).await,
+        (&Method::GET, ["playlists", id, "latest"]) => {
+            let key = format!("playlist-{id}");
+            download(&state.dir, |v| crate::last_run::key(&v.name) == key).await
+        }
+        (&Method::GET, ["liked", "latest"]) => {
+            download(&state.dir, |v| crate::last_run::key(&v.name) == "liked").await
+        }
+        (&Method::GET, ["metrics"]) => Ok(Response::builder()
+            .header(CONTENT_TYPE, crate::metrics::CONTENT_TYPE)
+            .body(Full::from(crate::metrics::render()))?),
+        _ => Ok(error(StatusCode::NOT_FOUND, "Not found")),
+    }
+}
+
+/// Queues the backup asked for in the body of `req`.
+async fn trigger(state: &State, req: Request<Incoming>) -> Result<Response<Full<Bytes>>> {
+    let body = match Limited::new(req.into_body(), MAX_BODY).collect().await {
+        Ok(v) => v.to_bytes(),
+        Err(e) => return Ok(error(StatusCode::BAD_REQUEST, &e.to_string())),
+    };
+
+    let request: RunRequest = match serde_json::from_slice(&body) {
+        Ok(v) => v,
+        Err(e) => {
+            return Ok(error(
+                StatusCode::BAD_REQUEST,
+                &format!("Invalid body: {e}"),
+            ))
+        }
+    };
+    if !request.liked && !request.all && request.playlists.is_empty() {
+        return Ok(error(
+            StatusCode::BAD_REQUEST,
+            "Nothing to back up, set liked, all or playlists",
+        ));
+    }
+
+    let playlists = match request
+        .playlists
+        .iter()
+        .map(|v| link::parse_playlist_id(v))
+        .collect::<Result<Vec<_>>>()
+    {
+        Ok(v) => v,
+        Err(e) => {
+            return Ok(error(
+                StatusCode::BAD_REQUEST,
+                &format!("Invalid playlist: {e}"),
+            ))
+        }
+    };
+
+    let run = Run {
+        id: state.next_id.fetch_add(1, Ordering::Relaxed),
+        status: Status::Queued,
+        liked: request.liked,
+        all: request.all,
+        playlists: playlists.clone(),
+        queued_at: Utc::now(),
+        started_at: None,
+        finished_at: None,
+        error: None,
+    };
+
+    {
+        let mut runs = state.runs.lock().unwrap_or_else(PoisonError::into_inner);
+        runs.push_back(run.clone());
+        while runs.len() > MAX_RUNS {
+            runs.pop_front();
+        }
+    }
+
+    let backups = daemon::Backups {
+        liked: request.liked,
+        all: request.all,
+        ids: playlists,
+    };
+    state
+        .queue
+        .send((run.id, backups))
+        .context("Backups are no longer being run")?;
+
+    json(StatusCode::ACCEPTED, &run)
+}
+
+/// Responds with the most recently written file in the manifest of `dir` matching `filter`. Only
+/// files listed in the manifest are served, so nothing else in the directory can be reached.
+async fn download(
+    dir: &Path,
+    filter: impl Fn(&manifest::ManifestFile) -> bool,
+) -> Result<Response<Full<Bytes>>> {
+    let file = manifest::read(dir)
+        .await?
+        .into_iter()
+        .flat_map(|v| v.files)
+        .filter(|v| filter(v))
+        .max_by(|a, b| a.created_at.cmp(&b.created_at));
+    let Some(file) = file else {
+        return Ok(error(StatusCode::NOT_FOUND, "No such backup file"));
+    };
+
+    let data = tokio::fs::read(dir.join(&file.name))
+        .await
+        .with_context(|| format!("Failed to read {}", file.name))?;
+    let content_type = match Path::new(&file.name).extension().and_then(|v| v.to_str()) {
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    };
+
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, content_type)
+        .header("content-disposition", content_disposition(&file.name))
+        .body(Full::from(data))?)
+}
+
+/// Content-Disposition downloading a file as `name`. Clients not understanding `filename*` get
+/// the name with anything but printable ASCII replaced, so it can't break out of the quotes.
+fn content_disposition(name: &str) -> String {
+    let fallback: String = name
+        .chars()
+        .map(|v| match v {
+            ' '..='~' if v != '"' && v != '\\' => v,
+            _ => '_',
+        })
+        .collect();
+    let encoded = percent_encoding::utf8_percent_encode(name, FILENAME);
+
+    format!("attachment; filename=\"{fallback}\"; filename*=UTF-8''{encoded}")
+}
+
+/// Whether the request with `headers` comes from a page served by us, or from something other
+/// than a browser (which doesn't send an Origin).
+fn same_origin(headers: &HeaderMap) -> bool {
+    let Some(origin) = headers.get(ORIGIN) else {
+        return true;
+    };
+
+    let origin = origin
+        .to_str()
+        .ok()
+        .and_then(|v| v.split_once("://"))
+        .map(|v| v.1);
+    let host = headers.get(HOST).and_then(|v| v.to_str().ok());
+
+    origin.is_some() && origin == host
+}
+
+/// Whether `req` carries the bearer token, if one is needed. The tokens are compared by hash so
+/// how long the comparison takes doesn't give away how much of the token was right.
+fn authorized(state: &State, req: &Request<Incoming>) -> bool {
+    let Some(token) = &state.token else {
+        return true;
+    };
+
+    let given = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or_default();
+
+    Sha256::digest(given) == Sha256::digest(token)
+}
+
+fn json(status: StatusCode, value: &impl Serialize) -> Result<Response<Full<Bytes>>> {
+    let body = serde_json::to_vec(value).context("Failed to serialize response")?;
+
+    Ok(Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Full::from(body))?)
+}
+
+fn error(status: StatusCode, message: &str) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::from(
+        serde_json::json!({ "error": message }).to_string(),
+    ));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_download_names() {
+        assert_eq!(
+            content_disposition("liked.json"),
+            "attachment; filename=\"liked.json\"; filename*=UTF-8''liked.json"
+        );
+        assert_eq!(
+            content_disposition("a \"b\"\\c.json"),
+            "attachment; filename=\"a _b__c.json\"; filename*=UTF-8''a%20%22b%22%5Cc.json"
+        );
+        assert_eq!(
+            content_disposition("Café\r\n.csv"),
+            "attachment; filename=\"Caf___.csv\"; filename*=UTF-8''Caf%C3%A9%0D%0A.csv"
+        );
+        assert!(HeaderValue::from_str(&content_disposition("é\"\n")).is_ok());
+    }
+
+    #[test]
+    fn allows_same_origin_requests_only() {
+        let headers = |origin: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            headers.insert(HOST, HeaderValue::from_static("localhost:8080"));
+            if let Some(origin) = origin {
+                headers.insert(ORIGIN, HeaderValue::from_str(origin).unwrap());
+            }
+            headers
+        };
+
+        assert!(same_origin(&headers(None)));
+        assert!(same_origin(&headers(Some("http://localhost:8080"))));
+        assert!(!same_origin(&headers(Some("http://localhost:8081"))));
+        assert!(!same_origin(&headers(Some("https://example.com"))));
+        assert!(!same_origin(&headers(Some("null"))));
+
+        let mut no_host = headers(Some("http://localhost:8080"));
+        no_host.remove(HOST);
+        assert!(!same_origin(&no_host));
+    }
+}
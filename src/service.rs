@@ -0,0 +1,250 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use chrono::{NaiveTime, Timelike};
+use clap::ValueEnum;
+use tracing::info;
+
+use crate::cli::Args;
+
+/// Service managers units can be generated for.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Manager {
+    /// A systemd user service and timer
+    Systemd,
+    /// A launchd agent, for macOS
+    Launchd,
+}
+
+impl Manager {
+    /// The service manager of the platform the tool is running on.
+    pub fn native() -> Self {
+        if cfg!(target_os = "macos") {
+            Self::Launchd
+        } else {
+            Self::Systemd
+        }
+    }
+}
+
+/// Parses the time of day backups run at (eg. 03:00).
+pub fn parse_time(value: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(value, "%H:%M").context("Expected a time of day (eg. 03:00)")
+}
+
+/// A generated unit file and where it's installed.
+struct Unit {
+    path: PathBuf,
+    contents: String,
+}
+
+/// Prints the units running `command` every day at `at` with the current profile, config file and
+/// state dir, or writes them into place and enables them with `install`.
+pub async fn run(
+    args: &Args,
+    manager: Manager,
+    at: NaiveTime,
+    install: bool,
+    command: &[String],
+) -> Result<()> {
+    let mut program = vec![
+        std::env::current_exe()
+            .context("Failed to find the path of the executable")?
+            .to_string_lossy()
+            .into_owned(),
+        "--profile".to_string(),
+        args.profile.clone(),
+    ];
+
+    if let Some(config) = &args.config {
+        let config = std::path::absolute(config)
+            .with_context(|| format!("Failed to resolve {}", config.display()))?;
+        program.extend([
+            "--config".to_string(),
+            config.to_string_lossy().into_owned(),
+        ]);
+    }
+
+    if let Some(dir) = &args.state_dir {
+        let dir = std::path::absolute(dir)
+            .with_context(|| format!("Failed to resolve {}", dir.display()))?;
+        program.extend([
+            "--state-dir".to_string(),
+            dir.to_string_lossy().into_owned(),
+        ]);
+    }
+
+    program.extend_from_slice(command);
+
+    let name = format!("spotify-backup-{}", args.profile);
+    let units = match manager {
+        Manager::Systemd => systemd(&name, &args.profile, &program, at)?,
+        Manager::Launchd => vec![launchd(&name, &program, at)?],
+    };
+
+    if !install {
+        for unit in &units {
+            println!("# {}\n{}", unit.path.display(), unit.contents);
+        }
+
+        return Ok(());
+    }
+
+    for unit in &units {
+        if let Some(parent) = unit.path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+
+        crate::atomic::write(&unit.path, &unit.contents).await?;
+        info!("Wrote {}", unit.path.display());
+    }
+
+    match manager {
+        Manager::Systemd => {
+            command_status("systemctl", &["--user", "daemon-reload"]).await?;
+            command_status(
+                "systemctl",
+                &["--user", "enable", "--now", &format!("{name}.timer")],
+            )
+            .await?;
+            info!("Enabled {name}.timer, check on it with systemctl --user list-timers");
+        }
+        Manager::Launchd => {
+            let path = units[0].path.to_string_lossy();
+            command_status("launchctl", &["load", "-w", &path]).await?;
+            info!("Loaded {name}");
+        }
+    }
+
+    Ok(())
+}
+
+fn systemd(name: &str, profile: &str, program: &[String], at: NaiveTime) -> Result<Vec<Unit>> {
+    let dir = dirs::config_dir()
+        .context("Unsupported operating system, no config dir")?
+        .join("systemd/user");
+    let exec_start = program
+        .iter()
+        .map(|v| systemd_quote(v))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    Ok(vec![
+        Unit {
+            path: dir.join(format!("{name}.service")),
+            contents: format!(
+                "[Unit]\n\
+                 Description=Back up Spotify (profile {profile})\n\
+                 Wants=network-online.target\n\
+                 After=network-online.target\n\
+                 \n\
+                 [Service]\n\
+                 Type=oneshot\n\
+                 ExecStart={exec_start}\n"
+            ),
+        },
+        Unit {
+            path: dir.join(format!("{name}.timer")),
+            // persistent, so a backup missed while the machine was off runs once it's back on
+            contents: format!(
+                "[Unit]\n\
+                 Description=Back up Spotify every day (profile {profile})\n\
+                 \n\
+                 [Timer]\n\
+                 OnCalendar=*-*-* {}\n\
+                 Persistent=true\n\
+                 \n\
+                 [Install]\n\
+                 WantedBy=timers.target\n",
+                at.format("%H:%M:00")
+            ),
+        },
+    ])
+}
+
+fn launchd(name: &str, program: &[String], at: NaiveTime) -> Result<Unit> {
+    let home = dirs::home_dir().context("Unsupported operating system, no home dir")?;
+    let label = name.replace('-', ".");
+    let log = home.join("Library/Logs").join(format!("{name}.log"));
+    let arguments: String = program
+        .iter()
+        .map(|v| format!("    <string>{}</string>\n", xml_escape(v)))
+        .collect();
+
+    Ok(Unit {
+        path: home
+            .join("Library/LaunchAgents")
+            .join(format!("{label}.plist")),
+        contents: format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+  <key>Label</key>
+  <string>{label}</string>
+  <key>ProgramArguments</key>
+  <array>
+{arguments}  </array>
+  <key>StartCalendarInterval</key>
+  <dict>
+    <key>Hour</key>
+    <integer>{}</integer>
+    <key>Minute</key>
+    <integer>{}</integer>
+  </dict>
+  <key>StandardOutPath</key>
+  <string>{log}</string>
+  <key>StandardErrorPath</key>
+  <string>{log}</string>
+</dict>
+</plist>
+"#,
+            at.hour(),
+            at.minute(),
+            log = xml_escape(&log.to_string_lossy()),
+        ),
+    })
+}
+
+/// Quotes an argument of `ExecStart` if it needs to be.
+fn systemd_quote(arg: &str) -> String {
+    if !arg.is_empty()
+        && !arg
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, '"' | '\'' | '\\' | '%' | '$' | ';'))
+    {
+        return arg.to_string();
+    }
+
+    let escaped = arg
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%")
+        .replace('$', "$$");
+    format!("\"{escaped}\"")
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+async fn command_status(program: &str, args: &[&str]) -> Result<()> {
+    let status = tokio::process::Command::new(program)
+        .args(args)
+        .status()
+        .await
+        .with_context(|| format!("Failed to run {program}"))?;
+
+    anyhow::ensure!(
+        status.success(),
+        "{program} {} failed with {status}",
+        args.join(" ")
+    );
+
+    Ok(())
+}
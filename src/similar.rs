@@ -0,0 +1,128 @@
+use std::{collections::HashSet, io::IsTerminal};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{cli::Args, output, profile::Profile};
+
+/// Two playlists sharing most of their tracks.
+#[derive(Serialize, Debug)]
+struct Pair {
+    a: Playlist,
+    b: Playlist,
+    /// Tracks in both, over the tracks in either (Jaccard similarity), between 0 and 1
+    similarity: f64,
+    /// Tracks in both
+    shared: usize,
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct Playlist {
+    id: Option<String>,
+    name: String,
+    tracks: usize,
+}
+
+/// Compares every pair of the playlists given by `ids`, or every playlist in the library if none
+/// are, and prints those whose tracks are at least `threshold` similar, most similar first.
+pub async fn run(args: &Args, ids: &[String], threshold: f64) -> Result<()> {
+    let profile = Profile::new(&args.profile)?;
+    let client = crate::cli::build_client(&profile, args).await?;
+
+    let playlists: Vec<(Playlist, HashSet<String>)> =
+        crate::cli::fetch_sources(&client, args, ids, ids.is_empty(), false)
+            .await?
+            .into_iter()
+            .map(|v| {
+                let uris: HashSet<_> = v.items.into_iter().map(|v| v.track.uri).collect();
+                let playlist = Playlist {
+                    id: v.id,
+                    name: v.name,
+                    tracks: uris.len(),
+                };
+                (playlist, uris)
+            })
+            .collect();
+
+    let mut pairs = Vec::new();
+    for (i, (a, a_uris)) in playlists.iter().enumerate() {
+        for (b, b_uris) in &playlists[i + 1..] {
+            let shared = a_uris.intersection(b_uris).count();
+            let union = a_uris.len() + b_uris.len() - shared;
+            // two empty playlists aren't copies of anything
+            if union == 0 {
+                continue;
+            }
+
+            let similarity = shared as f64 / union as f64;
+            if similarity >= threshold {
+                pairs.push(Pair {
+                    a: a.clone(),
+                    b: b.clone(),
+                    similarity,
+                    shared,
+                });
+            }
+        }
+    }
+    pairs.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+
+    print(args, &pairs)
+}
+
+fn print(args: &Args, pairs: &[Pair]) -> Result<()> {
+    let terminal = std::io::stdout().is_terminal();
+
+    match args.format {
+        output::Format::Json => {
+            let json = if args.pretty || terminal {
+                serde_json::to_string_pretty(pairs)?
+            } else {
+                serde_json::to_string(pairs)?
+            };
+            println!("{json}");
+        }
+        output::Format::Table => {
+            let mut table = output::table(
+                &[
+                    "Playlist",
+                    "Tracks",
+                    "Playlist",
+                    "Tracks",
+                    "Shared",
+                    "Similarity",
+                ],
+                terminal,
+                &[1, 3, 4, 5],
+            );
+            for pair in pairs {
+                output::add_row(
+                    &mut table,
+                    [
+                        pair.a.name.as_str(),
+                        &pair.a.tracks.to_string(),
+                        &pair.b.name,
+                        &pair.b.tracks.to_string(),
+                        &pair.shared.to_string(),
+                        &format!("{:.0}%", pair.similarity * 100.0),
+                    ],
+                );
+            }
+            println!("{table}");
+        }
+        _ => {
+            anyhow::bail!("Similar playlists can only be listed as JSON or a table")
+        }
+    }
+
+    Ok(())
+}
+
+pub fn parse_threshold(v: &str) -> Result<f64> {
+    let threshold: f64 = v.parse()?;
+    anyhow::ensure!(
+        (0.0..=1.0).contains(&threshold),
+        "Must be between 0 and 1 (eg. 0.8)"
+    );
+    Ok(threshold)
+}
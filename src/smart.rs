@@ -0,0 +1,316 @@
+use std::{
+    collections::HashSet,
+    io::{IsTerminal, Write},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use crate::{api::BASE_URL, cli::Args, manifest, migrate, output, profile::Profile, sync};
+
+/// Most tracks Spotify adds to a playlist in one request.
+const MAX_TRACKS_PER_REQUEST: usize = 100;
+
+/// Operators a rule can compare with, two character ones first so they're found before the one
+/// character ones they start with.
+const OPERATORS: &[(&str, Op)] = &[
+    (">=", Op::Ge),
+    ("<=", Op::Le),
+    ("!=", Op::Ne),
+    ("!~", Op::NotContains),
+    (">", Op::Gt),
+    ("<", Op::Lt),
+    ("=", Op::Eq),
+    ("~", Op::Contains),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Contains,
+    NotContains,
+}
+
+/// A condition tracks have to meet to be in the playlist, given to `--rule` as `field`, an
+/// operator and a value (eg. `energy>0.7` or `genres~house`).
+#[derive(Debug, Clone)]
+pub struct Rule {
+    field: String,
+    op: Op,
+    value: String,
+}
+
+impl FromStr for Rule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let start = s
+            .find(['<', '>', '=', '!', '~'])
+            .context("Expected a field, an operator (=, !=, >, >=, <, <=, ~ or !~) and a value")?;
+        let (symbol, op) = OPERATORS
+            .iter()
+            .find(|(symbol, _)| s[start..].starts_with(symbol))
+            .with_context(|| format!("Unknown operator in {s}"))?;
+
+        let field = s[..start].trim();
+        anyhow::ensure!(!field.is_empty(), "Rule {s} has no field");
+
+        Ok(Self {
+            field: field.to_string(),
+            op: *op,
+            value: s[start + symbol.len()..].trim().to_string(),
+        })
+    }
+}
+
+impl Rule {
+    /// Whether `track` meets the condition. Tracks missing the field never do, so rules on fields
+    /// the backup wasn't written with (eg. genres without `--genres`) match nothing.
+    fn matches(&self, track: &Value) -> bool {
+        match field(track, &self.field) {
+            Some(Value::Array(values)) => {
+                let any = |op| values.iter().any(|v| compare(v, op, &self.value));
+                match self.op {
+                    Op::Ne => !any(Op::Eq),
+                    Op::NotContains => !any(Op::Contains),
+                    op => any(op),
+                }
+            }
+            Some(value) => compare(&value, self.op, &self.value),
+            None => false,
+        }
+    }
+}
+
+/// Value of `name` in `track`. Besides the fields of the backup, audio features can be given
+/// whether or not they're nested under `audio_features`, and there are a few shorthands: `year`
+/// and `added-year` for the years the track was released and added, `duration` for its length in
+/// seconds, and the singular `artist`, `genre` and `added` for the fields they're short for.
+fn field(track: &Value, name: &str) -> Option<Value> {
+    let year = |v: &Value| {
+        let year: u64 = v.as_str()?.get(..4)?.parse().ok()?;
+        Some(Value::from(year))
+    };
+
+    let value = match name {
+        "year" => return year(&track["release_date"]),
+        "added-year" => return year(&track["added_at"]),
+        "duration" => return Some(Value::from(track["duration_ms"].as_u64()? / 1000)),
+        "artist" => &track["artists"],
+        "genre" => &track["genres"],
+        "added" => &track["added_at"],
+        "album" => &track["album"]["name"],
+        _ => match &track[name] {
+            Value::Null => &track["audio_features"][name],
+            value => value,
+        },
+    };
+
+    (!value.is_null()).then(|| value.clone())
+}
+
+/// Compares a single value of a track with the one a rule was given. Numbers are compared as
+/// numbers, and strings ignoring case, so dates compare chronologically (eg. `added>=2021-06`).
+fn compare(value: &Value, op: Op, expected: &str) -> bool {
+    let ordering = match value {
+        Value::Number(v) => {
+            let (Some(v), Ok(expected)) = (v.as_f64(), expected.parse::<f64>()) else {
+                return false;
+            };
+            v.total_cmp(&expected)
+        }
+        Value::String(v) => {
+            let v = v.to_lowercase();
+            let expected = expected.to_lowercase();
+            match op {
+                Op::Contains => return v.contains(&expected),
+                Op::NotContains => return !v.contains(&expected),
+                _ => v.as_str().cmp(&expected),
+            }
+        }
+        Value::Bool(v) => match expected.parse::<bool>() {
+            Ok(expected) => v.cmp(&expected),
+            Err(_) => return false,
+        },
+        _ => return false,
+    };
+
+    match op {
+        Op::Eq => ordering.is_eq(),
+        Op::Ne => ordering.is_ne(),
+        Op::Gt => ordering.is_gt(),
+        Op::Ge => ordering.is_ge(),
+        Op::Lt => ordering.is_lt(),
+        Op::Le => ordering.is_le(),
+        Op::Contains | Op::NotContains => false,
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct CreatedPlaylist {
+    id: String,
+}
+
+/// Prints the tracks of the backups at `paths` meeting every rule as a JSON backup, leaving out
+/// those in any of the playlists or backups in `not_in` and keeping at most `limit`. With `push`
+/// the tracks are also added to a new private playlist of that name on Spotify.
+pub async fn run(
+    args: &Args,
+    paths: &[PathBuf],
+    rules: &[Rule],
+    not_in: &[String],
+    limit: Option<usize>,
+    push: Option<&str>,
+) -> Result<()> {
+    let mut excluded = HashSet::new();
+    for (_, tracks) in sync::load(args, not_in, false, false, None).await? {
+        for track in tracks {
+            excluded.insert(track.uri);
+            excluded.extend(track.isrc);
+        }
+    }
+
+    let mut seen = HashSet::new();
+    let mut picked = Vec::new();
+    for path in paths {
+        let data = crate::cli::read_backup(path, &args.identity).await?;
+        let tracks = migrate::tracks(&data)
+            .with_context(|| format!("Failed to read tracks from {}", path.display()))?
+            .with_context(|| format!("{} isn't a JSON backup", path.display()))?;
+
+        for track in tracks {
+            let uri = track["uri"].as_str().unwrap_or_default();
+            let isrc = track["isrc"].as_str().unwrap_or_default();
+            if excluded.contains(uri) || excluded.contains(isrc) {
+                continue;
+            }
+            if !uri.is_empty() && !seen.insert(uri.to_string()) {
+                continue;
+            }
+
+            if rules.iter().all(|v| v.matches(&track)) {
+                picked.push(track);
+            }
+        }
+    }
+    if let Some(limit) = limit {
+        picked.truncate(limit);
+    }
+    info!("{} track(s) meet the rules", picked.len());
+
+    if let Some(name) = push {
+        create_playlist(args, name, &picked).await?;
+    }
+
+    print(args, &picked)
+}
+
+/// Creates a private playlist named `name` on Spotify with `tracks`. There's no restoring backups
+/// to Spotify yet, so this goes through the API directly.
+async fn create_playlist(args: &Args, name: &str, tracks: &[Value]) -> Result<()> {
+    let uris: Vec<_> = tracks
+        .iter()
+        .filter_map(|v| v["uri"].as_str())
+        .filter(|v| {
+            // local files can only be added from the desktop app
+            let local = v.starts_with("spotify:local:");
+            if local {
+                warn!("Skipping {v}, local files can't be added to playlists through the API");
+            }
+            !local
+        })
+        .collect();
+
+    let profile = Profile::new(&args.profile)?;
+    let client = crate::cli::build_client(&profile, args).await?;
+
+    let playlist: CreatedPlaylist = client
+        .post_json(
+            &format!("{BASE_URL}/me/playlists"),
+            &json!({
+                "name": name,
+                "public": false,
+                "description": "Made by spotify-backup smart",
+            }),
+        )
+        .await
+        .with_context(|| format!("Failed to create playlist {name}"))?;
+
+    for chunk in uris.chunks(MAX_TRACKS_PER_REQUEST) {
+        let _: Value = client
+            .post_json(
+                &format!("{BASE_URL}/playlists/{}/tracks", playlist.id),
+                &json!({ "uris": chunk }),
+            )
+            .await
+            .with_context(|| format!("Failed to add tracks to playlist {name}"))?;
+    }
+
+    info!(
+        "Created playlist {name} with {} track(s): https://open.spotify.com/playlist/{}",
+        uris.len(),
+        playlist.id
+    );
+
+    Ok(())
+}
+
+fn print(args: &Args, tracks: &[Value]) -> Result<()> {
+    let terminal = std::io::stdout().is_terminal();
+
+    match args.format {
+        output::Format::Json => {
+            let backup = json!({
+                "schema_version": manifest::SCHEMA_VERSION,
+                "tracks": tracks,
+            });
+
+            let mut stdout = std::io::stdout().lock();
+            if args.pretty || terminal {
+                serde_json::to_writer_pretty(&mut stdout, &backup)?;
+            } else {
+                serde_json::to_writer(&mut stdout, &backup)?;
+            }
+            writeln!(stdout)?;
+        }
+        output::Format::Table => {
+            let mut table = output::table(&["Name", "Artists", "Added", "URI"], terminal, &[3]);
+            for track in tracks {
+                let artists: Vec<_> = track["artists"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Value::as_str)
+                    .collect();
+                output::add_row(
+                    &mut table,
+                    [
+                        track["name"].as_str().unwrap_or_default(),
+                        &artists.join(", "),
+                        track["added_at"]
+                            .as_str()
+                            .and_then(|v| v.get(..10))
+                            .unwrap_or_default(),
+                        track["uri"].as_str().unwrap_or_default(),
+                    ],
+                );
+            }
+            println!("{table}");
+            println!("{} track(s)", tracks.len());
+        }
+        _ => {
+            anyhow::bail!("Smart playlists can only be written as JSON or a table")
+        }
+    }
+
+    Ok(())
+}
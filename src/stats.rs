@@ -0,0 +1,241 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::IsTerminal,
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{charts, cli::Args, migrate, output};
+
+/// Audio features averaged over the tracks that have them, in the order they're printed.
+const AUDIO_FEATURES: &[&str] = &[
+    "danceability",
+    "energy",
+    "valence",
+    "acousticness",
+    "instrumentalness",
+    "liveness",
+    "speechiness",
+    "loudness",
+    "tempo",
+];
+
+/// Summary of a backup's tracks. Anything worked out from a field the backup wasn't written with
+/// is left out.
+#[derive(Serialize, Debug, Default)]
+struct Stats {
+    tracks: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total_duration_ms: Option<u64>,
+    /// Share of the tracks that are explicit, between 0 and 1
+    #[serde(skip_serializing_if = "Option::is_none")]
+    explicit_ratio: Option<f64>,
+    top_artists: Vec<Count>,
+    top_albums: Vec<Count>,
+    /// Tracks released in each year
+    release_years: BTreeMap<String, usize>,
+    /// Tracks released in each decade (eg. 1990s)
+    release_decades: BTreeMap<String, usize>,
+    /// Tracks added in each month (eg. 2024-03)
+    added_per_month: BTreeMap<String, usize>,
+    /// Average of each audio feature over the tracks that have it
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    audio_features: BTreeMap<String, f64>,
+}
+
+#[derive(Serialize, Debug)]
+struct Count {
+    name: String,
+    tracks: usize,
+}
+
+/// Prints statistics about the tracks in the backup at `path`, with the `top` artists and albums
+/// with the most tracks, and renders charts of them into `charts` as `format` images if it's
+/// given.
+pub async fn run(
+    args: &Args,
+    path: &Path,
+    top: usize,
+    charts: Option<&Path>,
+    format: charts::Format,
+) -> Result<()> {
+    let data = crate::cli::read_backup(path, &args.identity).await?;
+    let tracks = migrate::tracks(&data)
+        .with_context(|| format!("Failed to read tracks from {}", path.display()))?
+        .with_context(|| format!("{} isn't a JSON backup", path.display()))?;
+    let stats = stats(&tracks, top);
+
+    if let Some(dir) = charts {
+        let top_artists: Vec<_> = stats
+            .top_artists
+            .iter()
+            .map(|v| (v.name.as_str(), v.tracks))
+            .collect();
+        charts::render(
+            dir,
+            format,
+            &stats.added_per_month,
+            &stats.release_decades,
+            &top_artists,
+            &stats.audio_features,
+        )
+        .await?;
+    }
+
+    print(args, &stats)
+}
+
+fn stats(tracks: &[Value], top: usize) -> Stats {
+    let mut stats = Stats {
+        tracks: tracks.len(),
+        ..Default::default()
+    };
+
+    let mut artists: HashMap<&str, usize> = HashMap::new();
+    let mut albums: HashMap<&str, usize> = HashMap::new();
+    let mut durations = Vec::new();
+    let mut explicit = Vec::new();
+    let mut features: HashMap<&str, Vec<f64>> = HashMap::new();
+
+    for track in tracks {
+        for artist in track["artists"].as_array().into_iter().flatten() {
+            if let Some(artist) = artist.as_str() {
+                *artists.entry(artist).or_default() += 1;
+            }
+        }
+        if let Some(album) = track["album"]["name"].as_str() {
+            *albums.entry(album).or_default() += 1;
+        }
+
+        let year = track["release_date"]
+            .as_str()
+            .and_then(|v| v.get(..4))
+            .filter(|v| v.bytes().all(|b| b.is_ascii_digit()));
+        if let Some(year) = year {
+            *stats.release_years.entry(year.to_string()).or_default() += 1;
+            *stats
+                .release_decades
+                .entry(format!("{}0s", &year[..3]))
+                .or_default() += 1;
+        }
+        if let Some(month) = track["added_at"].as_str().and_then(|v| v.get(..7)) {
+            *stats.added_per_month.entry(month.to_string()).or_default() += 1;
+        }
+
+        durations.extend(track["duration_ms"].as_u64());
+        explicit.extend(track["explicit"].as_bool());
+
+        // either nested under `audio_features`, or alongside the other fields
+        let audio_features = match &track["audio_features"] {
+            Value::Object(_) => &track["audio_features"],
+            _ => track,
+        };
+        for feature in AUDIO_FEATURES {
+            if let Some(value) = audio_features[feature].as_f64() {
+                features.entry(feature).or_default().push(value);
+            }
+        }
+    }
+
+    stats.total_duration_ms = (!durations.is_empty()).then(|| durations.iter().sum());
+    stats.explicit_ratio = (!explicit.is_empty())
+        .then(|| explicit.iter().filter(|v| **v).count() as f64 / explicit.len() as f64);
+    stats.top_artists = top_counts(artists, top);
+    stats.top_albums = top_counts(albums, top);
+    stats.audio_features = features
+        .into_iter()
+        .map(|(feature, values)| {
+            let average = values.iter().sum::<f64>() / values.len() as f64;
+            (feature.to_string(), average)
+        })
+        .collect();
+
+    stats
+}
+
+/// The `top` names with the most tracks, breaking ties alphabetically.
+fn top_counts(counts: HashMap<&str, usize>, top: usize) -> Vec<Count> {
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+    counts
+        .into_iter()
+        .take(top)
+        .map(|(name, tracks)| Count {
+            name: name.to_string(),
+            tracks,
+        })
+        .collect()
+}
+
+fn print(args: &Args, stats: &Stats) -> Result<()> {
+    let terminal = std::io::stdout().is_terminal();
+
+    match args.format {
+        output::Format::Json => {
+            let json = if args.pretty || terminal {
+                serde_json::to_string_pretty(stats)?
+            } else {
+                serde_json::to_string(stats)?
+            };
+            println!("{json}");
+        }
+        output::Format::Table => {
+            let mut summary = output::table(&["Statistic", "Value"], terminal, &[1]);
+            output::add_row(&mut summary, ["Tracks", &stats.tracks.to_string()]);
+            if let Some(duration) = stats.total_duration_ms {
+                let minutes = duration / 60_000;
+                let duration = format!("{}h {:02}m", minutes / 60, minutes % 60);
+                output::add_row(&mut summary, ["Total duration", &duration]);
+            }
+            if let Some(ratio) = stats.explicit_ratio {
+                let ratio = format!("{:.1}%", ratio * 100.0);
+                output::add_row(&mut summary, ["Explicit", &ratio]);
+            }
+            for feature in AUDIO_FEATURES {
+                if let Some(value) = stats.audio_features.get(*feature) {
+                    output::add_row(&mut summary, [*feature, &format!("{value:.2}")]);
+                }
+            }
+            println!("{summary}");
+
+            print_counts("Artist", &stats.top_artists, terminal);
+            print_counts("Album", &stats.top_albums, terminal);
+            print_map("Decade", &stats.release_decades, terminal);
+            print_map("Year", &stats.release_years, terminal);
+            print_map("Added", &stats.added_per_month, terminal);
+        }
+        _ => {
+            anyhow::bail!("Statistics can only be printed as JSON or a table")
+        }
+    }
+
+    Ok(())
+}
+
+fn print_counts(column: &str, counts: &[Count], terminal: bool) {
+    if counts.is_empty() {
+        return;
+    }
+
+    let mut table = output::table(&[column, "Tracks"], terminal, &[1]);
+    for count in counts {
+        output::add_row(&mut table, [count.name.as_str(), &count.tracks.to_string()]);
+    }
+    println!("\n{table}");
+}
+
+fn print_map(column: &str, counts: &BTreeMap<String, usize>, terminal: bool) {
+    if counts.is_empty() {
+        return;
+    }
+
+    let mut table = output::table(&[column, "Tracks"], terminal, &[0, 1]);
+    for (key, tracks) in counts {
+        output::add_row(&mut table, [key.as_str(), &tracks.to_string()]);
+    }
+    println!("\n{table}");
+}
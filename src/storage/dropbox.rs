@@ -0,0 +1,193 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use reqwest::{StatusCode, Url};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::io::AsyncRead;
+
+use super::{join_path, read_all, Storage, StorageArgs};
+use crate::{
+    authentication::{self, AuthArgs, OAuthProvider},
+    profile::Profile,
+};
+
+const UPLOAD_URL: &str = "https://content.dropboxapi.com/2/files/upload";
+const LIST_FOLDER_URL: &str = "https://api.dropboxapi.com/2/files/list_folder";
+const LIST_FOLDER_CONTINUE_URL: &str = "https://api.dropboxapi.com/2/files/list_folder/continue";
+const DELETE_URL: &str = "https://api.dropboxapi.com/2/files/delete_v2";
+
+/// Uploads backups into a Dropbox folder, overwriting files previously uploaded under the same
+/// name.
+pub struct DropboxStorage {
+    path: String,
+    client_id: String,
+    profile: Profile,
+    auth: AuthArgs,
+}
+
+impl DropboxStorage {
+    pub fn new(url: &Url, args: &StorageArgs, profile: &Profile, auth: &AuthArgs) -> Result<Self> {
+        // `dropbox://folder/sub` and `dropbox:///folder/sub` both refer to `/folder/sub`
+        let path = match url.host_str() {
+            Some(host) => format!("{host}{}", url.path()),
+            None => url.path().to_string(),
+        };
+
+        Ok(Self {
+            path,
+            client_id: args
+                .dropbox_client_id
+                .clone()
+                .context("Missing Dropbox client ID, set DROPBOX_CLIENT_ID")?,
+            profile: profile.clone(),
+            auth: auth.clone(),
+        })
+    }
+
+    fn oauth_provider(&self) -> OAuthProvider {
+        OAuthProvider {
+            name: "Dropbox",
+            token_file: "dropbox-token.json",
+            auth_url: "https://www.dropbox.com/oauth2/authorize",
+            token_url: "https://api.dropboxapi.com/oauth2/token",
+            client_id: self.client_id.clone(),
+            client_secret: None,
+            scopes: "files.content.write files.metadata.read".to_string(),
+            default_scopes: "files.content.write".to_string(),
+            extra_auth_params: &[("token_access_type", "offline")],
+        }
+    }
+
+    async fn token(&self) -> Result<String> {
+        authentication::authenticate_with(&self.oauth_provider(), &self.profile, &self.auth)
+            .await
+            .context("Failed to authenticate with Dropbox")
+    }
+
+    /// Path of `name` within the destination folder, or of the folder itself when it's empty.
+    fn path(&self, name: &str) -> String {
+        match join_path(&self.path, name).trim_end_matches('/') {
+            // the root of the Dropbox is the empty path rather than `/`
+            "" => String::new(),
+            path => format!("/{path}"),
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for DropboxStorage {
+    fn kind(&self) -> &'static str {
+        "Dropbox"
+    }
+
+    async fn put(&self, name: &str, data: &mut (dyn AsyncRead + Send + Unpin)) -> Result<()> {
+        let data = read_all(data).await?;
+        let token = self.token().await?;
+
+        let arg = json!({
+            "path": self.path(name),
+            "mode": "overwrite",
+            "mute": true,
+        });
+
+        crate::http::client()
+            .post(UPLOAD_URL)
+            .bearer_auth(token)
+            .header("Dropbox-API-Arg", arg.to_string())
+            .header("Content-Type", "application/octet-stream")
+            .body(data)
+            .send()
+            .await
+            .context("Failed to send Dropbox upload request")?
+            .error_for_status()
+            .context("Got non-200 response when uploading to Dropbox")?;
+
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<String>> {
+        let token = self.token().await?;
+        let mut names = Vec::new();
+
+        let mut resp = crate::http::client()
+            .post(LIST_FOLDER_URL)
+            .bearer_auth(&token)
+            .json(&json!({ "path": self.path("") }))
+            .send()
+            .await
+            .context("Failed to send Dropbox list request")?;
+        loop {
+            // a folder that hasn't been uploaded to yet doesn't exist
+            if resp.status() == StatusCode::CONFLICT {
+                let body = resp.text().await.unwrap_or_default();
+                if body.contains("not_found") {
+                    return Ok(names);
+                }
+                anyhow::bail!("Failed to list Dropbox folder: {body}");
+            }
+
+            let page: ListFolder = resp
+                .error_for_status()
+                .context("Got non-200 response when listing Dropbox folder")?
+                .json()
+                .await
+                .context("Failed to deserialize Dropbox folder listing")?;
+            names.extend(
+                page.entries
+                    .into_iter()
+                    .filter(|v| v.tag == "file")
+                    .map(|v| v.name),
+            );
+            if !page.has_more {
+                return Ok(names);
+            }
+
+            resp = crate::http::client()
+                .post(LIST_FOLDER_CONTINUE_URL)
+                .bearer_auth(&token)
+                .json(&json!({ "cursor": page.cursor }))
+                .send()
+                .await
+                .context("Failed to send Dropbox list request")?;
+        }
+    }
+
+    async fn delete(&self, name: &str) -> Result<()> {
+        let token = self.token().await?;
+
+        let resp = crate::http::client()
+            .post(DELETE_URL)
+            .bearer_auth(token)
+            .json(&json!({ "path": self.path(name) }))
+            .send()
+            .await
+            .context("Failed to send Dropbox delete request")?;
+        if resp.status() == StatusCode::CONFLICT {
+            let body = resp.text().await.unwrap_or_default();
+            anyhow::ensure!(
+                body.contains("not_found"),
+                "Failed to delete {name} from Dropbox: {body}"
+            );
+            return Ok(());
+        }
+
+        resp.error_for_status()
+            .context("Got non-200 response when deleting from Dropbox")?;
+
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct ListFolder {
+    entries: Vec<Metadata>,
+    cursor: String,
+    has_more: bool,
+}
+
+#[derive(Deserialize)]
+struct Metadata {
+    #[serde(rename = ".tag")]
+    tag: String,
+    name: String,
+}
@@ -0,0 +1,219 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::io::AsyncRead;
+
+use super::{read_all, Storage, StorageArgs};
+use crate::{
+    authentication::{self, AuthArgs, OAuthProvider},
+    profile::Profile,
+};
+
+const FILES_URL: &str = "https://www.googleapis.com/drive/v3/files";
+const UPLOAD_URL: &str = "https://www.googleapis.com/upload/drive/v3/files";
+const BOUNDARY: &str = "spotify-backup-boundary";
+const FOLDER_MIME_TYPE: &str = "application/vnd.google-apps.folder";
+
+/// Uploads backups into a Google Drive folder, replacing files previously uploaded under the same
+/// name.
+pub struct GoogleDriveStorage {
+    /// ID of the folder to upload into, or `None` for the root of "My Drive"
+    folder_id: Option<String>,
+    client_id: String,
+    client_secret: Option<String>,
+    profile: Profile,
+    auth: AuthArgs,
+}
+
+impl GoogleDriveStorage {
+    pub fn new(url: &Url, args: &StorageArgs, profile: &Profile, auth: &AuthArgs) -> Result<Self> {
+        Ok(Self {
+            folder_id: url.host_str().map(ToString::to_string),
+            client_id: args
+                .gdrive_client_id
+                .clone()
+                .context("Missing Google Drive client ID, set GDRIVE_CLIENT_ID")?,
+            client_secret: args.gdrive_client_secret.clone(),
+            profile: profile.clone(),
+            auth: auth.clone(),
+        })
+    }
+
+    fn oauth_provider(&self) -> OAuthProvider {
+        OAuthProvider {
+            name: "Google Drive",
+            token_file: "gdrive-token.json",
+            auth_url: "https://accounts.google.com/o/oauth2/v2/auth",
+            token_url: "https://oauth2.googleapis.com/token",
+            client_id: self.client_id.clone(),
+            client_secret: self.client_secret.clone(),
+            // only grants access to files created by this application
+            scopes: "https://www.googleapis.com/auth/drive.file".to_string(),
+            default_scopes: "https://www.googleapis.com/auth/drive.file".to_string(),
+            extra_auth_params: &[("access_type", "offline"), ("prompt", "consent")],
+        }
+    }
+
+    async fn find_existing(&self, client: &reqwest::Client, name: &str) -> Result<Option<String>> {
+        let parent = self.folder_id.as_deref().unwrap_or("root");
+        let query = format!(
+            "name = '{}' and '{parent}' in parents and trashed = false",
+            name.replace('\\', "\\\\").replace('\'', "\\'")
+        );
+
+        let resp: FileList = client
+            .get(FILES_URL)
+            .query(&[("q", query.as_str()), ("fields", "files(id)")])
+            .send()
+            .await
+            .context("Failed to send Google Drive file search request")?
+            .error_for_status()
+            .context("Got non-200 response when searching Google Drive")?
+            .json()
+            .await
+            .context("Failed to deserialize Google Drive file search response")?;
+
+        Ok(resp.files.into_iter().next().map(|v| v.id))
+    }
+
+    async fn client(&self) -> Result<reqwest::Client> {
+        let token =
+            authentication::authenticate_with(&self.oauth_provider(), &self.profile, &self.auth)
+                .await
+                .context("Failed to authenticate with Google Drive")?;
+
+        authorized_client(&token)
+    }
+}
+
+#[async_trait]
+impl Storage for GoogleDriveStorage {
+    fn kind(&self) -> &'static str {
+        "Google Drive"
+    }
+
+    async fn put(&self, name: &str, data: &mut (dyn AsyncRead + Send + Unpin)) -> Result<()> {
+        let data = read_all(data).await?;
+        let client = self.client().await?;
+
+        let request = match self.find_existing(&client, name).await? {
+            Some(id) => client
+                .patch(format!("{UPLOAD_URL}/{id}"))
+                .query(&[("uploadType", "media")])
+                .body(data),
+            None => {
+                let metadata = json!({
+                    "name": name,
+                    "parents": [self.folder_id.as_deref().unwrap_or("root")],
+                });
+
+                // Drive expects `multipart/related` rather than form data, with the metadata as
+                // the first part and the file contents as the second
+                let mut body = format!(
+                    "--{BOUNDARY}\r\nContent-Type: application/json; charset=UTF-8\r\n\r\n{metadata}\r\n\
+                     --{BOUNDARY}\r\nContent-Type: application/octet-stream\r\n\r\n"
+                )
+                .into_bytes();
+                body.extend(data);
+                body.extend(format!("\r\n--{BOUNDARY}--\r\n").into_bytes());
+
+                client
+                    .post(UPLOAD_URL)
+                    .query(&[("uploadType", "multipart")])
+                    .header(
+                        "Content-Type",
+                        format!("multipart/related; boundary={BOUNDARY}"),
+                    )
+                    .body(body)
+            }
+        };
+
+        request
+            .send()
+            .await
+            .context("Failed to send Google Drive upload request")?
+            .error_for_status()
+            .context("Got non-200 response when uploading to Google Drive")?;
+
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<String>> {
+        let client = self.client().await?;
+        let parent = self.folder_id.as_deref().unwrap_or("root");
+        let query = format!(
+            "'{parent}' in parents and trashed = false and mimeType != '{FOLDER_MIME_TYPE}'"
+        );
+        let mut names = Vec::new();
+        let mut page_token: Option<String> = None;
+
+        loop {
+            let mut params = vec![
+                ("q", query.as_str()),
+                ("fields", "nextPageToken, files(name)"),
+                ("pageSize", "1000"),
+            ];
+            if let Some(token) = &page_token {
+                params.push(("pageToken", token.as_str()));
+            }
+
+            let resp: FileList = client
+                .get(FILES_URL)
+                .query(&params)
+                .send()
+                .await
+                .context("Failed to send Google Drive list request")?
+                .error_for_status()
+                .context("Got non-200 response when listing Google Drive folder")?
+                .json()
+                .await
+                .context("Failed to deserialize Google Drive file list")?;
+
+            names.extend(resp.files.into_iter().filter_map(|v| v.name));
+            page_token = resp.next_page_token;
+            if page_token.is_none() {
+                return Ok(names);
+            }
+        }
+    }
+
+    async fn delete(&self, name: &str) -> Result<()> {
+        let client = self.client().await?;
+        let Some(id) = self.find_existing(&client, name).await? else {
+            return Ok(());
+        };
+
+        client
+            .delete(format!("{FILES_URL}/{id}"))
+            .send()
+            .await
+            .context("Failed to send Google Drive delete request")?
+            .error_for_status()
+            .context("Got non-200 response when deleting from Google Drive")?;
+
+        Ok(())
+    }
+}
+
+fn authorized_client(token: &str) -> Result<reqwest::Client> {
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert("Authorization", format!("Bearer {token}").parse()?);
+
+    Ok(crate::http::builder().default_headers(headers).build()?)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FileList {
+    files: Vec<File>,
+    next_page_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct File {
+    #[serde(default)]
+    id: String,
+    name: Option<String>,
+}
@@ -0,0 +1,80 @@
+use std::{io::ErrorKind, path::PathBuf};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncWriteExt};
+
+use super::Storage;
+use crate::atomic::TmpFile;
+
+/// Writes backups into a directory on the local filesystem.
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    fn kind(&self) -> &'static str {
+        "a local directory"
+    }
+
+    async fn put(&self, name: &str, data: &mut (dyn AsyncRead + Send + Unpin)) -> Result<()> {
+        let path = self.root.join(name);
+
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+
+        // streamed into a temporary file that only replaces the previous backup once it's complete
+        let tmp = TmpFile::new(path.clone());
+        let mut file = tokio::fs::File::create(tmp.path())
+            .await
+            .with_context(|| format!("Failed to create {}", tmp.path().display()))?;
+        tokio::io::copy(data, &mut file)
+            .await
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        file.flush()
+            .await
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+
+        tmp.commit().await
+    }
+
+    async fn list(&self) -> Result<Vec<String>> {
+        let mut entries = match tokio::fs::read_dir(&self.root).await {
+            Ok(v) => v,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to list {}", self.root.display()))
+            }
+        };
+
+        let mut names = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_type().await?.is_file() {
+                names.extend(entry.file_name().to_str().map(ToString::to_string));
+            }
+        }
+
+        Ok(names)
+    }
+
+    async fn delete(&self, name: &str) -> Result<()> {
+        let path = self.root.join(name);
+
+        match tokio::fs::remove_file(&path).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Failed to delete {}", path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,456 @@
+#[cfg(feature = "dropbox")]
+mod dropbox;
+#[cfg(feature = "gdrive")]
+mod gdrive;
+mod local;
+#[cfg(feature = "s3")]
+mod s3;
+#[cfg(feature = "sftp")]
+mod sftp;
+#[cfg(feature = "webdav")]
+mod webdav;
+
+#[cfg(feature = "sftp")]
+use std::path::PathBuf;
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, PoisonError, RwLock},
+};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use reqwest::Url;
+use tokio::io::AsyncRead;
+use tracing::info;
+
+use crate::{authentication::AuthArgs, profile::Profile};
+
+#[cfg(feature = "dropbox")]
+pub use dropbox::DropboxStorage;
+#[cfg(feature = "gdrive")]
+pub use gdrive::GoogleDriveStorage;
+pub use local::LocalStorage;
+#[cfg(feature = "s3")]
+pub use s3::S3Storage;
+#[cfg(feature = "sftp")]
+pub use sftp::SftpStorage;
+#[cfg(feature = "webdav")]
+pub use webdav::WebDavStorage;
+
+/// Format of the time in the names of archived copies of backups, which sorts chronologically.
+const ARCHIVE_TIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// A place finished backups can be written to. Backends for a new kind of destination, including
+/// ones outside this crate, implement this and are made from destination URLs by the constructor
+/// [`register`]ed for their scheme, leaving how backups are uploaded and pruned alone.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Kind of destination, for telling the user where backups went.
+    fn kind(&self) -> &'static str;
+
+    /// Writes everything read from `data` to the storage under `name`, replacing anything that is
+    /// already there. Backends that need all of it up front, to sign it or to send it again, read
+    /// it into memory first.
+    async fn put(&self, name: &str, data: &mut (dyn AsyncRead + Send + Unpin)) -> Result<()>;
+
+    /// Names of the files in the storage, not including those in any directories within it. A
+    /// destination that doesn't exist yet has no files.
+    async fn list(&self) -> Result<Vec<String>>;
+
+    /// Deletes the file `name`, which isn't an error if it's already gone.
+    async fn delete(&self, name: &str) -> Result<()>;
+}
+
+/// What a [`Constructor`] is given to make a storage with, besides the destination URL.
+pub struct StorageContext<'a> {
+    pub args: &'a StorageArgs,
+    /// Profile the tokens of backends that authenticate with OAuth are kept in.
+    pub profile: &'a Profile,
+    pub auth: &'a AuthArgs,
+}
+
+/// Makes the storage a destination URL is uploaded to, for the scheme it was [`register`]ed for.
+pub type Constructor = fn(&Url, &StorageContext) -> Result<Box<dyn Storage>>;
+
+/// Constructors by the scheme of the destination URLs they make storages for.
+static BACKENDS: LazyLock<RwLock<HashMap<&'static str, Constructor>>> =
+    LazyLock::new(|| RwLock::new(builtin()));
+
+/// Makes destination URLs with the scheme `scheme` upload to the storage `constructor` makes for
+/// them, in place of any backend already registered for it. Backends outside this crate are
+/// registered before calling [`crate::cli::main`].
+pub fn register(scheme: &'static str, constructor: Constructor) {
+    BACKENDS
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(scheme, constructor);
+}
+
+/// Constructor for the destinations of a backend that wasn't built in, saying how to get it.
+#[cfg(not(all(
+    feature = "dropbox",
+    feature = "gdrive",
+    feature = "s3",
+    feature = "sftp",
+    feature = "webdav"
+)))]
+macro_rules! not_built {
+    ($feature:literal) => {
+        |url: &Url, _: &StorageContext| -> Result<Box<dyn Storage>> {
+            anyhow::bail!(
+                "Uploading to {} destinations wasn't built in, reinstall with \
+                 `cargo install spotify-backup --features {}`",
+                url.scheme(),
+                $feature
+            )
+        }
+    };
+}
+
+/// The backends of this crate, those left out of the build only saying so.
+fn builtin() -> HashMap<&'static str, Constructor> {
+    let mut backends = HashMap::<_, Constructor>::new();
+
+    backends.insert("file", |url, _| {
+        let root = url
+            .to_file_path()
+            .ok()
+            .context("Invalid local destination path")?;
+        Ok(Box::new(LocalStorage::new(root)))
+    });
+
+    #[cfg(feature = "dropbox")]
+    backends.insert("dropbox", |url, context| {
+        Ok(Box::new(
+            DropboxStorage::new(url, context.args, context.profile, context.auth)
+                .context("Invalid Dropbox destination")?,
+        ))
+    });
+    #[cfg(not(feature = "dropbox"))]
+    backends.insert("dropbox", not_built!("dropbox"));
+
+    #[cfg(feature = "gdrive")]
+    backends.insert("gdrive", |url, context| {
+        Ok(Box::new(
+            GoogleDriveStorage::new(url, context.args, context.profile, context.auth)
+                .context("Invalid Google Drive destination")?,
+        ))
+    });
+    #[cfg(not(feature = "gdrive"))]
+    backends.insert("gdrive", not_built!("gdrive"));
+
+    #[cfg(feature = "s3")]
+    backends.insert("s3", |url, context| {
+        Ok(Box::new(
+            S3Storage::new(url, context.args).context("Invalid S3 destination")?,
+        ))
+    });
+    #[cfg(not(feature = "s3"))]
+    backends.insert("s3", not_built!("s3"));
+
+    #[cfg(feature = "sftp")]
+    backends.insert("sftp", |url, context| {
+        Ok(Box::new(
+            SftpStorage::new(url, context.args).context("Invalid SFTP destination")?,
+        ))
+    });
+    #[cfg(not(feature = "sftp"))]
+    backends.insert("sftp", not_built!("sftp"));
+
+    for scheme in ["webdav", "webdav+http"] {
+        #[cfg(feature = "webdav")]
+        backends.insert(scheme, |url, context| {
+            Ok(Box::new(
+                WebDavStorage::new(url, context.args).context("Invalid WebDAV destination")?,
+            ))
+        });
+        #[cfg(not(feature = "webdav"))]
+        backends.insert(scheme, not_built!("webdav"));
+    }
+
+    backends
+}
+
+/// Options configuring the individual storage backends.
+#[derive(clap::Args, Debug, Clone)]
+pub struct StorageArgs {
+    /// Endpoint of an S3-compatible store (eg. MinIO, Backblaze B2), defaults to AWS
+    #[cfg(feature = "s3")]
+    #[arg(long, env = "AWS_ENDPOINT_URL", global = true)]
+    pub s3_endpoint: Option<Url>,
+    /// Region of the S3 bucket
+    #[cfg(feature = "s3")]
+    #[arg(long, env = "AWS_REGION", default_value = "us-east-1", global = true)]
+    pub s3_region: String,
+    /// Access key used to sign S3 requests
+    #[cfg(feature = "s3")]
+    #[arg(long, env = "AWS_ACCESS_KEY_ID", hide_env_values = true, global = true)]
+    pub s3_access_key_id: Option<String>,
+    /// Secret key used to sign S3 requests
+    #[cfg(feature = "s3")]
+    #[arg(
+        long,
+        env = "AWS_SECRET_ACCESS_KEY",
+        hide_env_values = true,
+        global = true
+    )]
+    pub s3_secret_access_key: Option<String>,
+    /// Session token for temporary S3 credentials
+    #[cfg(feature = "s3")]
+    #[arg(long, env = "AWS_SESSION_TOKEN", hide_env_values = true, global = true)]
+    pub s3_session_token: Option<String>,
+    /// Username for WebDAV basic auth, may also be given in the destination URL
+    #[cfg(feature = "webdav")]
+    #[arg(long, env = "WEBDAV_USERNAME", global = true)]
+    pub webdav_username: Option<String>,
+    /// Password for WebDAV basic auth, may also be given in the destination URL
+    #[cfg(feature = "webdav")]
+    #[arg(long, env = "WEBDAV_PASSWORD", hide_env_values = true, global = true)]
+    pub webdav_password: Option<String>,
+    /// Bearer token for WebDAV servers using token auth instead of basic auth
+    #[cfg(feature = "webdav")]
+    #[arg(long, env = "WEBDAV_TOKEN", hide_env_values = true, global = true)]
+    pub webdav_token: Option<String>,
+    /// Private key used for SFTP uploads, defaults to the SSH agent and keys in ~/.ssh
+    #[cfg(feature = "sftp")]
+    #[arg(long, env = "SFTP_IDENTITY", global = true)]
+    pub sftp_identity: Option<PathBuf>,
+    /// Passphrase of the SFTP private key
+    #[cfg(feature = "sftp")]
+    #[arg(
+        long,
+        env = "SFTP_IDENTITY_PASSPHRASE",
+        hide_env_values = true,
+        global = true
+    )]
+    pub sftp_identity_passphrase: Option<String>,
+    /// OAuth client ID of your Google Cloud "desktop app" for Google Drive uploads
+    #[cfg(feature = "gdrive")]
+    #[arg(long, env = "GDRIVE_CLIENT_ID", global = true)]
+    pub gdrive_client_id: Option<String>,
+    /// OAuth client secret of your Google Cloud "desktop app" for Google Drive uploads
+    #[cfg(feature = "gdrive")]
+    #[arg(
+        long,
+        env = "GDRIVE_CLIENT_SECRET",
+        hide_env_values = true,
+        global = true
+    )]
+    pub gdrive_client_secret: Option<String>,
+    /// App key of your Dropbox app for Dropbox uploads
+    #[cfg(feature = "dropbox")]
+    #[arg(long, env = "DROPBOX_CLIENT_ID", global = true)]
+    pub dropbox_client_id: Option<String>,
+}
+
+/// The storage a destination URL is uploaded to, made by the backend registered for its scheme.
+pub struct Destination(Box<dyn Storage>);
+
+impl Destination {
+    /// Parses destinations of the form `file:///path`, `s3://bucket/prefix`,
+    /// `sftp://user@host/path`, `webdav://host/path` (`webdav+http://` for servers without TLS),
+    /// `gdrive://folder-id` or `dropbox:///path`, or of any scheme [`register`]ed since.
+    pub fn from_url(
+        url: &Url,
+        args: &StorageArgs,
+        profile: &Profile,
+        auth: &AuthArgs,
+    ) -> Result<Self> {
+        let scheme = url.scheme();
+        let constructor = BACKENDS
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(scheme)
+            .copied()
+            .with_context(|| format!("Unsupported upload destination scheme: {scheme}"))?;
+
+        let context = StorageContext {
+            args,
+            profile,
+            auth,
+        };
+        constructor(url, &context).map(Self)
+    }
+}
+
+#[async_trait]
+impl Storage for Destination {
+    fn kind(&self) -> &'static str {
+        self.0.kind()
+    }
+
+    async fn put(&self, name: &str, data: &mut (dyn AsyncRead + Send + Unpin)) -> Result<()> {
+        self.0.put(name, data).await
+    }
+
+    async fn list(&self) -> Result<Vec<String>> {
+        self.0.list().await
+    }
+
+    async fn delete(&self, name: &str) -> Result<()> {
+        self.0.delete(name).await
+    }
+}
+
+/// Uploads a copy of the backup `name` with the time in its name (eg.
+/// `liked.20240131T020000Z.json`), then deletes all but the `keep` most recent of those copies.
+pub async fn archive(
+    storage: &impl Storage,
+    name: &str,
+    mut data: &[u8],
+    time: DateTime<Utc>,
+    keep: usize,
+) -> Result<()> {
+    let (stem, extension) = split_name(name);
+    let archived = format!("{stem}.{}{extension}", time.format(ARCHIVE_TIME_FORMAT));
+    storage
+        .put(&archived, &mut data)
+        .await
+        .with_context(|| format!("Failed to upload {archived}"))?;
+
+    let mut copies: Vec<_> = storage
+        .list()
+        .await
+        .context("Failed to list archived backups")?
+        .into_iter()
+        .filter(|v| is_archived_copy(v, stem, extension))
+        .collect();
+    // the names only differ in their times, so the most recent sorts last
+    copies.sort();
+
+    let expired = copies.len().saturating_sub(keep);
+    for name in &copies[..expired] {
+        info!("Deleting {name}, only the {keep} most recent archived copies are kept");
+        storage
+            .delete(name)
+            .await
+            .with_context(|| format!("Failed to delete {name}"))?;
+    }
+
+    Ok(())
+}
+
+/// Splits a file name into the part before its first dot and its extensions (eg. `.json.zst`).
+fn split_name(name: &str) -> (&str, &str) {
+    match name.find('.') {
+        Some(i) => name.split_at(i),
+        None => (name, ""),
+    }
+}
+
+/// Whether `name` is a copy of the backup named `stem` and `extension` made by [`archive`].
+fn is_archived_copy(name: &str, stem: &str, extension: &str) -> bool {
+    let Some(time) = name
+        .strip_prefix(stem)
+        .and_then(|v| v.strip_prefix('.'))
+        .and_then(|v| v.strip_suffix(extension))
+    else {
+        return false;
+    };
+
+    chrono::NaiveDateTime::parse_from_str(time, ARCHIVE_TIME_FORMAT).is_ok()
+}
+
+/// Reads all of `data` into memory, for backends that need all of it before uploading it.
+#[cfg(any(
+    feature = "dropbox",
+    feature = "gdrive",
+    feature = "s3",
+    feature = "sftp",
+    feature = "webdav"
+))]
+async fn read_all(data: &mut (dyn AsyncRead + Send + Unpin)) -> Result<Vec<u8>> {
+    use tokio::io::AsyncReadExt;
+
+    let mut buf = Vec::new();
+    data.read_to_end(&mut buf)
+        .await
+        .context("Failed to read backup")?;
+    Ok(buf)
+}
+
+/// Joins a destination prefix and object name with a single `/`.
+#[cfg(any(feature = "dropbox", feature = "s3", feature = "sftp"))]
+fn join_path(prefix: &str, name: &str) -> String {
+    let prefix = prefix.trim_matches('/');
+
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{prefix}/{name}")
+    }
+}
+
+/// Text of every element named `tag` in an XML document, whatever namespace prefix it has, with
+/// entities decoded. Only as much XML as the listings of S3 and WebDAV servers use is understood.
+#[cfg(any(feature = "s3", feature = "webdav"))]
+fn xml_text(xml: &str, tag: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    let mut rest = xml;
+
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let Some(end) = rest.find('>') else {
+            break;
+        };
+        let name = rest[..end].split_whitespace().next().unwrap_or_default();
+        let local_name = name.rsplit(':').next().unwrap_or(name);
+        rest = &rest[end + 1..];
+
+        if local_name == tag && !rest.starts_with('<') {
+            let text = &rest[..rest.find('<').unwrap_or(rest.len())];
+            values.push(
+                text.replace("&lt;", "<")
+                    .replace("&gt;", ">")
+                    .replace("&quot;", "\"")
+                    .replace("&apos;", "'")
+                    .replace("&amp;", "&"),
+            );
+        }
+    }
+
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use clap::Parser;
+
+    use super::*;
+    use crate::cli::Args;
+
+    fn destination(url: &str) -> Result<Destination> {
+        let args = Args::try_parse_from(["spotify-backup", "playlists"]).unwrap();
+        let profile = Profile::new(&args.profile).unwrap();
+        Destination::from_url(&Url::parse(url)?, &args.storage, &profile, &args.auth)
+    }
+
+    #[tokio::test]
+    async fn uploads_to_registered_backends() {
+        let dir = tempfile::tempdir().unwrap();
+        register("scratch", |url, _| {
+            Ok(Box::new(LocalStorage::new(PathBuf::from(url.path()))))
+        });
+
+        let destination = destination(&format!("scratch://{}", dir.path().display())).unwrap();
+        destination
+            .put("liked.json", &mut &b"[]"[..])
+            .await
+            .unwrap();
+
+        assert_eq!(destination.kind(), "a local directory");
+        assert_eq!(destination.list().await.unwrap(), ["liked.json"]);
+        assert_eq!(std::fs::read(dir.path().join("liked.json")).unwrap(), b"[]");
+    }
+
+    #[test]
+    fn rejects_unregistered_schemes() {
+        let Err(e) = destination("ftp://example.com/backups") else {
+            panic!("ftp destinations aren't supported");
+        };
+        assert_eq!(e.to_string(), "Unsupported upload destination scheme: ftp");
+    }
+}
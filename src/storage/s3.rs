@@ -0,0 +1,309 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use percent_encoding::percent_decode_str;
+use reqwest::{Method, Url};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncRead;
+
+use super::{join_path, read_all, xml_text, Storage, StorageArgs};
+
+const SERVICE: &str = "s3";
+
+/// Uploads backups to an S3-compatible object store, signing requests with AWS Signature V4.
+pub struct S3Storage {
+    bucket: String,
+    prefix: String,
+    /// Custom endpoint for S3-compatible stores (MinIO, Backblaze B2, ...). When unset AWS is used.
+    endpoint: Option<Url>,
+    region: String,
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+}
+
+impl S3Storage {
+    pub fn new(url: &Url, args: &StorageArgs) -> Result<Self> {
+        Ok(Self {
+            bucket: url
+                .host_str()
+                .context("S3 destination is missing a bucket name")?
+                .to_string(),
+            // the path is kept percent-encoded by `Url`, but it's encoded again when signing
+            prefix: percent_decode_str(url.path())
+                .decode_utf8()
+                .context("S3 destination path isn't valid UTF-8")?
+                .into_owned(),
+            endpoint: args.s3_endpoint.clone(),
+            region: args.s3_region.clone(),
+            access_key_id: args
+                .s3_access_key_id
+                .clone()
+                .context("Missing S3 access key, set AWS_ACCESS_KEY_ID")?,
+            secret_access_key: args
+                .s3_secret_access_key
+                .clone()
+                .context("Missing S3 secret key, set AWS_SECRET_ACCESS_KEY")?,
+            session_token: args.s3_session_token.clone(),
+        })
+    }
+}
+
+impl S3Storage {
+    /// URL of the object `key`, or of the bucket itself when `key` is empty.
+    fn url(&self, key: &str) -> Result<Url> {
+        let bucket = &self.bucket;
+
+        // custom endpoints are addressed path-style since most S3-compatible stores don't support
+        // virtual-hosted buckets, whereas AWS itself is addressed virtual-hosted style
+        match &self.endpoint {
+            Some(endpoint) => {
+                // appended to the endpoint's own path rather than resolved against it, which would
+                // drop its last segment, and encoded the way the request is signed rather than the
+                // looser way `path_segments_mut` does
+                let mut url = endpoint.clone();
+                url.set_path(&format!(
+                    "{}/{}/{}",
+                    endpoint.path().trim_end_matches('/'),
+                    uri_encode(bucket, false),
+                    uri_encode(key, true)
+                ));
+                Ok(url)
+            }
+            None => Url::parse(&format!(
+                "https://{bucket}.s3.{}.amazonaws.com/{}",
+                self.region,
+                uri_encode(key, true)
+            ))
+            .context("Failed to build S3 object URL"),
+        }
+    }
+
+    /// Sends a request signed with AWS Signature V4, with `query` as the query string.
+    async fn send(
+        &self,
+        method: Method,
+        mut url: Url,
+        query: &[(&str, &str)],
+        data: Vec<u8>,
+    ) -> Result<reqwest::Response> {
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{host}:{port}"),
+            (Some(host), None) => host.to_string(),
+            (None, _) => anyhow::bail!("S3 object URL is missing a host"),
+        };
+
+        // the query is signed exactly as it's sent, with its parameters encoded and sorted
+        let mut query: Vec<_> = query
+            .iter()
+            .map(|(k, v)| format!("{}={}", uri_encode(k, false), uri_encode(v, false)))
+            .collect();
+        query.sort();
+        let query = query.join("&");
+        url.set_query(Some(&query).filter(|v| !v.is_empty()).map(|v| v.as_str()));
+
+        let payload_hash = hex::encode(Sha256::digest(&data));
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date_stamp = now.format("%Y%m%d").to_string();
+
+        let mut headers = vec![
+            ("host", host),
+            ("x-amz-content-sha256", payload_hash.clone()),
+            ("x-amz-date", amz_date.clone()),
+        ];
+        if let Some(token) = &self.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+
+        let signed_headers = headers
+            .iter()
+            .map(|(k, _)| *k)
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_headers = headers
+            .iter()
+            .map(|(k, v)| format!("{k}:{}\n", v.trim()))
+            .collect::<String>();
+        let canonical_request = format!(
+            "{method}\n{}\n{query}\n{canonical_headers}\n{signed_headers}\n{payload_hash}",
+            url.path()
+        );
+
+        let scope = format!("{date_stamp}/{}/{SERVICE}/aws4_request", self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let signing_key = [
+            date_stamp.as_bytes(),
+            self.region.as_bytes(),
+            SERVICE.as_bytes(),
+            b"aws4_request",
+        ]
+        .into_iter()
+        .try_fold(
+            format!("AWS4{}", self.secret_access_key).into_bytes(),
+            |key, data| hmac_sha256(&key, data),
+        )?;
+        let signature = hex::encode(hmac_sha256(&signing_key, string_to_sign.as_bytes())?);
+
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+            self.access_key_id
+        );
+
+        let mut request = crate::http::client()
+            .request(method, url)
+            .header("Authorization", authorization);
+        for (k, v) in headers.into_iter().filter(|(k, _)| *k != "host") {
+            request = request.header(k, v);
+        }
+
+        request
+            .body(data)
+            .send()
+            .await
+            .context("Failed to send S3 request")
+    }
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    fn kind(&self) -> &'static str {
+        "S3"
+    }
+
+    async fn put(&self, name: &str, data: &mut (dyn AsyncRead + Send + Unpin)) -> Result<()> {
+        let data = read_all(data).await?;
+        let url = self.url(&join_path(&self.prefix, name))?;
+
+        self.send(Method::PUT, url.clone(), &[], data)
+            .await?
+            .error_for_status()
+            .with_context(|| format!("Got non-200 response when uploading to {url}"))?;
+
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<String>> {
+        let prefix = join_path(&self.prefix, "");
+        let url = self.url("")?;
+        let mut names = Vec::new();
+        let mut continuation_token: Option<String> = None;
+
+        loop {
+            let mut query = vec![
+                ("list-type", "2"),
+                ("prefix", prefix.as_str()),
+                ("delimiter", "/"),
+            ];
+            if let Some(token) = &continuation_token {
+                query.push(("continuation-token", token.as_str()));
+            }
+
+            let body = self
+                .send(Method::GET, url.clone(), &query, Vec::new())
+                .await?
+                .error_for_status()
+                .with_context(|| format!("Got non-200 response when listing {url}"))?
+                .text()
+                .await
+                .context("Failed to read S3 listing")?;
+
+            names.extend(
+                xml_text(&body, "Key")
+                    .into_iter()
+                    .filter_map(|v| v.strip_prefix(&prefix).map(ToString::to_string)),
+            );
+
+            continuation_token = xml_text(&body, "NextContinuationToken").into_iter().next();
+            if continuation_token.is_none() {
+                return Ok(names);
+            }
+        }
+    }
+
+    async fn delete(&self, name: &str) -> Result<()> {
+        let url = self.url(&join_path(&self.prefix, name))?;
+
+        // deleting a key that doesn't exist succeeds too
+        self.send(Method::DELETE, url.clone(), &[], Vec::new())
+            .await?
+            .error_for_status()
+            .with_context(|| format!("Got non-200 response when deleting {url}"))?;
+
+        Ok(())
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).context("Invalid HMAC key")?;
+    mac.update(data);
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+
+/// Percent-encodes everything but the unreserved characters, as required by SigV4's canonical URI.
+fn uri_encode(input: &str, keep_slash: bool) -> String {
+    let mut out = String::with_capacity(input.len());
+
+    for b in input.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(b as char);
+            }
+            b'/' if keep_slash => out.push('/'),
+            _ => out.push_str(&format!("%{b:02X}")),
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::cli::Args;
+
+    fn storage(destination: &str, endpoint: Option<&str>) -> S3Storage {
+        let mut args = [
+            "spotify-backup",
+            "--s3-access-key-id=key",
+            "--s3-secret-access-key=secret",
+        ]
+        .map(String::from)
+        .to_vec();
+        args.extend(endpoint.map(|v| format!("--s3-endpoint={v}")));
+        args.push("playlists".to_string());
+        let args = Args::try_parse_from(args).unwrap();
+        S3Storage::new(&Url::parse(destination).unwrap(), &args.storage).unwrap()
+    }
+
+    #[test]
+    fn appends_objects_to_the_endpoint_path() {
+        for endpoint in ["https://minio.local/s3", "https://minio.local/s3/"] {
+            let s3 = storage("s3://bucket/backups", Some(endpoint));
+            assert_eq!(
+                s3.url("backups/a b.json").unwrap().as_str(),
+                "https://minio.local/s3/bucket/backups/a%20b.json"
+            );
+        }
+
+        let s3 = storage("s3://bucket", Some("https://minio.local"));
+        assert_eq!(s3.url("").unwrap().as_str(), "https://minio.local/bucket/");
+    }
+
+    #[test]
+    fn decodes_the_prefix() {
+        let s3 = storage("s3://bucket/my%20dir", None);
+        assert_eq!(s3.prefix, "/my dir");
+        assert_eq!(
+            s3.url(&join_path(&s3.prefix, "a.json")).unwrap().as_str(),
+            "https://bucket.s3.us-east-1.amazonaws.com/my%20dir/a.json"
+        );
+    }
+}
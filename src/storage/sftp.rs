@@ -0,0 +1,231 @@
+use std::{
+    io::Write,
+    net::TcpStream,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use reqwest::Url;
+use ssh2::{CheckResult, ErrorCode, KnownHostFileKind, Session, Sftp};
+use tokio::io::AsyncRead;
+use tracing::warn;
+
+use super::{join_path, read_all, Storage, StorageArgs};
+
+const DEFAULT_IDENTITIES: &[&str] = &["id_ed25519", "id_ecdsa", "id_rsa"];
+
+/// Uploads backups to a remote host over SFTP using key-based authentication.
+pub struct SftpStorage {
+    host: String,
+    port: u16,
+    username: String,
+    path: String,
+    identity: Option<PathBuf>,
+    identity_passphrase: Option<String>,
+}
+
+impl SftpStorage {
+    pub fn new(url: &Url, args: &StorageArgs) -> Result<Self> {
+        let username = match url.username() {
+            "" => std::env::var("USER").context("SFTP destination is missing a username")?,
+            v => v.to_string(),
+        };
+
+        Ok(Self {
+            host: url
+                .host_str()
+                .context("SFTP destination is missing a host")?
+                .to_string(),
+            port: url.port().unwrap_or(22),
+            username,
+            path: url.path().to_string(),
+            identity: args.sftp_identity.clone(),
+            identity_passphrase: args.sftp_identity_passphrase.clone(),
+        })
+    }
+
+    fn connect(&self) -> Result<Session> {
+        let tcp = TcpStream::connect((self.host.as_str(), self.port))
+            .with_context(|| format!("Failed to connect to {}:{}", self.host, self.port))?;
+
+        let mut session = Session::new().context("Failed to create SSH session")?;
+        session.set_tcp_stream(tcp);
+        session.handshake().context("SSH handshake failed")?;
+
+        self.verify_host_key(&session)?;
+        self.authenticate(&session)?;
+
+        Ok(session)
+    }
+
+    /// Refuses to talk to hosts that aren't in `~/.ssh/known_hosts`, connect with `ssh` once
+    /// beforehand to trust a new host.
+    fn verify_host_key(&self, session: &Session) -> Result<()> {
+        let (key, _) = session
+            .host_key()
+            .context("Server didn't send a host key")?;
+
+        let mut known_hosts = session.known_hosts()?;
+        let known_hosts_path = ssh_dir()?.join("known_hosts");
+        if known_hosts_path.exists() {
+            known_hosts
+                .read_file(&known_hosts_path, KnownHostFileKind::OpenSSH)
+                .context("Failed to read known_hosts")?;
+        }
+
+        match known_hosts.check_port(&self.host, self.port, key) {
+            CheckResult::Match => Ok(()),
+            CheckResult::Mismatch => anyhow::bail!(
+                "Host key for {} does not match known_hosts, refusing to connect",
+                self.host
+            ),
+            CheckResult::NotFound => anyhow::bail!(
+                "{} is not a known host, connect with ssh once to add it to known_hosts",
+                self.host
+            ),
+            CheckResult::Failure => anyhow::bail!("Failed to check host key for {}", self.host),
+        }
+    }
+
+    /// Tries the SSH agent first, then the configured identity or the default identities in
+    /// `~/.ssh`.
+    fn authenticate(&self, session: &Session) -> Result<()> {
+        if session.userauth_agent(&self.username).is_ok() && session.authenticated() {
+            return Ok(());
+        }
+
+        let identities = match &self.identity {
+            Some(v) => vec![v.clone()],
+            None => {
+                let ssh_dir = ssh_dir()?;
+                DEFAULT_IDENTITIES
+                    .iter()
+                    .map(|v| ssh_dir.join(v))
+                    .filter(|v| v.exists())
+                    .collect()
+            }
+        };
+
+        for identity in identities {
+            if let Err(e) = session.userauth_pubkey_file(
+                &self.username,
+                None,
+                &identity,
+                self.identity_passphrase.as_deref(),
+            ) {
+                warn!("SFTP key {} was rejected: {e}", identity.display());
+                continue;
+            }
+
+            if session.authenticated() {
+                return Ok(());
+            }
+        }
+
+        anyhow::bail!(
+            "Failed to authenticate with {} as {}",
+            self.host,
+            self.username
+        )
+    }
+
+    /// Remote path of `name` within the destination, or of the destination itself when it's
+    /// empty.
+    fn remote_path(&self, sftp: &Sftp, name: &str) -> Result<PathBuf> {
+        // paths starting with `~/` are relative to the user's home directory, everything else is
+        // absolute
+        let path = join_path(&self.path, name);
+        Ok(match path.strip_prefix("~/") {
+            Some(relative) => sftp
+                .realpath(Path::new("."))
+                .context("Failed to resolve home directory")?
+                .join(relative),
+            None => PathBuf::from(format!("/{path}")),
+        })
+    }
+
+    fn put_blocking(&self, name: &str, data: &[u8]) -> Result<()> {
+        let session = self.connect()?;
+        let sftp = session.sftp().context("Failed to start SFTP subsystem")?;
+        let path = self.remote_path(&sftp, name)?;
+
+        // mkdir fails for directories that already exist, whether it actually worked is checked
+        // when creating the file below
+        let mut current = PathBuf::from("/");
+        for component in path.parent().unwrap_or(Path::new("/")).components().skip(1) {
+            current.push(component);
+            let _res = sftp.mkdir(&current, 0o755);
+        }
+
+        let mut file = sftp
+            .create(&path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        file.write_all(data)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+
+        Ok(())
+    }
+
+    fn list_blocking(&self) -> Result<Vec<String>> {
+        let session = self.connect()?;
+        let sftp = session.sftp().context("Failed to start SFTP subsystem")?;
+        let path = self.remote_path(&sftp, "")?;
+
+        let entries = match sftp.readdir(&path) {
+            Ok(v) => v,
+            Err(e) if e.code() == ErrorCode::SFTP(LIBSSH2_FX_NO_SUCH_FILE) => return Ok(Vec::new()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to list {}", path.display())),
+        };
+
+        Ok(entries
+            .into_iter()
+            .filter(|(_, stat)| stat.is_file())
+            .filter_map(|(path, _)| Some(path.file_name()?.to_str()?.to_string()))
+            .collect())
+    }
+
+    fn delete_blocking(&self, name: &str) -> Result<()> {
+        let session = self.connect()?;
+        let sftp = session.sftp().context("Failed to start SFTP subsystem")?;
+        let path = self.remote_path(&sftp, name)?;
+
+        match sftp.unlink(&path) {
+            Err(e) if e.code() != ErrorCode::SFTP(LIBSSH2_FX_NO_SUCH_FILE) => {
+                Err(e).with_context(|| format!("Failed to delete {}", path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for SftpStorage {
+    fn kind(&self) -> &'static str {
+        "SFTP"
+    }
+
+    async fn put(&self, name: &str, data: &mut (dyn AsyncRead + Send + Unpin)) -> Result<()> {
+        let data = read_all(data).await?;
+
+        // libssh2 is blocking, so the upload is moved off the runtime
+        tokio::task::block_in_place(|| self.put_blocking(name, &data))
+    }
+
+    async fn list(&self) -> Result<Vec<String>> {
+        tokio::task::block_in_place(|| self.list_blocking())
+    }
+
+    async fn delete(&self, name: &str) -> Result<()> {
+        tokio::task::block_in_place(|| self.delete_blocking(name))
+    }
+}
+
+/// Status libssh2 reports for files that don't exist.
+const LIBSSH2_FX_NO_SUCH_FILE: i32 = 2;
+
+fn ssh_dir() -> Result<PathBuf> {
+    Ok(dirs::home_dir()
+        .context("Unable to determine home directory")?
+        .join(".ssh"))
+}
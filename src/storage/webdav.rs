@@ -0,0 +1,199 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use reqwest::{Method, StatusCode, Url};
+use tokio::io::AsyncRead;
+
+use percent_encoding::percent_decode_str;
+
+use super::{read_all, xml_text, Storage, StorageArgs};
+
+/// Body of PROPFIND requests listing a collection, which only need the paths of its members.
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?><propfind xmlns="DAV:"><prop><resourcetype/></prop></propfind>"#;
+
+/// Uploads backups to a WebDAV server (Nextcloud, ownCloud, Apache mod_dav, ...).
+pub struct WebDavStorage {
+    base: Url,
+    auth: WebDavAuth,
+}
+
+enum WebDavAuth {
+    None,
+    Basic {
+        username: String,
+        password: Option<String>,
+    },
+    Bearer(String),
+}
+
+impl WebDavStorage {
+    pub fn new(url: &Url, args: &StorageArgs) -> Result<Self> {
+        let scheme = if url.scheme() == "webdav+http" {
+            "http"
+        } else {
+            "https"
+        };
+        let host = url
+            .host_str()
+            .context("WebDAV destination is missing a host")?;
+        let port = url.port().map(|v| format!(":{v}")).unwrap_or_default();
+
+        let mut base = Url::parse(&format!("{scheme}://{host}{port}"))
+            .context("Failed to build WebDAV base URL")?;
+        base.set_path(&format!("{}/", url.path().trim_end_matches('/')));
+
+        let url_username = Some(url.username())
+            .filter(|v| !v.is_empty())
+            .map(ToString::to_string);
+
+        let auth = if let Some(token) = &args.webdav_token {
+            WebDavAuth::Bearer(token.clone())
+        } else if let Some(username) = url_username.or_else(|| args.webdav_username.clone()) {
+            WebDavAuth::Basic {
+                username,
+                password: url
+                    .password()
+                    .map(ToString::to_string)
+                    .or_else(|| args.webdav_password.clone()),
+            }
+        } else {
+            WebDavAuth::None
+        };
+
+        Ok(Self { base, auth })
+    }
+
+    fn request(&self, method: Method, url: Url) -> reqwest::RequestBuilder {
+        let request = crate::http::client().request(method, url);
+
+        match &self.auth {
+            WebDavAuth::None => request,
+            WebDavAuth::Basic { username, password } => {
+                request.basic_auth(username, password.as_ref())
+            }
+            WebDavAuth::Bearer(token) => request.bearer_auth(token),
+        }
+    }
+
+    /// Creates every collection leading up to `path`. Ancestors outside of our control (eg.
+    /// `remote.php` on Nextcloud) will refuse the request, so only the final collection has to
+    /// succeed or already exist.
+    async fn create_collections(&self, path: &str) -> Result<()> {
+        let mut url = self.base.clone();
+        let mut current = String::from("/");
+        let mut last_status = StatusCode::OK;
+
+        for segment in path.split('/').filter(|v| !v.is_empty()) {
+            current.push_str(segment);
+            current.push('/');
+            url.set_path(&current);
+
+            last_status = self
+                .request(Method::from_bytes(b"MKCOL")?, url.clone())
+                .send()
+                .await
+                .context("Failed to send WebDAV MKCOL request")?
+                .status();
+        }
+
+        // 405 is returned when the collection already exists
+        if !last_status.is_success() && last_status != StatusCode::METHOD_NOT_ALLOWED {
+            anyhow::bail!("Failed to create WebDAV collection {url}: {last_status}");
+        }
+
+        Ok(())
+    }
+
+    async fn send_put(&self, url: &Url, data: Vec<u8>) -> Result<reqwest::Response> {
+        self.request(Method::PUT, url.clone())
+            .body(data)
+            .send()
+            .await
+            .context("Failed to send WebDAV upload request")
+    }
+}
+
+#[async_trait]
+impl Storage for WebDavStorage {
+    fn kind(&self) -> &'static str {
+        "WebDAV"
+    }
+
+    async fn put(&self, name: &str, data: &mut (dyn AsyncRead + Send + Unpin)) -> Result<()> {
+        let data = read_all(data).await?;
+        let url = self
+            .base
+            .join(name)
+            .context("Failed to build WebDAV object URL")?;
+
+        let mut resp = self.send_put(&url, data.clone()).await?;
+
+        // servers respond with 409 Conflict when the parent collection is missing
+        if matches!(resp.status(), StatusCode::CONFLICT | StatusCode::NOT_FOUND) {
+            if let Some((parent, _)) = url.path().rsplit_once('/') {
+                self.create_collections(parent).await?;
+            }
+
+            resp = self.send_put(&url, data).await?;
+        }
+
+        resp.error_for_status()
+            .with_context(|| format!("Got non-200 response when uploading to {url}"))?;
+
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<String>> {
+        let resp = self
+            .request(Method::from_bytes(b"PROPFIND")?, self.base.clone())
+            .header("Depth", "1")
+            .header("Content-Type", "application/xml")
+            .body(PROPFIND_BODY)
+            .send()
+            .await
+            .context("Failed to send WebDAV PROPFIND request")?;
+        if resp.status() == StatusCode::NOT_FOUND {
+            return Ok(Vec::new());
+        }
+
+        let body = resp
+            .error_for_status()
+            .with_context(|| format!("Got non-200 response when listing {}", self.base))?
+            .text()
+            .await
+            .context("Failed to read WebDAV listing")?;
+
+        // every member is listed by its path (or full URL), collections with a trailing slash,
+        // along with the collection itself
+        Ok(xml_text(&body, "href")
+            .into_iter()
+            .filter_map(|href| {
+                let path = self.base.join(&href).ok()?.path().to_string();
+                let name = path.strip_prefix(self.base.path())?;
+                if name.is_empty() || name.contains('/') {
+                    return None;
+                }
+
+                Some(percent_decode_str(name).decode_utf8().ok()?.into_owned())
+            })
+            .collect())
+    }
+
+    async fn delete(&self, name: &str) -> Result<()> {
+        let url = self
+            .base
+            .join(name)
+            .context("Failed to build WebDAV object URL")?;
+
+        let resp = self
+            .request(Method::DELETE, url.clone())
+            .send()
+            .await
+            .context("Failed to send WebDAV DELETE request")?;
+        if resp.status() != StatusCode::NOT_FOUND {
+            resp.error_for_status()
+                .with_context(|| format!("Got non-200 response when deleting {url}"))?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,279 @@
+use anyhow::{Context, Result};
+use md5::{Digest, Md5};
+use rand::distributions::{Alphanumeric, DistString};
+use reqwest::Url;
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::{
+    map::{Candidate, Service, Track},
+    sync::Destination,
+};
+
+/// Version of the Subsonic API the requests are written against, which every server implementing
+/// it supports.
+const API_VERSION: &str = "1.16.1";
+
+/// Most search results scored for each track.
+const MAX_RESULTS: &str = "5";
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct SubsonicArgs {
+    /// URL of the Subsonic, Navidrome, Airsonic or Gonic server (eg. https://music.example.com)
+    #[arg(long, env = "SUBSONIC_URL", global = true)]
+    pub subsonic_url: Option<Url>,
+    /// User to sign in to the server as
+    #[arg(long, env = "SUBSONIC_USER", global = true)]
+    pub subsonic_user: Option<String>,
+    /// Password of the user
+    #[arg(long, env = "SUBSONIC_PASSWORD", hide_env_values = true, global = true)]
+    pub subsonic_password: Option<String>,
+}
+
+/// A server implementing the Subsonic API.
+pub struct Subsonic {
+    http: reqwest::Client,
+    url: Url,
+    user: String,
+    password: String,
+}
+
+/// Every response is wrapped in a `subsonic-response`, which says whether the request failed as
+/// the status code is 200 either way.
+#[derive(Deserialize, Debug)]
+struct Envelope<T> {
+    #[serde(rename = "subsonic-response")]
+    response: Response<T>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Response<T> {
+    status: String,
+    error: Option<ErrorBody>,
+    #[serde(flatten)]
+    body: Option<T>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ErrorBody {
+    code: Option<u32>,
+    message: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SearchBody {
+    search_result3: SearchResult,
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchResult {
+    #[serde(default)]
+    song: Vec<Song>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Song {
+    id: String,
+    title: String,
+    artist: Option<String>,
+    album: Option<String>,
+    album_id: Option<String>,
+    /// Length in seconds
+    duration: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PlaylistsBody {
+    playlists: Playlists,
+}
+
+#[derive(Deserialize, Debug)]
+struct Playlists {
+    #[serde(default)]
+    playlist: Vec<Playlist>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Playlist {
+    id: String,
+    name: String,
+    /// Missing on servers that don't say who playlists belong to
+    owner: Option<String>,
+}
+
+/// Body of requests that return nothing but their status.
+#[derive(Deserialize, Debug)]
+struct Empty {}
+
+impl Subsonic {
+    pub async fn connect(subsonic: &SubsonicArgs) -> Result<Self> {
+        let url = subsonic
+            .subsonic_url
+            .clone()
+            .context("Missing Subsonic server URL, set SUBSONIC_URL")?;
+        let user = subsonic
+            .subsonic_user
+            .clone()
+            .context("Missing Subsonic user, set SUBSONIC_USER")?;
+        let password = subsonic
+            .subsonic_password
+            .clone()
+            .context("Missing Subsonic password, set SUBSONIC_PASSWORD")?;
+
+        let server = Self {
+            http: crate::http::client(),
+            url,
+            user,
+            password,
+        };
+        server
+            .request::<Empty>("ping", &[])
+            .await
+            .context("Failed to connect to Subsonic server")?;
+
+        Ok(server)
+    }
+
+    /// Calls the API method `method`, authenticating with a salted token rather than sending the
+    /// password. Parameters are sent as a form, as adding many tracks to a playlist repeats
+    /// `songId` more times than fit in a URL.
+    async fn request<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: &[(&str, &str)],
+    ) -> Result<T> {
+        let salt = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+        let mut hasher = Md5::new();
+        hasher.update(self.password.as_bytes());
+        hasher.update(salt.as_bytes());
+        let token = hex::encode(hasher.finalize());
+
+        let mut form = vec![
+            ("u", self.user.as_str()),
+            ("t", &token),
+            ("s", &salt),
+            ("v", API_VERSION),
+            ("c", env!("CARGO_PKG_NAME")),
+            ("f", "json"),
+        ];
+        form.extend_from_slice(params);
+
+        let mut url = self.url.clone();
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("Invalid Subsonic server URL"))?
+            .pop_if_empty()
+            .extend(["rest", &format!("{method}.view")]);
+
+        let envelope: Envelope<T> = self
+            .http
+            .post(url)
+            .form(&form)
+            .send()
+            .await
+            .context("Failed to send request to Subsonic server")?
+            .error_for_status()
+            .context("Failed to query Subsonic server")?
+            .json()
+            .await
+            .context("Failed to parse response from Subsonic server")?;
+        let response = envelope.response;
+
+        if response.status != "ok" {
+            let error = response.error.unwrap_or(ErrorBody {
+                code: None,
+                message: None,
+            });
+            anyhow::bail!(
+                "Subsonic server returned an error{}: {}",
+                error.code.map(|v| format!(" ({v})")).unwrap_or_default(),
+                error.message.unwrap_or_default()
+            );
+        }
+
+        response
+            .body
+            .with_context(|| format!("Subsonic server returned nothing for {method}"))
+    }
+
+    async fn search_songs(&self, query: &str) -> Result<Vec<Song>> {
+        let body: SearchBody = self
+            .request(
+                "search3",
+                &[
+                    ("query", query),
+                    ("songCount", MAX_RESULTS),
+                    ("artistCount", "0"),
+                    ("albumCount", "0"),
+                ],
+            )
+            .await?;
+
+        Ok(body.search_result3.song)
+    }
+}
+
+impl Service for Subsonic {
+    fn name(&self) -> &'static str {
+        "subsonic"
+    }
+
+    /// Searches for the track's artist and title, as the API can't look tracks up by ISRC.
+    async fn search(&self, track: &Track) -> Result<Vec<Candidate>> {
+        let query = match track.artists.first() {
+            Some(artist) => format!("{artist} {}", track.name),
+            None => track.name.clone(),
+        };
+        let mut found = self.search_songs(&query).await?;
+        // servers match every word, so versions like "Remastered" missing from the files' tags
+        // find nothing
+        if found.is_empty() {
+            let title = crate::dupes::normalize_title(&track.name)
+                .filter(|v| *v != track.name.to_lowercase());
+            if let Some(title) = title {
+                found = self.search_songs(&title).await?;
+            }
+        }
+
+        Ok(found
+            .into_iter()
+            .map(|v| Candidate {
+                id: v.id,
+                title: v.title,
+                artists: v.artist.into_iter().collect(),
+                album: v.album,
+                album_id: v.album_id,
+                duration_ms: v.duration.map(|v| v * 1000),
+                by_isrc: false,
+            })
+            .collect())
+    }
+}
+
+impl Destination for Subsonic {
+    async fn replace_playlist(&self, name: &str, ids: &[String]) -> Result<bool> {
+        let body: PlaylistsBody = self
+            .request("getPlaylists", &[])
+            .await
+            .context("Failed to list Subsonic playlists")?;
+        // playlists shared by other users are listed too, but can't be changed
+        let existing = body
+            .playlists
+            .playlist
+            .into_iter()
+            .find(|v| v.name == name && v.owner.as_ref().is_none_or(|v| *v == self.user));
+
+        // createPlaylist replaces every track of the playlist given by playlistId
+        let mut params = match &existing {
+            Some(playlist) => vec![("playlistId", playlist.id.as_str())],
+            None => vec![("name", name)],
+        };
+        params.extend(ids.iter().map(|v| ("songId", v.as_str())));
+
+        self.request::<Empty>("createPlaylist", &params)
+            .await
+            .with_context(|| format!("Failed to write Subsonic playlist {name}"))?;
+
+        Ok(existing.is_none())
+    }
+}
@@ -0,0 +1,119 @@
+use std::io::IsTerminal;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::{api, cli::Args, output, profile::Profile, GetPlaylistsResponseItem};
+
+/// Counts of what's in the library.
+#[derive(Serialize, Debug)]
+struct Summary {
+    liked: u32,
+    saved_albums: u32,
+    saved_shows: u32,
+    playlists: Vec<PlaylistCount>,
+}
+
+#[derive(Serialize, Debug)]
+struct PlaylistCount {
+    id: String,
+    name: String,
+    tracks: u32,
+}
+
+/// Prints how many liked songs, saved albums and saved shows are in the library, along with the
+/// playlists and how many tracks each has. Only the first page of each is fetched, for its total,
+/// so it's quick even for large libraries and doubles as a check that authentication works.
+pub async fn run(args: &Args) -> Result<()> {
+    let profile = Profile::new(&args.profile)?;
+    let client = crate::cli::build_client(&profile, args).await?;
+
+    let (liked, saved_albums, saved_shows, playlists) = tokio::try_join!(
+        total(&client, args, "me/tracks", "liked songs"),
+        total(&client, args, "me/albums", "saved albums"),
+        total(&client, args, "me/shows", "saved shows"),
+        async {
+            crate::cli::fetch_all::<GetPlaylistsResponseItem>(
+                &client,
+                args.api.first_page_url("me/playlists", 50),
+            )
+            .await
+            .context("Failed to fetch playlists")
+        },
+    )?;
+
+    let summary = Summary {
+        liked,
+        saved_albums,
+        saved_shows,
+        playlists: playlists
+            .into_iter()
+            .map(|v| PlaylistCount {
+                id: v.id,
+                name: v.name,
+                tracks: v.tracks.total,
+            })
+            .collect(),
+    };
+
+    print(args, &summary)
+}
+
+/// Total number of items in the paginated endpoint at `path`.
+async fn total(client: &api::Client, args: &Args, path: &str, name: &str) -> Result<u32> {
+    let page = crate::fetch_page::<serde_json::Value>(client, args.api.first_page_url(path, 1))
+        .await
+        .with_context(|| format!("Failed to count {name}"))?;
+
+    Ok(page.total)
+}
+
+fn print(args: &Args, summary: &Summary) -> Result<()> {
+    let terminal = std::io::stdout().is_terminal();
+
+    match args.format {
+        output::Format::Json => {
+            let json = if args.pretty || terminal {
+                serde_json::to_string_pretty(summary)?
+            } else {
+                serde_json::to_string(summary)?
+            };
+            println!("{json}");
+        }
+        output::Format::Table => {
+            let playlist_tracks: u32 = summary.playlists.iter().map(|v| v.tracks).sum();
+
+            let mut table = output::table(&["Library", "Count"], terminal, &[1]);
+            for (name, count) in [
+                ("Liked songs", summary.liked),
+                ("Saved albums", summary.saved_albums),
+                ("Saved shows", summary.saved_shows),
+                ("Playlists", summary.playlists.len() as u32),
+                ("Tracks in playlists", playlist_tracks),
+            ] {
+                output::add_row(&mut table, [name, &count.to_string()]);
+            }
+            println!("{table}");
+
+            if !summary.playlists.is_empty() {
+                let mut table = output::table(&["Playlist", "Tracks", "ID"], terminal, &[1, 2]);
+                for playlist in &summary.playlists {
+                    output::add_row(
+                        &mut table,
+                        [
+                            playlist.name.as_str(),
+                            &playlist.tracks.to_string(),
+                            &playlist.id,
+                        ],
+                    );
+                }
+                println!("\n{table}");
+            }
+        }
+        _ => {
+            anyhow::bail!("The summary can only be printed as JSON or a table")
+        }
+    }
+
+    Ok(())
+}
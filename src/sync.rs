@@ -0,0 +1,241 @@
+use std::{collections::HashMap, io::IsTerminal, path::Path};
+
+use anyhow::Result;
+use futures::StreamExt;
+use serde::Serialize;
+use tracing::info;
+
+use crate::{
+    cli::Args,
+    map::{self, Service, Track},
+    output,
+    profile::Profile,
+};
+
+/// Searches of the server's library run at once.
+const CONCURRENCY: usize = 4;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct SyncArgs {
+    /// Playlist IDs, links or URIs to fetch from Spotify, or paths to JSON backups
+    #[arg(required_unless_present_any = ["all", "liked"])]
+    pub sources: Vec<String>,
+    /// Syncs every playlist in the user's library
+    #[arg(long)]
+    pub all: bool,
+    /// Syncs liked songs
+    #[arg(long)]
+    pub liked: bool,
+    /// Name of the playlist on the server, instead of that of the Spotify playlist or backup file
+    #[arg(long)]
+    pub name: Option<String>,
+    /// Lowest confidence, from 0 to 1, a match needs to be added to the playlist
+    #[arg(long, default_value_t = 0.6, value_parser = crate::similar::parse_threshold)]
+    pub min_confidence: f64,
+    /// Matches the tracks and prints the results without changing any playlists
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+/// A music server playlists can be mirrored onto, searched for tracks through [`Service`].
+pub trait Destination: Service {
+    /// Replaces the tracks of the playlist named `name` with those with the IDs `ids`, creating it
+    /// if there isn't one. Returns whether it was created.
+    async fn replace_playlist(&self, name: &str, ids: &[String]) -> Result<bool>;
+}
+
+/// Playlists mirrored onto the server, with the tracks that couldn't be found in its library.
+#[derive(Serialize, Debug)]
+struct Report {
+    playlists: Vec<PlaylistReport>,
+}
+
+#[derive(Serialize, Debug)]
+struct PlaylistReport {
+    name: String,
+    tracks: usize,
+    matched: usize,
+    /// Whether the playlist was created, rather than an existing one of the same name updated
+    created: bool,
+    unmatched: Vec<Unmatched>,
+}
+
+#[derive(Serialize, Debug)]
+struct Unmatched {
+    name: String,
+    artists: Vec<String>,
+    uri: String,
+}
+
+/// Matches the tracks of each source against the server's library and replaces the playlist of
+/// the same name with those found, creating it if needed. Sources are playlist IDs, links or URIs,
+/// fetched from Spotify and named after the playlist, or backup files, named after the file or
+/// `--name`. With `--dry-run` nothing on the server is changed.
+pub async fn run(args: &Args, server: &impl Destination, sync: &SyncArgs) -> Result<()> {
+    let sources = sync.sources.len() + usize::from(sync.all) + usize::from(sync.liked);
+    if sync.name.is_some() && sources > 1 {
+        anyhow::bail!("--name can only be given when syncing a single playlist");
+    }
+
+    let playlists = load(
+        args,
+        &sync.sources,
+        sync.all,
+        sync.liked,
+        sync.name.as_deref(),
+    )
+    .await?;
+
+    // tracks in several playlists are only looked up once
+    let mut matches = HashMap::new();
+    let mut report = Report {
+        playlists: Vec::new(),
+    };
+
+    for (name, tracks) in playlists {
+        info!(
+            "Matching {} track(s) of {name} on {}...",
+            tracks.len(),
+            server.name()
+        );
+
+        let lookups: Vec<_> = futures::stream::iter(&tracks)
+            .filter(|track| std::future::ready(!matches.contains_key(&track.uri)))
+            .map(|track| async move {
+                let (confidence, best) = map::best_match(server, track).await;
+                let id = best
+                    .filter(|_| confidence >= sync.min_confidence)
+                    .map(|v| v.id);
+                (track.uri.clone(), id)
+            })
+            .buffered(CONCURRENCY)
+            .collect()
+            .await;
+        matches.extend(lookups);
+
+        let mut ids = Vec::new();
+        let mut unmatched = Vec::new();
+        for track in &tracks {
+            match matches.get(&track.uri).cloned().flatten() {
+                Some(id) => ids.push(id),
+                None => unmatched.push(Unmatched {
+                    name: track.name.clone(),
+                    artists: track.artists.clone(),
+                    uri: track.uri.clone(),
+                }),
+            }
+        }
+
+        let created = match sync.dry_run {
+            true => false,
+            false => server.replace_playlist(&name, &ids).await?,
+        };
+        info!(
+            "Matched {} of {} track(s) of {name}",
+            ids.len(),
+            tracks.len()
+        );
+
+        report.playlists.push(PlaylistReport {
+            name,
+            tracks: tracks.len(),
+            matched: ids.len(),
+            created,
+            unmatched,
+        });
+    }
+
+    print(args, &report)
+}
+
+/// Tracks of each source (a playlist ID, link or URI, or a backup file), along with the name of
+/// its playlist, or `name` if given. With `all` and `liked` every playlist in the library and liked
+/// songs are fetched too.
+pub async fn load(
+    args: &Args,
+    sources: &[String],
+    all: bool,
+    liked: bool,
+    name: Option<&str>,
+) -> Result<Vec<(String, Vec<Track>)>> {
+    let mut playlists = Vec::new();
+    let mut ids = Vec::new();
+
+    for source in sources {
+        let path = Path::new(source);
+        if tokio::fs::metadata(path).await.is_ok_and(|v| v.is_file()) {
+            // backups are named like playlist-<id>.json.zst, which is the best there is to go on
+            let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+            let stem = file_name.split('.').next().unwrap_or_default();
+            let name = name.unwrap_or(stem).to_string();
+
+            playlists.push((name, map::read_tracks(args, path).await?));
+        } else {
+            ids.push(crate::link::parse_playlist_id(source)?);
+        }
+    }
+
+    if all || liked || !ids.is_empty() {
+        let profile = Profile::new(&args.profile)?;
+        let client = crate::cli::build_client(&profile, args).await?;
+
+        for source in crate::cli::fetch_sources(&client, args, &ids, all, liked).await? {
+            let tracks = source
+                .items
+                .iter()
+                .filter(|v| !v.track.uri.starts_with("spotify:episode:"))
+                .map(|v| Track::fetched(&v.track))
+                .collect();
+
+            playlists.push((name.unwrap_or(&source.name).to_string(), tracks));
+        }
+    }
+
+    Ok(playlists)
+}
+
+fn print(args: &Args, report: &Report) -> Result<()> {
+    let terminal = std::io::stdout().is_terminal();
+
+    match args.format {
+        output::Format::Json => {
+            let json = if args.pretty || terminal {
+                serde_json::to_string_pretty(report)?
+            } else {
+                serde_json::to_string(report)?
+            };
+            println!("{json}");
+        }
+        output::Format::Table => {
+            let mut table = output::table(&["Playlist", "Track", "Artists", "URI"], terminal, &[3]);
+            for playlist in &report.playlists {
+                for track in &playlist.unmatched {
+                    output::add_row(
+                        &mut table,
+                        [
+                            playlist.name.as_str(),
+                            &track.name,
+                            &track.artists.join(", "),
+                            &track.uri,
+                        ],
+                    );
+                }
+            }
+            if report.playlists.iter().any(|v| !v.unmatched.is_empty()) {
+                println!("{table}");
+            }
+
+            for playlist in &report.playlists {
+                println!(
+                    "{}: matched {} of {} track(s)",
+                    playlist.name, playlist.matched, playlist.tracks
+                );
+            }
+        }
+        _ => {
+            anyhow::bail!("Sync results can only be written as JSON or a table")
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,292 @@
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::{Context, Result};
+use reqwest::{StatusCode, Url};
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::sync::OnceCell;
+
+use crate::{
+    cli::Args,
+    map::{Candidate, Service, Track},
+};
+
+const API_URL: &str = "https://openapi.tidal.com/v2";
+
+const TOKEN_URL: &str = "https://auth.tidal.com/v1/oauth2/token";
+
+/// Media type of the JSON:API documents Tidal's API returns.
+const CONTENT_TYPE: &str = "application/vnd.api+json";
+
+/// Most search results scored for each track.
+const MAX_RESULTS: usize = 5;
+
+/// Country tracks are looked up in when `--market` isn't given, as the API needs one.
+const DEFAULT_COUNTRY: &str = "US";
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct TidalArgs {
+    /// Client ID of your Tidal developer app (https://developer.tidal.com/dashboard)
+    #[arg(long, env = "TIDAL_CLIENT_ID", global = true)]
+    pub tidal_client_id: Option<String>,
+    /// Client secret of your Tidal developer app
+    #[arg(
+        long,
+        env = "TIDAL_CLIENT_SECRET",
+        hide_env_values = true,
+        global = true
+    )]
+    pub tidal_client_secret: Option<String>,
+}
+
+/// Finds tracks on Tidal, through its public catalog API with the credentials of a developer app.
+pub struct Tidal {
+    http: reqwest::Client,
+    client_id: String,
+    client_secret: String,
+    country: String,
+    /// Fetched on the first request, as the app's tokens last longer than any run
+    token: OnceCell<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TokenResponse {
+    access_token: String,
+}
+
+/// A JSON:API document, with the resources asked for in `data` and those they refer to in
+/// `included`.
+#[derive(Deserialize, Debug)]
+struct Document {
+    #[serde(default)]
+    data: Vec<Resource>,
+    #[serde(default)]
+    included: Vec<Resource>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Resource {
+    id: String,
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    attributes: Value,
+    #[serde(default)]
+    relationships: Value,
+}
+
+impl Tidal {
+    pub fn new(args: &Args, tidal: &TidalArgs) -> Result<Self> {
+        let client_id = tidal.tidal_client_id.clone().context(
+            "Missing Tidal client ID, set TIDAL_CLIENT_ID to that of a developer app \
+             (https://developer.tidal.com/dashboard)",
+        )?;
+        let client_secret = tidal
+            .tidal_client_secret
+            .clone()
+            .context("Missing Tidal client secret, set TIDAL_CLIENT_SECRET")?;
+
+        Ok(Self {
+            http: crate::http::client(),
+            client_id,
+            client_secret,
+            country: args
+                .api
+                .market
+                .clone()
+                .unwrap_or_else(|| DEFAULT_COUNTRY.to_string()),
+            token: OnceCell::new(),
+        })
+    }
+
+    async fn token(&self) -> Result<&str> {
+        let token = self
+            .token
+            .get_or_try_init(|| async {
+                let response: TokenResponse = self
+                    .http
+                    .post(TOKEN_URL)
+                    .basic_auth(&self.client_id, Some(&self.client_secret))
+                    .form(&[("grant_type", "client_credentials")])
+                    .send()
+                    .await
+                    .context("Failed to send request to Tidal")?
+                    .error_for_status()
+                    .context("Failed to authenticate with Tidal, check the client ID and secret")?
+                    .json()
+                    .await
+                    .context("Failed to parse token from Tidal")?;
+
+                Ok::<_, anyhow::Error>(response.access_token)
+            })
+            .await?;
+
+        Ok(token)
+    }
+
+    /// Fetches `url`, waiting out the rate limit if it's been hit.
+    async fn get(&self, url: Url) -> Result<Document> {
+        loop {
+            let response = self
+                .http
+                .get(url.clone())
+                .bearer_auth(self.token().await?)
+                .header("Accept", CONTENT_TYPE)
+                .send()
+                .await
+                .context("Failed to send request to Tidal")?;
+
+            if response.status() == StatusCode::TOO_MANY_REQUESTS {
+                let wait = response
+                    .headers()
+                    .get("Retry-After")
+                    .and_then(|v| v.to_str().ok()?.parse().ok())
+                    .unwrap_or(5);
+                tokio::time::sleep(Duration::from_secs(wait)).await;
+                continue;
+            }
+
+            return response
+                .error_for_status()
+                .context("Failed to query Tidal")?
+                .json()
+                .await
+                .context("Failed to parse response from Tidal");
+        }
+    }
+
+    /// Fetches the tracks matching `filter` (eg. by ISRC or ID) with their artists and albums.
+    async fn tracks(&self, filter: (&str, &str), by_isrc: bool) -> Result<Vec<Candidate>> {
+        let url = Url::parse_with_params(
+            &format!("{API_URL}/tracks"),
+            [
+                ("countryCode", self.country.as_str()),
+                ("include", "artists,albums"),
+                filter,
+            ],
+        )?;
+
+        Ok(candidates(self.get(url).await?, by_isrc))
+    }
+}
+
+impl Service for Tidal {
+    fn name(&self) -> &'static str {
+        "tidal"
+    }
+
+    async fn search(&self, track: &Track) -> Result<Vec<Candidate>> {
+        if let Some(isrc) = &track.isrc {
+            let found = self.tracks(("filter[isrc]", isrc), true).await?;
+            if !found.is_empty() {
+                return Ok(found);
+            }
+        }
+
+        let query = match track.artists.first() {
+            Some(artist) => format!("{artist} {}", track.name),
+            None => track.name.clone(),
+        };
+        let mut url = Url::parse_with_params(
+            &format!("{API_URL}/searchResults"),
+            [("countryCode", self.country.as_str())],
+        )?;
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("Invalid Tidal API URL"))?
+            .extend([query.as_str(), "relationships", "tracks"]);
+
+        // search results only link to the tracks, so they're fetched again for their artists
+        let ids: Vec<_> = self
+            .get(url)
+            .await?
+            .data
+            .into_iter()
+            .filter(|v| v.kind == "tracks")
+            .take(MAX_RESULTS)
+            .map(|v| v.id)
+            .collect();
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        self.tracks(("filter[id]", &ids.join(",")), false).await
+    }
+}
+
+/// Reads the tracks out of a document, with the names of their artists and album looked up in the
+/// resources it includes.
+fn candidates(document: Document, by_isrc: bool) -> Vec<Candidate> {
+    let included: HashMap<_, _> = document
+        .included
+        .iter()
+        .map(|v| ((v.kind.as_str(), v.id.as_str()), &v.attributes))
+        .collect();
+    let related = |track: &Resource, relationship: &str, kind: &str| -> Vec<(String, String)> {
+        track.relationships[relationship]["data"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|v| {
+                let id = v["id"].as_str()?;
+                let attributes = included.get(&(kind, id))?;
+                let name = attributes["name"]
+                    .as_str()
+                    .or(attributes["title"].as_str())?;
+                Some((id.to_string(), name.to_string()))
+            })
+            .collect()
+    };
+
+    document
+        .data
+        .iter()
+        .filter(|v| v.kind == "tracks")
+        .map(|track| {
+            let mut title = track.attributes["title"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string();
+            // versions like "Remastered" are kept apart from the title
+            if let Some(version) = track.attributes["version"].as_str() {
+                title = format!("{title} ({version})");
+            }
+            let album = related(track, "albums", "albums").into_iter().next();
+
+            Candidate {
+                id: track.id.clone(),
+                title,
+                artists: related(track, "artists", "artists")
+                    .into_iter()
+                    .map(|v| v.1)
+                    .collect(),
+                album_id: album.as_ref().map(|v| v.0.clone()),
+                album: album.map(|v| v.1),
+                duration_ms: track.attributes["duration"]
+                    .as_str()
+                    .and_then(parse_duration),
+                by_isrc,
+            }
+        })
+        .collect()
+}
+
+/// Parses an ISO 8601 duration like "PT3M45S" into milliseconds.
+fn parse_duration(text: &str) -> Option<u64> {
+    let mut rest = text.strip_prefix("PT")?;
+    let mut ms = 0.0;
+
+    while !rest.is_empty() {
+        let end = rest.find(|c: char| c.is_ascii_alphabetic())?;
+        let value: f64 = rest[..end].parse().ok()?;
+        ms += value
+            * match &rest[end..end + 1] {
+                "H" => 3_600_000.0,
+                "M" => 60_000.0,
+                "S" => 1000.0,
+                _ => return None,
+            };
+        rest = &rest[end + 1..];
+    }
+
+    Some(ms as u64)
+}
@@ -0,0 +1,303 @@
+use std::{fs::TryLockError, io::ErrorKind, path::PathBuf};
+
+use anyhow::{Context, Result};
+use argon2::Argon2;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chacha20poly1305::{aead::Aead, ChaCha20Poly1305, KeyInit, Nonce};
+use clap::ValueEnum;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+#[cfg(feature = "keyring")]
+use tracing::warn;
+
+use crate::profile::Profile;
+
+#[cfg(feature = "keyring")]
+const KEYRING_SERVICE: &str = "spotify-backup";
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenStoreKind {
+    /// Use the OS keyring when one is available, falling back to a file in the state dir
+    Auto,
+    /// Use the OS keyring (macOS Keychain, Windows Credential Manager, Secret Service)
+    #[cfg(feature = "keyring")]
+    Keyring,
+    /// Use a file in the profile's state dir
+    File,
+}
+
+/// Persists serialized OAuth token state for a profile either in the OS keyring or a file in the
+/// profile's state directory.
+pub struct TokenStore {
+    kind: TokenStoreKind,
+    path: PathBuf,
+    keyring_user: String,
+    /// Passphrase the file is encrypted with, for machines without a keyring
+    passphrase: Option<String>,
+}
+
+impl TokenStore {
+    pub fn new(kind: TokenStoreKind, profile: &Profile, file_name: &str) -> Self {
+        Self {
+            kind,
+            path: profile.dir().join(file_name),
+            keyring_user: format!("{}/{file_name}", profile.name()),
+            passphrase: None,
+        }
+    }
+
+    /// Stores the token in a file encrypted with the given passphrase rather than the keyring.
+    pub fn with_passphrase(mut self, passphrase: String) -> Self {
+        self.kind = TokenStoreKind::File;
+        self.passphrase = Some(passphrase);
+        self
+    }
+
+    pub async fn read(&self) -> Result<Option<String>> {
+        // fall through to the file in case the token was stored before the keyring was used,
+        // it'll be migrated into the keyring on the next write
+        if let Some(data) = self.read_keyring().await? {
+            return Ok(Some(data));
+        }
+
+        let data = match tokio::fs::read_to_string(&self.path).await {
+            Ok(v) => v,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).context("Failed to read token state"),
+        };
+
+        // plaintext state is read as-is, it'll be encrypted on the next write if a passphrase
+        // was given
+        let Ok(sealed) = serde_json::from_str::<SealedTokenState>(&data) else {
+            return Ok(Some(data));
+        };
+
+        let passphrase = self
+            .passphrase
+            .as_deref()
+            .context("Token state is encrypted, pass --token-passphrase to decrypt it")?;
+
+        open(&sealed, passphrase).map(Some)
+    }
+
+    pub async fn write(&self, data: &str) -> Result<()> {
+        if self.write_keyring(data).await? {
+            return self.remove_file().await;
+        }
+
+        let data = match &self.passphrase {
+            Some(passphrase) => serde_json::to_string(&seal(data, passphrase)?)
+                .context("Failed to serialize encrypted token state")?,
+            None => data.to_string(),
+        };
+
+        crate::atomic::write_private(&self.path, data)
+            .await
+            .context("Failed to write token state")
+    }
+
+    /// Takes an exclusive advisory lock on the token, held until the returned file is dropped, so
+    /// overlapping runs don't both refresh it and clobber each other's rotated refresh token.
+    pub async fn lock(&self) -> Result<std::fs::File> {
+        let path = self.path.with_extension("lock");
+
+        tokio::task::spawn_blocking(move || {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&path)
+                .context("Failed to open token lock file")?;
+
+            match file.try_lock() {
+                Ok(()) => {}
+                Err(TryLockError::WouldBlock) => {
+                    info!("Waiting for another run to finish refreshing the token...");
+                    file.lock().context("Failed to lock token")?;
+                }
+                Err(TryLockError::Error(e)) => return Err(e).context("Failed to lock token"),
+            }
+
+            Ok(file)
+        })
+        .await
+        .context("Failed to wait for token lock")?
+    }
+
+    /// Deletes the token from both the keyring and the state dir, returning a description of
+    /// every location it was removed from.
+    pub async fn delete(&self) -> Result<Vec<String>> {
+        let mut removed = Vec::new();
+
+        if self.delete_keyring().await? {
+            removed.push(format!("OS keyring entry {}", self.keyring_user));
+        }
+
+        match tokio::fs::remove_file(&self.path).await {
+            Ok(()) => removed.push(self.path.display().to_string()),
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e).context("Failed to delete token state file"),
+        }
+
+        Ok(removed)
+    }
+
+    /// Removes the plaintext copy of the token once it's been moved into the keyring.
+    async fn remove_file(&self) -> Result<()> {
+        match tokio::fs::remove_file(&self.path).await {
+            Ok(()) => {
+                info!(
+                    "Moved token from {} into the OS keyring",
+                    self.path.display()
+                );
+                Ok(())
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e).context("Failed to remove token state file"),
+        }
+    }
+
+    /// The token in the keyring, if it's used and there is one.
+    #[cfg(feature = "keyring")]
+    async fn read_keyring(&self) -> Result<Option<String>> {
+        if self.kind == TokenStoreKind::File {
+            return Ok(None);
+        }
+
+        match self.keyring(|entry| entry.get_password()).await {
+            Ok(v) => Ok(Some(v)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) if self.kind == TokenStoreKind::Auto && is_unavailable(&e) => Ok(None),
+            Err(e) => Err(e).context("Failed to read token from keyring"),
+        }
+    }
+
+    /// Writes the token to the keyring if it's used, returning whether it was.
+    #[cfg(feature = "keyring")]
+    async fn write_keyring(&self, data: &str) -> Result<bool> {
+        if self.kind == TokenStoreKind::File {
+            return Ok(false);
+        }
+
+        let data = data.to_string();
+        match self.keyring(move |entry| entry.set_password(&data)).await {
+            Ok(()) => Ok(true),
+            Err(e) if self.kind == TokenStoreKind::Auto && is_unavailable(&e) => {
+                warn!("OS keyring unavailable ({e}), storing token in a file instead");
+                Ok(false)
+            }
+            Err(e) => Err(e).context("Failed to write token to keyring"),
+        }
+    }
+
+    /// Deletes the token from the keyring if it's used, returning whether there was one.
+    #[cfg(feature = "keyring")]
+    async fn delete_keyring(&self) -> Result<bool> {
+        if self.kind == TokenStoreKind::File {
+            return Ok(false);
+        }
+
+        match self.keyring(|entry| entry.delete_credential()).await {
+            Ok(()) => Ok(true),
+            Err(keyring::Error::NoEntry) => Ok(false),
+            Err(e) if self.kind == TokenStoreKind::Auto && is_unavailable(&e) => Ok(false),
+            Err(e) => Err(e).context("Failed to delete token from keyring"),
+        }
+    }
+
+    // without keyring support every token is stored the way `auto` does on machines without one
+
+    #[cfg(not(feature = "keyring"))]
+    async fn read_keyring(&self) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    #[cfg(not(feature = "keyring"))]
+    async fn write_keyring(&self, _: &str) -> Result<bool> {
+        Ok(false)
+    }
+
+    #[cfg(not(feature = "keyring"))]
+    async fn delete_keyring(&self) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// Runs a keyring operation off the async runtime, since the platform APIs are blocking.
+    #[cfg(feature = "keyring")]
+    async fn keyring<T, F>(&self, f: F) -> keyring::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&keyring::Entry) -> keyring::Result<T> + Send + 'static,
+    {
+        let user = self.keyring_user.clone();
+
+        tokio::task::spawn_blocking(move || f(&keyring::Entry::new(KEYRING_SERVICE, &user)?))
+            .await
+            .map_err(|e| keyring::Error::PlatformFailure(Box::new(e)))?
+    }
+}
+
+/// Whether the error indicates there's no usable keyring on this machine, rather than a problem
+/// with the entry itself.
+#[cfg(feature = "keyring")]
+fn is_unavailable(e: &keyring::Error) -> bool {
+    matches!(
+        e,
+        keyring::Error::PlatformFailure(_) | keyring::Error::NoStorageAccess(_)
+    )
+}
+
+/// Token state encrypted with ChaCha20-Poly1305 using a key derived from a passphrase with
+/// Argon2id.
+#[derive(Serialize, Deserialize)]
+struct SealedTokenState {
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<chacha20poly1305::Key> {
+    let mut key = chacha20poly1305::Key::default();
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow::anyhow!("Failed to derive key from passphrase: {e}"))?;
+    Ok(key)
+}
+
+fn seal(data: &str, passphrase: &str) -> Result<SealedTokenState> {
+    let mut salt = [0; 16];
+    let mut nonce = [0; 12];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let ciphertext = ChaCha20Poly1305::new(&derive_key(passphrase, &salt)?)
+        .encrypt(Nonce::from_slice(&nonce), data.as_bytes())
+        .map_err(|_| anyhow::anyhow!("Failed to encrypt token state"))?;
+
+    Ok(SealedTokenState {
+        salt: BASE64.encode(salt),
+        nonce: BASE64.encode(nonce),
+        ciphertext: BASE64.encode(ciphertext),
+    })
+}
+
+fn open(sealed: &SealedTokenState, passphrase: &str) -> Result<String> {
+    let salt = BASE64
+        .decode(&sealed.salt)
+        .context("Invalid token state salt")?;
+    let nonce = BASE64
+        .decode(&sealed.nonce)
+        .context("Invalid token state nonce")?;
+    let ciphertext = BASE64
+        .decode(&sealed.ciphertext)
+        .context("Invalid token state ciphertext")?;
+
+    anyhow::ensure!(nonce.len() == 12, "Invalid token state nonce");
+
+    let plaintext = ChaCha20Poly1305::new(&derive_key(passphrase, &salt)?)
+        .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+        .map_err(|_| anyhow::anyhow!("Failed to decrypt token state, wrong passphrase?"))?;
+
+    String::from_utf8(plaintext).context("Decrypted token state isn't valid UTF-8")
+}
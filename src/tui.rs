@@ -0,0 +1,419 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    future::Future,
+    pin::Pin,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout},
+    style::{Style, Stylize},
+    text::Line,
+    widgets::{Block, Gauge, List, ListItem, ListState, Paragraph, Row, Table, TableState},
+    DefaultTerminal, Frame,
+};
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+use crate::{
+    api,
+    cli::fetch_all,
+    cli::playlist_jobs,
+    cli::Args,
+    profile::Profile,
+    progress::{self, Progress},
+    GetPlaylistTracksResponseItem, GetPlaylistsResponseItem,
+};
+
+/// Number of log lines shown beneath the library.
+const LOG_LINES: usize = 3;
+
+type Task<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
+
+/// Lets the user browse their playlists and their tracks, and back up the ones they mark while
+/// watching the progress of each.
+pub async fn run(args: &Args) -> Result<()> {
+    anyhow::ensure!(
+        !args.auth.non_interactive,
+        "The TUI can't be used with --non-interactive"
+    );
+
+    let profile = Profile::new(&args.profile)?;
+    // authenticated before taking over the terminal, in case the user has to be prompted
+    let client = crate::cli::build_client(&profile, args).await?;
+
+    let playlists =
+        fetch_all::<GetPlaylistsResponseItem>(&client, args.api.first_page_url("me/playlists", 50))
+            .await
+            .context("Failed to fetch playlists")?;
+
+    let (log_sender, logs) = mpsc::unbounded_channel();
+    progress::capture_logs(Some(log_sender));
+
+    let mut terminal = ratatui::init();
+    let result = App::new(args, playlists)
+        .run(&mut terminal, &client, logs)
+        .await;
+    ratatui::restore();
+
+    progress::capture_logs(None);
+
+    result
+}
+
+enum View {
+    Playlists,
+    Tracks {
+        name: String,
+        tracks: Vec<GetPlaylistTracksResponseItem>,
+        state: TableState,
+    },
+}
+
+/// What a key press asked for that can't be done by updating the state straight away.
+enum Action {
+    Quit,
+    Open(usize),
+    Backup(Vec<String>),
+}
+
+enum JobState {
+    Running { fetched: u64, total: u64 },
+    Done,
+    Failed(String),
+}
+
+struct App<'a> {
+    args: &'a Args,
+    playlists: Vec<GetPlaylistsResponseItem>,
+    /// IDs of the playlists marked for backing up
+    marked: HashSet<String>,
+    list: ListState,
+    view: View,
+    jobs: Vec<(String, JobState)>,
+    logs: VecDeque<String>,
+}
+
+impl<'a> App<'a> {
+    fn new(args: &'a Args, playlists: Vec<GetPlaylistsResponseItem>) -> Self {
+        Self {
+            args,
+            playlists,
+            marked: HashSet::new(),
+            list: ListState::default().with_selected(Some(0)),
+            view: View::Playlists,
+            jobs: Vec::new(),
+            logs: VecDeque::new(),
+        }
+    }
+
+    async fn run(
+        mut self,
+        terminal: &mut DefaultTerminal,
+        client: &api::Client,
+        mut logs: UnboundedReceiver<String>,
+    ) -> Result<()> {
+        let mut keys = read_keys();
+        let (progress_sender, mut progress_events) = mpsc::unbounded_channel();
+
+        // a playlist's tracks and a backup are fetched in the background, so the library can
+        // still be browsed while they run
+        let mut loading: Option<Task<'_, (String, Result<Vec<GetPlaylistTracksResponseItem>>)>> =
+            None;
+        let mut backup: Option<Task<'_, Result<()>>> = None;
+
+        loop {
+            terminal
+                .draw(|frame| self.draw(frame, loading.is_some()))
+                .context("Failed to draw TUI")?;
+
+            tokio::select! {
+                Some(key) = keys.recv() => match self.handle_key(key) {
+                    Some(Action::Quit) => return Ok(()),
+                    Some(Action::Open(index)) => {
+                        let playlist = &self.playlists[index];
+                        let name = playlist.name.clone();
+                        let url = self
+                            .args
+                            .api
+                            .first_tracks_page_url(
+                                &format!("playlists/{}/tracks", playlist.id),
+                                100,
+                            );
+
+                        loading = Some(Box::pin(async move { (name, fetch_all(client, url).await) }));
+                    }
+                    Some(Action::Backup(_)) if backup.is_some() => {
+                        self.log("A backup is already running".to_string());
+                    }
+                    Some(Action::Backup(_)) if self.args.output.is_none() => {
+                        self.log("Pass --output to back up from the TUI".to_string());
+                    }
+                    Some(Action::Backup(ids)) => {
+                        let jobs = playlist_jobs(self.args, &ids);
+                        let progress = Progress::channel(jobs.len(), progress_sender.clone());
+
+                        self.jobs.clear();
+                        backup = Some(Box::pin(crate::cli::backup_with_progress(
+                            self.args, jobs, progress,
+                        )));
+                    }
+                    None => {}
+                },
+                Some(line) = logs.recv() => self.log(line),
+                Some(event) = progress_events.recv() => self.update_job(event),
+                (name, result) = async { loading.as_mut().unwrap().await }, if loading.is_some() => {
+                    loading = None;
+
+                    match result {
+                        Ok(tracks) => {
+                            self.view = View::Tracks {
+                                name,
+                                tracks,
+                                state: TableState::default().with_selected(Some(0)),
+                            };
+                        }
+                        Err(e) => self.log(format!("Failed to fetch {name}: {e:#}")),
+                    }
+                }
+                result = async { backup.as_mut().unwrap().await }, if backup.is_some() => {
+                    backup = None;
+
+                    match result {
+                        Ok(()) => self.log("Backup finished".to_string()),
+                        Err(e) => self.log(format!("Backup failed: {e:#}")),
+                    }
+                }
+            }
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> Option<Action> {
+        if key.kind != KeyEventKind::Press {
+            return None;
+        }
+
+        if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            return Some(Action::Quit);
+        }
+
+        if let View::Tracks { state, .. } = &mut self.view {
+            match key.code {
+                KeyCode::Esc | KeyCode::Backspace | KeyCode::Char('q') => {
+                    self.view = View::Playlists;
+                }
+                KeyCode::Up | KeyCode::Char('k') => state.select_previous(),
+                KeyCode::Down | KeyCode::Char('j') => state.select_next(),
+                _ => {}
+            }
+
+            return None;
+        }
+
+        let selected = self.list.selected().filter(|v| *v < self.playlists.len());
+
+        match key.code {
+            KeyCode::Esc | KeyCode::Char('q') => return Some(Action::Quit),
+            KeyCode::Up | KeyCode::Char('k') => self.list.select_previous(),
+            KeyCode::Down | KeyCode::Char('j') => self.list.select_next(),
+            KeyCode::Enter => return selected.map(Action::Open),
+            KeyCode::Char(' ') => {
+                if let Some(playlist) = selected.map(|v| &self.playlists[v]) {
+                    if !self.marked.remove(&playlist.id) {
+                        self.marked.insert(playlist.id.clone());
+                    }
+                }
+            }
+            KeyCode::Char('a') => {
+                if self.marked.len() == self.playlists.len() {
+                    self.marked.clear();
+                } else {
+                    self.marked = self.playlists.iter().map(|v| v.id.clone()).collect();
+                }
+            }
+            KeyCode::Char('b') => {
+                // backs up the selected playlist if none have been marked
+                let ids: Vec<_> = if self.marked.is_empty() {
+                    selected
+                        .map(|v| self.playlists[v].id.clone())
+                        .into_iter()
+                        .collect()
+                } else {
+                    self.playlists
+                        .iter()
+                        .filter(|v| self.marked.contains(&v.id))
+                        .map(|v| v.id.clone())
+                        .collect()
+                };
+
+                if !ids.is_empty() {
+                    return Some(Action::Backup(ids));
+                }
+            }
+            _ => {}
+        }
+
+        None
+    }
+
+    fn update_job(&mut self, event: progress::Event) {
+        match event {
+            progress::Event::Started { file } => self.jobs.push((
+                file,
+                JobState::Running {
+                    fetched: 0,
+                    total: 0,
+                },
+            )),
+            progress::Event::Page {
+                file,
+                fetched,
+                total,
+            } => self.set_job(&file, JobState::Running { fetched, total }),
+            progress::Event::Finished { file, error: None } => self.set_job(&file, JobState::Done),
+            progress::Event::Finished {
+                file,
+                error: Some(e),
+            } => self.set_job(&file, JobState::Failed(e)),
+            progress::Event::Retrying { .. }
+            | progress::Event::RateLimited { .. }
+            | progress::Event::Done { .. } => {}
+        }
+    }
+
+    fn set_job(&mut self, file: &str, state: JobState) {
+        if let Some((_, v)) = self.jobs.iter_mut().find(|(name, _)| name == file) {
+            *v = state;
+        }
+    }
+
+    fn log(&mut self, line: String) {
+        self.logs.push_back(progress::strip_ansi(&line));
+
+        while self.logs.len() > LOG_LINES {
+            self.logs.pop_front();
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame, loading: bool) {
+        let [main, jobs, logs, help] = Layout::vertical([
+            Constraint::Min(3),
+            Constraint::Length(self.jobs.len().min(10) as u16),
+            Constraint::Length(LOG_LINES as u16),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        match &mut self.view {
+            View::Playlists => {
+                let items = self.playlists.iter().map(|v| {
+                    let mark = if self.marked.contains(&v.id) {
+                        "[x]"
+                    } else {
+                        "[ ]"
+                    };
+                    ListItem::new(format!("{mark} {} ({} tracks)", v.name, v.tracks.total))
+                });
+                let title = if loading {
+                    "Playlists (loading...)".to_string()
+                } else {
+                    format!("Playlists ({} marked)", self.marked.len())
+                };
+
+                frame.render_stateful_widget(
+                    List::new(items)
+                        .block(Block::bordered().title(title))
+                        .highlight_style(Style::new().reversed()),
+                    main,
+                    &mut self.list,
+                );
+            }
+            View::Tracks {
+                name,
+                tracks,
+                state,
+            } => {
+                let rows = tracks.iter().map(|v| {
+                    Row::new([
+                        v.track.name.clone(),
+                        v.track
+                            .artists
+                            .iter()
+                            .map(|v| v.name.as_str())
+                            .collect::<Vec<_>>()
+                            .join(", "),
+                        v.track.album.name.clone(),
+                    ])
+                });
+
+                frame.render_stateful_widget(
+                    Table::new(rows, [Constraint::Fill(1); 3])
+                        .header(Row::new(["Name", "Artists", "Album"]).bold())
+                        .block(Block::bordered().title(format!("{name} ({} tracks)", tracks.len())))
+                        .row_highlight_style(Style::new().reversed()),
+                    main,
+                    state,
+                );
+            }
+        }
+
+        let rows = Layout::vertical(vec![Constraint::Length(1); jobs.height as usize]).split(jobs);
+        for ((file, state), area) in self.jobs.iter().zip(rows.iter()) {
+            let (ratio, label) = match state {
+                JobState::Running { fetched, total } => (
+                    if *total == 0 {
+                        0.0
+                    } else {
+                        (*fetched as f64 / *total as f64).min(1.0)
+                    },
+                    format!("{file}: {fetched}/{total} tracks"),
+                ),
+                JobState::Done => (1.0, format!("{file}: done")),
+                JobState::Failed(e) => (0.0, format!("{file}: failed, {e}")),
+            };
+
+            frame.render_widget(Gauge::default().ratio(ratio).label(label), *area);
+        }
+
+        frame.render_widget(
+            Paragraph::new(
+                self.logs
+                    .iter()
+                    .map(|v| Line::raw(v.as_str()))
+                    .collect::<Vec<_>>(),
+            )
+            .dim(),
+            logs,
+        );
+
+        let keys = match self.view {
+            View::Playlists => {
+                "↑/↓ move  space mark  a mark all  enter tracks  b back up marked  q quit"
+            }
+            View::Tracks { .. } => "↑/↓ move  esc back",
+        };
+        frame.render_widget(Line::raw(keys).reversed(), help);
+    }
+}
+
+/// Reads key presses on a separate thread, as crossterm only offers a blocking API.
+fn read_keys() -> UnboundedReceiver<KeyEvent> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+
+    std::thread::spawn(move || {
+        while !sender.is_closed() {
+            match event::poll(Duration::from_millis(100)) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(_) => return,
+            }
+
+            if let Ok(Event::Key(key)) = event::read() {
+                let _ = sender.send(key);
+            }
+        }
+    });
+
+    receiver
+}
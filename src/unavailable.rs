@@ -0,0 +1,122 @@
+use std::io::IsTerminal;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{cli::Args, output, profile::Profile, GetPlaylistTracksResponseItem};
+
+/// Tracks that can't be played in one playlist, or in liked songs.
+#[derive(Serialize, Debug)]
+struct Report {
+    /// Playlist ID, missing for liked songs
+    id: Option<String>,
+    name: String,
+    tracks: usize,
+    unavailable: Vec<UnavailableTrack>,
+}
+
+#[derive(Serialize, Debug)]
+struct UnavailableTrack {
+    name: String,
+    artists: Vec<String>,
+    uri: String,
+    /// Why Spotify won't play the track (eg. market, product or explicit), if it says
+    reason: Option<String>,
+}
+
+/// Prints the tracks in liked songs with `liked` and the playlists given by `ids` (or every
+/// playlist with `all`) that Spotify no longer lets the user play in their market, which the app
+/// shows greyed out, along with how many there are in each.
+pub async fn run(args: &Args, ids: &[String], all: bool, liked: bool) -> Result<()> {
+    let profile = Profile::new(&args.profile)?;
+    let client = crate::cli::build_client(&profile, args).await?;
+
+    let reports: Vec<Report> = crate::cli::fetch_sources(&client, args, ids, all, liked)
+        .await?
+        .into_iter()
+        .map(|v| report(v.id, v.name, v.items))
+        .collect();
+
+    print(args, &reports)
+}
+
+fn report(id: Option<String>, name: String, items: Vec<GetPlaylistTracksResponseItem>) -> Report {
+    let tracks = items.len();
+    let unavailable = items
+        .into_iter()
+        .map(|v| v.track)
+        // local files are never playable through Spotify, there's nothing to lose
+        .filter(|v| v.is_playable == Some(false) && !v.uri.starts_with("spotify:local:"))
+        .map(|v| UnavailableTrack {
+            artists: v.artists.into_iter().map(|v| v.name).collect(),
+            reason: v.restrictions.map(|v| v.reason),
+            name: v.name,
+            uri: v.uri,
+        })
+        .collect();
+
+    Report {
+        id,
+        name,
+        tracks,
+        unavailable,
+    }
+}
+
+fn print(args: &Args, reports: &[Report]) -> Result<()> {
+    let terminal = std::io::stdout().is_terminal();
+
+    match args.format {
+        output::Format::Json => {
+            let json = if args.pretty || terminal {
+                serde_json::to_string_pretty(reports)?
+            } else {
+                serde_json::to_string(reports)?
+            };
+            println!("{json}");
+        }
+        output::Format::Table => {
+            let mut summary =
+                output::table(&["Playlist", "Tracks", "Unavailable"], terminal, &[1, 2]);
+            for report in reports {
+                output::add_row(
+                    &mut summary,
+                    [
+                        report.name.as_str(),
+                        &report.tracks.to_string(),
+                        &report.unavailable.len().to_string(),
+                    ],
+                );
+            }
+            println!("{summary}");
+
+            if reports.iter().any(|v| !v.unavailable.is_empty()) {
+                let mut table = output::table(
+                    &["Playlist", "Track", "Artists", "Reason", "URI"],
+                    terminal,
+                    &[4],
+                );
+                for report in reports {
+                    for track in &report.unavailable {
+                        output::add_row(
+                            &mut table,
+                            [
+                                report.name.as_str(),
+                                &track.name,
+                                &track.artists.join(", "),
+                                track.reason.as_deref().unwrap_or_default(),
+                                &track.uri,
+                            ],
+                        );
+                    }
+                }
+                println!("\n{table}");
+            }
+        }
+        _ => {
+            anyhow::bail!("Unavailable tracks can only be listed as JSON or a table")
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,213 @@
+use std::{
+    collections::BTreeMap,
+    io::IsTerminal,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+
+use reqwest::Url;
+use tracing::info;
+
+use crate::output;
+
+/// Most endpoints listed in the report printed by `--stats`.
+const SLOWEST_ENDPOINTS: usize = 10;
+
+/// How much of the Spotify API a run has used, for tuning `--concurrency` and `--page-size`. Unlike
+/// the metrics, which are kept for as long as the process runs, each run counts its own, shared by
+/// the clients it creates through their [`crate::api::ApiArgs`].
+#[derive(Clone, Debug, Default)]
+pub struct Usage(Arc<Mutex<Counts>>);
+
+#[derive(Debug, Default)]
+struct Counts {
+    /// Requests sent, including retries
+    requests: u64,
+    /// Conditional requests answered with 304 as the cached response was still current
+    not_modified: u64,
+    /// Bytes of response bodies read
+    downloaded: u64,
+    rate_limited: u64,
+    /// Time spent waiting out rate limiting and before retrying failed requests
+    slept: Duration,
+    endpoints: BTreeMap<String, Endpoint>,
+}
+
+/// Timing of the requests to an endpoint.
+#[derive(Debug, Default)]
+struct Endpoint {
+    requests: u32,
+    total: Duration,
+    slowest: Duration,
+}
+
+impl Usage {
+    fn counts(&self) -> MutexGuard<'_, Counts> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Counts a request to `url` that took `elapsed` to respond (or fail), with the status it got.
+    pub fn request(&self, url: &str, elapsed: Duration, status: Option<u16>) {
+        let mut counts = self.counts();
+        counts.requests += 1;
+        if status == Some(304) {
+            counts.not_modified += 1;
+        }
+        if status == Some(429) {
+            counts.rate_limited += 1;
+        }
+
+        let endpoint = counts.endpoints.entry(endpoint(url)).or_default();
+        endpoint.requests += 1;
+        endpoint.total += elapsed;
+        endpoint.slowest = endpoint.slowest.max(elapsed);
+    }
+
+    /// Counts the bytes of a response body that was read.
+    pub fn downloaded(&self, bytes: usize) {
+        self.counts().downloaded += bytes as u64;
+    }
+
+    /// Counts time spent waiting before retrying a request.
+    pub fn slept(&self, duration: Duration) {
+        self.counts().slept += duration;
+    }
+
+    /// Logs how much of the API was used since the last report, or with `detailed` prints it to
+    /// stderr along with the slowest endpoints, then starts counting afresh. Nothing is logged if
+    /// no requests were sent, eg. for commands that only read backups.
+    pub fn report(&self, detailed: bool) {
+        report(std::mem::take(&mut *self.counts()), detailed);
+    }
+}
+
+/// Path of the endpoint `url` is a request to, with the IDs in it replaced so requests for
+/// different playlists (eg. `playlists/{id}/tracks`) are counted together. IDs are the segments
+/// following the name of a kind of object, which is every other segment outside of `me`.
+fn endpoint(url: &str) -> String {
+    let Ok(url) = Url::parse(url) else {
+        return url.to_string();
+    };
+    let path = url.path().trim_start_matches("/v1/");
+
+    let segments: Vec<_> = path.split('/').collect();
+    if segments.first() == Some(&"me") {
+        return path.to_string();
+    }
+
+    segments
+        .iter()
+        .enumerate()
+        .map(|(i, v)| if i % 2 == 1 { "{id}" } else { v })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn report(usage: Counts, detailed: bool) {
+    if usage.requests == 0 {
+        return;
+    }
+
+    let summary = format!(
+        "Sent {} request(s) to Spotify ({} unchanged since cached), downloaded {}, rate limited {} \
+         time(s), waited {:.1}s before retrying",
+        usage.requests,
+        usage.not_modified,
+        format_bytes(usage.downloaded),
+        usage.rate_limited,
+        usage.slept.as_secs_f64(),
+    );
+    if !detailed {
+        info!(
+            requests = usage.requests,
+            not_modified = usage.not_modified,
+            downloaded = usage.downloaded,
+            rate_limited = usage.rate_limited,
+            slept = ?usage.slept,
+            "{summary}"
+        );
+        return;
+    }
+
+    // printed rather than logged, so it's there even with --quiet
+    eprintln!("{summary}");
+
+    let mut endpoints: Vec<_> = usage.endpoints.into_iter().collect();
+    endpoints.sort_by_key(|(_, v)| std::cmp::Reverse(v.total / v.requests.max(1)));
+
+    let mut table = output::table(
+        &["Endpoint", "Requests", "Average", "Slowest"],
+        std::io::stderr().is_terminal(),
+        &[1, 2, 3],
+    );
+    for (path, endpoint) in endpoints.iter().take(SLOWEST_ENDPOINTS) {
+        output::add_row(
+            &mut table,
+            [
+                path.as_str(),
+                &endpoint.requests.to_string(),
+                &format_duration(endpoint.total / endpoint.requests.max(1)),
+                &format_duration(endpoint.slowest),
+            ],
+        );
+    }
+    eprintln!("{table}");
+    if endpoints.len() > SLOWEST_ENDPOINTS {
+        eprintln!(
+            "and {} more endpoint(s)",
+            endpoints.len() - SLOWEST_ENDPOINTS
+        );
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    format!("{}ms", duration.as_millis())
+}
+
+fn format_bytes(bytes: u64) -> String {
+    match bytes {
+        0..1024 => format!("{bytes} B"),
+        1024..1_048_576 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MiB", bytes as f64 / 1_048_576.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_each_run_separately() {
+        let run = Usage::default();
+        let client = run.clone();
+        let other = Usage::default();
+
+        client.request(
+            "https://api.spotify.com/v1/playlists/abc/tracks?offset=0",
+            Duration::from_millis(20),
+            Some(200),
+        );
+        run.request(
+            "https://api.spotify.com/v1/playlists/def/tracks",
+            Duration::from_millis(40),
+            Some(429),
+        );
+        client.downloaded(100);
+        other.request("https://api.spotify.com/v1/me/tracks", Duration::ZERO, None);
+
+        let counts = run.counts();
+        assert_eq!(counts.requests, 2);
+        assert_eq!(counts.rate_limited, 1);
+        assert_eq!(counts.downloaded, 100);
+        let endpoint = &counts.endpoints["playlists/{id}/tracks"];
+        assert_eq!(endpoint.requests, 2);
+        assert_eq!(endpoint.slowest, Duration::from_millis(40));
+        drop(counts);
+
+        assert_eq!(other.counts().requests, 1);
+        assert!(other.counts().endpoints.contains_key("me/tracks"));
+
+        run.report(false);
+        assert_eq!(client.counts().requests, 0);
+    }
+}
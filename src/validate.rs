@@ -0,0 +1,163 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde_json::Value;
+
+use crate::{manifest, migrate, output::Field, parts};
+
+/// Length of the base-62 IDs in Spotify URIs.
+const ID_LEN: usize = 22;
+
+/// Checks a backup file, or every file in a backup directory's manifest, can still be restored
+/// from: the manifest's schema version is one this version understands, each file matches its
+/// checksum, and each JSON backup holds tracks with known fields and well-formed URIs. Prints the
+/// result and number of tracks for each file, failing if any are broken.
+pub async fn run(path: &Path, identities: &[PathBuf]) -> Result<()> {
+    let metadata = tokio::fs::metadata(path)
+        .await
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+
+    let mut failures = 0;
+    let mut files = 0;
+    let mut tracks = 0;
+
+    if metadata.is_dir() {
+        let manifest = manifest::read(path)
+            .await?
+            .with_context(|| format!("No {} found in {}", manifest::FILE_NAME, path.display()))?;
+
+        anyhow::ensure!(
+            manifest.schema_version <= manifest::SCHEMA_VERSION,
+            "Backup was written with a newer schema version ({}) than this version understands \
+             ({}), validate it with a newer version of spotify-backup",
+            manifest.schema_version,
+            manifest::SCHEMA_VERSION
+        );
+
+        for file in &manifest.files {
+            files += 1;
+
+            let result = match manifest::check(path, file).await {
+                Some(status) => Err(status),
+                None => validate_file(&path.join(&file.name), identities).await,
+            };
+            match result {
+                Ok(count) => {
+                    // the tracks of a split backup are counted with each of its parts
+                    if !parts::is_index(&file.name) {
+                        tracks += count.unwrap_or_default();
+                    }
+                    println!("OK       {}{}", file.name, describe(count));
+                }
+                Err(status) => {
+                    failures += 1;
+                    println!("FAILED   {}: {status}", file.name);
+                }
+            }
+        }
+    } else {
+        files += 1;
+        let name = path.display();
+
+        match validate_file(path, identities).await {
+            Ok(count) => {
+                tracks += count.unwrap_or_default();
+                println!("OK       {name}{}", describe(count));
+            }
+            Err(status) => {
+                failures += 1;
+                println!("FAILED   {name}: {status}");
+            }
+        }
+    }
+
+    println!("{files} file(s), {tracks} track(s), {failures} failed");
+    anyhow::ensure!(failures == 0, "{failures} file(s) failed validation");
+
+    Ok(())
+}
+
+fn describe(tracks: Option<usize>) -> String {
+    match tracks {
+        Some(tracks) => format!(": {tracks} track(s)"),
+        None => ": not JSON, only checked it can be read".to_string(),
+    }
+}
+
+/// Reads the backup at `path` and checks its tracks, returning how many there are, or `None` for
+/// backups in a text format, which have no structure to check.
+async fn validate_file(path: &Path, identities: &[PathBuf]) -> Result<Option<usize>, String> {
+    let data = crate::cli::read_backup(path, identities)
+        .await
+        .map_err(|e| format!("UNREADABLE ({e:#})"))?;
+
+    let tracks: Vec<Value> = match data.trim_ascii_start().first() {
+        // written before backups were versioned, as a bare array of tracks
+        Some(b'[') => serde_json::from_slice(&data).map_err(|e| format!("INVALID JSON ({e})"))?,
+        Some(b'{') => {
+            let mut backup: Value =
+                serde_json::from_slice(&data).map_err(|e| format!("INVALID JSON ({e})"))?;
+
+            match backup.get("schema_version").and_then(Value::as_u64) {
+                Some(version) if version > u64::from(manifest::SCHEMA_VERSION) => {
+                    return Err(format!("UNSUPPORTED SCHEMA VERSION ({version})"));
+                }
+                Some(_) => {}
+                None => return Err("INVALID BACKUP (no schema_version)".to_string()),
+            }
+
+            match (
+                backup.get_mut("tracks").map(Value::take),
+                backup.get_mut("albums").map(Value::take),
+            ) {
+                (Some(Value::Array(tracks)), _) => tracks,
+                // written with --group-by album
+                (None, Some(albums)) => migrate::flatten_albums(albums).ok_or_else(|| {
+                    "INVALID BACKUP (albums isn't an array of albums with tracks)".to_string()
+                })?,
+                _ => return Err("INVALID BACKUP (tracks isn't an array)".to_string()),
+            }
+        }
+        _ => {
+            return match std::str::from_utf8(&data) {
+                Ok(_) => Ok(None),
+                Err(_) => Err("UNREADABLE (neither JSON nor text)".to_string()),
+            };
+        }
+    };
+
+    for (i, track) in tracks.iter().enumerate() {
+        let track = track
+            .as_object()
+            .ok_or_else(|| format!("INVALID TRACK (track {i} isn't an object)"))?;
+
+        if let Some(key) = track
+            .keys()
+            .find(|key| !Field::value_variants().iter().any(|v| v.name() == *key))
+        {
+            return Err(format!("UNKNOWN FIELD (track {i} has {key:?})"));
+        }
+
+        // only there if it was one of the fields written
+        if let Some(uri) = track.get("uri") {
+            if !uri.as_str().is_some_and(valid_uri) {
+                return Err(format!("INVALID URI (track {i} has {uri})"));
+            }
+        }
+    }
+
+    Ok(Some(tracks.len()))
+}
+
+/// Whether `uri` is a well-formed track, episode or local file URI.
+fn valid_uri(uri: &str) -> bool {
+    match uri.split(':').collect::<Vec<_>>().as_slice() {
+        ["spotify", "track" | "episode", id] => {
+            id.len() == ID_LEN && id.chars().all(|c| c.is_ascii_alphanumeric())
+        }
+        // spotify:local:<artist>:<album>:<title>:<duration>
+        ["spotify", "local", rest @ ..] => rest.len() == 4,
+        _ => false,
+    }
+}
@@ -0,0 +1,282 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::IsTerminal,
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use futures::{StreamExt, TryStreamExt};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    api, cli::Args, migrate, output, profile::Profile, search, GetPlaylistTracksResponseItemTrack,
+};
+
+/// Most tracks Spotify looks up in one request.
+const MAX_IDS: usize = 50;
+
+/// Tracks of a backup that can no longer be played.
+#[derive(Serialize, Debug)]
+struct Report {
+    /// Tracks in the backup that were checked, which excludes episodes and local files
+    checked: usize,
+    lost: Vec<LostTrack>,
+}
+
+#[derive(Serialize, Debug)]
+struct LostTrack {
+    name: String,
+    artists: Vec<String>,
+    uri: String,
+    status: Status,
+    /// Why Spotify won't play the track, if it says
+    reason: Option<String>,
+    /// Playable tracks that are likely the same song, found by ISRC or failing that by title and
+    /// artist
+    suggestions: Vec<Suggestion>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum Status {
+    /// Spotify no longer knows about the track
+    Gone,
+    /// The track still exists but can't be played in the market
+    Unplayable,
+}
+
+#[derive(Serialize, Debug)]
+struct Suggestion {
+    name: String,
+    artists: Vec<String>,
+    album: String,
+    uri: String,
+}
+
+/// A track read from the backup.
+struct BackupTrack<'a> {
+    id: &'a str,
+    uri: &'a str,
+    name: &'a str,
+    artists: Vec<String>,
+    isrc: Option<&'a str>,
+}
+
+/// Looks up every track in the backup at `path` on Spotify and prints those that are gone or can't
+/// be played in the market given by `--market`, with up to `suggestions` playable replacements for
+/// each.
+pub async fn run(args: &Args, path: &Path, suggestions: u32) -> Result<()> {
+    let data = crate::cli::read_backup(path, &args.identity).await?;
+    let tracks = migrate::tracks(&data)
+        .with_context(|| format!("Failed to read tracks from {}", path.display()))?
+        .with_context(|| format!("{} isn't a JSON backup", path.display()))?;
+
+    let mut backup_tracks = Vec::new();
+    let mut seen = HashSet::new();
+    for (i, track) in tracks.iter().enumerate() {
+        let uri = track["uri"].as_str().with_context(|| {
+            format!(
+                "Track {i} of {} has no URI, back it up with uri in --fields",
+                path.display()
+            )
+        })?;
+        // episodes and local files aren't looked up through the tracks endpoint
+        let Some(id) = uri.strip_prefix("spotify:track:") else {
+            continue;
+        };
+        // a track in the backup more than once is only reported once
+        if !seen.insert(uri) {
+            continue;
+        }
+
+        backup_tracks.push(BackupTrack {
+            id,
+            uri,
+            name: track["name"].as_str().unwrap_or_default(),
+            artists: track["artists"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+                .map(str::to_string)
+                .collect(),
+            isrc: track["isrc"].as_str(),
+        });
+    }
+
+    let profile = Profile::new(&args.profile)?;
+    let client = crate::cli::build_client(&profile, args).await?;
+
+    let found = lookup(&client, args, &backup_tracks).await?;
+
+    let lost = backup_tracks
+        .iter()
+        .filter_map(|track| match found.get(track.id) {
+            None => Some((track, Status::Gone, None)),
+            Some(v) if v.is_playable == Some(false) => Some((
+                track,
+                Status::Unplayable,
+                v.restrictions.as_ref().map(|v| v.reason.clone()),
+            )),
+            Some(_) => None,
+        });
+
+    let lost: Vec<LostTrack> = futures::stream::iter(lost)
+        .map(|(track, status, reason)| {
+            let client = &client;
+            async move {
+                let suggestions = match suggestions {
+                    0 => Vec::new(),
+                    limit => suggest(client, args, track, limit).await?,
+                };
+
+                Ok::<_, anyhow::Error>(LostTrack {
+                    name: track.name.to_string(),
+                    artists: track.artists.clone(),
+                    uri: track.uri.to_string(),
+                    status,
+                    reason,
+                    suggestions,
+                })
+            }
+        })
+        .buffered(client.concurrency())
+        .try_collect()
+        .await?;
+
+    print(
+        args,
+        &Report {
+            checked: backup_tracks.len(),
+            lost,
+        },
+    )
+}
+
+/// Looks up `tracks` in bulk, returning those Spotify still knows about by the ID they were looked
+/// up with.
+async fn lookup(
+    client: &api::Client,
+    args: &Args,
+    tracks: &[BackupTrack<'_>],
+) -> Result<HashMap<String, GetPlaylistTracksResponseItemTrack>> {
+    #[derive(serde::Deserialize)]
+    struct Response {
+        tracks: Vec<Option<GetPlaylistTracksResponseItemTrack>>,
+    }
+
+    let ids: Vec<_> = tracks.iter().map(|v| v.id).collect();
+
+    let chunks: Vec<_> = futures::stream::iter(ids.chunks(MAX_IDS))
+        .map(|chunk| async move {
+            let url = format!(
+                "{}/tracks?ids={}&market={}",
+                api::BASE_URL,
+                chunk.join(","),
+                args.api.market.as_deref().unwrap_or("from_token")
+            );
+            let response: Response = client
+                .get_json(&url)
+                .await
+                .context("Failed to look up tracks")?;
+
+            // tracks are returned in the order they were requested, relinked ones included
+            Ok::<_, anyhow::Error>(chunk.iter().zip(response.tracks).collect::<Vec<_>>())
+        })
+        .buffered(client.concurrency())
+        .try_collect()
+        .await?;
+
+    Ok(chunks
+        .into_iter()
+        .flatten()
+        .filter_map(|(id, track)| Some((id.to_string(), track?)))
+        .collect())
+}
+
+/// Searches for playable tracks that are likely the same song as `track`.
+async fn suggest(
+    client: &api::Client,
+    args: &Args,
+    track: &BackupTrack<'_>,
+    limit: u32,
+) -> Result<Vec<Suggestion>> {
+    let mut queries = Vec::new();
+    if let Some(isrc) = track.isrc {
+        queries.push(format!("isrc:{isrc}"));
+    }
+    match track.artists.first() {
+        Some(artist) => queries.push(format!("artist:\"{artist}\" track:\"{}\"", track.name)),
+        None => queries.push(format!("track:\"{}\"", track.name)),
+    }
+
+    for query in queries {
+        let suggestions: Vec<_> = search::tracks(client, args, &query, limit)
+            .await
+            .with_context(|| format!("Failed to search for replacements of {}", track.uri))?
+            .into_iter()
+            .filter(|v| v.uri != track.uri && v.is_playable != Some(false))
+            .map(|v| Suggestion {
+                artists: v.artists.into_iter().map(|v| v.name).collect(),
+                album: v.album.name,
+                name: v.name,
+                uri: v.uri,
+            })
+            .collect();
+
+        if !suggestions.is_empty() {
+            return Ok(suggestions);
+        }
+    }
+
+    Ok(Vec::new())
+}
+
+fn print(args: &Args, report: &Report) -> Result<()> {
+    let terminal = std::io::stdout().is_terminal();
+
+    match args.format {
+        output::Format::Json => {
+            let json = if args.pretty || terminal {
+                serde_json::to_string_pretty(report)?
+            } else {
+                serde_json::to_string(report)?
+            };
+            println!("{json}");
+        }
+        output::Format::Table => {
+            let mut table = output::table(
+                &["Track", "Artists", "Status", "URI", "Suggestion"],
+                terminal,
+                &[3, 4],
+            );
+            for track in &report.lost {
+                let status = match (track.status, &track.reason) {
+                    (Status::Gone, _) => "gone".to_string(),
+                    (Status::Unplayable, Some(reason)) => format!("unplayable ({reason})"),
+                    (Status::Unplayable, None) => "unplayable".to_string(),
+                };
+                let suggestion = track.suggestions.first().map(|v| v.uri.as_str());
+
+                output::add_row(
+                    &mut table,
+                    [
+                        track.name.as_str(),
+                        &track.artists.join(", "),
+                        &status,
+                        &track.uri,
+                        suggestion.unwrap_or_default(),
+                    ],
+                );
+            }
+            println!("{table}");
+            println!("{} of {} track(s) lost", report.lost.len(), report.checked);
+        }
+        _ => {
+            anyhow::bail!("Lost tracks can only be listed as JSON or a table")
+        }
+    }
+
+    Ok(())
+}
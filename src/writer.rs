@@ -0,0 +1,71 @@
+use std::io::{self, Write};
+
+use sha2::{Digest, Sha256};
+
+/// A layer of the chain of writers a backup is streamed through (eg. compression, encryption),
+/// which has to be finished once everything has been written to flush any trailing data.
+pub trait FinishWrite: Write {
+    /// Finishes this layer and every layer beneath it, returning details of what was written to
+    /// the destination.
+    fn finish(self: Box<Self>) -> io::Result<Written>;
+}
+
+/// Details of the data written to a backup's destination, after compression and encryption.
+pub struct Written {
+    pub size: u64,
+    pub sha256: String,
+    /// Copy of everything written, if one was requested when creating the [`Sink`]
+    pub data: Option<Vec<u8>>,
+}
+
+/// Bottom of the writer chain, writing to the destination while keeping track of the size and
+/// checksum of the output.
+pub struct Sink<W> {
+    inner: W,
+    hasher: Sha256,
+    size: u64,
+    copy: Option<Vec<u8>>,
+}
+
+impl<W: Write> Sink<W> {
+    /// Creates a sink writing to `inner`, optionally keeping a copy of everything written for
+    /// destinations that can't be read back (eg. stdout).
+    pub fn new(inner: W, keep_copy: bool) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            size: 0,
+            copy: keep_copy.then(Vec::new),
+        }
+    }
+}
+
+impl<W: Write> Write for Sink<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+
+        self.hasher.update(&buf[..n]);
+        self.size += n as u64;
+        if let Some(copy) = &mut self.copy {
+            copy.extend_from_slice(&buf[..n]);
+        }
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> FinishWrite for Sink<W> {
+    fn finish(mut self: Box<Self>) -> io::Result<Written> {
+        self.inner.flush()?;
+
+        Ok(Written {
+            size: self.size,
+            sha256: hex::encode(self.hasher.finalize()),
+            data: self.copy,
+        })
+    }
+}
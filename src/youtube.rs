@@ -0,0 +1,180 @@
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+
+use crate::map::{Candidate, Service, Track};
+
+/// Endpoint the YouTube Music web app searches through, as there's no public API for it.
+const SEARCH_URL: &str = "https://music.youtube.com/youtubei/v1/search?prettyPrint=false";
+
+/// Version of the web app the requests claim to come from.
+const CLIENT_VERSION: &str = "1.20241023.01.00";
+
+/// Filters search results to songs, rather than videos, albums or playlists.
+const SONGS_FILTER: &str = "EgWKAQIIAWoMEA4QChADEAQQCRAF";
+
+/// Most search results scored for each track.
+const MAX_RESULTS: usize = 5;
+
+/// Separator between the artists, album and length under each search result.
+const SEPARATOR: &str = " • ";
+
+/// Finds tracks on YouTube Music.
+pub struct YoutubeMusic {
+    http: reqwest::Client,
+}
+
+impl YoutubeMusic {
+    pub fn new() -> Self {
+        Self {
+            http: crate::http::client(),
+        }
+    }
+}
+
+impl Service for YoutubeMusic {
+    fn name(&self) -> &'static str {
+        "youtube"
+    }
+
+    async fn search(&self, track: &Track) -> Result<Vec<Candidate>> {
+        let query = match track.artists.first() {
+            Some(artist) => format!("{artist} {}", track.name),
+            None => track.name.clone(),
+        };
+
+        let body = json!({
+            "context": {
+                "client": {
+                    "clientName": "WEB_REMIX",
+                    "clientVersion": CLIENT_VERSION,
+                    "hl": "en",
+                },
+            },
+            "query": query,
+            "params": SONGS_FILTER,
+        });
+
+        let response: Value = self
+            .http
+            .post(SEARCH_URL)
+            .header("Origin", "https://music.youtube.com")
+            .json(&body)
+            .send()
+            .await
+            .context("Failed to send request to YouTube Music")?
+            .error_for_status()
+            .context("Failed to search YouTube Music")?
+            .json()
+            .await
+            .context("Failed to parse response from YouTube Music")?;
+
+        let mut renderers = Vec::new();
+        find_renderers(&response, &mut renderers);
+
+        Ok(renderers
+            .into_iter()
+            .filter_map(candidate)
+            .take(MAX_RESULTS)
+            .collect())
+    }
+}
+
+/// Collects every search result in the response, which is buried several layers deep in tabs,
+/// shelves and sections whose nesting YouTube changes from time to time.
+fn find_renderers<'a>(value: &'a Value, renderers: &mut Vec<&'a Value>) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                if key == "musicResponsiveListItemRenderer" {
+                    renderers.push(value);
+                } else {
+                    find_renderers(value, renderers);
+                }
+            }
+        }
+        Value::Array(values) => {
+            for value in values {
+                find_renderers(value, renderers);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Reads a song out of a search result, which is laid out as columns of text runs: the title in
+/// the first, then the artists, album and length separated by " • " in the second.
+fn candidate(renderer: &Value) -> Option<Candidate> {
+    let columns = renderer["flexColumns"].as_array()?;
+    let runs = |column: &Value| {
+        column["musicResponsiveListItemFlexColumnRenderer"]["text"]["runs"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default()
+    };
+
+    let title_runs = runs(columns.first()?);
+    let watch_id = title_runs
+        .first()
+        .and_then(|v| v["navigationEndpoint"]["watchEndpoint"]["videoId"].as_str());
+    let id = renderer["playlistItemData"]["videoId"]
+        .as_str()
+        .or(watch_id)?
+        .to_string();
+    let title: String = title_runs
+        .iter()
+        .filter_map(|v| v["text"].as_str())
+        .collect();
+
+    let mut artists = Vec::new();
+    let mut album = None;
+    let mut album_id = None;
+    let mut duration_ms = None;
+
+    let details = columns.get(1).map(runs).unwrap_or_default();
+    for (i, section) in details
+        .split(|v| v["text"].as_str() == Some(SEPARATOR))
+        .enumerate()
+    {
+        let browse_id = section
+            .iter()
+            .find_map(|v| v["navigationEndpoint"]["browseEndpoint"]["browseId"].as_str());
+        let text: String = section.iter().filter_map(|v| v["text"].as_str()).collect();
+
+        if let Some(id) = browse_id.filter(|v| v.starts_with("MPREb")) {
+            album = Some(text);
+            album_id = Some(id.to_string());
+        } else if let Some(v) = parse_duration(&text) {
+            duration_ms = Some(v);
+        } else if i == 0 {
+            artists = section
+                .iter()
+                .filter_map(|v| v["text"].as_str())
+                .filter(|v| !matches!(*v, ", " | " & "))
+                .map(str::to_string)
+                .collect();
+        }
+    }
+
+    Some(Candidate {
+        id,
+        title,
+        artists,
+        album,
+        album_id,
+        duration_ms,
+        by_isrc: false,
+    })
+}
+
+/// Parses a length like "3:45" or "1:02:03" into milliseconds.
+fn parse_duration(text: &str) -> Option<u64> {
+    let mut seconds = 0;
+    for part in text.split(':') {
+        if part.is_empty() || !part.bytes().all(|v| v.is_ascii_digit()) {
+            return None;
+        }
+        seconds = seconds * 60 + part.parse::<u64>().ok()?;
+    }
+
+    text.contains(':').then_some(seconds * 1000)
+}
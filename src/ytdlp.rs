@@ -0,0 +1,135 @@
+use std::{collections::HashSet, io::Read, path::Path};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::Value;
+
+/// A track missing from the local collection, as listed in a match-local or beets report.
+#[derive(Deserialize, Debug)]
+struct Missing {
+    name: String,
+    #[serde(default)]
+    artists: Vec<String>,
+    album: Option<String>,
+    uri: String,
+}
+
+/// Reads the missing tracks out of the JSON printed by `match-local`, or by `beets --emit missing`
+/// (or `report`, keeping the tracks without a file).
+fn read_missing(report: &Value) -> Result<Vec<Missing>> {
+    let entries: Vec<&Value> = match report {
+        Value::Object(v) if v.contains_key("playlists") => v["playlists"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .flat_map(|v| v["unmatched"].as_array().into_iter().flatten())
+            .collect(),
+        Value::Array(v) => v.iter().filter(|v| v["path"].is_null()).collect(),
+        _ => anyhow::bail!("Not a match-local or beets report"),
+    };
+
+    // tracks in several playlists only need downloading once
+    let mut seen = HashSet::new();
+    let mut missing = Vec::new();
+    for entry in entries {
+        let track = Missing::deserialize(entry).context("Report has malformed tracks")?;
+        if seen.insert(track.uri.clone()) {
+            missing.push(track);
+        }
+    }
+
+    Ok(missing)
+}
+
+/// Prints a script downloading each track missing from `report` (a path, or - for stdin) with
+/// yt-dlp, by searching YouTube for its artists and title. With `batch` a batch file of the
+/// searches for `yt-dlp -a` is printed instead.
+pub async fn run(report: &Path, batch: bool) -> Result<()> {
+    let data = if report == Path::new("-") {
+        let mut data = Vec::new();
+        std::io::stdin()
+            .read_to_end(&mut data)
+            .context("Failed to read report from stdin")?;
+        data
+    } else {
+        tokio::fs::read(report)
+            .await
+            .with_context(|| format!("Failed to read {}", report.display()))?
+    };
+    let report: Value = serde_json::from_slice(&data).context("Failed to parse report")?;
+    let missing = read_missing(&report)?;
+
+    if batch {
+        println!(
+            "# {} track(s) missing from the local collection",
+            missing.len()
+        );
+        for track in &missing {
+            println!("# {}", track.uri);
+            println!("{}", search(track));
+        }
+        return Ok(());
+    }
+
+    println!("#!/bin/sh");
+    println!(
+        "# Downloads the {} track(s) missing from the local collection",
+        missing.len()
+    );
+    println!("# Any arguments (eg. -P ~/Music) are passed on to yt-dlp");
+    println!("YT_DLP=\"${{YT_DLP:-yt-dlp}}\"");
+    for track in &missing {
+        let artist = track.artists.join(", ");
+
+        // yt-dlp fills in the tags from the video, which are rarely right for music
+        let mut tags = format!(
+            "Metadata:-metadata {} -metadata {}",
+            quote(&format!("artist={artist}")),
+            quote(&format!("title={}", track.name))
+        );
+        let mut output = sanitize(&artist);
+        if let Some(album) = &track.album {
+            tags.push_str(&format!(" -metadata {}", quote(&format!("album={album}"))));
+            output.push('/');
+            output.push_str(&sanitize(album));
+        }
+        output.push_str(&format!(
+            "/{} - {}.%(ext)s",
+            sanitize(&artist),
+            sanitize(&track.name)
+        ));
+
+        println!();
+        println!("# {}", track.uri);
+        println!(
+            "\"$YT_DLP\" -x --embed-metadata --postprocessor-args {} -o {} \"$@\" {}",
+            quote(&tags),
+            quote(&output),
+            quote(&search(track))
+        );
+    }
+
+    Ok(())
+}
+
+/// yt-dlp URL searching YouTube for a track, downloading the first result.
+fn search(track: &Missing) -> String {
+    match track.artists.first() {
+        Some(artist) => format!("ytsearch1:{artist} - {}", track.name),
+        None => format!("ytsearch1:{}", track.name),
+    }
+}
+
+/// Quotes `value` for the shell, which yt-dlp also splits the postprocessor arguments like.
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Makes `value` usable as a path component in a yt-dlp output template.
+fn sanitize(value: &str) -> String {
+    value
+        .replace(['/', '\\'], "_")
+        .replace('%', "%%")
+        .trim_start_matches('.')
+        .to_string()
+}
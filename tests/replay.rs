@@ -0,0 +1,112 @@
+//! Backs up from the responses recorded in `tests/fixtures/replay` with `--replay`, comparing the
+//! backups written with those in `tests/fixtures/expected`.
+
+use std::{path::Path, process::Command};
+
+use serde_json::Value;
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+
+/// Fields playlists are backed up with, leaving out those the recordings don't have.
+const PLAYLIST_FIELDS: &str = "name,artists,album,added_at,added_by,duration_ms,isrc";
+const PLAYLIST: &str = "37i9dQZF1DXcBWIGoYBM5M";
+
+/// Runs spotify-backup with `args` against the recorded responses, in a home and state dir of its
+/// own so nothing of the user's is read or written, returning whether it succeeded and its output.
+fn replay(args: &[&str]) -> (bool, String) {
+    let home = tempfile::tempdir().unwrap();
+    replay_in(home.path(), args)
+}
+
+/// Runs spotify-backup like [`replay`], with `home` as its home.
+fn replay_in(home: &Path, args: &[&str]) -> (bool, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_spotify-backup"))
+        .env_clear()
+        .env("HOME", home)
+        .env("XDG_CONFIG_HOME", home.join("config"))
+        .env("XDG_DATA_HOME", home.join("data"))
+        .arg("--state-dir")
+        .arg(home.join("state"))
+        .arg("--replay")
+        .arg(Path::new(FIXTURES).join("replay"))
+        // the recordings are paged two items at a time, to cover following pages
+        .args(["--page-size", "2"])
+        .args(args)
+        .output()
+        .unwrap();
+
+    (
+        output.status.success(),
+        String::from_utf8(output.stdout).unwrap(),
+    )
+}
+
+fn expected(name: &str) -> Value {
+    let path = Path::new(FIXTURES).join("expected").join(name);
+    serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap()
+}
+
+#[test]
+fn backs_up_liked_songs() {
+    let (success, output) = replay(&["liked"]);
+
+    assert!(success);
+    assert_eq!(
+        serde_json::from_str::<Value>(&output).unwrap(),
+        expected("liked.json")
+    );
+}
+
+#[test]
+fn backs_up_playlist() {
+    let (success, output) = replay(&["--fields", PLAYLIST_FIELDS, "playlist", PLAYLIST]);
+
+    assert!(success);
+    assert_eq!(
+        serde_json::from_str::<Value>(&output).unwrap(),
+        expected("playlist.json")
+    );
+}
+
+#[test]
+fn fails_on_requests_not_recorded() {
+    let (success, output) = replay(&["playlist", "0000000000000000000000"]);
+
+    assert!(!success);
+    assert!(output.is_empty());
+}
+
+#[test]
+fn reads_split_backups_back_whole() {
+    for format in ["json", "ndjson", "csv"] {
+        let home = tempfile::tempdir().unwrap();
+        let dir = home.path().join("backup");
+        let backup = ["--format", format, "--fields", PLAYLIST_FIELDS];
+
+        let (success, whole) = replay_in(
+            home.path(),
+            &[&backup[..], &["playlist", PLAYLIST]].concat(),
+        );
+        assert!(success);
+
+        // five tracks, so the last part isn't full
+        let (success, _) = replay_in(
+            home.path(),
+            &[
+                &backup[..],
+                &["--split-tracks", "2", "--output", dir.to_str().unwrap()],
+                &["playlist", PLAYLIST],
+            ]
+            .concat(),
+        );
+        assert!(success);
+        assert!(dir
+            .join(format!("playlist-{PLAYLIST}.part-003.{format}"))
+            .exists());
+
+        let joined = dir.join(format!("playlist-{PLAYLIST}.{format}"));
+        let (success, read) = replay_in(home.path(), &["cat", joined.to_str().unwrap()]);
+        assert!(success);
+        assert_eq!(read, whole, "{format}");
+    }
+}